@@ -1,10 +1,25 @@
 use criterion::{criterion_group, criterion_main};
 mod merge_pcaps;
 mod tournament_tree;
+mod writer;
 
 criterion_group!(merge, tournament_tree::identical_inputs);
 criterion_group!(
     stream_decompress_and_merge_pcaps,
     merge_pcaps::stream_and_decompress_throughput
 );
-criterion_main!(/*merge,*/ stream_decompress_and_merge_pcaps);
+criterion_group!(
+    dedicated_decode_threads,
+    merge_pcaps::dedicated_decode_threads_throughput
+);
+criterion_group!(
+    single_file_fast_path,
+    merge_pcaps::single_file_fast_path_throughput
+);
+criterion_group!(write_packets, writer::rewritten_headers);
+criterion_main!(
+    /*merge,*/ stream_decompress_and_merge_pcaps,
+    dedicated_decode_threads,
+    single_file_fast_path,
+    write_packets
+);
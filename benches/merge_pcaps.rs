@@ -1,134 +1,9 @@
+#[path = "../tests/common/corpus.rs"]
+mod corpus;
+
 use assert_cmd::prelude::*;
+use corpus::{CompressionFormat, Corpus, CorpusConfiguration, StorageLocation};
 use criterion::Criterion;
-use fake::Fake;
-use std::io::prelude::*;
-
-#[derive(Copy, Clone)]
-enum CompressionFormat {
-    Uncompressed,
-    Zstd, /* deaults: frame_size : 128Kb, compression_level : -12 */
-    Gzip,
-}
-
-impl std::fmt::Display for CompressionFormat {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        let text = match *self {
-            CompressionFormat::Uncompressed => "Uncompressed",
-            CompressionFormat::Gzip => "Gzip",
-            CompressionFormat::Zstd => "Zstd",
-        };
-        write!(f, "{}", text)
-    }
-}
-
-enum StorageLocation<'tmpdir> {
-    Local { directory: &'tmpdir std::path::Path },
-}
-
-unsafe fn any_as_u8_slice<T: Sized>(p: &T) -> &[u8] {
-    ::std::slice::from_raw_parts((p as *const T) as *const u8, ::std::mem::size_of::<T>())
-}
-
-#[repr(C)]
-pub struct PacketHeader {
-    pub seconds: u32,
-    pub nanoseconds: u32,
-    pub caplen: u32,
-    pub len: u32,
-}
-
-fn write_fake_packet<W: std::io::Write>(
-    mut file: W,
-    seconds_since_epoch: u32,
-    nanoseconds_since_second: u32,
-) -> usize {
-    // all bytes are 7 for now. TODO: randomize these bytes for more realistic compresssion
-    let packet_bytes = [7u8; 153];
-
-    // TODO: somehow use the property testing or mocking framework for choosing the "time step" between packets that is >= 0
-    let header = PacketHeader {
-        seconds: seconds_since_epoch,
-        nanoseconds: nanoseconds_since_second,
-        caplen: packet_bytes.len() as u32,
-        len: packet_bytes.len() as u32,
-    };
-    unsafe { file.write_all(any_as_u8_slice(&header)).unwrap() };
-    file.write_all(&packet_bytes).unwrap();
-
-    std::mem::size_of::<PacketHeader>() + packet_bytes.len()
-}
-
-struct Corpus(Vec<std::path::PathBuf>);
-
-impl Corpus {
-    fn new(config: &CorpusConfiguration) -> Corpus {
-        const GB: usize = 1024 * 1024 * 1024;
-        let mut paths = Vec::with_capacity(config.n_files as usize);
-        let mut current_time: (u32, u32) = (1637796620, 7);
-        for i in 1..=config.n_files {
-            let (mut file, path) = match config.storage_location {
-                StorageLocation::Local { directory } => {
-                    let file_path =
-                        directory.join(std::format!("file_{}_of_{}.pcap", i, config.n_files));
-                    (
-                        std::io::BufWriter::new(std::fs::File::create(&file_path).unwrap()),
-                        file_path,
-                    )
-                }
-            };
-
-            const PCAP_HDR_NSEC: &[u8] = &hex_literal::hex!(
-                "4D 3C B2 A1 02 00 04 00 00 00 00 00 00 00 00 00
-    00 00 04 00 01 00 00 00"
-            );
-            file.write_all(PCAP_HDR_NSEC).unwrap();
-
-            let mut n_bytes_written = PCAP_HDR_NSEC.len();
-            while n_bytes_written < ((config.total_size_gb * GB) / config.n_files as usize) {
-                let to_advance: (u32, u32) = ((0..1).fake(), (0..1000000000).fake());
-                current_time = (
-                    current_time.0 + to_advance.0,
-                    (current_time.1 + to_advance.1) % 1000000000,
-                );
-                n_bytes_written += write_fake_packet(&mut file, current_time.0, current_time.1);
-                /* TODO: pick a random step for the packet timestamp? */
-            }
-
-            drop(file); // flush
-
-            // compress
-            let path = match config.compression_format {
-                CompressionFormat::Uncompressed => {
-                    // nothing to do
-                    path
-                }
-                CompressionFormat::Gzip => {
-                    // replace w/ gzip-compressed .pcap.gz
-                    std::process::Command::new("gzip")
-                        .arg(&path)
-                        .status()
-                        .expect("failed to gzip compress pcap");
-                    path.with_extension("pcap.gz")
-                }
-                CompressionFormat::Zstd => {
-                    // TODO: compress w/ zstd
-                    path.with_extension("pcap.zst")
-                }
-            };
-            paths.push(path);
-        }
-        Corpus(paths)
-    }
-}
-
-// TODO: consider letting users control the packet size?
-// TODO better description: create a benchmark corpus locally or at s3, with the desired compression format, pcap sizes, etc..
-struct CorpusConfiguration<'dir> {
-    total_size_gb: usize,
-    n_files: u16,
-    storage_location: StorageLocation<'dir>,
-    compression_format: CompressionFormat,
-}
 
 pub fn stream_and_decompress_throughput(c: &mut Criterion) {
     const TEST_BINARIES: [&str; 2] = ["merge_pcaps", "mergecap"];
@@ -138,7 +13,8 @@ pub fn stream_and_decompress_throughput(c: &mut Criterion) {
     for total_corpus_size_gb in &[1, 2] {
         for n_files in &[1, 2, 8 /*1, 2, 4, 8, 16, 32*/] {
             for n_runtime_threads in &[1, 2, 7 /*, 5, 6*/] {
-                for compression_format in &[CompressionFormat::Gzip] {
+                // zstd against the gzip baseline, over the same grid
+                for compression_format in &[CompressionFormat::Gzip, CompressionFormat::Zstd] {
                     /*  TODO is the tmp dir automatically deleted at the end of the loop iteration (i.e. on drop)?
                     I think so, which means the corpus gets regenerated and discarded with each benchmark. Not a big deal though */
                     let tmp_dir = tempfile::Builder::new()
@@ -148,7 +24,7 @@ pub fn stream_and_decompress_throughput(c: &mut Criterion) {
 
                     // generate a corpus matching the benchmark parameters, then run the entire merge operation to /dev/null
                     let corpus_config = CorpusConfiguration {
-                        total_size_gb: *total_corpus_size_gb,
+                        total_n_bytes: *total_corpus_size_gb * GB,
                         n_files: *n_files,
                         storage_location: StorageLocation::Local {
                             directory: tmp_dir.path(),
@@ -157,6 +33,7 @@ pub fn stream_and_decompress_throughput(c: &mut Criterion) {
                     };
 
                     let corpus = Corpus::new(&corpus_config);
+                    assert_merges_in_full(&corpus);
 
                     /* TODO: create and interact w/ the corpus via the Corpus type. Might feature commands like compress(Zstd, -12) or copy_to_s3 or something */
 
@@ -212,7 +89,7 @@ pub fn stream_and_decompress_throughput(c: &mut Criterion) {
                                         cmd.env("SMOL_THREADS", n_runtime_threads.to_string()); // TODO: experiment with getting rid of the smol runtime and just using tokio. At least re-do perf experiments now that I understand tokio vs smol better.
                                         cmd.stdout(std::process::Stdio::null());
                                         cmd.stderr(std::process::Stdio::inherit());
-                                        cmd.args(corpus.0.iter());
+                                        cmd.args(corpus.paths.iter());
                                         cmd.assert().success();
                                     }
                                     "mergecap" => {
@@ -220,7 +97,7 @@ pub fn stream_and_decompress_throughput(c: &mut Criterion) {
                                         cmd.stdout(std::process::Stdio::null());
                                         cmd.stderr(std::process::Stdio::inherit());
                                         cmd.arg("-F").arg("nseclibpcap").arg("-w").arg("-");
-                                        cmd.args(corpus.0.iter());
+                                        cmd.args(corpus.paths.iter());
                                         cmd.assert().success();
                                     }
                                     _ => {}
@@ -233,3 +110,122 @@ pub fn stream_and_decompress_throughput(c: &mut Criterion) {
         }
     }
 }
+
+/// Merge `corpus` once, checking that every byte of its inputs is decompressed into the output, so that a corpus which
+/// isn't compressed as its extension says fails rather than being benchmarked as though it were
+fn assert_merges_in_full(corpus: &Corpus) {
+    const PCAP_HEADER_LEN: u64 = 24;
+    let n_record_bytes: u64 = corpus
+        .uncompressed_paths
+        .iter()
+        .map(|path| std::fs::metadata(path).unwrap().len() - PCAP_HEADER_LEN)
+        .sum();
+    let mut merge = std::process::Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .args(corpus.paths.iter())
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    let n_merged_bytes =
+        std::io::copy(&mut merge.stdout.take().unwrap(), &mut std::io::sink()).unwrap();
+    assert!(merge.wait().unwrap().success());
+    assert_eq!(
+        n_merged_bytes,
+        PCAP_HEADER_LEN + n_record_bytes,
+        "merging {:?} didn't decompress every packet",
+        corpus.paths
+    );
+}
+
+/// Compare decoding each file on the global executor's SMOL_THREADS workers against dedicated `--decode-threads`
+pub fn dedicated_decode_threads_throughput(c: &mut Criterion) {
+    const GB: usize = 1024 * 1024 * 1024;
+    const N_FILES: u16 = 8;
+    let mut group = c.benchmark_group("Dedicated Decode Threads");
+    let tmp_dir = tempfile::Builder::new()
+        .prefix("pcap_benchmark_corpus")
+        .tempdir()
+        .unwrap();
+    let corpus_config = CorpusConfiguration {
+        total_n_bytes: GB,
+        n_files: N_FILES,
+        storage_location: StorageLocation::Local {
+            directory: tmp_dir.path(),
+        },
+        compression_format: CompressionFormat::Gzip,
+    };
+    let corpus = Corpus::new(&corpus_config);
+
+    group.throughput(criterion::Throughput::Bytes(
+        corpus_config.total_n_bytes as u64,
+    ));
+    group.sample_size(10);
+    // None: decode on the global executor (the default). Some(n): decode on n dedicated threads
+    for decode_threads in &[None, Some(2), Some(N_FILES as usize)] {
+        let id = match decode_threads {
+            None => "SMOL_THREADS workers".to_string(),
+            Some(n) => std::format!("{} decode threads", n),
+        };
+        group.bench_with_input(
+            criterion::BenchmarkId::new(std::format!("{} Files/Gzip", N_FILES), id),
+            decode_threads,
+            |b, decode_threads| {
+                b.iter(|| {
+                    let mut cmd = std::process::Command::cargo_bin("merge_pcaps").unwrap();
+                    cmd.env("SMOL_THREADS", "1"); // so that any speedup comes from the dedicated decode threads
+                    if let Some(n) = decode_threads {
+                        cmd.arg("--decode-threads").arg(n.to_string());
+                    }
+                    cmd.stdout(std::process::Stdio::null());
+                    cmd.stderr(std::process::Stdio::inherit());
+                    cmd.args(corpus.paths.iter());
+                    cmd.assert().success();
+                });
+            },
+        );
+    }
+}
+
+/// Compare merging a single sorted, uncompressed file via the verbatim copy fast path against the full merge, forced by
+/// a `--start-ns 0` window which includes every packet
+pub fn single_file_fast_path_throughput(c: &mut Criterion) {
+    const GB: usize = 1024 * 1024 * 1024;
+    let mut group = c.benchmark_group("Single File");
+    let tmp_dir = tempfile::Builder::new()
+        .prefix("pcap_benchmark_corpus")
+        .tempdir()
+        .unwrap();
+    let corpus_config = CorpusConfiguration {
+        total_n_bytes: GB,
+        n_files: 1,
+        storage_location: StorageLocation::Local {
+            directory: tmp_dir.path(),
+        },
+        compression_format: CompressionFormat::Uncompressed,
+    };
+    let corpus = Corpus::new(&corpus_config);
+
+    group.throughput(criterion::Throughput::Bytes(
+        corpus_config.total_n_bytes as u64,
+    ));
+    group.sample_size(10);
+    for (id, extra_args) in &[
+        ("Verbatim Copy", &[][..]),
+        ("Full Merge", &["--start-ns", "0"][..]),
+    ] {
+        group.bench_with_input(
+            criterion::BenchmarkId::new("1 GB/Uncompressed", id),
+            extra_args,
+            |b, extra_args| {
+                b.iter(|| {
+                    let mut cmd = std::process::Command::cargo_bin("merge_pcaps").unwrap();
+                    cmd.args(extra_args.iter());
+                    cmd.stdout(std::process::Stdio::null());
+                    cmd.stderr(std::process::Stdio::inherit());
+                    cmd.args(corpus.paths.iter());
+                    cmd.assert().success();
+                });
+            },
+        );
+    }
+}
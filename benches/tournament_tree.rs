@@ -1,5 +1,7 @@
 use criterion::{black_box, BenchmarkId, Criterion};
 use itertools::Itertools;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
 use stream_merge;
 
 pub struct InputStream<T: Iterator<Item = u64>> {
@@ -30,6 +32,44 @@ impl<T: Iterator<Item = u64>> stream_merge::tournament_tree::Mergeable for Input
     }
 }
 
+/// K-way merge of [Mergeable](stream_merge::tournament_tree::Mergeable) inputs using a [BinaryHeap] of each input's
+/// next timestamp: the baseline most would reach for before a tournament tree. Like the tree, an input is only
+/// re-inserted with its next timestamp on the pop after its data was returned, so that the data can be borrowed from
+/// the input in the meantime.
+pub struct HeapMerge<T: stream_merge::tournament_tree::Mergeable> {
+    heap: BinaryHeap<Reverse<(u64, usize)>>, // (next timestamp, input index), ties popped in input order
+    input_streams: Vec<T>,
+    last_popped: Option<usize>, // input whose data was last returned, yet to be re-inserted
+}
+impl<T: stream_merge::tournament_tree::Mergeable> HeapMerge<T> {
+    pub fn new(mut input_streams: Vec<T>) -> HeapMerge<T> {
+        let heap = input_streams
+            .iter_mut()
+            .enumerate()
+            .map(|(index, input)| (input.peek_timestamp(), index))
+            .filter(|(ts, _index)| *ts != std::u64::MAX)
+            .map(Reverse)
+            .collect();
+        HeapMerge {
+            heap,
+            input_streams,
+            last_popped: None,
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<&T::Data> {
+        if let Some(index) = self.last_popped.take() {
+            let ts = self.input_streams[index].peek_timestamp();
+            if ts != std::u64::MAX {
+                self.heap.push(Reverse((ts, index)));
+            }
+        }
+        let Reverse((_ts, index)) = self.heap.pop()?;
+        self.last_popped = Some(index);
+        self.input_streams[index].pop()
+    }
+}
+
 const STEP: usize = 1; // change this to 3, for example, to run every 3rd power of two
 const BENCHMARK_N_INPUTS: [usize; 13] = [1, 2, 4, 8, 16, 32, 64, 128, 256, 512, 1024, 2048, 4096];
 const N_STEPS: usize = BENCHMARK_N_INPUTS.len();
@@ -58,6 +98,20 @@ pub fn identical_inputs(c: &mut Criterion) {
                 })
             },
         );
+        group.bench_with_input(
+            BenchmarkId::new("BinaryHeap", n_streams),
+            n_streams,
+            |b, n_streams| {
+                let inputs = (0..*n_streams)
+                    .map(|_| InputStream::new(0..std::u64::MAX))
+                    .collect();
+                let mut merged = HeapMerge::new(inputs);
+                b.iter(|| {
+                    let _popped = black_box(merged.pop())
+                        .expect("I thought this iterator would yield values for forever");
+                })
+            },
+        );
         group.bench_with_input(
             BenchmarkId::new("Kmerge", n_streams),
             n_streams,
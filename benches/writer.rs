@@ -0,0 +1,54 @@
+use criterion::{black_box, BenchmarkId, Criterion};
+use std::io::Write;
+use stream_merge::pcap::{OutputFormat, Writer, RECORD_HEADER_LEN};
+
+const N_PACKETS: u64 = 10_000;
+
+/// 64-byte packets with standard pcap record headers
+fn records() -> Vec<(u64, Vec<u8>)> {
+    (0..N_PACKETS)
+        .map(|i| {
+            let mut record = vec![0; RECORD_HEADER_LEN];
+            record[8..12].copy_from_slice(&64u32.to_le_bytes());
+            record[12..16].copy_from_slice(&64u32.to_le_bytes());
+            record.extend_from_slice(&[i as u8; 64]);
+            (i * 1_000, record)
+        })
+        .collect()
+}
+
+/// Write each packet's rewritten header with [Writer], which reuses one header buffer across packets, versus building
+/// each length-prefixed packet in a newly allocated buffer
+pub fn rewritten_headers(c: &mut Criterion) {
+    let records = records();
+    let mut group = c.benchmark_group("Write Rewritten Packet Headers");
+    group.throughput(criterion::Throughput::Elements(N_PACKETS));
+    for &format in &[OutputFormat::Pcap, OutputFormat::LengthPrefixed] {
+        group.bench_with_input(BenchmarkId::new("Writer", format), &format, |b, &format| {
+            b.iter(|| {
+                let mut writer = Writer::new(std::io::sink(), format).unwrap();
+                for (ts, record) in &records {
+                    writer.write_packet(*ts, record).unwrap();
+                }
+                black_box(writer.into_inner())
+            })
+        });
+    }
+    group.bench_function(
+        BenchmarkId::new("Allocating per packet", OutputFormat::LengthPrefixed),
+        |b| {
+            b.iter(|| {
+                let mut sink = std::io::sink();
+                for (ts, record) in &records {
+                    let payload = &record[RECORD_HEADER_LEN..];
+                    let mut packet = Vec::new();
+                    packet.extend_from_slice(&ts.to_le_bytes());
+                    packet.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+                    packet.extend_from_slice(payload);
+                    sink.write_all(black_box(&packet)).unwrap();
+                }
+            })
+        },
+    );
+    group.finish();
+}
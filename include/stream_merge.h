@@ -0,0 +1,36 @@
+/*
+ * C interface to stream-merge, for merging pcaps without running merge_pcaps. Build the library with the ffi feature,
+ * e.g. as a static library to link against:
+ *
+ *     cargo rustc --release --lib --features ffi --crate-type staticlib
+ */
+#ifndef STREAM_MERGE_H
+#define STREAM_MERGE_H
+
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* The merge succeeded */
+#define SMERGE_OK 0
+/* A path was null or not UTF-8, or the file descriptor negative */
+#define SMERGE_EINVAL 1
+/* An input couldn't be read, or the output written */
+#define SMERGE_EMERGE 2
+/* The merge panicked */
+#define SMERGE_EPANIC 3
+
+/*
+ * Merge the n pcaps at paths (local or s3://, optionally .gz or .zst compressed) into a nanosecond-precision pcap
+ * written to the open file descriptor out_fd, which is left open. Blocks until the merge is done, returning SMERGE_OK
+ * or one of the other SMERGE_ error codes.
+ */
+int smerge_merge_files(const char *const *paths, size_t n, int out_fd);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* STREAM_MERGE_H */
@@ -1,103 +1,2041 @@
+use anyhow::Context;
 use bytes::Bytes;
+use futures::stream::{Stream, StreamExt};
 use std::io::{BufWriter, Write};
-use stream_merge::tournament_tree;
-
-use hex_literal::hex;
+use stream_merge::compression::{AdaptiveEncoder, Compression, Encoder};
+use stream_merge::config::{
+    discover_inputs, offset_timestamp, InputConfig, InputOrder, MergeConfig, TimeWindow,
+    WatchedDirectory,
+};
+use stream_merge::output::{
+    AtomicFile, CommandFailed, HashAlgorithm, HashingOutput, Output, PipedCommand,
+};
+use stream_merge::spill::Spill;
+use stream_merge::stats::{self, RateSeries, TimeRange};
+use stream_merge::tournament_tree::{
+    ArrivalOrdered, MergeClock, MergeableOwned, OwnedTree, PacketStream, SidecarKeyed,
+    SidecarMismatch,
+};
+use stream_merge::{
+    pcap, pcap::pcapng::Interface, pcap::pcapng::PacketMeta, s3, Deadline, DecodePool,
+    IdleWatchdog, MemoryBudget, MonotonicTimestamps, OnBackwards, OpenFileLimit, RateLimiter,
+};
 
+#[cfg(feature = "jemalloc")]
 #[global_allocator]
 static GLOBAL: jemallocator::Jemalloc = jemallocator::Jemalloc;
 
+use std::cell::Cell;
 use std::path::PathBuf;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::Duration;
 use structopt::StructOpt;
 
-struct PacketStream<T: Iterator<Item = (u64, Bytes)>> {
-    iterator: std::iter::Peekable<T>,
-    current_value: Option<(u64, Bytes)>,
+#[derive(StructOpt)]
+#[structopt(
+    version = "1.0",
+    author = "Bobby McShane <mcshane.bobby@gmail.com>",
+    about = "Merge PCAP files [s3:/]/path/to/files*.pcap[.gz|.zst] files together in time-sequence from AWS S3 or a local filesystem",
+    after_help = "EXIT CODES:
+    0      every input was merged in full
+    1      any failure not listed below, e.g. invalid arguments or an output which couldn't be written
+    2      an input couldn't be parsed, e.g. a corrupt pcap
+    3      a request to S3 failed, e.g. because access was denied or the object is archived, or --preflight failed
+    4      the merge completed without the remainder of an input which failed to be read, e.g. a missing file
+    124    the merge didn't complete within --deadline, so only the packets merged until then were written
+    130    the merge was interrupted by SIGINT or SIGTERM
+
+An input which fails part way through is merged up to the failure, and the exit code then reports the first input \
+to fail."
+)]
+struct Args {
+    /// pcap files to merge. Replaces the inputs listed in --config, if any. Local directories and s3://bucket/prefix/
+    /// URIs (with a trailing /) are searched recursively for *.pcap, *.pcap.gz and *.pcap.zst files to merge. With the
+    /// live-capture feature, iface:<name> (e.g. iface:eth0) merges the packets captured live from a network interface
+    #[structopt(required_unless_one = &["config", "watch", "capabilities"], parse(from_os_str))]
+    pcaps: Vec<PathBuf>,
+
+    /// search directories given as pcaps at most this many levels deep, 1 being only the files directly within them
+    #[structopt(long)]
+    max_depth: Option<usize>,
+
+    /// framing of the pcaps given on the command line: pcap, raw (u64 timestamp + u32 length + payload) or pcapng.
+    /// Detected from each file's extension (.raw for raw, .pcapng for pcapng) when unset
+    #[structopt(long)]
+    input_format: Option<pcap::InputFormat>,
+
+    /// order in which each pcap's packets are merged: timestamp (the default), or arrival for inputs without reliable
+    /// timestamps, merged as soon as each packet is read. Given once to apply to every pcap, or once per pcap in order
+    #[structopt(long, number_of_values = 1)]
+    order: Vec<InputOrder>,
+
+    /// merge the packets of a pcap by the timestamps of a sidecar file rather than their own, written <pcap>=<sidecar>.
+    /// The sidecar holds one little-endian u64 nanosecond timestamp per packet, in ascending order, matched to the
+    /// pcap's packets by position. May be given once per pcap
+    #[structopt(long, number_of_values = 1, parse(try_from_str = parse_ts_sidecar))]
+    ts_sidecar: Vec<(String, String)>,
+
+    /// rewrite the packets of every pcap of a link type into Ethernet framing, so captures of the same traffic with
+    /// different encapsulations merge into uniform output: sll-to-ethernet (Linux cooked captures) or
+    /// raw-ip-to-ethernet. Pcaps of other link types are merged as they are
+    #[structopt(long)]
+    transcode: Option<pcap::Transcode>,
+
+    /// sort the packets of each input which are out of timestamp order by no more than this long (e.g. 10us), as in
+    /// captures with some local reordering, holding each packet until one this much later is read. Packets further out
+    /// of order are left so
+    #[structopt(long, conflicts_with_all = &["key-expr", "ts-sidecar"], parse(try_from_str = parse_duration))]
+    reorder_window: Option<Duration>,
+
+    /// merge inputs which refer to the same file (e.g. a path given twice) once per occurrence, rather than once
+    #[structopt(long)]
+    allow_duplicate_inputs: bool,
+
+    /// when a directory or `s3://` prefix holds no pcaps (e.g. a day without captures), or there are no inputs at all,
+    /// write an output of no packets and succeed, rather than fail
+    #[structopt(long)]
+    allow_empty: bool,
+
+    /// JSON merge config describing inputs, per-file offsets, time window and output. Other flags override its values
+    #[structopt(long, parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    /// write merged output to this file instead of stdout. It only replaces the file once the merge has completed
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// write merged output to the stdin of this command, rather than stdout, like a shell pipe: the program followed by
+    /// its whitespace-separated arguments, e.g. "tshark -r -". Exits with the command's exit status if it fails
+    #[structopt(long, conflicts_with_all = &["output", "output-dir"])]
+    pipe_to: Option<String>,
+
+    /// hash each output's bytes with this algorithm (sha256 or xxh3) as they're written, after any --compress, printing the
+    /// digest to stderr once the output is complete: a `<digest>  <path>` line like sha256sum's, with `-` for stdout
+    #[structopt(long)]
+    output_hash: Option<HashAlgorithm>,
+
+    /// once merged, wait for output files to be durably stored on disk (fsync) before exiting
+    #[structopt(long)]
+    fsync: bool,
+
+    /// output encoding: pcap (default), length-prefixed (u64 timestamp + u32 length + payload, no global header), or
+    /// pcapng, describing the interface each packet was captured on: one per input, or per interface of a pcapng input
+    #[structopt(long)]
+    format: Option<pcap::OutputFormat>,
+
+    /// compress merged output: none (default), gzip or zstd
+    #[structopt(long)]
+    compress: Option<Compression>,
+
+    /// start at the fastest --compress level, raising it while the output sink (rather than compression) is the bottleneck
+    #[structopt(long, requires = "compress")]
+    compress_adaptive: bool,
+
+    /// drop packets with (offset-adjusted) timestamps before this many nanoseconds since the epoch
+    #[structopt(long)]
+    start_ns: Option<u64>,
+
+    /// stop merging at the first packet with a timestamp at or after this many nanoseconds since the epoch
+    #[structopt(long)]
+    end_ns: Option<u64>,
+
+    /// add this signed number of nanoseconds to the timestamp of every packet written, after any per-file offsets and
+    /// the --start-ns/--end-ns window have been applied
+    #[structopt(long, allow_hyphen_values = true)]
+    output_offset: Option<i64>,
+
+    /// shift every packet written so that the first is at timestamp 0, preserving the spacing between packets. Like
+    /// --output-offset, applied after per-file offsets and the --start-ns/--end-ns window
+    #[structopt(long, conflicts_with = "output-offset")]
+    rebase_to_zero: bool,
+
+    /// cap on the bytes of S3 chunk read-ahead buffered across all files at once
+    #[structopt(long)]
+    memory_budget: Option<usize>,
+
+    /// cap the aggregate rate of S3 downloads across all files at this many bytes per second
+    #[structopt(long)]
+    max_read_bps: Option<u64>,
+
+    /// read (and list) `s3://` inputs in requester-pays buckets, accepting the charges for doing so
+    #[structopt(long)]
+    requester_pays: bool,
+
+    /// read (and list) `s3://` inputs with the credentials and region of this profile of the shared AWS credentials and
+    /// config files, rather than those found from the environment (where AWS_PROFILE selects a profile)
+    #[structopt(long)]
+    profile: Option<String>,
+
+    /// request that any `s3://` input archived in the GLACIER or DEEP_ARCHIVE storage class be restored for this many
+    /// days. The merge still fails until every restore has completed, so retry once they have
+    #[structopt(long)]
+    restore: Option<u32>,
+
+    /// learn the size of each `s3://` input with a HEAD request before requesting its first chunk, rather than from
+    /// the response to that request, at the cost of a round trip per input before it's read
+    #[structopt(long)]
+    head_first: bool,
+
+    /// before merging, check that every `s3://` input exists, isn't empty and, unless --restore is given, isn't
+    /// archived, reporting every input which fails at once rather than failing the merge when it's reached
+    #[structopt(long)]
+    preflight: bool,
+
+    /// with --preflight, also fetch the first bytes of each `s3://` input to check that they're the magic number of its
+    /// compression format or, uncompressed, of a pcap or pcapng file
+    #[structopt(long, requires = "preflight")]
+    preflight_magic: bool,
+
+    /// keep reading each `s3://` input as it grows, like `tail -f`: once read to its end, check its size again every
+    /// --follow-interval and read on if it has grown. For objects still being written. The merge then only ends once
+    /// interrupted
+    #[structopt(long)]
+    follow: bool,
+
+    /// how long --follow waits between checks of an input's size once it has been read to its end, e.g. 500ms or 10s
+    /// (default 1s)
+    #[structopt(long, requires = "follow", parse(try_from_str = parse_duration))]
+    follow_interval: Option<Duration>,
+
+    /// keep merging the files of a directory which match a file name pattern as they appear, e.g.
+    /// '/captures/ring-*.pcap' (quoted, so the shell doesn't expand it), for capture tools which rotate through a ring of
+    /// files. The directory is rescanned every --watch-interval, and each new file is merged from then on, so should be
+    /// complete once it matches (e.g. written under another name and renamed into place). Any of its packets older than
+    /// those already merged are written next, out of time order unless --clamp restamps them (or --fail-on-backwards
+    /// fails on them). Watches until interrupted, or for --watch-idle
+    #[structopt(long, conflicts_with_all = &["key-expr", "coalesce"])]
+    watch: Option<String>,
+
+    /// how long --watch waits between scans of its directory, e.g. 500ms or 10s (default 1s)
+    #[structopt(long, requires = "watch", parse(try_from_str = parse_duration))]
+    watch_interval: Option<Duration>,
+
+    /// stop watching once no new file has appeared for this long, e.g. 10m, ending the merge once its inputs are merged
+    #[structopt(long, requires = "watch", parse(try_from_str = parse_duration))]
+    watch_idle: Option<Duration>,
+
+    /// decode .zst inputs with this zstd dictionary (e.g. as trained by `zstd --train`), which inputs compressed with
+    /// it can't be decoded without. Inputs compressed without a dictionary are still decoded
+    #[structopt(long, parse(from_os_str))]
+    zstd_dict: Option<PathBuf>,
+
+    /// decode every input as fast as it can be read rather than only just ahead of the merge, writing the decoded packets
+    /// waiting to be merged to a temporary file per input in this directory once more than --spill-threshold bytes of
+    /// them are waiting in memory
+    #[structopt(long, parse(from_os_str))]
+    spill_dir: Option<PathBuf>,
+
+    /// bytes of decoded packets to hold in memory across every input before spilling to --spill-dir (default 64MiB)
+    #[structopt(long, requires = "spill-dir")]
+    spill_threshold: Option<usize>,
+
+    /// decompress and parse files on this many dedicated threads rather than the SMOL_THREADS async workers
+    #[structopt(long)]
+    decode_threads: Option<usize>,
+
+    /// keep at most this many local input files open at once, opening each once its packets are first needed and
+    /// closing it while they wait to be merged. Useful for merging more files than the open file descriptor limit
+    #[structopt(long)]
+    max_open_files: Option<usize>,
+
+    /// report an input ending part way through a record as an error, rather than silently dropping the partial record
+    #[structopt(long)]
+    strict: bool,
+
+    /// correct for timestamps' 32-bit seconds wrapping around (e.g. past 2038) part way through a pcap, rather than
+    /// treating the jump back in time as out of order
+    #[structopt(long)]
+    fix_wraparound: bool,
+
+    /// fail before merging if any input's timestamps are not of this precision: us or ns. Raw inputs and live captures
+    /// have nanosecond precision
+    #[structopt(long)]
+    require_precision: Option<pcap::Precision>,
+
+    /// check that the first packets of every file are in ascending time order, failing before merging any that are not
+    #[structopt(long, default_value = "true", parse(try_from_str))]
+    check_order: bool,
+
+    /// restamp any packet which would go backwards in time with the previous packet's timestamp, rather than writing
+    /// it out of order
+    #[structopt(long, conflicts_with = "key-expr")]
+    clamp: bool,
+
+    /// fail if the merged output would go backwards in time, rather than writing the packet out of order
+    #[structopt(long, conflicts_with_all = &["clamp", "key-expr"])]
+    fail_on_backwards: bool,
+
+    /// merge packets by an integer field of their captured bytes rather than by timestamp, written <type>@<offset>
+    /// (e.g. u32be@42). The type is one of u8, u16be, u16le, u32be, u32le, u64be or u64le. Every input must already be
+    /// sorted by the field, so --check-order does not apply. Only for pcap inputs merged in timestamp order without
+    /// offsets
+    #[structopt(long)]
+    key_expr: Option<pcap::KeyExpr>,
+
+    /// split merged output into this many shards by a stable hash of each packet's 5-tuple, written to --output-dir
+    #[structopt(long, requires = "output-dir", conflicts_with = "output")]
+    shard_by_hash: Option<usize>,
+
+    /// roll merged output over to a new file in --output-dir after every this many packets, named by sequence number
+    /// (e.g. part_000002.pcap)
+    #[structopt(long, requires = "output-dir", conflicts_with_all = &["output", "shard-by-hash"])]
+    split_packets: Option<u64>,
+
+    /// directory in which --shard-by-hash writes one file per shard, --split-packets writes its sequence of files, or
+    /// --no-merge writes one file per input
+    #[structopt(long, parse(from_os_str))]
+    output_dir: Option<PathBuf>,
+
+    /// rather than merging the inputs together, write each to its own file in --output-dir, named after it (e.g.
+    /// a.pcap.gz becomes a.pcap), with its packets read, transformed and written as they would be merged alone
+    #[structopt(
+        long,
+        requires = "output-dir",
+        conflicts_with_all = &["output", "shard-by-hash", "split-packets", "pipe-to", "coalesce", "tag-source", "watch"]
+    )]
+    no_merge: bool,
+
+    /// rather than merge, print to stdout the first and last timestamps of each input and of all of them together.
+    /// Those of an uncompressed pcap are read from its first record and a range at its end, others decoded in full
+    #[structopt(long, conflicts_with_all = &["key-expr", "watch"])]
+    time_ranges_only: bool,
+
+    /// rather than merge, print to stdout as JSON what this build supports: its version, input schemes, formats and
+    /// compressions, output formats and compressions, and enabled features
+    #[structopt(long)]
+    capabilities: bool,
+
+    /// once merged, print to stderr how much the time ranges of files adjacent in time overlap
+    #[structopt(long)]
+    report_overlap: bool,
+
+    /// once merged, print to stderr each pair of files adjacent in time whose time ranges don't abut within this
+    /// tolerance (e.g. 1ms, 5s): a gap from one's last packet to the next's first, or an overlap, longer than it. For
+    /// rolling captures which should tile the timeline
+    #[structopt(long, parse(try_from_str = parse_duration))]
+    check_contiguous: Option<Duration>,
+
+    /// fail the merge if --check-contiguous finds any gap or overlap
+    #[structopt(long, requires = "check-contiguous")]
+    require_contiguous: bool,
+
+    /// while merging, write the number of packets and captured bytes merged in each --rate-bucket of time to this CSV
+    #[structopt(long, conflicts_with = "key-expr", parse(from_os_str))]
+    rate_csv: Option<PathBuf>,
+
+    /// length of each bucket of time counted in --rate-csv, e.g. 100ms or 1m (default 1s)
+    #[structopt(long, requires = "rate-csv", parse(try_from_str = parse_duration))]
+    rate_bucket: Option<Duration>,
+
+    /// log a WARN each time this long (e.g. 30s, 500ms, 5m) passes without a packet being merged, e.g. while tailing
+    /// inputs which have all gone quiet
+    #[structopt(long, parse(try_from_str = parse_duration))]
+    idle_warn: Option<Duration>,
+
+    /// stop the merge if it hasn't completed this long (e.g. 10m, 30s) after starting, even while waiting on a slow
+    /// input, writing out the packets merged so far and exiting with status 124
+    #[structopt(long, parse(try_from_str = parse_duration))]
+    deadline: Option<Duration>,
+
+    /// listen on a Unix domain socket at this path while merging, sending each connection the merge's progress as a
+    /// line of JSON: the packets merged from each input and in all, the timestamp merged up to, and an ETA if the
+    /// size of every input is known (only that of local, uncompressed pcaps is)
+    #[cfg(unix)]
+    #[structopt(long, parse(from_os_str))]
+    stats_socket: Option<PathBuf>,
+
+    /// wherever more than this long (e.g. 1s, 500ms) passes between consecutive written packets, insert marker packets
+    /// with no captured bytes and an original length of 0 at this interval, keeping the output's timeline dense
+    #[structopt(long, conflicts_with = "key-expr", parse(try_from_str = parse_duration))]
+    max_gap: Option<Duration>,
+
+    /// rather than write each packet, write one record per group of packets within this long (e.g. 500ns, 2us) of the
+    /// group's first packet, stamped with its time. The record's captured bytes are the little-endian u32 number of
+    /// packets in the group, then each packet's own nanosecond pcap record
+    #[structopt(long, conflicts_with_all = &["key-expr", "shard-by-hash"], parse(try_from_str = parse_duration))]
+    coalesce: Option<Duration>,
+
+    /// keep only about one in every N merged packets, written 1/N (e.g. 1/10), to cut lightweight fixtures from real
+    /// captures. Which packets are kept is chosen by --sample-by
+    #[structopt(long, parse(try_from_str = parse_sample))]
+    sample: Option<u64>,
+
+    /// how --sample chooses the packets it keeps: every (the default) keeps the first of every N merged packets, and hash
+    /// the packets whose timestamp and bytes hash to a multiple of N, so that the same packets are kept however the
+    /// inputs are combined
+    #[structopt(long, requires = "sample")]
+    sample_by: Option<pcap::SampleMode>,
+
+    /// truncate the captured bytes of every packet to at most this many, rewriting its record's captured length while
+    /// keeping its original length on the wire. Pcap output's header gives this as its snaplen
+    #[structopt(long, conflicts_with = "coalesce")]
+    truncate_snaplen: Option<u32>,
+
+    /// the thiszone field of pcap output's header: the offset in seconds of the capture's local time from UTC (e.g.
+    /// -18000), which some tools read. Carried over from the header of the first input when unset
+    #[structopt(long, allow_hyphen_values = true)]
+    thiszone: Option<i32>,
+
+    /// the sigfigs field of pcap output's header, the accuracy of its timestamps. Carried over from the header of the
+    /// first input when unset
+    #[structopt(long)]
+    sigfigs: Option<u32>,
+
+    /// prefix each length-prefixed frame with the little-endian u32 index of the input it was merged from, in the order
+    /// inputs are given, so that their time-aligned lanes can be compared. Requires --format length-prefixed
+    #[structopt(long, conflicts_with = "coalesce")]
+    tag_source: bool,
+
+    /// include each packet's pcapng metadata in its length-prefixed frame, after any --tag-source tag: the
+    /// little-endian u32 ID of the interface it was captured on, a byte giving its direction (0 unknown, 1 inbound,
+    /// 2 outbound) and the little-endian u64 count of packets dropped before it (u64::MAX if unknown). Packets of pcap
+    /// and raw inputs have the unknown metadata of interface 0. Requires --format length-prefixed
+    #[structopt(long, conflicts_with = "coalesce")]
+    packet_meta: bool,
+
+    /// only log errors, suppressing all other tracing output whatever RUST_LOG enables
+    #[structopt(long, short)]
+    quiet: bool,
+}
+
+/// Exit status of the merge, distinguishing why it failed for scripts. Listed in the --help text
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ExitCode {
+    Success = 0,
+    /// any failure not described below
+    Failure = 1,
+    /// an input couldn't be parsed
+    InvalidInput = 2,
+    /// a request to S3 failed, or an `s3://` input failed --preflight
+    S3 = 3,
+    /// the merge completed without the remainder of an input, which failed for another reason (e.g. a missing file)
+    SkippedInputs = 4,
+    /// the merge didn't complete within its deadline, as timeout(1) reports
+    DeadlineExceeded = 124,
+    /// the merge was interrupted by a signal, as the shell reports a process killed by SIGINT
+    Interrupted = 130,
 }
-impl<T: Iterator<Item = (u64, Bytes)>> PacketStream<T> {
-    pub fn new(iterator: T) -> PacketStream<T> {
-        PacketStream {
-            iterator: iterator.peekable(),
-            current_value: None,
+
+impl ExitCode {
+    /// The exit status for a merge which failed with `error`
+    fn of(error: &anyhow::Error) -> ExitCode {
+        let failed_request = error.chain().any(|cause| {
+            cause
+                .downcast_ref::<std::io::Error>()
+                .is_some_and(|error| s3::FailureKind::of_request(error).is_some())
+        });
+        let invalid_input = error.chain().any(|cause| {
+            cause
+                .downcast_ref::<pcap::PacketError>()
+                .is_some_and(|error| !matches!(error, pcap::PacketError::Read(_)))
+        });
+        if error.is::<Interrupted>() {
+            ExitCode::Interrupted
+        } else if error.is::<DeadlineExceeded>() {
+            ExitCode::DeadlineExceeded
+        } else if failed_request || error.is::<PreflightFailed>() {
+            ExitCode::S3
+        } else if invalid_input {
+            ExitCode::InvalidInput
+        } else if error.is::<SkippedInputs>() {
+            ExitCode::SkippedInputs
+        } else {
+            ExitCode::Failure
         }
     }
 }
 
-// TODO: rather than taking a "PacketStream", just take an iterator which returns tuples of
-// (Value,Data) as packet to tournament_tree::Tree::new!
-impl<T: Iterator<Item = (u64, Bytes)>> tournament_tree::Mergeable for PacketStream<T> {
-    type Data = <T>::Item;
+/// Context of the error of the first input which the merge completed without the remainder of
+#[derive(Debug)]
+struct SkippedInputs {
+    n_skipped: usize,
+}
 
-    // returns an tuple of (timestamp,data) or None
-    fn pop(&mut self) -> Option<&<T>::Item> {
-        self.current_value = self.iterator.next();
-        self.current_value.as_ref()
+impl std::fmt::Display for SkippedInputs {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Merged without the remainder of {} input(s), the first of which failed",
+            self.n_skipped
+        )
     }
+}
 
-    fn peek_timestamp(&mut self) -> u64 {
-        //println!("peeking {:?}", self.iterator.peek());
-        if let Some((ts, _bytes)) = self.iterator.peek() {
-            *ts
+/// Error of a merge which didn't start because `s3://` inputs failed --preflight
+#[derive(Debug)]
+struct PreflightFailed {
+    n_failed: usize,
+    n_checked: usize,
+}
+
+impl std::fmt::Display for PreflightFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} of {} s3:// inputs failed --preflight",
+            self.n_failed, self.n_checked
+        )
+    }
+}
+
+impl std::error::Error for PreflightFailed {}
+
+/// Error of a merge stopped by SIGINT or SIGTERM
+#[derive(Debug)]
+struct Interrupted;
+
+impl std::fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("Interrupted before the merge completed. No output files were written")
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+/// Error of a merge stopped by its --deadline
+#[derive(Debug)]
+struct DeadlineExceeded;
+
+impl std::fmt::Display for DeadlineExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("The merge didn't complete within --deadline. The output holds the packets merged until then")
+    }
+}
+
+impl std::error::Error for DeadlineExceeded {}
+
+/// Parse a `--ts-sidecar` argument, `<pcap>=<sidecar>`, into its pcap and sidecar paths
+fn parse_ts_sidecar(text: &str) -> anyhow::Result<(String, String)> {
+    match text.rsplit_once('=') {
+        Some((pcap, sidecar)) if !pcap.is_empty() && !sidecar.is_empty() => {
+            Ok((pcap.to_string(), sidecar.to_string()))
+        }
+        _ => anyhow::bail!("Invalid --ts-sidecar '{}'. Expected <pcap>=<sidecar>", text),
+    }
+}
+
+/// Parse a `--sample` argument, `1/<n>`, into `n`
+fn parse_sample(text: &str) -> anyhow::Result<u64> {
+    match text.split_once('/') {
+        Some(("1", n)) => match n.parse() {
+            Ok(n) if n > 0 => Ok(n),
+            _ => anyhow::bail!("Invalid --sample '{}'. N must be at least 1", text),
+        },
+        _ => anyhow::bail!("Invalid --sample '{}'. Expected 1/N, e.g. 1/10", text),
+    }
+}
+
+/// Parse a duration such as `250ns`, `10us`, `500ms`, `30s`, `5m` or `1h`. A bare number is taken as seconds
+fn parse_duration(text: &str) -> anyhow::Result<Duration> {
+    let split_at = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let (value, unit) = text.split_at(split_at);
+    let value: u64 = value
+        .parse()
+        .with_context(|| format!("Invalid duration '{}'", text))?;
+    Ok(match unit {
+        "ns" => Duration::from_nanos(value),
+        "us" => Duration::from_micros(value),
+        "ms" => Duration::from_millis(value),
+        "" | "s" => Duration::from_secs(value),
+        "m" => Duration::from_secs(value * 60),
+        "h" => Duration::from_secs(value * 60 * 60),
+        _ => anyhow::bail!(
+            "Invalid duration '{}'. Expected a unit of ns, us, ms, s, m or h",
+            text
+        ),
+    })
+}
+
+impl Args {
+    /// Resolve the effective merge configuration: the --config file (if any) with command line flags taking precedence.
+    /// `s3://` prefixes are listed with the credentials of `aws_profile`, if given.
+    fn into_merge_config(
+        self,
+        aws_profile: Option<&s3::AwsProfile>,
+    ) -> anyhow::Result<MergeConfig> {
+        let mut config = match &self.config {
+            Some(path) => MergeConfig::from_path(path)?,
+            None => MergeConfig::default(),
+        };
+        if !self.pcaps.is_empty() {
+            let format = self.input_format;
+            let orders = match self.order.len() {
+                0 => vec![InputOrder::Timestamp; self.pcaps.len()],
+                1 => vec![self.order[0]; self.pcaps.len()],
+                n if n == self.pcaps.len() => self.order,
+                n => anyhow::bail!(
+                    "--order was given {} times for {} pcaps. Give it once for every pcap, or once for all of them",
+                    n,
+                    self.pcaps.len()
+                ),
+            };
+            let mut inputs = Vec::new();
+            for (path, order) in self.pcaps.into_iter().zip(orders) {
+                let path = path.into_os_string().into_string().unwrap();
+                for path in discover_inputs(
+                    &path,
+                    self.max_depth,
+                    self.allow_empty,
+                    self.requester_pays,
+                    aws_profile,
+                )? {
+                    inputs.push(InputConfig {
+                        format,
+                        order,
+                        ..InputConfig::new(path)
+                    });
+                }
+            }
+            config.inputs = inputs;
+        }
+        for (pcap, sidecar) in self.ts_sidecar {
+            let identity = InputConfig::new(pcap.clone()).identity();
+            let mut matched = false;
+            for input in &mut config.inputs {
+                if input.identity() == identity {
+                    input.ts_sidecar = Some(sidecar.clone());
+                    matched = true;
+                }
+            }
+            if !matched {
+                anyhow::bail!(
+                    "--ts-sidecar was given for '{}', which isn't an input",
+                    pcap
+                );
+            }
+        }
+        config.output = self.output.or(config.output);
+        config.format = self.format.or(config.format);
+        config.compression = self.compress.or(config.compression);
+        config.window.start_ns = self.start_ns.or(config.window.start_ns);
+        config.window.end_ns = self.end_ns.or(config.window.end_ns);
+        if !self.allow_duplicate_inputs {
+            for duplicate in config.dedup_inputs() {
+                tracing::event!(
+                    tracing::Level::WARN,
+                    path = duplicate.path.as_str(),
+                    "Skipping duplicate input. Pass --allow-duplicate-inputs to merge it again"
+                );
+            }
+        }
+        if config.inputs.is_empty() && self.watch.is_none() && !self.allow_empty {
+            anyhow::bail!("No pcap files to merge. Pass --allow-empty to merge none");
+        }
+        Ok(config)
+    }
+}
+
+/// Bytes of decoded packets held in memory before `--spill-dir` is used
+const DEFAULT_SPILL_THRESHOLD: usize = 1024 * 1024 * 64;
+
+/// Interval between `--follow` checks of an input's size unless `--follow-interval` is given
+const DEFAULT_FOLLOW_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Interval between `--watch` scans of its directory unless `--watch-interval` is given
+const DEFAULT_WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Length of each `--rate-csv` bucket unless `--rate-bucket` is given
+const DEFAULT_RATE_BUCKET: Duration = Duration::from_secs(1);
+
+/// Number of uncompressed output bytes between `--compress-adaptive` compression level adjustments
+const ADAPTIVE_COMPRESSION_RUN_N_BYTES: usize = 1024 * 1024 * 8;
+
+/// Compressor for merged output, at either a fixed or an adaptive level
+enum OutputEncoder<W: Write> {
+    Fixed(Encoder<W>),
+    Adaptive(AdaptiveEncoder<W>),
+}
+
+impl<W: Write> OutputEncoder<W> {
+    fn new(compression: Compression, adaptive: bool, writer: W) -> std::io::Result<Self> {
+        Ok(if adaptive {
+            OutputEncoder::Adaptive(AdaptiveEncoder::new(
+                compression,
+                writer,
+                ADAPTIVE_COMPRESSION_RUN_N_BYTES,
+            )?)
         } else {
-            std::u64::MAX
+            OutputEncoder::Fixed(compression.encoder(writer)?)
+        })
+    }
+
+    fn finish(self) -> std::io::Result<W> {
+        match self {
+            OutputEncoder::Fixed(encoder) => encoder.finish(),
+            OutputEncoder::Adaptive(encoder) => encoder.finish(),
         }
     }
 }
 
-#[derive(StructOpt)]
-#[structopt(
-    version = "1.0",
-    author = "Bobby McShane <mcshane.bobby@gmail.com>",
-    about = "Merge PCAP files [s3:/]/path/to/files*.pcap[.gz|.zst] files together in time-sequence from AWS S3 or a local filesystem"
-)]
-struct Args {
-    /// pcap files to merge
-    #[structopt(required = true, min_values = 1, parse(from_os_str))]
-    pcaps: Vec<PathBuf>,
+impl<W: Write> Write for OutputEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            OutputEncoder::Fixed(encoder) => encoder.write(buf),
+            OutputEncoder::Adaptive(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            OutputEncoder::Fixed(encoder) => encoder.flush(),
+            OutputEncoder::Adaptive(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Packets of an [InputOrder::Arrival] file, polled as they are decoded
+type ArrivalStream = Pin<Box<dyn Stream<Item = (u64, Bytes)>>>;
+
+/// A file to merge, ordered by its packets' timestamps, by those of its timestamp sidecar, or by their arrival (see
+/// [InputOrder])
+enum MergeInput<T: Iterator<Item = (u64, Bytes)>, S: Iterator<Item = u64>> {
+    Timestamp(PacketStream<T>),
+    Sidecar(SidecarKeyed<T, S>),
+    Arrival(ArrivalOrdered<ArrivalStream>),
+}
+
+impl<T: Iterator<Item = (u64, Bytes)>, S: Iterator<Item = u64>> MergeableOwned
+    for MergeInput<T, S>
+{
+    type Data = (u64, Bytes);
+
+    fn pop(&mut self) -> Option<(u64, Bytes)> {
+        match self {
+            MergeInput::Timestamp(input) => input.pop(),
+            MergeInput::Sidecar(input) => input.pop(),
+            MergeInput::Arrival(input) => input.pop(),
+        }
+    }
+
+    fn peek_timestamp(&mut self) -> u64 {
+        match self {
+            MergeInput::Timestamp(input) => input.peek_timestamp(),
+            MergeInput::Sidecar(input) => input.peek_timestamp(),
+            MergeInput::Arrival(input) => input.peek_timestamp(),
+        }
+    }
+}
+
+/// The inputs whose packets are written: merged together in order, or with `--no-merge`, streamed one after another,
+/// each straight to its own output
+enum Inputs<T: Iterator<Item = (u64, Bytes)>, S: Iterator<Item = u64>> {
+    Merged(OwnedTree<MergeInput<T, S>>),
+    Streamed {
+        inputs: Vec<MergeInput<T, S>>,
+        /// index of the input being streamed, those before it having been drained or skipped
+        current: usize,
+    },
+}
+
+impl<T: Iterator<Item = (u64, Bytes)>, S: Iterator<Item = u64>> Inputs<T, S> {
+    fn input_mut(&mut self, input_index: usize) -> &mut MergeInput<T, S> {
+        match self {
+            Inputs::Merged(merger) => merger.input_mut(input_index),
+            Inputs::Streamed { inputs, .. } => &mut inputs[input_index],
+        }
+    }
+
+    /// Pick up the packets which have arrived on the input since. See [OwnedTree::refresh]
+    fn refresh(&mut self, input_index: usize) {
+        if let Inputs::Merged(merger) = self {
+            merger.refresh(input_index);
+        }
+    }
+
+    fn add_input(&mut self, input: MergeInput<T, S>) {
+        match self {
+            Inputs::Merged(merger) => {
+                merger.add_input(input);
+            }
+            Inputs::Streamed { inputs, .. } => inputs.push(input),
+        }
+    }
+
+    /// The next packet to write and the index of its input, or [None] once every input is exhausted or waiting
+    fn pop_with_index(&mut self) -> Option<(usize, (u64, Bytes))> {
+        let (inputs, current) = match self {
+            Inputs::Merged(merger) => return merger.pop_with_index(),
+            Inputs::Streamed { inputs, current } => (inputs, current),
+        };
+        while let Some(input) = inputs.get_mut(*current) {
+            if input.peek_timestamp() != u64::MAX {
+                return input.pop().map(|packet| (*current, packet));
+            }
+            if let MergeInput::Arrival(input) = input {
+                if input.is_open() {
+                    return None; // waiting for its next packet to arrive
+                }
+            }
+            *current += 1;
+        }
+        None
+    }
+
+    /// Stop streaming the input at `input_index`, moving on to the next. Merged inputs are only skipped together, by
+    /// ending the merge.
+    fn skip(&mut self, input_index: usize) {
+        if let Inputs::Streamed { current, .. } = self {
+            *current = (*current).max(input_index + 1);
+        }
+    }
+}
+
+/// Once every input is exhausted or waiting, block until one of the `arrival_inputs` has a packet ready or ends.
+/// Returns false, ending the merge, once none of them remain open.
+fn wait_for_arrival<T: Iterator<Item = (u64, Bytes)>, S: Iterator<Item = u64>>(
+    merger: &mut Inputs<T, S>,
+    arrival_inputs: &[usize],
+) -> bool {
+    let is_open = |input: &mut MergeInput<T, S>| match input {
+        MergeInput::Arrival(input) => input.is_open(),
+        MergeInput::Timestamp(_) | MergeInput::Sidecar(_) => false,
+    };
+    if !arrival_inputs
+        .iter()
+        .any(|index| is_open(merger.input_mut(*index)))
+    {
+        return false;
+    }
+    smol::block_on(futures::future::poll_fn(|cx| {
+        for index in arrival_inputs {
+            if let MergeInput::Arrival(input) = merger.input_mut(*index) {
+                if input.poll_ready(cx).is_ready() {
+                    return Poll::Ready(());
+                }
+            }
+        }
+        Poll::Pending
+    }));
+    true
+}
+
+/// Number of leading packets read from each file by `--check-order`
+const N_PACKETS_ORDER_CHECKED: usize = 2;
+
+/// Fail if the leading packets of `packets` are not in ascending time order, which the merge relies on. Returns the
+/// packets which were read for the check, to be yielded ahead of the rest of the file.
+fn check_ascending(
+    path: &str,
+    packets: &mut impl Iterator<Item = (u64, Bytes)>,
+) -> anyhow::Result<Vec<(u64, Bytes)>> {
+    let head: Vec<_> = packets.take(N_PACKETS_ORDER_CHECKED).collect();
+    for (i, pair) in head.windows(2).enumerate() {
+        if pair[1].0 < pair[0].0 {
+            anyhow::bail!(
+                "'{}' is not sorted in ascending time order: packet {} has timestamp {} ns, before packet {} at {} ns. \
+                 Pass --check-order false to merge it anyway",
+                path,
+                i + 2,
+                pair[1].0,
+                i + 1,
+                pair[0].0
+            );
+        }
+    }
+    Ok(head)
+}
+
+/// Name of the file holding `shard` of `n_shards` for `--shard-by-hash`, e.g. `shard_3_of_8.pcap.zst`
+fn shard_file_name(
+    shard: usize,
+    n_shards: usize,
+    format: pcap::OutputFormat,
+    compression: Compression,
+) -> String {
+    format!(
+        "shard_{}_of_{}.{}",
+        shard,
+        n_shards,
+        output_extension(format, compression)
+    )
+}
+
+/// Name of the `sequence_number`th file written by `--split-packets`, e.g. `part_000002.pcap.zst`
+fn split_file_name(
+    sequence_number: u64,
+    format: pcap::OutputFormat,
+    compression: Compression,
+) -> String {
+    format!(
+        "part_{:06}.{}",
+        sequence_number,
+        output_extension(format, compression)
+    )
+}
+
+/// Name of the file `--no-merge` writes the input at `path` to: that of the input, without its framing and compression
+/// extensions, e.g. `a.pcap` for `s3://bucket/a.pcap.gz` written as uncompressed pcap
+fn per_input_file_name(path: &str, format: pcap::OutputFormat, compression: Compression) -> String {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    let name = [".gz", ".zst", ".bz2", ".xz"]
+        .iter()
+        .find_map(|extension| name.strip_suffix(extension))
+        .unwrap_or(name);
+    let name = [".pcapng", ".pcap", ".raw"]
+        .iter()
+        .find_map(|extension| name.strip_suffix(extension))
+        .unwrap_or(name);
+    format!("{}.{}", name, output_extension(format, compression))
+}
+
+/// Extension of output files in `format` with `compression`, e.g. `pcap.zst`
+fn output_extension(format: pcap::OutputFormat, compression: Compression) -> String {
+    let extension = match format {
+        pcap::OutputFormat::Pcap => "pcap",
+        pcap::OutputFormat::LengthPrefixed => "bin",
+        pcap::OutputFormat::Pcapng => "pcapng",
+    };
+    let compression_extension = match compression {
+        Compression::None => "",
+        Compression::Gzip => ".gz",
+        Compression::Zstd => ".zst",
+        #[cfg(feature = "bzip2")]
+        Compression::Bzip2 => ".bz2",
+        #[cfg(feature = "xz")]
+        Compression::Xz => ".xz",
+    };
+    format!("{}{}", extension, compression_extension)
+}
+
+/// Merged output written through the configured compression
+type OutputWriter<'a> = pcap::Writer<OutputEncoder<BufWriter<Box<dyn Output + 'a>>>>;
+
+/// Finish `writer`'s compression, flush it and make its output visible at its destination
+fn commit_output(writer: OutputWriter) -> anyhow::Result<()> {
+    let sink = writer.into_inner().finish()?;
+    sink.into_inner().map_err(|e| e.into_error())?.commit()?;
+    Ok(())
+}
+
+/// Length of the records of `input` for its `--stats-socket` progress, only known for a local, uncompressed pcap
+fn records_len(input: &InputConfig) -> Option<u64> {
+    if input.format() != pcap::InputFormat::Pcap
+        || Compression::from_path(&input.path) != Compression::None
+        || input.path.starts_with("s3://")
+        || input.path.starts_with("iface:")
+    {
+        return None;
+    }
+    let metadata = std::fs::metadata(&input.path).ok()?;
+    // a fifo's length says nothing of what will be read from it
+    Some(
+        metadata
+            .len()
+            .saturating_sub(pcap::PCAP_HDR_NSEC.len() as u64),
+    )
+    .filter(|_| metadata.is_file())
+}
+
+/// What this build supports, printed by `--capabilities`
+#[derive(Debug, serde::Serialize)]
+struct Capabilities {
+    version: &'static str,
+    input_schemes: Vec<&'static str>,
+    input_formats: Vec<&'static str>,
+    input_compressions: Vec<&'static str>,
+    output_formats: Vec<&'static str>,
+    output_compressions: Vec<&'static str>,
+    features: Vec<&'static str>,
+}
+
+impl Capabilities {
+    fn of_this_build() -> Capabilities {
+        let mut input_schemes = vec!["local", "s3"];
+        if cfg!(all(feature = "live-capture", target_os = "linux")) {
+            input_schemes.push("iface");
+        }
+        let mut input_compressions = vec!["none", "gzip", "zstd"];
+        if cfg!(feature = "bzip2") {
+            input_compressions.push("bzip2");
+        }
+        if cfg!(feature = "xz") {
+            input_compressions.push("xz");
+        }
+        let features = [
+            ("jemalloc", cfg!(feature = "jemalloc")),
+            ("bzip2", cfg!(feature = "bzip2")),
+            ("xz", cfg!(feature = "xz")),
+            ("live-capture", cfg!(feature = "live-capture")),
+            ("ffi", cfg!(feature = "ffi")),
+            ("debug-tree", cfg!(feature = "debug-tree")),
+        ];
+        Capabilities {
+            version: env!("CARGO_PKG_VERSION"),
+            input_schemes,
+            input_formats: vec!["pcap", "pcapng", "raw"],
+            input_compressions,
+            output_formats: vec!["pcap", "length-prefixed", "pcapng"],
+            output_compressions: vec!["none", "gzip", "zstd"],
+            features: features
+                .iter()
+                .filter(|(_, enabled)| *enabled)
+                .map(|(feature, _)| *feature)
+                .collect(),
+        }
+    }
+}
+
+/// Print the `--time-ranges-only` report to stdout: the time range of each input, then of every input together
+fn print_time_ranges(inputs: &[InputConfig], ranges: &[Option<TimeRange>]) {
+    for (input, range) in inputs.iter().zip(ranges) {
+        match range {
+            Some(range) => println!("'{}' [{}, {}]", input.path, range.first_ns, range.last_ns),
+            None => println!("'{}' has no packets", input.path),
+        }
+    }
+    let merged = ranges.iter().flatten().fold(None, |merged, range| {
+        let merged = TimeRange::observe(merged, range.first_ns);
+        Some(TimeRange::observe(Some(merged), range.last_ns))
+    });
+    match merged {
+        Some(merged) => println!("merged [{}, {}]", merged.first_ns, merged.last_ns),
+        None => println!("merged has no packets"),
+    }
+}
+
+/// Print the `--report-overlap` summary to stderr. Time ranges cover the packets read during the merge, after offsets
+fn print_overlap_report(paths: &[String], ranges: &[Option<TimeRange>]) {
+    let overlaps = stats::adjacent_overlaps(ranges);
+    let n_files_with_packets = ranges.iter().filter(|range| range.is_some()).count();
+    eprintln!(
+        "Overlap report: {} of {} adjacent file pairs overlap in time",
+        overlaps.len(),
+        n_files_with_packets.saturating_sub(1)
+    );
+    for overlap in overlaps {
+        let (earlier, later) = (
+            ranges[overlap.earlier].unwrap(),
+            ranges[overlap.later].unwrap(),
+        );
+        eprintln!(
+            "  '{}' [{}, {}] overlaps '{}' [{}, {}] by {} ns",
+            paths[overlap.earlier],
+            earlier.first_ns,
+            earlier.last_ns,
+            paths[overlap.later],
+            later.first_ns,
+            later.last_ns,
+            overlap.overlap_ns
+        );
+    }
+}
+
+/// Print the `--check-contiguous` summary to stderr, returning how many adjacent file pairs don't abut. Time ranges are
+/// those of [print_overlap_report]
+fn print_contiguity_report(
+    paths: &[String],
+    ranges: &[Option<TimeRange>],
+    tolerance_ns: u64,
+) -> usize {
+    let discontinuities = stats::discontinuities(ranges, tolerance_ns);
+    let n_files_with_packets = ranges.iter().filter(|range| range.is_some()).count();
+    eprintln!(
+        "Contiguity report: {} of {} adjacent file pairs don't abut within {} ns",
+        discontinuities.len(),
+        n_files_with_packets.saturating_sub(1),
+        tolerance_ns
+    );
+    for adjacency in &discontinuities {
+        let (earlier, later) = (
+            ranges[adjacency.earlier].unwrap(),
+            ranges[adjacency.later].unwrap(),
+        );
+        let (kind, ns) = match adjacency.discontinuity {
+            stats::Discontinuity::Gap(ns) => ("gap", ns),
+            stats::Discontinuity::Overlap(ns) => ("overlap", ns),
+        };
+        eprintln!(
+            "  {} of {} ns between '{}' [{}, {}] and '{}' [{}, {}]",
+            kind,
+            ns,
+            paths[adjacency.earlier],
+            earlier.first_ns,
+            earlier.last_ns,
+            paths[adjacency.later],
+            later.first_ns,
+            later.last_ns
+        );
+    }
+    discontinuities.len()
+}
+
+/// Split `inputs` so that each merges the packets of a single capture interface, returned alongside the interfaces in
+/// the same order: a pcapng file describing several interfaces becomes one input per interface, unless its
+/// `interface` is given.
+fn split_interfaces(
+    inputs: Vec<InputConfig>,
+    download_config: &s3::DownloadConfig,
+) -> anyhow::Result<(Vec<InputConfig>, Vec<Interface>)> {
+    let (mut split_inputs, mut interfaces) = (Vec::new(), Vec::new());
+    for input in inputs {
+        let described = match input.path.strip_prefix("iface:") {
+            Some(name) => vec![Interface {
+                name: Some(name.to_string()),
+                ..Interface::new(pcap::pcapng::LINKTYPE_ETHERNET)
+            }],
+            None => smol::block_on(stream_merge::read_interfaces(
+                &input.path,
+                input.format(),
+                &input.download_config(download_config),
+            ))?,
+        };
+        match input.interface {
+            Some(id) => {
+                let interface = described.get(id as usize).cloned().with_context(|| {
+                    format!("'{}' doesn't describe an interface {}", input.path, id)
+                })?;
+                split_inputs.push(input);
+                interfaces.push(interface);
+            }
+            None if described.len() == 1 => {
+                split_inputs.push(input);
+                interfaces.extend(described);
+            }
+            None if input.ts_sidecar.is_some() => anyhow::bail!(
+                "'{}' describes {} interfaces, so can't be written as pcapng with a timestamp sidecar, which \
+                 stamps the packets of every interface",
+                input.path,
+                described.len()
+            ),
+            None => {
+                for (id, interface) in described.into_iter().enumerate() {
+                    split_inputs.push(InputConfig {
+                        interface: Some(id as u32),
+                        ..input.clone()
+                    });
+                    interfaces.push(interface);
+                }
+            }
+        }
+    }
+    Ok((split_inputs, interfaces))
+}
+
+/// `sink`, hashed with `algorithm` if given, printing the digest of its bytes to stderr alongside `name` once committed
+fn hashed<'a>(
+    sink: Box<dyn Output + 'a>,
+    algorithm: Option<HashAlgorithm>,
+    name: String,
+) -> Box<dyn Output + 'a> {
+    match algorithm {
+        Some(algorithm) => Box::new(HashingOutput::new(sink, algorithm, move |digest| {
+            eprintln!("{}  {}", digest, name)
+        })),
+        None => sink,
+    }
 }
 
-fn main() {
+/// Merge the single local, uncompressed pcap at `path` by copying its records verbatim, when they are already exactly
+/// as the merge would write them (see [pcap::sorted_nanosecond_records]). Returns whether it was copied; if not, the
+/// file needs merging as usual and nothing has been written.
+fn copy_sorted_file(
+    path: &str,
+    output: Option<&PathBuf>,
+    fsync: bool,
+    output_hash: Option<HashAlgorithm>,
+) -> anyhow::Result<bool> {
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(_) => return Ok(false), // merged as usual, which reports the failure
+    };
+    if file.metadata()?.len() == 0 {
+        return Ok(false); // can't be mapped, and isn't a pcap anyway
+    }
+    let map = unsafe { memmap2::Mmap::map(&file)? }; // safe so long as the input isn't modified during the merge
+    let records = match pcap::sorted_nanosecond_records(&map) {
+        Some(records) => records,
+        None => return Ok(false),
+    };
+    let stdout = std::io::stdout();
+    let sink: Box<dyn Output + '_> = match output {
+        Some(path) => Box::new(
+            AtomicFile::create(path)
+                .with_context(|| format!("Failed to create '{}'", path.display()))?
+                .fsync(fsync),
+        ),
+        None => Box::new(stdout.lock()),
+    };
+    let name = output.map_or("-".to_string(), |path| path.display().to_string());
+    let mut sink = hashed(sink, output_hash, name);
+    // the file's own header but for its snaplen, as a merge carries over its thiszone and sigfigs
+    let mut header = pcap::PCAP_HDR_NSEC.to_vec();
+    header[8..16].copy_from_slice(&map[8..16]);
+    sink.write_all(&header)?;
+    sink.write_all(records)?;
+    sink.commit()?;
+    tracing::event!(
+        tracing::Level::DEBUG,
+        path,
+        "Copied the only input's records verbatim"
+    );
+    Ok(true)
+}
+
+fn main() -> std::process::ExitCode {
+    let args = Args::from_args();
     // TODO: tracing feature gate?
+    let filter = if args.quiet {
+        tracing_subscriber::EnvFilter::new("error")
+    } else {
+        tracing_subscriber::EnvFilter::from_default_env()
+    };
     tracing_subscriber::fmt()
-        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .with_env_filter(filter)
         /* TODO: use proper tracing library output formatting for testing:
          * https://docs.rs/tracing-subscriber/0.3.1/tracing_subscriber/fmt/writer/struct.TestWriter.html */
         .with_writer(std::io::stderr)
         .init();
 
-    let packet_streams = Args::from_args()
-        .pcaps
-        .into_iter()
+    // the first SIGINT or SIGTERM stops the merge once it next merges a packet, and a second exits immediately
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let catch_signals = || -> std::io::Result<()> {
+        for &signal in &[signal_hook::consts::SIGINT, signal_hook::consts::SIGTERM] {
+            let exit_code = ExitCode::Interrupted as i32;
+            signal_hook::flag::register_conditional_shutdown(
+                signal,
+                exit_code,
+                interrupted.clone(),
+            )?;
+            signal_hook::flag::register(signal, interrupted.clone())?;
+        }
+        Ok(())
+    };
+    let merged = catch_signals()
+        .context("Failed to handle signals")
+        .and_then(|()| merge(args, &interrupted));
+    let exit_code = match merged {
+        Ok(()) => ExitCode::Success,
+        Err(error) => {
+            eprintln!("Error: {:?}", error);
+            // forward the exit status of a failed --pipe-to command, as a shell pipe with pipefail would
+            if let Some(code) = CommandFailed::of(&error).and_then(|failed| failed.status.code()) {
+                return std::process::ExitCode::from(code as u8);
+            }
+            ExitCode::of(&error)
+        }
+    };
+    std::process::ExitCode::from(exit_code as u8)
+}
+
+fn merge(args: Args, interrupted: &AtomicBool) -> anyhow::Result<()> {
+    if args.capabilities {
+        let capabilities = serde_json::to_string_pretty(&Capabilities::of_this_build())?;
+        println!("{}", capabilities);
+        return Ok(());
+    }
+    if args.decode_threads == Some(0) {
+        anyhow::bail!("--decode-threads must be at least 1");
+    }
+    if args.max_read_bps == Some(0) {
+        anyhow::bail!("--max-read-bps must be at least 1");
+    }
+    if args.max_open_files == Some(0) {
+        anyhow::bail!("--max-open-files must be at least 1");
+    }
+    if args.follow_interval == Some(Duration::ZERO) {
+        anyhow::bail!("--follow-interval must be longer than 0");
+    }
+    if args.watch_interval == Some(Duration::ZERO) {
+        anyhow::bail!("--watch-interval must be longer than 0");
+    }
+    let aws_profile = args
+        .profile
+        .as_deref()
+        .map(s3::AwsProfile::named)
+        .transpose()?;
+    let zstd_dictionary = args
+        .zstd_dict
+        .as_ref()
         .map(|path| {
-            PacketStream::new(smol::stream::block_on(
-                stream_merge::stream_and_decode_pcap_packets(
-                    path.into_os_string().into_string().unwrap(),
-                ),
-            ))
+            std::fs::read(path)
+                .with_context(|| format!("Failed to read the zstd dictionary '{}'", path.display()))
+        })
+        .transpose()?;
+    let download_config = s3::DownloadConfig {
+        memory_budget: args.memory_budget.map(MemoryBudget::new),
+        decode_pool: args.decode_threads.map(DecodePool::new),
+        rate_limiter: args.max_read_bps.map(RateLimiter::new),
+        open_file_limit: args.max_open_files.map(OpenFileLimit::new),
+        strict: args.strict,
+        fix_wraparound: args.fix_wraparound,
+        key_expr: args.key_expr,
+        packet_meta: args.packet_meta,
+        transcode: args.transcode,
+        reorder_window_ns: args.reorder_window.map(|window| window.as_nanos() as u64),
+        requester_pays: args.requester_pays,
+        aws_profile,
+        restore_days: args.restore,
+        head_first: args.head_first,
+        follow: args
+            .follow
+            .then_some(args.follow_interval.unwrap_or(DEFAULT_FOLLOW_INTERVAL)),
+        zstd_dictionary: zstd_dictionary.map(Arc::new),
+        spill: args
+            .spill_dir
+            .clone()
+            .map(|dir| Spill::new(dir, args.spill_threshold.unwrap_or(DEFAULT_SPILL_THRESHOLD))),
+        ..Default::default()
+    };
+    if args.shard_by_hash == Some(0) {
+        anyhow::bail!("--shard-by-hash must be at least 1");
+    }
+    if args.split_packets == Some(0) {
+        anyhow::bail!("--split-packets must be at least 1");
+    }
+    if args.output_dir.is_some()
+        && args.shard_by_hash.is_none()
+        && args.split_packets.is_none()
+        && !args.no_merge
+    {
+        anyhow::bail!("--output-dir requires --shard-by-hash, --split-packets or --no-merge");
+    }
+    let keyed = args.key_expr.is_some();
+    let check_order = args.check_order && !keyed;
+    let clamp = args.clamp;
+    let fail_on_backwards = args.fail_on_backwards;
+    let new_monotonic_timestamps = move || match (clamp, fail_on_backwards) {
+        (true, _) => Some(MonotonicTimestamps::new(OnBackwards::Clamp)),
+        (false, true) => Some(MonotonicTimestamps::new(OnBackwards::Error)),
+        (false, false) => None,
+    };
+    let compress_adaptive = args.compress_adaptive;
+    let report_overlap = args.report_overlap;
+    let time_ranges_only = args.time_ranges_only;
+    let check_contiguous = args
+        .check_contiguous
+        .map(|tolerance| tolerance.as_nanos() as u64);
+    let require_contiguous = args.require_contiguous;
+    // both reports cover the time ranges of the inputs' packets, observed as they're merged
+    let observe_time_ranges = report_overlap || check_contiguous.is_some();
+    let idle_warn = args.idle_warn;
+    let deadline = args.deadline.map(Deadline::new);
+    #[cfg(unix)]
+    let (progress, _stats_socket) = match &args.stats_socket {
+        Some(path) => {
+            let progress = Arc::new(stats::Progress::default());
+            let socket = stats::StatsSocket::bind(path, progress.clone())
+                .with_context(|| format!("Failed to listen on '{}'", path.display()))?;
+            (Some(progress), Some(socket))
+        }
+        None => (None, None),
+    };
+    #[cfg(not(unix))]
+    let progress: Option<Arc<stats::Progress>> = None;
+    let fsync = args.fsync;
+    let output_hash = args.output_hash;
+    let rate_bucket_ns = args.rate_bucket.unwrap_or(DEFAULT_RATE_BUCKET).as_nanos() as u64;
+    if rate_bucket_ns == 0 {
+        anyhow::bail!("--rate-bucket must be at least 1ns");
+    }
+    let mut rate_series = match &args.rate_csv {
+        Some(path) => {
+            let file = AtomicFile::create(path)
+                .with_context(|| format!("Failed to create '{}'", path.display()))?
+                .fsync(fsync);
+            Some(RateSeries::new(BufWriter::new(file), rate_bucket_ns)?)
+        }
+        None => None,
+    };
+    let require_precision = args.require_precision;
+    let output_offset = args.output_offset;
+    let rebase_to_zero = args.rebase_to_zero;
+    let max_gap_ns = args.max_gap.map(|max_gap| max_gap.as_nanos() as u64);
+    if max_gap_ns == Some(0) {
+        anyhow::bail!("--max-gap must be longer than 0");
+    }
+    let sampler = args
+        .sample
+        .map(|n| pcap::Sampler::new(n, args.sample_by.unwrap_or(pcap::SampleMode::Every)));
+    let mut coalescer = match args.coalesce {
+        Some(window) if window.as_nanos() == 0 => anyhow::bail!("--coalesce must be at least 1ns"),
+        Some(window) => Some(pcap::Coalescer::new(window.as_nanos() as u64)),
+        None => None,
+    };
+    let truncate_snaplen = args.truncate_snaplen;
+    if truncate_snaplen == Some(0) {
+        anyhow::bail!("--truncate-snaplen must be at least 1");
+    }
+    let tag_source = args.tag_source;
+    let packet_meta = args.packet_meta;
+    let preflight = args.preflight.then_some(args.preflight_magic);
+    let (thiszone, sigfigs) = (args.thiszone, args.sigfigs);
+    let pipe_to = args.pipe_to.clone();
+    let shards = args.shard_by_hash.zip(args.output_dir.clone());
+    let split = args.split_packets.zip(args.output_dir.clone());
+    let per_input = args.output_dir.clone().filter(|_| args.no_merge);
+    let mut watch = args
+        .watch
+        .as_deref()
+        .map(|pattern| {
+            WatchedDirectory::new(
+                pattern,
+                args.watch_interval.unwrap_or(DEFAULT_WATCH_INTERVAL),
+            )
         })
+        .transpose()?;
+    let watch_idle = args.watch_idle;
+    let mut config = args.into_merge_config(download_config.aws_profile.as_ref())?;
+    if let Some(watch) = &mut watch {
+        for path in watch.scan()? {
+            if !config.inputs.iter().any(|input| input.path == path) {
+                config.inputs.push(InputConfig::new(path));
+            }
+        }
+        tracing::event!(
+            tracing::Level::INFO,
+            n_inputs = config.inputs.len(),
+            "Watching for new files"
+        );
+    }
+    // rescanned on a thread of its own from here on, the merge only taking the files it finds
+    let mut watch = watch.map(WatchedDirectory::watch);
+    if pipe_to.is_some() && config.output.is_some() {
+        anyhow::bail!("--pipe-to can't be combined with the config's output file");
+    }
+    if (shards.is_some() || split.is_some() || per_input.is_some()) && config.output.is_some() {
+        anyhow::bail!(
+            "--shard-by-hash, --split-packets and --no-merge write to --output-dir, so can't be combined with the \
+             config's output file"
+        );
+    }
+    let format = config.format.unwrap_or(pcap::OutputFormat::Pcap);
+    if tag_source && format != pcap::OutputFormat::LengthPrefixed {
+        anyhow::bail!(
+            "--tag-source requires --format length-prefixed, the only format with a frame to tag"
+        );
+    }
+    if packet_meta && format != pcap::OutputFormat::LengthPrefixed {
+        anyhow::bail!(
+            "--packet-meta requires --format length-prefixed, the only format with a frame to carry it"
+        );
+    }
+    if let Some(input) = config
+        .inputs
+        .iter()
+        .find(|input| input.max_n_buffered == Some(0))
+    {
+        anyhow::bail!("max_n_buffered of '{}' must be at least 1", input.path);
+    }
+    if keyed {
+        for input in &config.inputs {
+            if input.format() != pcap::InputFormat::Pcap
+                || input.order != InputOrder::Timestamp
+                || input.offset_ns != 0
+                || input.ts_sidecar.is_some()
+            {
+                anyhow::bail!(
+                    "--key-expr only applies to pcap inputs merged in timestamp order without an offset or timestamp \
+                     sidecar, unlike '{}'",
+                    input.path
+                );
+            }
+        }
+    }
+    // a sidecar stamps every packet of its file in turn, so none may be skipped or restamped by arrival
+    if let Some(input) = config.inputs.iter().find(|input| {
+        input.ts_sidecar.is_some()
+            && (input.order != InputOrder::Timestamp || input.interface.is_some())
+    }) {
+        anyhow::bail!(
+            "The timestamp sidecar of '{}' only applies to an input merged in timestamp order, with every interface",
+            input.path
+        );
+    }
+    if let Some(check_magic) = preflight {
+        let uris: Vec<String> = config
+            .inputs
+            .iter()
+            .filter(|input| input.path.starts_with("s3://"))
+            .map(|input| input.path.clone())
+            .collect();
+        let problems = s3::preflight(&uris, &download_config, check_magic);
+        for problem in &problems {
+            tracing::event!(tracing::Level::ERROR, %problem, "Failed --preflight");
+        }
+        if !problems.is_empty() {
+            return Err(PreflightFailed {
+                n_failed: problems.len(),
+                n_checked: uris.len(),
+            }
+            .into());
+        }
+    }
+    if let Some(required) = require_precision {
+        for input in &config.inputs {
+            // raw and pcapng timestamps are always read at nanosecond precision
+            let precision =
+                if input.format() != pcap::InputFormat::Pcap || input.path.starts_with("iface:") {
+                    pcap::Precision::Nanosecond
+                } else {
+                    smol::block_on(stream_merge::read_precision(
+                        &input.path,
+                        &input.download_config(&download_config),
+                    ))?
+                };
+            if precision != required {
+                anyhow::bail!(
+                    "'{}' has {}-precision timestamps, but --require-precision {} was given",
+                    input.path,
+                    precision,
+                    required
+                );
+            }
+        }
+    }
+    if time_ranges_only {
+        let ranges = smol::block_on(futures::future::try_join_all(config.inputs.iter().map(
+            |input| {
+                let download_config = input.download_config(&download_config);
+                async move {
+                    let range = stream_merge::read_time_range(
+                        &input.path,
+                        input.format(),
+                        &download_config,
+                    )
+                    .await?;
+                    Ok::<_, anyhow::Error>(range)
+                }
+            },
+        )))?;
+        print_time_ranges(&config.inputs, &ranges);
+        return Ok(());
+    }
+    // pcapng output describes the interface each packet was captured on, which is that of the input it was merged from
+    let interfaces = if format == pcap::OutputFormat::Pcapng {
+        if watch.is_some() {
+            anyhow::bail!(
+                "--watch can't be written as pcapng, whose interfaces are all described ahead of the first packet"
+            );
+        }
+        if coalescer.is_some() {
+            anyhow::bail!(
+                "--coalesce can't be written as pcapng, whose packets each belong to one interface"
+            );
+        }
+        if per_input.is_some() {
+            anyhow::bail!(
+                "--no-merge can't be written as pcapng, whose interfaces are described for every input together"
+            );
+        }
+        let (inputs, mut interfaces) = split_interfaces(config.inputs, &download_config)?;
+        config.inputs = inputs;
+        if let Some(snaplen) = truncate_snaplen {
+            for interface in &mut interfaces {
+                if interface.snaplen == 0 || interface.snaplen > snaplen {
+                    interface.snaplen = snaplen;
+                }
+            }
+        }
+        Some(interfaces)
+    } else {
+        None
+    };
+    // a lone input may already be exactly what merging it would write, so long as nothing transforms its packets
+    let is_verbatim = |input: &InputConfig| {
+        !input.path.starts_with("s3://")
+            && !input.path.starts_with("iface:")
+            && Compression::from_path(&input.path) == Compression::None
+            && input.format() == pcap::InputFormat::Pcap
+            && input.order == InputOrder::Timestamp
+            && input.offset_ns == 0
+            && input.ts_sidecar.is_none()
+            && input.transcode.is_none()
+    };
+    if config.inputs.len() == 1
+        && is_verbatim(&config.inputs[0])
+        && format == pcap::OutputFormat::Pcap
+        && config.compression.unwrap_or(Compression::None) == Compression::None
+        && config.window == TimeWindow::default()
+        && shards.is_none()
+        && split.is_none()
+        && per_input.is_none()
+        && output_offset.is_none()
+        && !rebase_to_zero
+        && max_gap_ns.is_none()
+        && coalescer.is_none()
+        && sampler.is_none()
+        && thiszone.is_none()
+        && sigfigs.is_none()
+        && truncate_snaplen.is_none()
+        && !keyed
+        && !download_config.fix_wraparound
+        && download_config.transcode.is_none()
+        && download_config.reorder_window_ns.is_none()
+        && !observe_time_ranges
+        && idle_warn.is_none()
+        && rate_series.is_none()
+        && pipe_to.is_none()
+        && watch.is_none()
+        && deadline.is_none()
+        && progress.is_none()
+        && copy_sorted_file(
+            &config.inputs[0].path,
+            config.output.as_ref(),
+            fsync,
+            output_hash,
+        )?
+    {
+        return Ok(());
+    }
+    let mut input_paths: Vec<String> = config
+        .inputs
+        .iter()
+        .map(|input| input.path.clone())
+        .collect();
+    let mut time_ranges: Vec<Rc<Cell<Option<TimeRange>>>> = input_paths
+        .iter()
+        .map(|_| Rc::new(Cell::new(None)))
+        .collect();
+    let merge_clock = MergeClock::default();
+    let arrival_inputs: Vec<usize> = config
+        .inputs
+        .iter()
+        .enumerate()
+        .filter(|(_index, input)| input.order == InputOrder::Arrival)
+        .map(|(index, _input)| index)
         .collect();
+    let sidecar_inputs: Vec<usize> = config
+        .inputs
+        .iter()
+        .enumerate()
+        .filter(|(_index, input)| input.ts_sidecar.is_some())
+        .map(|(index, _input)| index)
+        .collect();
+    // an input which fails part way through is merged up to the failure, its decode task reporting it
+    let open_merge_input = |(input, time_range): (InputConfig, Rc<Cell<Option<TimeRange>>>)| {
+        let (packets, decode_task) = stream_merge::stream_and_decode_packets_as(
+            input.path.clone(),
+            input.format(),
+            input.download_config(&download_config),
+        );
+        // once the deadline passes, an input still being waited on ends there
+        let packets = match &deadline {
+            Some(deadline) => deadline.bound(packets).left_stream(),
+            None => packets.right_stream(),
+        };
+        if input.order == InputOrder::Arrival {
+            // packets are restamped as they are merged, so neither their order nor offset apply
+            let input = MergeInput::Arrival(ArrivalOrdered::new(
+                Box::pin(packets) as ArrivalStream,
+                merge_clock.clone(),
+            ));
+            return Ok((input, decode_task));
+        }
+        let sidecar = match &input.ts_sidecar {
+            Some(path) => Some(pcap::SidecarTimestamps::open(path)?),
+            None => None,
+        };
+        let mut packets = smol::stream::block_on(packets);
+        // a live capture's timestamps never decrease, and reading ahead would wait on the interface. Nor do the
+        // timestamps of a file merged by its sidecar's matter
+        let head = if check_order && !input.path.starts_with("iface:") && sidecar.is_none() {
+            check_ascending(&input.path, &mut packets)?
+        } else {
+            Vec::new()
+        };
+        let offset_ns = input.offset_ns;
+        let observe_packets = observe_time_ranges && sidecar.is_none();
+        let sidecar_time_range = time_range.clone();
+        // each packet's timestamp has been offset as it was decoded
+        let packets = head
+            .into_iter()
+            .chain(packets)
+            .inspect(move |(ts, packet)| {
+                if observe_packets {
+                    let ts = if keyed {
+                        pcap::record_timestamp(packet)
+                    } else {
+                        *ts
+                    };
+                    time_range.set(Some(TimeRange::observe(time_range.get(), ts)));
+                }
+            });
+        let input = match sidecar {
+            Some(sidecar) => MergeInput::Sidecar(SidecarKeyed::new(
+                packets,
+                sidecar.map(move |ts| {
+                    let ts = offset_timestamp(ts, offset_ns);
+                    if observe_time_ranges {
+                        sidecar_time_range
+                            .set(Some(TimeRange::observe(sidecar_time_range.get(), ts)));
+                    }
+                    ts
+                }),
+            )),
+            None => MergeInput::Timestamp(PacketStream::new(packets)),
+        };
+        Ok((input, decode_task))
+    };
+    let mut input_progress: Vec<_> = match &progress {
+        Some(progress) => config
+            .inputs
+            .iter()
+            .map(|input| progress.add_input(input.path.clone(), records_len(input)))
+            .collect(),
+        None => Vec::new(),
+    };
+    let (packet_streams, mut decode_tasks): (Vec<_>, Vec<_>) = config
+        .inputs
+        .into_iter()
+        .zip(time_ranges.iter().cloned())
+        .map(&open_merge_input)
+        .collect::<anyhow::Result<Vec<_>>>()?
+        .into_iter()
+        .unzip();
 
     {
         // TODO: pull the tournament tree module into the stream-merge crate directly
-        let mut merger = tournament_tree::Tree::new(packet_streams);
+        let mut merger = match per_input {
+            Some(_) => Inputs::Streamed {
+                inputs: packet_streams,
+                current: 0,
+            },
+            None => Inputs::Merged(OwnedTree::new(packet_streams)),
+        };
+        // pcap output carries over the legacy header fields of the first input, unless they're given. Its header has
+        // been read by now, as its first packet has been, so long as it's merged in timestamp order
+        if let Inputs::Streamed { inputs, .. } = &mut merger {
+            // unmerged inputs are only read once streamed, so the first is peeked at for its header
+            if let Some(first) = inputs.first_mut() {
+                first.peek_timestamp();
+            }
+        }
+        let header_fields = decode_tasks
+            .first()
+            .and_then(stream_merge::DecodeTask::header_fields)
+            .unwrap_or_default();
+        let header_fields = pcap::HeaderFields {
+            thiszone: thiszone.unwrap_or(header_fields.thiszone),
+            sigfigs: sigfigs.unwrap_or(header_fields.sigfigs),
+        };
         let stdout = std::io::stdout();
+        let compression = config.compression.unwrap_or(Compression::None);
+        // files only appear at their destination once the whole merge has been written to them
+        let create_file = |path: &std::path::Path| -> anyhow::Result<Box<dyn Output>> {
+            let file = AtomicFile::create(path)
+                .with_context(|| format!("Failed to create '{}'", path.display()))?
+                .fsync(fsync);
+            Ok(hashed(
+                Box::new(file),
+                output_hash,
+                path.display().to_string(),
+            ))
+        };
+        let sinks: Vec<Box<dyn Output + '_>> = match (&shards, &split, &per_input, &config.output) {
+            (Some((n_shards, dir)), _, _, _) => (0..*n_shards)
+                .map(|shard| {
+                    create_file(&dir.join(shard_file_name(shard, *n_shards, format, compression)))
+                })
+                .collect::<anyhow::Result<_>>()?,
+            (None, Some((_, dir)), _, _) => {
+                vec![create_file(&dir.join(split_file_name(
+                    0,
+                    format,
+                    compression,
+                )))?]
+            }
+            (None, None, Some(dir), _) => {
+                let mut names = std::collections::HashSet::new();
+                input_paths
+                    .iter()
+                    .map(|path| {
+                        let name = per_input_file_name(path, format, compression);
+                        if !names.insert(name.clone()) {
+                            anyhow::bail!(
+                                "--no-merge would write more than one input to '{}', such as '{}'",
+                                name,
+                                path
+                            );
+                        }
+                        create_file(&dir.join(name))
+                    })
+                    .collect::<anyhow::Result<_>>()?
+            }
+            (None, None, None, Some(path)) => vec![create_file(path)?],
+            (None, None, None, None) => match &pipe_to {
+                Some(command) => {
+                    let piped = PipedCommand::spawn(command)
+                        .with_context(|| format!("Failed to run '{}'", command))?;
+                    vec![hashed(Box::new(piped), output_hash, command.clone())]
+                }
+                None => vec![hashed(
+                    Box::new(stdout.lock()),
+                    output_hash,
+                    "-".to_string(),
+                )],
+            },
+        };
         // TODO: consider changing the stdout PIPE SIZE to be the max configured for the system
         // then configuring the buffer accordingly
-        let mut writer = BufWriter::with_capacity(1024 * 1024 * 2, stdout.lock());
-        // pcap header with nanosecond-precision timestamping
-        const PCAP_HDR_NSEC: &[u8] = &hex!(
-            "4D 3C B2 A1 02 00 04 00 00 00 00 00 00 00 00 00
-    00 00 04 00 01 00 00 00"
-        );
-        writer.write_all(PCAP_HDR_NSEC).unwrap();
+        let buffer_capacity = (1024 * 1024 * 2 / sinks.len()).max(1024 * 64);
+        let open_writer = |sink| -> std::io::Result<OutputWriter> {
+            let writer = pcap::Writer::with_header(
+                OutputEncoder::new(
+                    compression,
+                    compress_adaptive,
+                    BufWriter::with_capacity(buffer_capacity, sink),
+                )?,
+                format,
+                truncate_snaplen,
+                header_fields,
+            )?;
+            let writer = match max_gap_ns {
+                Some(max_gap_ns) => writer.max_gap(max_gap_ns),
+                None => writer,
+            };
+            let writer = if tag_source {
+                writer.tag_sources()
+            } else {
+                writer
+            };
+            let writer = if packet_meta {
+                writer.packet_meta()
+            } else {
+                writer
+            };
+            Ok(match &interfaces {
+                Some(interfaces) => writer.interfaces(interfaces.clone()),
+                None => writer,
+            })
+        };
+        let mut writers = sinks
+            .into_iter()
+            .map(open_writer)
+            .collect::<std::io::Result<Vec<_>>>()?;
+        let (mut n_split_files, mut n_packets_in_split_file) = (1, 0);
         // TODO: should some of these be spans?
-        tracing::event!(tracing::Level::TRACE, "Wrote PCAP header");
-        while let Some((ts, packet)) = merger.pop() {
-            writer.write_all(&packet).unwrap();
-            tracing::event!(tracing::Level::TRACE, text = "Wrote packet", ts);
-            //coz::progress!("wrote packet");
+        tracing::event!(tracing::Level::TRACE, %format, n_outputs = writers.len(), "Wrote output header");
+        let mut write =
+            |ts: u64, packet: Bytes, input_index: usize, meta: PacketMeta| -> anyhow::Result<()> {
+                let output = match &shards {
+                    Some((n_shards, _)) => {
+                        (pcap::flow::flow_hash(&packet[pcap::RECORD_HEADER_LEN..])
+                            % *n_shards as u64) as usize
+                    }
+                    None if per_input.is_some() => input_index,
+                    None => 0,
+                };
+                // each input is a single interface of pcapng output, described in the same order, or a tagged source
+                let interface = if interfaces.is_some() || tag_source {
+                    input_index as u32
+                } else {
+                    0
+                };
+                if let Some((n_packets_per_file, dir)) = &split {
+                    if n_packets_in_split_file == *n_packets_per_file {
+                        // roll over to the next file only once it has a packet, so that none are left empty
+                        let path = dir.join(split_file_name(n_split_files, format, compression));
+                        let full =
+                            std::mem::replace(&mut writers[0], open_writer(create_file(&path)?)?);
+                        commit_output(full)?;
+                        n_split_files += 1;
+                        n_packets_in_split_file = 0;
+                    }
+                    n_packets_in_split_file += 1;
+                }
+                if let Some(rate_series) = &mut rate_series {
+                    rate_series.observe(ts, packet.len() - pcap::RECORD_HEADER_LEN)?;
+                }
+                writers[output].write_packet_with_meta(ts, &packet, interface, &meta)?;
+                tracing::event!(tracing::Level::TRACE, text = "Wrote packet", ts, output);
+                //coz::progress!("wrote packet");
+                Ok(())
+            };
+        // each merged packet in the window is sampled, then offset and truncated, before it's written
+        let new_output_processors = || {
+            let mut output_processors = pcap::Processors::default();
+            if let Some(mut sampler) = sampler.clone() {
+                output_processors.push(move |ts, record| {
+                    if sampler.keep(ts, &record) {
+                        pcap::PacketAction::Keep
+                    } else {
+                        pcap::PacketAction::Drop
+                    }
+                });
+            }
+            if output_offset.is_some() || rebase_to_zero {
+                // when rebasing to zero, the offset is only known once the first packet is written
+                let mut output_offset = output_offset;
+                output_processors.push(move |ts, record| {
+                    let offset_ns =
+                        *output_offset.get_or_insert_with(|| -(ts.min(i64::MAX as u64) as i64));
+                    pcap::PacketAction::Replace(offset_timestamp(ts, offset_ns), record)
+                });
+            }
+            if let Some(snaplen) = truncate_snaplen {
+                output_processors.push(move |ts, record| {
+                    pcap::PacketAction::Replace(ts, pcap::truncate_record(record, snaplen as usize))
+                });
+            }
+            output_processors
+        };
+        // with --no-merge, the packets of each input are checked and processed as if it were merged alone
+        let n_processed_alone = match per_input {
+            Some(_) => input_paths.len(),
+            None => 1,
+        };
+        let mut output_processors: Vec<_> = (0..n_processed_alone)
+            .map(|_| new_output_processors())
+            .collect();
+        let mut monotonic_timestamps: Vec<_> = (0..n_processed_alone)
+            .map(|_| new_monotonic_timestamps())
+            .collect();
+        let idle_watchdog = idle_warn.map(IdleWatchdog::start);
+        let mut timed_out = false;
+        loop {
+            if interrupted.load(Ordering::Relaxed) {
+                return Err(Interrupted.into()); // files are only written once committed, so none are
+            }
+            if deadline.as_ref().is_some_and(Deadline::expired) {
+                timed_out = true;
+                decode_tasks
+                    .iter()
+                    .for_each(stream_merge::DecodeTask::expect_early_stop);
+                break;
+            }
+            for input in &arrival_inputs {
+                merger.refresh(*input); // pick up newly arrived packets
+            }
+            if let Some(watch) = &mut watch {
+                for path in watch.found()? {
+                    tracing::event!(
+                        tracing::Level::INFO,
+                        path = path.as_str(),
+                        "Merging newly found file"
+                    );
+                    let time_range = Rc::new(Cell::new(None));
+                    let input = InputConfig::new(path.clone());
+                    if let Some(progress) = &progress {
+                        input_progress.push(progress.add_input(path.clone(), records_len(&input)));
+                    }
+                    let (input, decode_task) = open_merge_input((input, time_range.clone()))?;
+                    merger.add_input(input);
+                    decode_tasks.push(decode_task);
+                    input_paths.push(path);
+                    time_ranges.push(time_range);
+                }
+            }
+            let (input_index, (ts, packet)) = match merger.pop_with_index() {
+                Some(packet) => packet,
+                None => {
+                    if wait_for_arrival(&mut merger, &arrival_inputs) {
+                        continue;
+                    }
+                    match &mut watch {
+                        Some(watch) if watch_idle.is_none_or(|idle| watch.idle_for() < idle) => {
+                            watch.wait()?;
+                            continue;
+                        }
+                        _ => break,
+                    }
+                }
+            };
+            // carried through the merge after the record, and split off before anything reads it
+            let (packet, meta) = match packet_meta {
+                true => PacketMeta::split_from(packet)?,
+                false => (packet, PacketMeta::default()),
+            };
+            let processed_alone = match per_input {
+                Some(_) => input_index,
+                None => 0,
+            };
+            let ts = match &mut monotonic_timestamps[processed_alone] {
+                Some(monotonic_timestamps) => monotonic_timestamps.check(ts)?,
+                None if keyed => pcap::record_timestamp(&packet), // merged by key, so the timestamp is in the header
+                None => ts,
+            };
+            merge_clock.set(ts);
+            if let Some(progress) = &progress {
+                progress.observe(&input_progress[input_index], ts, packet.len());
+            }
+            if let Some(idle_watchdog) = &idle_watchdog {
+                idle_watchdog.observe(ts);
+            }
+            if config.window.is_before(ts) || (keyed && config.window.is_after(ts)) {
+                continue; // packets merged by key aren't time-ordered, so a later one may still fall in the window
+            }
+            if config.window.is_after(ts) {
+                if per_input.is_some() {
+                    // only this input's later packets are past the window's end
+                    decode_tasks[input_index].expect_early_stop();
+                    merger.skip(input_index);
+                    continue;
+                }
+                decode_tasks
+                    .iter()
+                    .for_each(stream_merge::DecodeTask::expect_early_stop);
+                break;
+            }
+            let (ts, packet) = match output_processors[processed_alone].process(ts, packet) {
+                Some(packet) => packet,
+                None => continue,
+            };
+            match &mut coalescer {
+                Some(coalescer) => {
+                    if let Some((ts, unit)) = coalescer.push(ts, &packet) {
+                        write(ts, unit, 0, PacketMeta::default())?;
+                    }
+                }
+                None => write(ts, packet, input_index, meta)?,
+            }
+        }
+        if let Some((ts, unit)) = coalescer.as_mut().and_then(|coalescer| coalescer.finish()) {
+            write(ts, unit, 0, PacketMeta::default())?;
+        }
+        for index in &sidecar_inputs {
+            if let MergeInput::Sidecar(input) = merger.input_mut(*index) {
+                let unmatched = match input.mismatch() {
+                    Some(SidecarMismatch::MorePackets) => "packets (which were not merged)",
+                    Some(SidecarMismatch::MoreTimestamps) => "timestamps",
+                    None => continue,
+                };
+                tracing::event!(
+                    tracing::Level::WARN,
+                    path = input_paths[*index].as_str(),
+                    "The file's packets and its sidecar's timestamps differ in number, leaving {} unmatched",
+                    unmatched
+                );
+            }
+        }
+        for writer in writers {
+            commit_output(writer)?;
+        }
+        if let Some(rate_series) = rate_series {
+            let file = rate_series.finish()?;
+            Box::new(file.into_inner().map_err(|e| e.into_error())?).commit()?;
+        }
+        if timed_out || deadline.as_ref().is_some_and(Deadline::cut_short) {
+            // the packets merged so far have been written, and the decode tasks are cancelled as they're dropped
+            return Err(DeadlineExceeded.into());
         }
-        writer.flush().unwrap();
         tracing::event!(tracing::Level::TRACE, "Merge complete. No more packets.");
     }
+    let ranges: Vec<_> = time_ranges.iter().map(|range| range.get()).collect();
+    if report_overlap {
+        print_overlap_report(&input_paths, &ranges);
+    }
+    let n_discontinuities = check_contiguous
+        .map(|tolerance_ns| print_contiguity_report(&input_paths, &ranges, tolerance_ns))
+        .unwrap_or(0);
+    // the merge has dropped every input's stream, so each decode task has finished or soon will
+    let mut failures = smol::block_on(futures::future::join_all(decode_tasks))
+        .into_iter()
+        .filter_map(Result::err);
+    match failures.next() {
+        Some(failure) => {
+            let n_skipped = 1 + failures.count();
+            Err(failure.context(SkippedInputs { n_skipped }))
+        }
+        None if require_contiguous && n_discontinuities > 0 => Err(anyhow::anyhow!(
+            "{} adjacent file pairs don't abut within --check-contiguous, but --require-contiguous was given",
+            n_discontinuities
+        )),
+        None => Ok(()),
+    }
 }
@@ -0,0 +1,240 @@
+use super::{Compression, Encoder};
+use crate::{Clock, SystemClock};
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Fraction of a run's wall time spent writing to the sink above which the sink is considered the bottleneck, so
+/// spare CPU can be spent on a higher compression level
+const SINK_BOUND_FRACTION: f64 = 0.5;
+/// Fraction of a run's wall time spent writing to the sink below which compression is considered the bottleneck, so
+/// the compression level is lowered to keep up
+const CPU_BOUND_FRACTION: f64 = 0.2;
+
+/// [Write] adapter which compresses with `compression`, starting at its fastest level and re-evaluating the level after
+/// every run of `run_n_bytes` uncompressed bytes. When most of a run was spent waiting on the sink, the level is
+/// raised (output gets smaller at no cost to throughput); when little was, the level is lowered again.
+///
+/// Changing level ends the current gzip member or zstd frame and starts a new one, so the output is a concatenation of
+/// members/frames, which standard tools and [Compression::decoder] decode as a single stream.
+pub struct AdaptiveEncoder<W: Write> {
+    compression: Compression,
+    level: i32,
+    encoder: Option<Encoder<TimedWriter<W>>>,
+    run_n_bytes: usize,
+    run_n_bytes_written: usize,
+    run_start: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+impl<W: Write> AdaptiveEncoder<W> {
+    pub fn new(compression: Compression, writer: W, run_n_bytes: usize) -> std::io::Result<Self> {
+        AdaptiveEncoder::with_clock(
+            compression,
+            writer,
+            run_n_bytes,
+            Arc::new(SystemClock::default()),
+        )
+    }
+
+    /// Like [AdaptiveEncoder::new], timing the sink and each run by `clock` rather than the wall clock
+    pub fn with_clock(
+        compression: Compression,
+        writer: W,
+        run_n_bytes: usize,
+        clock: Arc<dyn Clock>,
+    ) -> std::io::Result<Self> {
+        let level = compression.levels().0;
+        let encoder =
+            compression.encoder_with_level(TimedWriter::new(writer, clock.clone()), level)?;
+        Ok(AdaptiveEncoder {
+            compression,
+            level,
+            encoder: Some(encoder),
+            run_n_bytes,
+            run_n_bytes_written: 0,
+            run_start: clock.now(),
+            clock,
+        })
+    }
+
+    /// The compression level currently in use
+    pub fn level(&self) -> i32 {
+        self.level
+    }
+
+    /// Complete the compressed stream and return the wrapped writer
+    pub fn finish(mut self) -> std::io::Result<W> {
+        Ok(self.encoder.take().unwrap().finish()?.writer)
+    }
+
+    fn encoder(&mut self) -> &mut Encoder<TimedWriter<W>> {
+        self.encoder.as_mut().unwrap() // only None while switching levels or once finished
+    }
+
+    /// Choose the level for the next run from the share of the last run's wall time spent in the sink
+    fn end_run(&mut self) -> std::io::Result<()> {
+        let elapsed = (self.clock.now() - self.run_start).as_secs_f64();
+        let sink_fraction = if elapsed > 0.0 {
+            self.encoder().get_mut().take_time_in_writer().as_secs_f64() / elapsed
+        } else {
+            0.0
+        };
+        let (fastest, smallest) = self.compression.levels();
+        let level = if sink_fraction > SINK_BOUND_FRACTION {
+            (self.level + 1).min(smallest)
+        } else if sink_fraction < CPU_BOUND_FRACTION {
+            (self.level - 1).max(fastest)
+        } else {
+            self.level
+        };
+        if level != self.level {
+            tracing::event!(
+                tracing::Level::DEBUG,
+                from = self.level,
+                to = level,
+                sink_fraction,
+                "Changing compression level"
+            );
+            let writer = self.encoder.take().unwrap().finish()?;
+            self.encoder = Some(self.compression.encoder_with_level(writer, level)?);
+            self.level = level;
+        }
+        self.run_n_bytes_written = 0;
+        self.run_start = self.clock.now();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for AdaptiveEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n_bytes = self.encoder().write(buf)?;
+        self.run_n_bytes_written += n_bytes;
+        if self.run_n_bytes_written >= self.run_n_bytes {
+            self.end_run()?;
+        }
+        Ok(n_bytes)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.encoder().flush()
+    }
+}
+
+/// Tracks the time spent inside the wrapped [Write]r's calls
+struct TimedWriter<W: Write> {
+    writer: W,
+    time_in_writer: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+impl<W: Write> TimedWriter<W> {
+    fn new(writer: W, clock: Arc<dyn Clock>) -> Self {
+        TimedWriter {
+            writer,
+            time_in_writer: Duration::default(),
+            clock,
+        }
+    }
+
+    fn take_time_in_writer(&mut self) -> Duration {
+        std::mem::take(&mut self.time_in_writer)
+    }
+}
+
+impl<W: Write> Write for TimedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let start = self.clock.now();
+        let result = self.writer.write(buf);
+        self.time_in_writer += self.clock.now() - start;
+        result
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let start = self.clock.now();
+        let result = self.writer.flush();
+        self.time_in_writer += self.clock.now() - start;
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::SimulatedClock;
+    use std::io::Read;
+
+    /// A sink which takes `write_time` of `clock`'s time to write each buffer, as if writing to a slow network or disk
+    struct SlowSink {
+        bytes: Vec<u8>,
+        clock: Arc<SimulatedClock>,
+        write_time: Duration,
+    }
+
+    impl Write for SlowSink {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            drop(self.clock.sleep(self.write_time)); // a simulated sleep passes as soon as it's made
+            self.bytes.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn input() -> Vec<u8> {
+        (0..4 * 1024 * 1024u32)
+            .map(|i| (i % 251) as u8 ^ (i / 4096) as u8)
+            .collect()
+    }
+
+    /// Compress `input` with `compression` to a [SlowSink], passing `compress_time` for each chunk compressed
+    fn encoder_after_input(
+        compression: Compression,
+        compress_time: Duration,
+        write_time: Duration,
+    ) -> AdaptiveEncoder<SlowSink> {
+        let clock = Arc::new(SimulatedClock::default());
+        let sink = SlowSink {
+            bytes: Vec::new(),
+            clock: clock.clone(),
+            write_time,
+        };
+        let mut encoder =
+            AdaptiveEncoder::with_clock(compression, sink, 64 * 1024, clock.clone()).unwrap();
+        assert_eq!(encoder.level(), 1);
+        for chunk in input().chunks(4096) {
+            drop(clock.sleep(compress_time));
+            encoder.write_all(chunk).unwrap();
+        }
+        encoder
+    }
+
+    #[test]
+    fn level_rises_when_the_sink_is_the_bottleneck() {
+        let encoder = encoder_after_input(
+            Compression::Gzip,
+            Duration::from_micros(10),
+            Duration::from_millis(2),
+        );
+        assert!(encoder.level() > 1, "level stayed at {}", encoder.level());
+
+        // the output, a concatenation of gzip members at different levels, still decodes to the input
+        let compressed = encoder.finish().unwrap().bytes;
+        let mut decompressed = Vec::new();
+        flate2::read::MultiGzDecoder::new(&compressed[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert!(decompressed == input());
+    }
+
+    #[test]
+    fn level_stays_fastest_when_the_sink_keeps_up() {
+        let encoder = encoder_after_input(
+            Compression::Zstd,
+            Duration::from_millis(1),
+            Duration::from_micros(10),
+        );
+        assert_eq!(encoder.level(), 1);
+    }
+}
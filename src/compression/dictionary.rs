@@ -0,0 +1,110 @@
+use futures::io::{AsyncBufRead, AsyncRead};
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use zstd::stream::raw::{Decoder, Operation};
+
+/// Decoder of zstd frames compressed with a dictionary, which async-compression's `ZstdDecoder` can't be given. Like
+/// it, decodes every frame of concatenated streams. Frames compressed without a dictionary are decoded too.
+pub struct ZstdDictionaryDecoder<R> {
+    reader: R,
+    decoder: Decoder<'static>,
+    in_frame: bool, // whether bytes of a frame have been read without it being fully decoded
+}
+
+impl<R: AsyncBufRead + Unpin> ZstdDictionaryDecoder<R> {
+    /// Decode the zstd frames read from `reader` with `dictionary`, e.g. as trained by `zstd --train`
+    pub fn new(reader: R, dictionary: &[u8]) -> io::Result<Self> {
+        Ok(ZstdDictionaryDecoder {
+            reader,
+            decoder: Decoder::with_dictionary(dictionary)?,
+            in_frame: false,
+        })
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncRead for ZstdDictionaryDecoder<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        let this = &mut *self;
+        loop {
+            let input = match Pin::new(&mut this.reader).poll_fill_buf(cx) {
+                Poll::Ready(Ok(input)) => input,
+                Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+                Poll::Pending => return Poll::Pending,
+            };
+            let at_eof = input.is_empty();
+            if at_eof && !this.in_frame {
+                return Poll::Ready(Ok(0));
+            }
+            // at the end of the input, a partly decoded frame may still have decoded bytes to flush
+            let status = this.decoder.run_on_buffers(input, buf)?;
+            Pin::new(&mut this.reader).consume(status.bytes_read);
+            this.in_frame = status.remaining != 0;
+            if status.bytes_written > 0 {
+                return Poll::Ready(Ok(status.bytes_written));
+            }
+            if at_eof {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "zstd stream ends part way through a frame",
+                )));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::io::AsyncReadExt;
+
+    fn compress(dictionary: &[u8], bytes: &[u8]) -> Vec<u8> {
+        let mut encoder =
+            zstd::stream::write::Encoder::with_dictionary(Vec::new(), 3, dictionary).unwrap();
+        std::io::Write::write_all(&mut encoder, bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn concatenated_frames_are_decoded_with_the_dictionary() {
+        let dictionary = b"a dictionary of bytes common to every frame".repeat(4);
+        let frames: Vec<Vec<u8>> = (0..3u8)
+            .map(|i| {
+                let mut frame = dictionary.clone();
+                frame.extend(vec![i; 100_000]); // larger than a single read, so decoded across several
+                frame
+            })
+            .collect();
+        let mut compressed = Vec::new();
+        for frame in &frames {
+            compressed.extend(compress(&dictionary, frame));
+        }
+        compressed.extend(zstd::encode_all(&b"no dictionary"[..], 3).unwrap());
+
+        let mut decoder = ZstdDictionaryDecoder::new(&compressed[..], &dictionary).unwrap();
+        let mut decoded = Vec::new();
+        smol::block_on(decoder.read_to_end(&mut decoded)).unwrap();
+        assert_eq!(
+            decoded,
+            [frames.concat(), b"no dictionary".to_vec()].concat()
+        );
+    }
+
+    #[test]
+    fn a_truncated_frame_fails() {
+        let dictionary = b"dictionary".repeat(8);
+        let compressed = compress(&dictionary, &vec![7; 10_000]);
+        let mut decoder =
+            ZstdDictionaryDecoder::new(&compressed[..compressed.len() - 4], &dictionary).unwrap();
+        let mut decoded = Vec::new();
+        let error = smol::block_on(decoder.read_to_end(&mut decoded)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
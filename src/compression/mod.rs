@@ -0,0 +1,234 @@
+//! Compression formats understood by stream-merge
+//!
+//! Inputs are decompressed according to their file extension. Merged output can optionally be compressed with
+//! an [Encoder] wrapping any [Write]r.
+//!
+//! A [FramedParts] compresses output into parts which each decompress on their own, for uploading in parts.
+//!
+//! `.zst` inputs compressed with a dictionary are decoded by a [ZstdDictionaryDecoder] given the same dictionary.
+//!
+//! Decompressing `.bz2` and `.xz` inputs requires the `bzip2` and `xz` features respectively. Output can't be
+//! compressed in either format.
+
+#[cfg(feature = "bzip2")]
+use async_compression::futures::bufread::BzDecoder;
+#[cfg(feature = "xz")]
+use async_compression::futures::bufread::XzDecoder;
+use async_compression::futures::bufread::{GzipDecoder, ZstdDecoder};
+use futures::io::{AsyncBufRead, AsyncRead};
+use serde::Deserialize;
+use std::io::Write;
+
+mod adaptive;
+pub use adaptive::AdaptiveEncoder;
+mod dictionary;
+pub use dictionary::ZstdDictionaryDecoder;
+mod parts;
+pub use parts::{FramedParts, PartSink};
+
+/// A (de)compression format, detected from a path's extension for inputs or chosen explicitly for output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    /// input only
+    #[cfg(feature = "bzip2")]
+    #[serde(skip)]
+    Bzip2,
+    /// input only
+    #[cfg(feature = "xz")]
+    #[serde(skip)]
+    Xz,
+}
+
+impl Compression {
+    /// Detect the compression format of a file from its extension (e.g. `.pcap.gz` or `.pcap.zst`)
+    pub fn from_path(path: &str) -> Compression {
+        if path.ends_with(".zst") {
+            Compression::Zstd
+        } else if path.ends_with(".gz") {
+            Compression::Gzip
+        } else {
+            #[cfg(feature = "bzip2")]
+            if path.ends_with(".bz2") {
+                return Compression::Bzip2;
+            }
+            #[cfg(feature = "xz")]
+            if path.ends_with(".xz") {
+                return Compression::Xz;
+            }
+            Compression::None
+        }
+    }
+
+    /// Wrap `reader` in the decoder for this format, yielding the uncompressed bytes
+    pub fn decoder<R>(self, reader: R) -> Box<dyn AsyncRead + Unpin + Send>
+    where
+        R: AsyncBufRead + Unpin + Send + 'static,
+    {
+        // decode every member/frame of concatenated streams, as written by e.g. pigz or an [AdaptiveEncoder]
+        match self {
+            Compression::None => Box::new(reader),
+            Compression::Gzip => {
+                let mut decoder = GzipDecoder::new(reader);
+                decoder.multiple_members(true);
+                Box::new(decoder)
+            }
+            Compression::Zstd => {
+                let mut decoder = ZstdDecoder::new(reader);
+                decoder.multiple_members(true);
+                Box::new(decoder)
+            }
+            #[cfg(feature = "bzip2")]
+            Compression::Bzip2 => {
+                let mut decoder = BzDecoder::new(reader);
+                decoder.multiple_members(true);
+                Box::new(decoder)
+            }
+            #[cfg(feature = "xz")]
+            Compression::Xz => {
+                let mut decoder = XzDecoder::new(reader);
+                decoder.multiple_members(true);
+                Box::new(decoder)
+            }
+        }
+    }
+
+    /// Like [Compression::decoder], decoding zstd frames with `zstd_dictionary` if given, which frames compressed with
+    /// it require
+    pub fn decoder_with_dictionary<R>(
+        self,
+        reader: R,
+        zstd_dictionary: Option<&[u8]>,
+    ) -> std::io::Result<Box<dyn AsyncRead + Unpin + Send>>
+    where
+        R: AsyncBufRead + Unpin + Send + 'static,
+    {
+        Ok(match (self, zstd_dictionary) {
+            (Compression::Zstd, Some(dictionary)) => {
+                Box::new(ZstdDictionaryDecoder::new(reader, dictionary)?)
+            }
+            _ => self.decoder(reader),
+        })
+    }
+
+    /// Wrap `writer` in an [Encoder] which compresses everything written through it with this format
+    pub fn encoder<W: Write>(self, writer: W) -> std::io::Result<Encoder<W>> {
+        let level = match self {
+            Compression::Zstd => 3,
+            _ => self.levels().0,
+        };
+        self.encoder_with_level(writer, level)
+    }
+
+    /// Like [Compression::encoder], at an explicit compression `level` within [Compression::levels]
+    pub fn encoder_with_level<W: Write>(
+        self,
+        writer: W,
+        level: i32,
+    ) -> std::io::Result<Encoder<W>> {
+        Ok(match self {
+            Compression::None => Encoder::None(writer),
+            Compression::Gzip => Encoder::Gzip(flate2::write::GzEncoder::new(
+                writer,
+                flate2::Compression::new(level as u32),
+            )),
+            Compression::Zstd => Encoder::Zstd(zstd::stream::write::Encoder::new(writer, level)?),
+            #[cfg(any(feature = "bzip2", feature = "xz"))]
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    format!("{:?} output isn't supported", self),
+                ))
+            }
+        })
+    }
+
+    /// The magic number every stream of this format begins with, if it has one
+    pub fn magic(self) -> Option<&'static [u8]> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some(&[0x1F, 0x8B]),
+            Compression::Zstd => Some(&[0x28, 0xB5, 0x2F, 0xFD]),
+            #[cfg(feature = "bzip2")]
+            Compression::Bzip2 => Some(b"BZh"),
+            #[cfg(feature = "xz")]
+            Compression::Xz => Some(&[0xFD, b'7', b'z', b'X', b'Z', 0x00]),
+        }
+    }
+
+    /// The fastest and smallest-output compression levels supported by this format
+    pub fn levels(self) -> (i32, i32) {
+        match self {
+            Compression::None => (0, 0),
+            Compression::Gzip => (1, 9),
+            Compression::Zstd => (1, 19),
+            #[cfg(any(feature = "bzip2", feature = "xz"))]
+            _ => (0, 0),
+        }
+    }
+}
+
+impl std::str::FromStr for Compression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "none" => Ok(Compression::None),
+            "gzip" => Ok(Compression::Gzip),
+            "zstd" => Ok(Compression::Zstd),
+            _ => anyhow::bail!(
+                "Unknown compression '{}'. Expected one of: none, gzip, zstd",
+                s
+            ),
+        }
+    }
+}
+
+/// [Write] adapter returned by [Compression::encoder]. [Encoder::finish] must be called once writing is complete
+/// so that the compressed stream's trailer is written.
+pub enum Encoder<W: Write> {
+    None(W),
+    Gzip(flate2::write::GzEncoder<W>),
+    Zstd(zstd::stream::write::Encoder<'static, W>),
+}
+
+impl<W: Write> Encoder<W> {
+    /// The wrapped writer, which receives the compressed bytes
+    pub fn get_mut(&mut self) -> &mut W {
+        match self {
+            Encoder::None(writer) => writer,
+            Encoder::Gzip(encoder) => encoder.get_mut(),
+            Encoder::Zstd(encoder) => encoder.get_mut(),
+        }
+    }
+
+    /// Complete the compressed stream and return the wrapped writer
+    pub fn finish(self) -> std::io::Result<W> {
+        match self {
+            Encoder::None(writer) => Ok(writer),
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Encoder::None(writer) => writer.write(buf),
+            Encoder::Gzip(encoder) => encoder.write(buf),
+            Encoder::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Encoder::None(writer) => writer.flush(),
+            Encoder::Gzip(encoder) => encoder.flush(),
+            Encoder::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
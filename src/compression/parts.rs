@@ -0,0 +1,145 @@
+use super::{Compression, Encoder};
+use std::io::Write;
+
+/// Receives the parts written by a [FramedParts], e.g. uploading each as a part of an S3 multipart upload
+pub trait PartSink {
+    /// Take the `part_number`th part, numbered from 1 as S3 numbers the parts of an upload
+    fn write_part(&mut self, part_number: usize, part: Vec<u8>) -> std::io::Result<()>;
+}
+
+impl<F: FnMut(usize, Vec<u8>) -> std::io::Result<()>> PartSink for F {
+    fn write_part(&mut self, part_number: usize, part: Vec<u8>) -> std::io::Result<()> {
+        self(part_number, part)
+    }
+}
+
+/// [Write] adapter which compresses with `compression` into parts of about `part_n_bytes` compressed bytes, each a
+/// complete zstd frame (or gzip member) of its own. A part can then be decompressed without any other, e.g. by a range
+/// read of just that part of an uploaded object, while the parts concatenated are a single stream which standard tools
+/// and [Compression::decoder] decode in full.
+///
+/// A part is only ended once the encoder has emitted at least `part_n_bytes`, so each part other than the last is at
+/// least that long, as S3 requires of all but the last part of a multipart upload (at least 5MiB).
+pub struct FramedParts<S: PartSink> {
+    compression: Compression,
+    encoder: Option<Encoder<Vec<u8>>>,
+    part_n_bytes: usize,
+    n_parts: usize,
+    sink: S,
+}
+
+impl<S: PartSink> FramedParts<S> {
+    pub fn new(compression: Compression, part_n_bytes: usize, sink: S) -> std::io::Result<Self> {
+        Ok(FramedParts {
+            compression,
+            encoder: Some(compression.encoder(Vec::with_capacity(part_n_bytes))?),
+            part_n_bytes,
+            n_parts: 0,
+            sink,
+        })
+    }
+
+    /// Parts written to the sink so far
+    pub fn n_parts(&self) -> usize {
+        self.n_parts
+    }
+
+    /// End the last part, write it to the sink and return the sink. A part is written even if nothing was, so that
+    /// every output has at least one.
+    pub fn finish(mut self) -> std::io::Result<S> {
+        let part = self.encoder.take().unwrap().finish()?;
+        self.write_part(part)?;
+        Ok(self.sink)
+    }
+
+    fn encoder(&mut self) -> &mut Encoder<Vec<u8>> {
+        self.encoder.as_mut().unwrap() // only None while ending a part or once finished
+    }
+
+    fn write_part(&mut self, part: Vec<u8>) -> std::io::Result<()> {
+        self.n_parts += 1;
+        tracing::event!(
+            tracing::Level::DEBUG,
+            part_number = self.n_parts,
+            n_bytes = part.len(),
+            "Writing part"
+        );
+        self.sink.write_part(self.n_parts, part)
+    }
+}
+
+impl<S: PartSink> Write for FramedParts<S> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n_bytes = self.encoder().write(buf)?;
+        if self.encoder().get_mut().len() >= self.part_n_bytes {
+            // end the frame, so that the part holds every byte needed to decompress it
+            let part = self.encoder.take().unwrap().finish()?;
+            self.encoder = Some(
+                self.compression
+                    .encoder(Vec::with_capacity(self.part_n_bytes))?,
+            );
+            self.write_part(part)?;
+        }
+        Ok(n_bytes)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        // a part can only be written once it's complete, so this only flushes the encoder into the part it's building
+        self.encoder().flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_part_is_an_independently_decodable_frame() {
+        // a merged pcap of packets with payloads which don't compress away to nothing
+        let packets: Vec<(u64, Vec<u8>)> = (0..20_000u64)
+            .map(|i| {
+                let payload = (0..100u64)
+                    .map(|j| ((i * 7919 + j * 104_729) % 251) as u8 ^ (i >> 3) as u8)
+                    .collect();
+                (1_000_000_000 + i, payload)
+            })
+            .collect();
+        let mut merged =
+            crate::pcap::Writer::new(Vec::new(), crate::pcap::OutputFormat::Pcap).unwrap();
+        for (ts, payload) in &packets {
+            let mut record = Vec::new();
+            record.extend_from_slice(&((ts / 1_000_000_000) as u32).to_le_bytes());
+            record.extend_from_slice(&((ts % 1_000_000_000) as u32).to_le_bytes());
+            record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+            record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+            record.extend_from_slice(payload);
+            merged.write_packet(*ts, &record).unwrap();
+        }
+        let merged = merged.into_inner();
+
+        let mut parts = Vec::new();
+        let sink = |part_number: usize, part: Vec<u8>| {
+            assert_eq!(part_number, parts.len() + 1);
+            parts.push(part);
+            Ok(())
+        };
+        let part_n_bytes = 64 * 1024;
+        let mut writer = FramedParts::new(Compression::Zstd, part_n_bytes, sink).unwrap();
+        for chunk in merged.chunks(1000) {
+            writer.write_all(chunk).unwrap();
+        }
+        let _sink = writer.finish().unwrap();
+
+        assert!(parts.len() > 3, "{} parts", parts.len());
+        let mut decoded = Vec::new();
+        for (i, part) in parts.iter().enumerate() {
+            if i + 1 < parts.len() {
+                assert!(part.len() >= part_n_bytes);
+            }
+            decoded.extend(zstd::decode_all(&part[..]).unwrap());
+        }
+        assert_eq!(decoded, merged);
+        // and the parts concatenated are a single stream
+        assert_eq!(zstd::decode_all(&parts.concat()[..]).unwrap(), merged);
+    }
+}
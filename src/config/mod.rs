@@ -0,0 +1,393 @@
+//! Declarative merge configuration
+//!
+//! A [MergeConfig] describes an entire merge (inputs, per-file timestamp offsets, a time window, and the output
+//! path, format and compression) and can be loaded from a JSON file, e.g.
+//!
+//! ```json
+//! {
+//!     "inputs": [
+//!         { "path": "s3://bucket/capture_a.pcap.zst", "max_n_buffered": 8 },
+//!         { "path": "/data/capture_b.pcap.gz", "offset_ns": -1500 },
+//!         { "path": "/data/logger_c.bin", "format": "raw" },
+//!         { "path": "/data/telemetry_d.pcap", "order": "arrival" },
+//!         { "path": "/data/capture_e.pcap", "ts_sidecar": "/data/capture_e.ts" },
+//!         { "path": "/data/capture_f.pcap", "transcode": "sll-to-ethernet" }
+//!     ],
+//!     "window": { "start_ns": 1637796620000000000, "end_ns": 1637800220000000000 },
+//!     "output": "merged.pcap.zst",
+//!     "format": "pcap",
+//!     "compression": "zstd"
+//! }
+//! ```
+
+use crate::compression::Compression;
+use crate::pcap::{InputFormat, OutputFormat, Transcode};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+mod watch;
+pub use watch::{DirectoryWatch, WatchedDirectory};
+
+/// Everything needed to run a merge. Fields left unset fall back to the `merge_pcaps` defaults.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MergeConfig {
+    #[serde(default)]
+    pub inputs: Vec<InputConfig>,
+    #[serde(default)]
+    pub window: TimeWindow,
+    /// write merged output to this path rather than `stdout`
+    pub output: Option<PathBuf>,
+    pub format: Option<OutputFormat>,
+    /// compression applied to the merged output
+    pub compression: Option<Compression>,
+}
+
+impl MergeConfig {
+    /// Load a [MergeConfig] from a JSON file
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<MergeConfig> {
+        let path = path.as_ref();
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("Failed to open merge config '{}'", path.display()))?;
+        serde_json::from_reader(std::io::BufReader::new(file))
+            .with_context(|| format!("Failed to parse merge config '{}'", path.display()))
+    }
+
+    /// Remove inputs which refer to the same file as an earlier input (see [InputConfig::identity]), returning the
+    /// removed inputs
+    pub fn dedup_inputs(&mut self) -> Vec<InputConfig> {
+        let mut seen = std::collections::HashSet::new();
+        let mut duplicates = Vec::new();
+        let inputs = std::mem::take(&mut self.inputs);
+        for input in inputs {
+            if seen.insert(input.identity()) {
+                self.inputs.push(input);
+            } else {
+                duplicates.push(input);
+            }
+        }
+        duplicates
+    }
+}
+
+/// A single file to merge, identified by its local path or `s3://` URI
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InputConfig {
+    pub path: String,
+    /// signed number of nanoseconds added to every timestamp in this file before merging (e.g. to correct clock skew)
+    #[serde(default)]
+    pub offset_ns: i64,
+    /// how this file's packets are framed. Detected from the path's extension when unset
+    #[serde(default)]
+    pub format: Option<InputFormat>,
+    /// whether this file is merged by its packets' timestamps (the default) or in the order its packets arrive
+    #[serde(default)]
+    pub order: InputOrder,
+    /// overrides [crate::s3::DownloadConfig::take_n_serially] for this file
+    #[serde(default)]
+    pub take_n_serially: Option<usize>,
+    /// overrides [crate::s3::DownloadConfig::max_n_buffered] for this file, e.g. to download a large file with more
+    /// concurrency than the rest
+    #[serde(default)]
+    pub max_n_buffered: Option<usize>,
+    /// only merge the packets captured on this interface (by ID) of a pcapng file, rather than those of every interface
+    #[serde(default)]
+    pub interface: Option<u32>,
+    /// merge this file's packets by the timestamps of this local sidecar file rather than their own, matched by
+    /// position (see [crate::pcap::SidecarTimestamps]). The `offset_ns` is applied to the sidecar's timestamps
+    #[serde(default)]
+    pub ts_sidecar: Option<String>,
+    /// reframe the link-layer headers of this pcap file's packets, if of the link type it transcodes, overriding
+    /// [crate::s3::DownloadConfig::transcode]
+    #[serde(default)]
+    pub transcode: Option<Transcode>,
+}
+
+/// How an input's packets are ordered relative to the other inputs' in the merge
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum InputOrder {
+    /// by each packet's (offset-adjusted) timestamp
+    #[default]
+    Timestamp,
+    /// as soon as each packet has been read, restamped with the timestamp of the packet merged before it. For inputs
+    /// without reliable timestamps. See [crate::tournament_tree::ArrivalOrdered]
+    Arrival,
+}
+
+impl std::str::FromStr for InputOrder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "timestamp" => Ok(InputOrder::Timestamp),
+            "arrival" => Ok(InputOrder::Arrival),
+            _ => anyhow::bail!(
+                "Unknown input order '{}'. Expected one of: timestamp, arrival",
+                s
+            ),
+        }
+    }
+}
+
+impl InputConfig {
+    pub fn new(path: String) -> InputConfig {
+        InputConfig {
+            path,
+            offset_ns: 0,
+            format: None,
+            order: InputOrder::Timestamp,
+            take_n_serially: None,
+            max_n_buffered: None,
+            interface: None,
+            ts_sidecar: None,
+            transcode: None,
+        }
+    }
+
+    /// `config` with this input's download overrides, if any, applied
+    pub fn download_config(&self, config: &crate::s3::DownloadConfig) -> crate::s3::DownloadConfig {
+        crate::s3::DownloadConfig {
+            take_n_serially: self.take_n_serially.unwrap_or(config.take_n_serially),
+            max_n_buffered: self.max_n_buffered.unwrap_or(config.max_n_buffered),
+            pcapng_interface: self.interface.or(config.pcapng_interface),
+            transcode: self.transcode.or(config.transcode),
+            offset_ns: self.offset_ns,
+            ..config.clone()
+        }
+    }
+
+    /// This input's explicit `format`, falling back to the format detected from its path
+    pub fn format(&self) -> InputFormat {
+        self.format
+            .unwrap_or_else(|| InputFormat::from_path(&self.path))
+    }
+
+    /// A normalized form of `path` which is equal for any two inputs referring to the same file: the canonical path of
+    /// a local file (or `path` itself if it cannot be canonicalized, e.g. because it does not exist), or the
+    /// `s3://bucket/key` URI with a lowercase scheme and bucket name. Followed by `#` and the `interface`, if only one
+    /// interface of the file is merged.
+    pub fn identity(&self) -> String {
+        let scheme = self.path.get(..5).unwrap_or_default();
+        let file = if scheme.eq_ignore_ascii_case("s3://") {
+            let uri = &self.path[5..];
+            let (bucket, key) = uri.split_at(uri.find('/').unwrap_or(uri.len()));
+            format!("s3://{}{}", bucket.to_ascii_lowercase(), key)
+        } else {
+            std::fs::canonicalize(&self.path)
+                .map(|path| path.display().to_string())
+                .unwrap_or_else(|_| self.path.clone())
+        };
+        match self.interface {
+            Some(interface) => format!("{}#{}", file, interface),
+            None => file,
+        }
+    }
+
+    /// Apply this input's `offset_ns` to a nanosecond timestamp, saturating at the bounds of `u64`
+    pub fn offset(&self, ts: u64) -> u64 {
+        offset_timestamp(ts, self.offset_ns)
+    }
+}
+
+/// Add the signed `offset_ns` to the nanosecond timestamp `ts`, saturating at the bounds of `u64`
+pub fn offset_timestamp(ts: u64, offset_ns: i64) -> u64 {
+    if offset_ns >= 0 {
+        ts.saturating_add(offset_ns as u64)
+    } else {
+        ts.saturating_sub(offset_ns.unsigned_abs())
+    }
+}
+
+/// File name suffixes of the pcaps found by [discover_inputs]
+const DISCOVERED_SUFFIXES: &[&str] = &[".pcap", ".pcap.gz", ".pcap.zst"];
+
+fn is_discoverable(path: &str) -> bool {
+    DISCOVERED_SUFFIXES
+        .iter()
+        .any(|suffix| path.ends_with(suffix))
+}
+
+/// Expand `path` into the paths of the files to merge. A local directory or an `s3://bucket/prefix/` URI (note the
+/// trailing `/`) is searched recursively for `*.pcap`, `*.pcap.gz` and `*.pcap.zst` files at most `max_depth` levels
+/// below it (1 being the directory's own files), returned sorted by path. One in which none are found is an error,
+/// unless `allow_empty`. Any other path is returned as-is. `requester_pays` is needed to list a prefix in a
+/// requester-pays bucket (see [crate::s3::S3Store::requester_pays]), which is listed with the credentials of
+/// `aws_profile` if given.
+pub fn discover_inputs(
+    path: &str,
+    max_depth: Option<usize>,
+    allow_empty: bool,
+    requester_pays: bool,
+    aws_profile: Option<&crate::s3::AwsProfile>,
+) -> Result<Vec<String>> {
+    discover_inputs_with_store(path, max_depth, allow_empty, || {
+        crate::s3::default_store(requester_pays, aws_profile)
+    })
+}
+
+/// Like [discover_inputs], listing an `s3://` prefix from the store returned by `store`, which is only called if
+/// `path` is one
+pub fn discover_inputs_with_store<S: crate::s3::ObjectStore>(
+    path: &str,
+    max_depth: Option<usize>,
+    allow_empty: bool,
+    store: impl FnOnce() -> std::sync::Arc<S>,
+) -> Result<Vec<String>> {
+    let max_depth = max_depth.unwrap_or(usize::MAX);
+    let is_s3 = path
+        .get(..5)
+        .is_some_and(|scheme| scheme.eq_ignore_ascii_case("s3://"));
+    let paths = if is_s3 {
+        if !path.ends_with('/') {
+            return Ok(vec![path.to_string()]);
+        }
+        crate::s3::list_prefix_with_store(path, &*store())?
+            .into_iter()
+            .filter(|uri| {
+                let depth = uri[path.len()..].matches('/').count() + 1;
+                depth <= max_depth && is_discoverable(uri)
+            })
+            .collect()
+    } else if Path::new(path).is_dir() {
+        let mut paths = Vec::new();
+        discover_local(Path::new(path), max_depth, &mut paths)?;
+        paths.sort();
+        paths
+    } else {
+        return Ok(vec![path.to_string()]);
+    };
+    if paths.is_empty() && !allow_empty {
+        anyhow::bail!(
+            "No inputs matched '{}'. Pass --allow-empty to merge none",
+            path
+        );
+    }
+    Ok(paths)
+}
+
+/// Append the discoverable files under `dir`, descending at most `max_depth` levels, to `paths`. Symbolic links to
+/// directories are not followed.
+fn discover_local(dir: &Path, max_depth: usize, paths: &mut Vec<String>) -> Result<()> {
+    if max_depth == 0 {
+        return Ok(());
+    }
+    let entries = std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read directory '{}'", dir.display()))?;
+    for entry in entries {
+        let entry =
+            entry.with_context(|| format!("Failed to read directory '{}'", dir.display()))?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            discover_local(&path, max_depth - 1, paths)?;
+        } else if path.is_file() {
+            let path = path.into_os_string().into_string().map_err(|path| {
+                anyhow::anyhow!("Non UTF-8 path '{}'", Path::new(&path).display())
+            })?;
+            if is_discoverable(&path) {
+                paths.push(path);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Half-open `[start_ns, end_ns)` range of (offset-adjusted) nanosecond timestamps to include in the merged output.
+/// An unset bound is unbounded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TimeWindow {
+    pub start_ns: Option<u64>,
+    pub end_ns: Option<u64>,
+}
+
+impl TimeWindow {
+    /// Whether `ts` falls before the start of the window
+    pub fn is_before(&self, ts: u64) -> bool {
+        self.start_ns.is_some_and(|start| ts < start)
+    }
+
+    /// Whether `ts` falls at or after the end of the window. Because merged output is time-ordered, no later packet
+    /// can fall within the window either.
+    pub fn is_after(&self, ts: u64) -> bool {
+        self.end_ns.is_some_and(|end| ts >= end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn discovers_pcaps_recursively_up_to_max_depth() {
+        let dir = tempfile::tempdir().unwrap();
+        for file in &[
+            "b.pcap",
+            "a/c.pcap.gz",
+            "a/notes.txt",
+            "a/deeper/d.pcap.zst",
+            "a/deeper/e.pcapng",
+        ] {
+            let path = dir.path().join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, b"").unwrap();
+        }
+        let root = dir.path().to_str().unwrap();
+        let discovered = |max_depth| -> Vec<String> {
+            discover_inputs(root, max_depth, false, false, None)
+                .unwrap()
+                .iter()
+                .map(|path| path[root.len() + 1..].to_string())
+                .collect()
+        };
+
+        assert_eq!(
+            discovered(None),
+            vec!["a/c.pcap.gz", "a/deeper/d.pcap.zst", "b.pcap"]
+        );
+        assert_eq!(discovered(Some(2)), vec!["a/c.pcap.gz", "b.pcap"]);
+        assert_eq!(discovered(Some(1)), vec!["b.pcap"]);
+
+        let file = dir.path().join("b.pcap");
+        let file = file.to_str().unwrap();
+        assert_eq!(
+            discover_inputs(file, Some(1), false, false, None).unwrap(),
+            vec![file]
+        );
+    }
+
+    #[test]
+    fn a_prefix_with_no_pcaps_is_an_error_unless_allowed() {
+        let store = || std::sync::Arc::new(crate::s3::InMemoryStore(bytes::Bytes::new()));
+        let error = discover_inputs_with_store("s3://bucket/2024-01-01/", None, false, store)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("No inputs matched"), "{}", error);
+        assert!(
+            discover_inputs_with_store("s3://bucket/2024-01-01/", None, true, store)
+                .unwrap()
+                .is_empty()
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path().to_str().unwrap();
+        assert!(discover_inputs(dir, None, false, false, None).is_err());
+        assert!(discover_inputs(dir, None, true, false, None)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn s3_identity_normalizes_scheme_and_bucket() {
+        assert_eq!(
+            InputConfig::new("S3://My-Bucket/Captures/a.pcap".to_string()).identity(),
+            "s3://my-bucket/Captures/a.pcap"
+        );
+        assert_eq!(
+            InputConfig::new("s3://bucket".to_string()).identity(),
+            "s3://bucket"
+        );
+    }
+}
@@ -0,0 +1,282 @@
+use anyhow::{Context, Result};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, Instant, SystemTime};
+
+/// The files of a local directory which match a file name pattern, rescanned every interval to find those which have
+/// appeared since, such as the files of a capture tool which rotates through a ring of them. A file which disappears
+/// is forgotten, so that one later written under the same name is found again, as is one replaced in place: files are
+/// known by their inode, size and modification time as well as their path.
+///
+/// The directory is polled rather than watched with inotify (e.g. through the `notify` crate): capture rings often
+/// live on NFS or other network mounts, where inotify never hears of files written by another host, and a file is
+/// only merged once it's complete anyway, so finding it up to an interval late costs nothing but latency. Polling also
+/// matches how `--follow` checks a growing input, and needs no dependency of its own.
+#[derive(Debug)]
+pub struct WatchedDirectory {
+    dir: PathBuf,
+    pattern: String,
+    interval: Duration,
+    known: BTreeSet<(String, FileVersion)>,
+}
+
+/// What tells a file apart from another written under the same path: its inode (on unix), size and modification time
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct FileVersion {
+    inode: u64,
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+impl FileVersion {
+    fn of(metadata: &std::fs::Metadata) -> FileVersion {
+        #[cfg(unix)]
+        let inode = std::os::unix::fs::MetadataExt::ino(metadata);
+        #[cfg(not(unix))]
+        let inode = 0;
+        FileVersion {
+            inode,
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+        }
+    }
+}
+
+impl WatchedDirectory {
+    /// Watch the files matching `pattern`, a path whose file name may hold `*` (any run of characters) and `?` (any
+    /// one character) wildcards, e.g. `/captures/ring-*.pcap`. The directory itself is given literally.
+    pub fn new(pattern: &str, interval: Duration) -> Result<WatchedDirectory> {
+        let path = Path::new(pattern);
+        let file_pattern = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .with_context(|| format!("'{}' doesn't end in a file name pattern", pattern))?;
+        let dir = match path.parent() {
+            Some(dir) if dir.as_os_str().is_empty() => Path::new("."),
+            Some(dir) => dir,
+            None => Path::new("/"),
+        };
+        if dir.to_string_lossy().contains(['*', '?']) {
+            anyhow::bail!(
+                "Only the file name of '{}' may hold wildcards, not its directory",
+                pattern
+            );
+        }
+        if !dir.is_dir() {
+            anyhow::bail!("'{}' isn't a directory to watch", dir.display());
+        }
+        Ok(WatchedDirectory {
+            dir: dir.to_path_buf(),
+            pattern: file_pattern.to_string(),
+            interval,
+            known: BTreeSet::new(),
+        })
+    }
+
+    /// The paths of the matching files which have appeared or been replaced since the last scan (every matching file,
+    /// on the first), sorted by path
+    pub fn scan(&mut self) -> Result<Vec<String>> {
+        let entries = std::fs::read_dir(&self.dir)
+            .with_context(|| format!("Failed to read directory '{}'", self.dir.display()))?;
+        let mut present = BTreeSet::new();
+        for entry in entries {
+            let entry = entry
+                .with_context(|| format!("Failed to read directory '{}'", self.dir.display()))?;
+            let path = entry.path();
+            let matched = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| matches(self.pattern.as_bytes(), name.as_bytes()));
+            if !matched {
+                continue;
+            }
+            let metadata = match std::fs::metadata(&path) {
+                Ok(metadata) if metadata.is_file() => metadata,
+                _ => continue, // not a file, or already gone again
+            };
+            let path = path.into_os_string().into_string().map_err(|path| {
+                anyhow::anyhow!("Non UTF-8 path '{}'", Path::new(&path).display())
+            })?;
+            present.insert((path, FileVersion::of(&metadata)));
+        }
+        let appeared: Vec<String> = present
+            .difference(&self.known)
+            .map(|(path, _)| path.clone())
+            .collect();
+        self.known = present;
+        Ok(appeared)
+    }
+
+    /// Rescan every interval on a thread of its own, so that a caller checking for new files (e.g. once per merged
+    /// packet) only takes what the scans have found. The thread stops once the [DirectoryWatch] is dropped, or after
+    /// a scan fails.
+    pub fn watch(mut self) -> DirectoryWatch {
+        let interval = self.interval;
+        let (sender, found) = mpsc::channel();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(self.interval);
+            let scanned = self.scan();
+            let failed = scanned.is_err();
+            let sent = match scanned {
+                Ok(appeared) if appeared.is_empty() => Ok(()),
+                scanned => sender.send(scanned),
+            };
+            if failed || sent.is_err() {
+                return;
+            }
+        });
+        DirectoryWatch {
+            found,
+            interval,
+            pending: Vec::new(),
+            last_found: Instant::now(),
+        }
+    }
+}
+
+/// The files found by the scans of a [WatchedDirectory::watch]
+#[derive(Debug)]
+pub struct DirectoryWatch {
+    found: mpsc::Receiver<Result<Vec<String>>>,
+    interval: Duration,
+    pending: Vec<String>,
+    last_found: Instant,
+}
+
+impl DirectoryWatch {
+    /// The paths of the matching files which scans have found since the last call, without waiting for another
+    pub fn found(&mut self) -> Result<Vec<String>> {
+        loop {
+            match self.found.try_recv() {
+                Ok(appeared) => self.pending.extend(appeared?),
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    anyhow::bail!("Stopped watching for files")
+                }
+            }
+        }
+        if !self.pending.is_empty() {
+            self.last_found = Instant::now();
+        }
+        Ok(std::mem::take(&mut self.pending))
+    }
+
+    /// Block until a scan finds a file, for no longer than the interval between scans. Those found are returned by
+    /// the next [DirectoryWatch::found].
+    pub fn wait(&mut self) -> Result<()> {
+        match self.found.recv_timeout(self.interval) {
+            Ok(appeared) => self.pending.extend(appeared?),
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                anyhow::bail!("Stopped watching for files")
+            }
+        }
+        Ok(())
+    }
+
+    /// How long it has been since a file was last found (or since the watch began, if none has been)
+    pub fn idle_for(&self) -> Duration {
+        self.last_found.elapsed()
+    }
+}
+
+/// Whether `name` matches the wildcard `pattern`. See [WatchedDirectory::new]
+fn matches(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.split_first(), name.split_first()) {
+        (None, _) => name.is_empty(),
+        (Some((b'*', rest)), _) => (0..=name.len()).any(|skipped| matches(rest, &name[skipped..])),
+        (Some((b'?', rest)), Some((_, name))) => matches(rest, name),
+        (Some((expected, rest)), Some((byte, name))) => expected == byte && matches(rest, name),
+        (Some(_), None) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_names_match_wildcards() {
+        assert!(matches(b"ring-*.pcap", b"ring-0001.pcap"));
+        assert!(matches(b"ring-*.pcap", b"ring-.pcap"));
+        assert!(!matches(b"ring-*.pcap", b"ring-0001.pcap.tmp"));
+        assert!(matches(b"ring-?.pcap*", b"ring-1.pcap.zst"));
+        assert!(!matches(b"ring-?.pcap", b"ring-10.pcap"));
+        assert!(matches(b"*", b""));
+    }
+
+    #[test]
+    fn files_are_found_as_they_appear_and_again_once_rewritten() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str| std::fs::write(dir.path().join(name), b"").unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        write("ring-1.pcap");
+        write("ring-2.pcap.tmp");
+        let pattern = dir.path().join("ring-*.pcap");
+        let mut watch =
+            WatchedDirectory::new(pattern.to_str().unwrap(), Duration::from_millis(1)).unwrap();
+        assert_eq!(watch.scan().unwrap(), vec![path("ring-1.pcap")]);
+        assert!(watch.scan().unwrap().is_empty());
+
+        // renamed into place, as a capture tool finishes each file
+        std::fs::rename(path("ring-2.pcap.tmp"), path("ring-2.pcap")).unwrap();
+        write("ring-3.pcap");
+        std::fs::remove_file(path("ring-1.pcap")).unwrap();
+        assert_eq!(
+            watch.scan().unwrap(),
+            vec![path("ring-2.pcap"), path("ring-3.pcap")]
+        );
+        write("ring-1.pcap");
+        assert_eq!(watch.scan().unwrap(), vec![path("ring-1.pcap")]);
+    }
+
+    #[test]
+    fn files_replaced_in_place_are_found_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        std::fs::write(path("ring-1.pcap"), b"first").unwrap();
+        std::fs::write(path("ring-2.pcap"), b"second").unwrap();
+        let pattern = dir.path().join("ring-*.pcap");
+        let mut watch =
+            WatchedDirectory::new(pattern.to_str().unwrap(), Duration::from_millis(1)).unwrap();
+        assert_eq!(
+            watch.scan().unwrap(),
+            vec![path("ring-1.pcap"), path("ring-2.pcap")]
+        );
+
+        // rewritten through the same inode, and renamed over by a new one
+        std::fs::write(path("ring-1.pcap"), b"first, rewritten").unwrap();
+        std::fs::write(path("ring-2.pcap.tmp"), b"second, replaced").unwrap();
+        std::fs::rename(path("ring-2.pcap.tmp"), path("ring-2.pcap")).unwrap();
+        assert_eq!(
+            watch.scan().unwrap(),
+            vec![path("ring-1.pcap"), path("ring-2.pcap")]
+        );
+        assert!(watch.scan().unwrap().is_empty());
+    }
+
+    #[test]
+    fn a_watch_finds_files_on_its_own_thread() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        let pattern = dir.path().join("ring-*.pcap");
+        let mut directory =
+            WatchedDirectory::new(pattern.to_str().unwrap(), Duration::from_millis(1)).unwrap();
+        assert!(directory.scan().unwrap().is_empty());
+        let mut watch = directory.watch();
+        assert!(watch.found().unwrap().is_empty());
+
+        std::fs::write(path("ring-1.pcap"), b"").unwrap();
+        let mut found = Vec::new();
+        while found.is_empty() {
+            watch.wait().unwrap();
+            found = watch.found().unwrap();
+        }
+        assert_eq!(found, vec![path("ring-1.pcap")]);
+        assert!(watch.found().unwrap().is_empty()); // each file is found once
+
+        drop(dir); // the next scan fails, stopping the watch
+        while watch.wait().is_ok() {}
+    }
+}
@@ -0,0 +1,67 @@
+//! A C interface for merging pcaps from other languages without running `merge_pcaps`, declared in
+//! `include/stream_merge.h`. Built with the `ffi` feature.
+
+use std::ffi::CStr;
+use std::fs::File;
+use std::io::BufWriter;
+use std::mem::ManuallyDrop;
+use std::os::raw::{c_char, c_int};
+use std::os::unix::io::FromRawFd;
+
+/// The merge succeeded
+pub const SMERGE_OK: c_int = 0;
+/// A path was null or not UTF-8, or the file descriptor negative
+pub const SMERGE_EINVAL: c_int = 1;
+/// An input couldn't be read, or the output written
+pub const SMERGE_EMERGE: c_int = 2;
+/// The merge panicked
+pub const SMERGE_EPANIC: c_int = 3;
+
+/// Merge the `n` pcaps at `paths` (local or `s3://`, optionally .gz or .zst compressed) into a nanosecond-precision
+/// pcap written to the open file descriptor `out_fd`, which is left open. Blocks until the merge is done, returning
+/// [SMERGE_OK] or one of the other `SMERGE_` error codes, with the reason for an error logged.
+///
+/// # Safety
+///
+/// `paths` must point to `n` pointers to NUL-terminated strings (or be null if `n` is 0), and `out_fd` be an open file
+/// descriptor not written by anything else during the merge.
+#[no_mangle]
+pub unsafe extern "C" fn smerge_merge_files(
+    paths: *const *const c_char,
+    n: usize,
+    out_fd: c_int,
+) -> c_int {
+    if out_fd < 0 || (paths.is_null() && n > 0) {
+        return SMERGE_EINVAL;
+    }
+    let paths = if n == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(paths, n)
+    };
+    let paths = paths
+        .iter()
+        .map(|&path| match path.is_null() {
+            true => None,
+            false => CStr::from_ptr(path).to_str().ok().map(String::from),
+        })
+        .collect::<Option<Vec<String>>>();
+    let paths = match paths {
+        Some(paths) => paths,
+        None => return SMERGE_EINVAL,
+    };
+
+    // the caller owns the descriptor, so it's never closed here
+    let out = ManuallyDrop::new(File::from_raw_fd(out_fd));
+    let merge = std::panic::catch_unwind(|| {
+        crate::merge_pcaps_to_writer(paths, BufWriter::new(&*out)).map(drop)
+    });
+    match merge {
+        Ok(Ok(())) => SMERGE_OK,
+        Ok(Err(error)) => {
+            tracing::event!(tracing::Level::ERROR, error = %format!("{:#}", error), "Merge failed");
+            SMERGE_EMERGE
+        }
+        Err(_panic) => SMERGE_EPANIC,
+    }
+}
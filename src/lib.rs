@@ -1,89 +1,1238 @@
+pub mod compression;
+pub mod config;
+#[cfg(all(feature = "ffi", unix))]
+pub mod ffi;
+#[cfg(all(feature = "live-capture", target_os = "linux"))]
+pub mod live;
+pub mod output;
 pub mod pcap;
 pub mod s3;
+pub mod spill;
+pub mod stats;
 pub mod tournament_tree;
 mod util;
 
+pub use util::{
+    Bounded, Clock, Deadline, DecodePool, IdleWatchdog, MemoryBudget, OpenFileLimit, RateLimiter,
+    SystemClock,
+};
+
+use anyhow::Context;
 use async_channel::bounded;
-use async_compression::futures::bufread::{GzipDecoder, ZstdDecoder};
 use bytes::Bytes;
+use compression::Compression;
+use futures::future::FutureExt;
 use futures::io::AsyncRead;
 use futures::stream::{StreamExt, TryStreamExt};
+use pcap::ParseOffset;
+use std::path::PathBuf;
 use tracing::{Instrument, Level};
-use util::TakeThenBuffered;
+use util::{TakeThenBuffered, WarnIfAbandoned};
 
 fn download_s3_object_chunks_in_parallel(
     path: &str,
+    config: &s3::DownloadConfig,
 ) -> impl futures::AsyncBufRead + std::marker::Unpin {
-    // 128kb * 4_simultaneous_downloads == 128kB * 4000 files == 512MB for one chunk per-file. If decompression rate requires > 1 parallel chunk to achieve goal throughput,
-    // let's say 4 to start are needed, our expected max memory footprint will be 512MB + (512MB/12) * 4 == 512MB + 170.6666MB == 682.6666MB when 1/12th of the files are "active" at a time.
-    let object_chunks = s3::ObjectChunks::new(path, 1024 * 128).unwrap().boxed(); /* TODO: proper error on failure */
+    let store = s3::default_store(config.requester_pays, config.aws_profile.as_ref());
+    let mut object_chunks = s3::ObjectChunks::with_store(path, config.chunk_size, store).unwrap(); /* TODO: proper error on failure */
+    if let Some(days) = config.restore_days {
+        object_chunks.restore_archived(days);
+    }
+    object_chunks.head_first(config.head_first);
+    object_chunks.follow(config.follow);
+    download_object_chunks_in_parallel(object_chunks, config)
+}
+
+/// Open the input at `path` for reading its decompressed bytes, alongside a [util::LazyFileCloser] for a local file
+/// which is opened lazily within the config's [OpenFileLimit]
+fn open_input(
+    path: &str,
+    config: &s3::DownloadConfig,
+) -> anyhow::Result<(
+    Box<dyn AsyncRead + std::marker::Unpin + Send>,
+    Option<util::LazyFileCloser>,
+)> {
+    let compression = Compression::from_path(path);
+    let dictionary = config.zstd_dictionary.as_deref().map(Vec::as_slice);
+    let dictionary_context = || format!("Failed to load the zstd dictionary to decode '{}'", path);
+    if path.starts_with("s3://") {
+        Ok((
+            compression
+                .decoder_with_dictionary(
+                    download_s3_object_chunks_in_parallel(path, config),
+                    dictionary,
+                )
+                .with_context(dictionary_context)?,
+            None,
+        ))
+    } else if let Some(limit) = &config.open_file_limit {
+        // fail on a missing file now, rather than once a descriptor is available to open it
+        std::fs::metadata(path).with_context(|| format!("Failed to open '{}'", path))?;
+        let (file, closer) = util::LazyFile::new(path, limit.clone());
+        Ok((
+            compression
+                .decoder_with_dictionary(
+                    smol::io::BufReader::with_capacity(1024 * 128, file),
+                    dictionary,
+                )
+                .with_context(dictionary_context)?,
+            Some(closer),
+        ))
+    } else {
+        // local file loader. TODO: consider switching to use io_uring w/ Tokio for this?
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .open(path)
+            .with_context(|| format!("Failed to open '{}'", path))?;
+        Ok((
+            compression
+                .decoder_with_dictionary(
+                    smol::io::BufReader::with_capacity(
+                        1024 * 128,
+                        smol::Unblock::with_capacity(1024 * 128, file),
+                    ),
+                    dictionary,
+                )
+                .with_context(dictionary_context)?,
+            None,
+        ))
+    }
+}
+
+/// Read the [pcap::Precision] of the timestamps in the pcap at `path` (local or `s3://`, optionally compressed) from its
+/// file header, without reading any of its packets
+pub async fn read_precision(
+    path: &str,
+    config: &s3::DownloadConfig,
+) -> anyhow::Result<pcap::Precision> {
+    let (reader, _) = open_input(path, config)?;
+    let packets = pcap::Packets::new(1024, reader)
+        .await
+        .with_context(|| format!("Failed to read the pcap header of '{}'", path))?;
+    Ok(packets.precision())
+}
+
+/// Read the legacy `thiszone` and `sigfigs` fields of the header of the pcap at `path` (local or `s3://`, optionally
+/// compressed), without reading any of its packets
+pub async fn read_header_fields(
+    path: &str,
+    config: &s3::DownloadConfig,
+) -> anyhow::Result<pcap::HeaderFields> {
+    let (reader, _) = open_input(path, config)?;
+    let packets = pcap::Packets::new(1024, reader)
+        .await
+        .with_context(|| format!("Failed to read the pcap header of '{}'", path))?;
+    Ok(packets.header_fields())
+}
+
+/// The timestamp of the first packet of the pcap at `path` (local or `s3://`, optionally compressed), or [None] if it
+/// has none, reading no further than that packet
+pub async fn read_first_timestamp(
+    path: &str,
+    config: &s3::DownloadConfig,
+) -> anyhow::Result<Option<u64>> {
+    let (reader, _) = open_input(path, config)?;
+    let mut packets = pcap::Packets::new(1024, reader)
+        .await
+        .with_context(|| format!("Failed to read the pcap header of '{}'", path))?;
+    match packets.next().await {
+        Some(Ok((ts, _))) => Ok(Some(ts)),
+        Some(Err(error)) => Err(anyhow::Error::new(error)
+            .context(format!("Failed to read the first packet of '{}'", path))),
+        None => Ok(None),
+    }
+}
+
+/// The interfaces the packets of the file at `path` (local or `s3://`, optionally compressed) were captured on, read
+/// as `format`, indexed by interface ID. Only a pcapng file describes more than one, and only those described ahead
+/// of its first packet are read. Other formats are described by a single interface: an unnamed one with the link type
+/// of a pcap's header (or that it's transcoded to, by the config's [pcap::Transcode]), or an Ethernet one for raw files.
+pub async fn read_interfaces(
+    path: &str,
+    format: pcap::InputFormat,
+    config: &s3::DownloadConfig,
+) -> anyhow::Result<Vec<pcap::pcapng::Interface>> {
+    let (reader, _) = open_input(path, config)?;
+    match format {
+        pcap::InputFormat::Pcap => {
+            let packets = pcap::Packets::new(1024, reader)
+                .await
+                .with_context(|| format!("Failed to read the pcap header of '{}'", path))?;
+            let link_type = match config.transcode {
+                Some(transcode) if transcode.from_link_type() == packets.link_type() => {
+                    transcode.to_link_type()
+                }
+                _ => packets.link_type(),
+            };
+            Ok(vec![pcap::pcapng::Interface::new(link_type)])
+        }
+        pcap::InputFormat::Raw => Ok(vec![pcap::pcapng::Interface::new(
+            pcap::pcapng::LINKTYPE_ETHERNET,
+        )]),
+        pcap::InputFormat::Pcapng => {
+            let mut packets = pcap::pcapng::PcapngPackets::new(1024 * 64, reader);
+            if let Some(Err(error)) = packets.next().await {
+                return Err(
+                    anyhow::Error::new(error).context(format!("Failed to read pcapng '{}'", path))
+                );
+            }
+            Ok(packets.interfaces().to_vec())
+        }
+    }
+}
+
+/// Bytes first read from each end of an input by [read_time_range], doubled until they hold a whole record
+const TIME_RANGE_PROBE_LEN: usize = 64 * 1024;
+
+/// Most bytes read from the end of an input by [read_time_range] in search of its last record, before decoding the
+/// whole input instead: enough for a chain of a couple of the longest plausible records
+const MAX_TIME_RANGE_TAIL_LEN: usize = 2 * pcap::MAX_PLAUSIBLE_RECORD_LEN;
+
+/// The [stats::TimeRange] of the packets of the file at `path` (local or `s3://`, optionally compressed), read as
+/// `format`, or [None] if it has none. Files are taken to be in timestamp order, as they are merged, so that the first
+/// and last timestamps of an uncompressed pcap are read from ranges at its start and end rather than decoding every
+/// packet (see [pcap::Packets::last_timestamp_in]). Other files, and those whose timestamps are corrected for
+/// wraparound or reordered, are decoded in full, ranging over every packet. Either way, the range is shifted by the
+/// `config`'s `offset_ns`.
+pub async fn read_time_range(
+    path: &str,
+    format: pcap::InputFormat,
+    config: &s3::DownloadConfig,
+) -> anyhow::Result<Option<stats::TimeRange>> {
+    let read_from_ends = format == pcap::InputFormat::Pcap
+        && Compression::from_path(path) == Compression::None
+        && !path.starts_with("iface:")
+        && !config.fix_wraparound
+        && config.reorder_window_ns.is_none();
+    if read_from_ends {
+        if let Some(range) = read_time_range_from_ends(path, config).await? {
+            return Ok(Some(stats::TimeRange {
+                first_ns: config::offset_timestamp(range.first_ns, config.offset_ns),
+                last_ns: config::offset_timestamp(range.last_ns, config.offset_ns),
+            }));
+        }
+    }
+    let (packets, decode_task) =
+        stream_and_decode_packets_as(path.to_string(), format, config.clone());
+    let range = packets
+        .fold(None, |range, (ts, _)| {
+            futures::future::ready(Some(stats::TimeRange::observe(range, ts)))
+        })
+        .await;
+    decode_task.await?;
+    Ok(range)
+}
+
+/// The time range of the uncompressed pcap at `path`, from its first record and the last found in a range at its end,
+/// or [None] if it has no packets or no last record was found within [MAX_TIME_RANGE_TAIL_LEN] bytes of its end (e.g.
+/// as the file ends part way through a record), for it to be decoded in full
+async fn read_time_range_from_ends(
+    path: &str,
+    config: &s3::DownloadConfig,
+) -> anyhow::Result<Option<stats::TimeRange>> {
+    let len = input_len(path, config).await?;
+    let mut head_len = TIME_RANGE_PROBE_LEN;
+    let (head, first_ts) = loop {
+        let head = read_input_range(path, config, 0, head_len).await?;
+        let mut packets = pcap::Packets::new(1024, &head[..])
+            .await
+            .with_context(|| format!("Failed to read the pcap header of '{}'", path))?;
+        let first_ts = match packets.next().await {
+            Some(Ok((ts, _))) => Some(ts),
+            Some(Err(error)) => {
+                return Err(anyhow::Error::new(error)
+                    .context(format!("Failed to read the first packet of '{}'", path)))
+            }
+            None => None,
+        };
+        if first_ts.is_some() || head_len as u64 >= len {
+            break (head, first_ts);
+        }
+        head_len *= 2;
+    };
+    let first_ts = match first_ts {
+        Some(first_ts) => first_ts,
+        None => return Ok(None),
+    };
+    let packets = pcap::Packets::new(1024, &head[..]).await?;
+    let records_len = len - pcap::PCAP_HDR_NSEC.len() as u64;
+    let mut tail_len = TIME_RANGE_PROBE_LEN;
+    while tail_len <= MAX_TIME_RANGE_TAIL_LEN {
+        let tail_len_read = (tail_len as u64).min(records_len);
+        let tail =
+            read_input_range(path, config, len - tail_len_read, tail_len_read as usize).await?;
+        let is_every_record = tail_len_read == records_len;
+        if let Some(last_ts) = packets.last_timestamp_in(&tail, is_every_record) {
+            return Ok(Some(stats::TimeRange {
+                first_ns: first_ts,
+                last_ns: last_ts,
+            }));
+        }
+        if is_every_record {
+            break;
+        }
+        tail_len *= 2;
+    }
+    tracing::event!(
+        tracing::Level::DEBUG,
+        path,
+        "Found no last record in the end of the file, so decoding it all"
+    );
+    Ok(None)
+}
+
+/// Size in bytes of the local or `s3://` file at `path`
+async fn input_len(path: &str, config: &s3::DownloadConfig) -> anyhow::Result<u64> {
+    if path.starts_with("s3://") {
+        Ok(s3::object_len(path, config).await? as u64)
+    } else {
+        Ok(std::fs::metadata(path)
+            .with_context(|| format!("Failed to open '{}'", path))?
+            .len())
+    }
+}
+
+/// Up to `len` bytes of the local or `s3://` file at `path`, from `start`
+async fn read_input_range(
+    path: &str,
+    config: &s3::DownloadConfig,
+    start: u64,
+    len: usize,
+) -> anyhow::Result<Vec<u8>> {
+    if path.starts_with("s3://") {
+        let end = start as usize + len.max(1) - 1;
+        return Ok(s3::object_range(path, config, start as usize, end)
+            .await?
+            .to_vec());
+    }
+    let path = path.to_string();
+    smol::unblock(move || {
+        use std::io::{Read, Seek};
+        let mut file =
+            std::fs::File::open(&path).with_context(|| format!("Failed to open '{}'", path))?;
+        file.seek(std::io::SeekFrom::Start(start))?;
+        let mut bytes = Vec::with_capacity(len);
+        file.take(len as u64).read_to_end(&mut bytes)?;
+        Ok(bytes)
+    })
+    .await
+}
+
+fn download_object_chunks_in_parallel(
+    object_chunks: std::pin::Pin<Box<s3::ObjectChunks>>,
+    config: &s3::DownloadConfig,
+) -> impl futures::AsyncBufRead + std::marker::Unpin {
+    let rate_limiter = config.rate_limiter.clone();
+    let chunk_size = config.chunk_size;
+    let object_chunks = object_chunks
+        .map(move |chunk| {
+            // don't issue the request until the rate limit allows for a whole chunk to be read
+            let acquired = rate_limiter
+                .as_ref()
+                .map(|rate_limiter| rate_limiter.acquire(chunk_size));
+            async move {
+                if let Some(acquired) = acquired {
+                    acquired.await;
+                }
+                chunk.await
+            }
+            .boxed()
+        })
+        .boxed();
     // TODO: confirm that s3 object downloads actually happen in parallel. If they're just concurrent, might need to add a spawn() somewhere?
-    let parallel_downloader = TakeThenBuffered::new(object_chunks, 1, 4);
+    let parallel_downloader = TakeThenBuffered::catch_panics(
+        object_chunks,
+        config.take_n_serially,
+        config.max_n_buffered,
+    )
+    .with_memory_budget(config.memory_budget.clone(), config.chunk_size)
+    .map(|chunk| {
+        // a panic while downloading a chunk becomes a read error for this file rather than aborting the whole merge
+        chunk.unwrap_or_else(|_panic| {
+            Err(std::io::Error::other(
+                "panicked while downloading S3 object chunk",
+            ))
+        })
+    });
     parallel_downloader.into_async_read()
 }
 
-#[tracing::instrument]
 pub fn stream_and_decode_pcap_packets(
     path: String,
 ) -> impl futures::stream::Stream<Item = (u64, Bytes)> {
+    stream_and_decode_pcap_packets_with(path, s3::DownloadConfig::default())
+}
+
+/// Like [stream_and_decode_pcap_packets], with explicit control over how `s3://` objects are downloaded
+pub fn stream_and_decode_pcap_packets_with(
+    path: String,
+    download_config: s3::DownloadConfig,
+) -> impl futures::stream::Stream<Item = (u64, Bytes)> {
+    let format = pcap::InputFormat::from_path(&path);
+    let (packets, decode_task) = stream_and_decode_packets_as(path, format, download_config);
+    decode_task.detach(); // any error which ends the file early is still logged
+    packets
+}
+
+/// Handle to the task which reads and decodes a file for [stream_and_decode_packets_as], resolving once the file has
+/// been decoded to the end, or to the error which ended its packet stream early. Dropping it cancels the task, so
+/// [detach](DecodeTask::detach) it to read the file without waiting on the outcome.
+#[must_use = "dropping a DecodeTask stops decoding its file"]
+pub struct DecodeTask {
+    task: smol::Task<anyhow::Result<()>>,
+    header_fields: async_channel::Receiver<pcap::HeaderFields>,
+    abandon_warning: util::DisarmHandle,
+}
+
+impl DecodeTask {
+    /// Let the file be decoded in the background, discarding its outcome
+    pub fn detach(self) {
+        self.task.detach()
+    }
+
+    /// Expect the merge to stop reading the file's packet stream before its end, as it does on reaching the end of its
+    /// time window or deadline, so that dropping the stream isn't logged as a `WARN` of incomplete output
+    pub fn expect_early_stop(&self) {
+        self.abandon_warning.disarm()
+    }
+
+    /// The legacy header fields of a pcap file, once its header has been read, as it has by the time its first packet
+    /// (or the end of its stream) is. [None] until then, for other formats, and once taken.
+    pub fn header_fields(&self) -> Option<pcap::HeaderFields> {
+        self.header_fields.try_recv().ok()
+    }
+}
+
+impl std::future::Future for DecodeTask {
+    type Output = anyhow::Result<()>;
+
+    fn poll(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Self::Output> {
+        std::pin::Pin::new(&mut self.task).poll(cx)
+    }
+}
+
+/// Packets decoded from an input by [decode_packets], ending at the first error
+type DecodedPackets = Box<
+    dyn futures::stream::Stream<Item = Result<(u64, Bytes), pcap::ParseError>>
+        + std::marker::Unpin
+        + Send,
+>;
+
+/// Decode the decompressed bytes of the input at `path`, read from `reader`, as `format`, applying every decoding option
+/// of `config`: [s3::DownloadConfig::strict], [s3::DownloadConfig::fix_wraparound] and the rest. Every
+/// `stream_and_decode_*` function decodes its input with this, so they all decode alike. Alongside the packets are the
+/// legacy header fields of a pcap, which fails if its header can't be read.
+async fn decode_packets<R: AsyncRead + std::marker::Unpin + Send + 'static>(
+    path: &str,
+    format: pcap::InputFormat,
+    config: &s3::DownloadConfig,
+    reader: R,
+) -> Result<(DecodedPackets, Option<pcap::HeaderFields>), pcap::ParseError> {
+    /* TODO: create a "tracing" span tree associated with this file. would be cool to see this tree build all the way up to the merge function in the single-threaded case */
+    let mut header_fields = None;
+    let packets: DecodedPackets = match format {
+        pcap::InputFormat::Pcap => {
+            let packets = crate::pcap::Packets::new(1024 * 64, reader)
+                .await
+                .map_err(|kind| pcap::ParseError {
+                    path: path.to_string(),
+                    offset: 0,
+                    kind,
+                })?
+                .strict(config.strict)
+                .fix_wraparound(config.fix_wraparound);
+            let packets = match config.offset_ns {
+                0 => packets,
+                offset_ns => packets.with_processor(pcap::offset_processor(offset_ns)),
+            };
+            tracing::event!(
+                Level::INFO,
+                path,
+                precision = %packets.precision(),
+                "Detected timestamp precision"
+            );
+            header_fields = Some(packets.header_fields());
+            // only the files of the link type it reframes are transcoded, so it can apply to every input
+            let packets = match config.transcode {
+                Some(transcode) if transcode.from_link_type() == packets.link_type() => {
+                    packets.with_transform(move |packet| transcode.transcode(packet))
+                }
+                _ => packets,
+            };
+            match config.key_expr {
+                Some(key_expr) => Box::new(
+                    packets
+                        .with_key_fn(move |packet| key_expr.key(packet))
+                        .located(path),
+                ),
+                None => Box::new(packets.located(path)),
+            }
+        }
+        pcap::InputFormat::Raw => {
+            let packets = crate::pcap::RawFramed::new(1024 * 64, reader).strict(config.strict);
+            Box::new(
+                match config.offset_ns {
+                    0 => packets,
+                    offset_ns => packets.with_processor(pcap::offset_processor(offset_ns)),
+                }
+                .located(path),
+            )
+        }
+        pcap::InputFormat::Pcapng => {
+            let packets = crate::pcap::pcapng::PcapngPackets::new(1024 * 64, reader)
+                .strict(config.strict)
+                .only_interface(config.pcapng_interface);
+            let packets = match config.offset_ns {
+                0 => packets,
+                offset_ns => packets.with_processor(pcap::offset_processor(offset_ns)),
+            };
+            match config.packet_meta {
+                true => Box::new(
+                    packets
+                        .with_meta()
+                        .located(path)
+                        .map_ok(|(ts, record, meta)| (ts, meta.append_to(&record))),
+                ),
+                false => Box::new(packets.located(path)),
+            }
+        }
+    };
+    // the records of every input carry metadata alike, that of other formats' packets being the default
+    let packets =
+        match config.packet_meta && format != pcap::InputFormat::Pcapng {
+            true => Box::new(packets.map_ok(|(ts, record)| {
+                (ts, pcap::pcapng::PacketMeta::default().append_to(&record))
+            })),
+            false => packets,
+        };
+    let packets = match config.reorder_window_ns {
+        Some(window_ns) => Box::new(pcap::Reorder::new(packets, window_ns)),
+        None => packets,
+    };
+    Ok((packets, header_fields))
+}
+
+/// Like [stream_and_decode_pcap_packets_with], parsing the file as `format` regardless of its extension, and returning
+/// the [DecodeTask] producing the stream's packets alongside it
+#[tracing::instrument(skip(download_config))]
+pub fn stream_and_decode_packets_as(
+    path: String,
+    format: pcap::InputFormat,
+    download_config: s3::DownloadConfig,
+) -> (
+    impl futures::stream::Stream<Item = (u64, Bytes)>,
+    DecodeTask,
+) {
     // Load the file with the provided path from S3 or the local file system based on the presence or absence of s3:// at the beginning
-    // of the file name. Wrap the file loader (which implements AsyncRead) in a ZstdDecoder or GzipDecoder if path ends with .zst or .gz.
-    // Continually batch all available packets into a vector using ready_chunks(), then forward them to the receiver in the 1-deep async
+    // of the file name. Wrap the file loader (which implements AsyncRead) in the decoder for the compression format given by its extension.
+    // Continually batch all available packets into a vector using util::ReadyBatches, then forward them to the receiver in the 1-deep async
     // channel created below. NOTE: the purpose of the 1-deep channel is to allow for parallelism and cross-thread communication between
     // the thread/task which decompresses the file and parses out a stream of packets with the thread/task responsible for merging the packets
     // together. Because there is only one merging thread, it is critical for throughput that our design allows for parallel merging w/r/t file decompression.
     // NOTE: by using a one-deep channel holding all ready chunks associated w/ the stream, we guarantee that no further downloading,
     // decompression, or file reading will occur until the first packet has been processed for the file and the next has been requested.
-    let (sender, receiver) = bounded(1);
+    // with a spill, decoding runs on ahead of the merge instead, batches which don't fit in memory waiting on disk
+    let (sender, receiver) = match &download_config.spill {
+        Some(_) => async_channel::unbounded(),
+        None => bounded(1),
+    };
+    let (spill_writer, mut spill_reader) = match &download_config.spill {
+        Some(spill) => {
+            let (writer, reader) = spill.input();
+            (Some(writer), reader)
+        }
+        None => (None, spill::SpillReader::default()),
+    };
+    let (header_fields_sender, header_fields) = bounded(1);
+    let stream_path = path.clone();
+    let decode_pool = download_config.decode_pool.clone();
 
-    smol::spawn(async move {
-        async fn decode_pcap_packets_to_channel<T: AsyncRead + std::marker::Unpin>(
+    let decode = async move {
+        async fn decode_pcap_packets_to_channel<
+            T: AsyncRead + std::marker::Unpin + Send + 'static,
+        >(
+            path: &str,
+            format: pcap::InputFormat,
+            config: &s3::DownloadConfig,
             reader: T,
-            channel: async_channel::Sender<Vec<(u64, Bytes)>>,
-        ) {
-            /* TODO: create a "tracing" span tree associated with this file. would be cool to see this tree build all the way up to the merge function in the single-threaded case */
-            // TODO: is this better than stream.forward()?
-            let mut packet_stream = crate::pcap::Packets::new(1024 * 64, reader)
-                .await
-                .unwrap() /* TODO: nice error indicating what the issue is and bail */
-                .map(std::result::Result::unwrap)
-                .ready_chunks(2048); // batch as many packets as are available (up to 2048) into a single vector.. Maybe use a 1 or 2 deep channel somehow? while next_packet = ready!(packet_stream.next()) { packets.push(next_packet) }; send(ready_packets).. maybe could collect_ready()
-                // TODO: validate the choice of 2048 here or choose a number which makes more sense? is a smaller number like 1024 any better?? trade off between parallelism and memory usage
+            lazy_file: Option<util::LazyFileCloser>,
+            header_fields: async_channel::Sender<pcap::HeaderFields>,
+            sender: BatchSender,
+        ) -> anyhow::Result<()> {
+            let (packets, fields) =
+                decode_packets(path, format, config, reader)
+                    .await
+                    .map_err(|error| {
+                        tracing::event!(Level::ERROR, path, ?error, "Skipping file");
+                        anyhow::Error::new(error)
+                            .context(format!("Failed to read the pcap header of '{}'", path))
+                    })?;
+            if let Some(fields) = fields {
+                // sent ahead of any packet, so it has been by the time the merge receives one
+                let _ = header_fields.try_send(fields);
+            }
+            header_fields.close();
+            forward_packets_to_channel(
+                path,
+                packets,
+                lazy_file,
+                config.memory_budget.clone(),
+                sender,
+            )
+            .await
+        }
+
+        /// Sends batches of decoded packets to the merge, through the input's spill writer when spilling
+        struct BatchSender {
+            spill: Option<spill::SpillWriter>,
+            channel: async_channel::Sender<spill::Batch>,
+        }
+
+        #[rustfmt::skip]
+        async fn forward_packets_to_channel<E: std::error::Error + Send + Sync + 'static>(
+            path: &str,
+            packets: impl futures::stream::Stream<Item = Result<(u64, Bytes), E>> + std::marker::Unpin,
+            lazy_file: Option<util::LazyFileCloser>,
+            memory_budget: Option<MemoryBudget>,
+            sender: BatchSender,
+        ) -> anyhow::Result<()> {
+            let BatchSender { mut spill, channel } = sender;
+            let mut skipped_by = None;
+            let packets = packets.scan((), |_, packet| {
+                // end this file's stream on the first read or parse error so the rest of the merge can proceed without it
+                futures::future::ready(match packet {
+                    Ok(packet) => Some(packet),
+                    Err(error) => {
+                        tracing::event!(Level::ERROR, path, ?error, "Skipping remainder of file");
+                        skipped_by = Some(error);
+                        None
+                    }
+                })
+            });
+            // batch as many packets as are available into a single vector, fewer while memory is scarce
+            let mut packet_stream =
+                util::ReadyBatches::new(packets, util::BatchSize::new(memory_budget));
             while let Some(packets) = packet_stream.next().instrument(tracing::trace_span!("NextPacket")).await {
                 tracing::event!(Level::TRACE, ts = packets[0].0);
-                channel.send(packets).await.unwrap();
+                let packets = match spill.take() {
+                    Some(mut writer) => {
+                        let (writer, batch) = smol::unblock(move || {
+                            let batch = writer.stash(packets);
+                            (writer, batch)
+                        })
+                        .await;
+                        spill = Some(writer);
+                        batch.with_context(|| format!("Failed to spill packets of '{}'", path))?
+                    }
+                    None => spill::Batch::from(packets),
+                };
+                let sent = match &lazy_file {
+                    Some(lazy_file) => match channel.try_send(packets) {
+                        Err(async_channel::TrySendError::Full(packets)) => {
+                            // let another file use this one's descriptor until the merge catches up with it
+                            lazy_file.close().await;
+                            channel.send(packets).await.is_ok()
+                        }
+                        sent => sent.is_ok(),
+                    },
+                    None => channel.send(packets).await.is_ok(),
+                };
+                if !sent {
+                    return Ok(()); // the receiving stream was dropped, so nobody is waiting on the rest of this file
+                }
             }
             channel.close();
+            drop(packet_stream);
+            match skipped_by {
+                Some(error) => Err(anyhow::Error::new(error)
+                    .context(format!("Skipped the remainder of '{}'", path))),
+                None => Ok(()),
+            }
         }
 
-        // TODO: ask the rust user's forum for ideas about how to remove redundancy and simplify this code
-        //       perhaps implement a .decompressed() function  on an enum type to return a decompressed stream?
-        if path.starts_with("s3://") {
-            let s3_object_stream = download_s3_object_chunks_in_parallel(&path);
-            if path.ends_with(".zst") {
-                // TODO: consider implementing some sort of from() function for the enum to unify this code?
-                decode_pcap_packets_to_channel(ZstdDecoder::new(s3_object_stream), sender).await
-            } else if path.ends_with(".gz") {
-                decode_pcap_packets_to_channel(GzipDecoder::new(s3_object_stream), sender).await
-            } else /* if path.ends_with(".pcap") */ {
-                // uncompressed
-                decode_pcap_packets_to_channel(s3_object_stream, sender).await
-            }
-        } else { // local file loader. TODO: consider switching to use io_uring w/ Tokio for this?
-            // TODO: proper error handling if file doesn't exist
-            let file = std::fs::OpenOptions::new().read(true).open(&path).unwrap();
-            let loader = smol::io::BufReader::with_capacity( 1024 * 128, smol::Unblock::with_capacity(1024 * 128, file));
-            if path.ends_with(".zst") {
-                decode_pcap_packets_to_channel(ZstdDecoder::new(loader), sender).await;
-            } else if path.ends_with(".gz") {
-                decode_pcap_packets_to_channel(GzipDecoder::new(loader), sender).await;
-            } else /* if path.ends_with(".pcap") */ {
-                // uncompressed
-                decode_pcap_packets_to_channel(loader, sender).await;
+        let sender = BatchSender {
+            spill: spill_writer,
+            channel: sender,
+        };
+        if let Some(interface) = path.strip_prefix("iface:") {
+            drop(header_fields_sender);
+            #[cfg(all(feature = "live-capture", target_os = "linux"))]
+            {
+                let packets = live::capture(interface)
+                    .map_err(|error| {
+                        tracing::event!(
+                            Level::ERROR,
+                            path = path.as_str(),
+                            ?error,
+                            "Skipping file"
+                        );
+                        error
+                    })
+                    .with_context(|| format!("Failed to capture from '{}'", interface))?;
+                let memory_budget = download_config.memory_budget.clone();
+                forward_packets_to_channel(&path, packets, None, memory_budget, sender).await
+            }
+            #[cfg(not(all(feature = "live-capture", target_os = "linux")))]
+            {
+                tracing::event!(Level::ERROR, path = path.as_str(), "Skipping file");
+                anyhow::bail!(
+                    "Capturing from '{}' requires the live-capture feature on Linux",
+                    interface
+                )
             }
+        } else {
+            let (reader, lazy_file) = open_input(&path, &download_config).map_err(|error| {
+                tracing::event!(Level::ERROR, path = path.as_str(), ?error, "Skipping file");
+                error
+            })?;
+            decode_pcap_packets_to_channel(
+                &path,
+                format,
+                &download_config,
+                reader,
+                lazy_file,
+                header_fields_sender,
+                sender,
+            )
+            .await
         }
+    };
+    let task = match decode_pool {
+        Some(pool) => pool.spawn(decode),
+        None => smol::spawn(decode),
+    };
+
+    // hide the vector-batching we used to minimize atomic operations w/ inter-thread communication, and log a warning if
+    // the merge abandons this file before reaching its end
+    let read_path = stream_path.clone();
+    let packets = receiver
+        .scan((), move |_, batch| {
+            // a spill file which can't be read back ends this file's stream, like any other read error
+            futures::future::ready(match spill_reader.read(batch) {
+                Ok(packets) => Some(packets),
+                Err(error) => {
+                    tracing::event!(
+                        Level::ERROR,
+                        path = read_path.as_str(),
+                        ?error,
+                        "Skipping remainder of file, whose spilled packets couldn't be read back"
+                    );
+                    None
+                }
+            })
+        })
+        .map(futures::stream::iter)
+        .flatten();
+    let packets = WarnIfAbandoned::new(packets, stream_path);
+    let decode_task = DecodeTask {
+        task,
+        header_fields,
+        abandon_warning: packets.disarm_handle(),
+    };
+    (packets, decode_task)
+}
+
+/// Decode pcap bytes read from `reader` and compressed as `compression` as a stream of `(timestamp, record)` tuples, so
+/// that byte sources other than local files and S3 objects (e.g. a network transport) can be merged. Parsed on the
+/// polling task itself, like [pcap::stream_file], which delegates to this. Failing to read the pcap header or parse a
+/// packet is yielded as an error item, ending the stream.
+pub fn stream_and_decode_reader<R>(
+    reader: R,
+    compression: Compression,
+) -> impl futures::stream::Stream<Item = anyhow::Result<(u64, Bytes)>>
+where
+    R: AsyncRead + std::marker::Unpin + Send + 'static,
+{
+    stream_and_decode_reader_with(reader, compression, s3::DownloadConfig::default())
+}
+
+/// Like [stream_and_decode_reader], decoding the pcap with the options of `download_config` (e.g.
+/// [s3::DownloadConfig::strict] and [s3::DownloadConfig::fix_wraparound]) as [stream_and_decode_pcap_packets_with] does
+pub fn stream_and_decode_reader_with<R>(
+    reader: R,
+    compression: Compression,
+    download_config: s3::DownloadConfig,
+) -> impl futures::stream::Stream<Item = anyhow::Result<(u64, Bytes)>>
+where
+    R: AsyncRead + std::marker::Unpin + Send + 'static,
+{
+    futures::stream::once(async move {
+        let reader = compression.decoder(futures::io::BufReader::with_capacity(1024 * 128, reader));
+        let (packets, _) = decode_packets("-", pcap::InputFormat::Pcap, &download_config, reader)
+            .await
+            .map_err(|e| anyhow::Error::new(e).context("Invalid pcap header"))?;
+        Ok::<_, anyhow::Error>(
+            packets.map_err(|e| anyhow::Error::new(e).context("Failed to parse packet")),
+        )
     })
-    .detach();
+    .try_flatten()
+}
+
+/// Merge the pcaps at `paths` (local or `s3://`, optionally .gz or .zst compressed), calling `on_packet` with the
+/// timestamp and captured bytes (excluding the record header) of each packet in time order. Lighter weight than
+/// consuming a merged stream for simple side-effecting consumers such as counters or samplers. See [PcapMerge] for
+/// more callbacks.
+pub fn merge_pcap_streams_with<F: FnMut(u64, &[u8])>(paths: Vec<String>, on_packet: F) {
+    PcapMerge::new(paths).run(on_packet)
+}
+
+/// Callback given the path of each input as it's drained
+type OnFileEof<'a> = Box<dyn FnMut(&str) + 'a>;
+
+/// First packets of the inputs of a [PcapMerge::max_active_inputs] merge read at once
+const MAX_FIRST_TIMESTAMP_PROBES_IN_FLIGHT: usize = 64;
+
+/// Bytes of each `s3://` input requested at a time while reading its first packet, one request at a time, so that
+/// probing an input costs a small range read rather than the read-ahead of a merge
+const FIRST_TIMESTAMP_PROBE_CHUNK_SIZE: usize = 1024 * 16;
+
+/// Builder for a merge like [merge_pcap_streams_with], with callbacks for events other than each packet
+pub struct PcapMerge<'a> {
+    paths: Vec<String>,
+    on_file_eof: Option<OnFileEof<'a>>,
+    max_active_inputs: Option<usize>,
+}
+
+impl<'a> PcapMerge<'a> {
+    /// Merge the pcaps at `paths` (local or `s3://`, optionally .gz or .zst compressed)
+    pub fn new(paths: Vec<String>) -> PcapMerge<'a> {
+        PcapMerge {
+            paths,
+            on_file_eof: None,
+            max_active_inputs: None,
+        }
+    }
+
+    /// Open only about `max_active_inputs` of the inputs at a time, in order of their first packets, so that the
+    /// resources held open by the merge don't grow with the number of inputs. The first packet of every input is read
+    /// up front to order them, and an input whose first packet can't be read is skipped, its error logged. See
+    /// [tournament_tree::ActiveSetMerge]
+    pub fn max_active_inputs(mut self, max_active_inputs: usize) -> PcapMerge<'a> {
+        self.max_active_inputs = Some(max_active_inputs);
+        self
+    }
+
+    /// Call `on_file_eof` with the path of each input once the merge has taken its last packet, e.g. to delete the
+    /// input or fetch the next. Called once per input, in the order the inputs are drained.
+    pub fn on_file_eof(mut self, on_file_eof: impl FnMut(&str) + 'a) -> PcapMerge<'a> {
+        self.on_file_eof = Some(Box::new(on_file_eof));
+        self
+    }
+
+    /// Run the merge, calling `on_packet` with the timestamp and captured bytes (excluding the record header) of each
+    /// packet in time order
+    pub fn run<F: FnMut(u64, &[u8])>(self, mut on_packet: F) {
+        let on_file_eof = std::rc::Rc::new(std::cell::RefCell::new(self.on_file_eof));
+        let open = |path: String| {
+            let packets = tournament_tree::PacketStream::new(smol::stream::block_on(
+                stream_and_decode_pcap_packets(path.clone()),
+            ));
+            let on_file_eof = on_file_eof.clone();
+            tournament_tree::OnExhausted::new(packets, move || {
+                if let Some(on_file_eof) = &mut *on_file_eof.borrow_mut() {
+                    on_file_eof(&path);
+                }
+            })
+        };
+        match self.max_active_inputs {
+            None => {
+                let packet_streams = self.paths.into_iter().map(open).collect();
+                let mut merger = tournament_tree::OwnedTree::new(packet_streams);
+                while let Some((ts, record)) = merger.pop() {
+                    on_packet(ts, &record[pcap::RECORD_HEADER_LEN..]);
+                }
+            }
+            Some(max_active_inputs) => {
+                let mut inputs = Vec::with_capacity(self.paths.len());
+                for (path, first_ts) in smol::block_on(first_timestamps(self.paths)) {
+                    match first_ts {
+                        Ok(first_ts) => inputs.push((first_ts, path)),
+                        Err(error) => {
+                            tracing::event!(
+                                Level::ERROR,
+                                path = path.as_str(),
+                                ?error,
+                                "Skipping file"
+                            );
+                            if let Some(on_file_eof) = &mut *on_file_eof.borrow_mut() {
+                                on_file_eof(&path);
+                            }
+                        }
+                    }
+                }
+                let mut merger =
+                    tournament_tree::ActiveSetMerge::new(inputs, max_active_inputs, open);
+                while let Some((ts, record)) = merger.pop() {
+                    on_packet(ts, &record[pcap::RECORD_HEADER_LEN..]);
+                }
+            }
+        }
+    }
+}
+
+/// Each of `paths` along with the timestamp of its first packet, 0 if it has none, or the error which prevented it being
+/// read. Little more of each input than its first packet is read, and the input is closed again once it has been.
+async fn first_timestamps(paths: Vec<String>) -> Vec<(String, anyhow::Result<u64>)> {
+    let config = s3::DownloadConfig {
+        chunk_size: FIRST_TIMESTAMP_PROBE_CHUNK_SIZE,
+        take_n_serially: 1,
+        max_n_buffered: 1,
+        ..Default::default()
+    };
+    futures::stream::iter(paths)
+        .map(|path| {
+            let config = &config;
+            async move {
+                let first_ts = read_first_timestamp(&path, config).await;
+                (path, first_ts.map(|first_ts| first_ts.unwrap_or(0)))
+            }
+        })
+        .buffered(MAX_FIRST_TIMESTAMP_PROBES_IN_FLIGHT)
+        .collect()
+        .await
+}
+
+/// Merge the pcaps at `paths` (local or `s3://`, optionally .gz or .zst compressed) into a nanosecond-precision pcap
+/// written to `writer`, which is returned once flushed. Blocks until the merge is done, failing if any input couldn't be
+/// read to its end.
+pub fn merge_pcaps_to_writer<W: std::io::Write>(
+    paths: Vec<String>,
+    writer: W,
+) -> anyhow::Result<W> {
+    let mut writer = pcap::Writer::new(writer, pcap::OutputFormat::Pcap)?;
+    let (packet_streams, decode_tasks): (Vec<_>, Vec<_>) = paths
+        .into_iter()
+        .map(|path| {
+            let format = pcap::InputFormat::from_path(&path);
+            let (packets, decode_task) =
+                stream_and_decode_packets_as(path, format, s3::DownloadConfig::default());
+            (
+                tournament_tree::PacketStream::new(smol::stream::block_on(packets)),
+                decode_task,
+            )
+        })
+        .unzip();
+    let mut merger = tournament_tree::OwnedTree::new(packet_streams);
+    while let Some((ts, record)) = merger.pop() {
+        writer.write_packet(ts, &record)?;
+    }
+    for decode_task in decode_tasks {
+        smol::block_on(decode_task)?;
+    }
+    writer.flush()?;
+    Ok(writer.into_inner())
+}
+
+/// What to do with a packet whose timestamp is before that of the packet preceding it in a supposedly time-ordered
+/// stream, such as one merged from an input which isn't sorted
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OnBackwards {
+    /// restamp the packet with the previous packet's timestamp
+    Clamp,
+    /// fail, ending the stream
+    Error,
+}
+
+/// Enforces that the timestamps of a sequence of packets never decrease. See [enforce_monotonic].
+#[derive(Debug)]
+pub struct MonotonicTimestamps {
+    on_backwards: OnBackwards,
+    previous_ts: Option<u64>,
+}
+
+impl MonotonicTimestamps {
+    pub fn new(on_backwards: OnBackwards) -> MonotonicTimestamps {
+        MonotonicTimestamps {
+            on_backwards,
+            previous_ts: None,
+        }
+    }
+
+    /// The timestamp to use for the next packet, whose own timestamp is `ts`
+    pub fn check(&mut self, ts: u64) -> anyhow::Result<u64> {
+        let ts = match self.previous_ts {
+            Some(previous_ts) if ts < previous_ts => match self.on_backwards {
+                OnBackwards::Clamp => previous_ts,
+                OnBackwards::Error => anyhow::bail!(
+                    "Packet timestamp {} ns is before the previous packet's {} ns, so the merged output would not be \
+                     time-ordered. Is an input not sorted?",
+                    ts,
+                    previous_ts
+                ),
+            },
+            _ => ts,
+        };
+        self.previous_ts = Some(ts);
+        Ok(ts)
+    }
+}
+
+/// Wrap a merged stream of `(timestamp, record)` tuples so that its timestamps never decrease, whether or not its inputs
+/// were correctly sorted: a packet which would go backwards in time is handled as `on_backwards` directs. After an
+/// error the stream ends.
+pub fn enforce_monotonic<St: futures::stream::Stream<Item = (u64, Bytes)>>(
+    stream: St,
+    on_backwards: OnBackwards,
+) -> impl futures::stream::Stream<Item = anyhow::Result<(u64, Bytes)>> {
+    stream.scan(
+        Some(MonotonicTimestamps::new(on_backwards)),
+        |timestamps, (ts, record)| {
+            let item = timestamps
+                .as_mut()
+                .map(|timestamps| timestamps.check(ts).map(|ts| (ts, record)));
+            if let Some(Err(_)) = item {
+                *timestamps = None; // end the stream after yielding the error
+            }
+            futures::future::ready(item)
+        },
+    )
+}
+
+/// Split the local (and optionally .gz or .zst compressed) pcap at `path` into one nanosecond-precision pcap per entry
+/// of `outputs`, routing each packet to the output whose index is returned by `route` for the packet's captured bytes.
+/// See [pcap::demux].
+pub fn demux_pcaps<F: FnMut(&[u8]) -> usize>(
+    path: &str,
+    outputs: &[PathBuf],
+    route: F,
+) -> anyhow::Result<()> {
+    let file = std::fs::File::open(path).with_context(|| format!("Failed to open '{}'", path))?;
+    let loader = smol::io::BufReader::with_capacity(
+        1024 * 128,
+        smol::Unblock::with_capacity(1024 * 128, file),
+    );
+    let mut writers = outputs
+        .iter()
+        .map(|output| {
+            let file = std::fs::File::create(output)
+                .with_context(|| format!("Failed to create '{}'", output.display()))?;
+            Ok(pcap::Writer::new(
+                std::io::BufWriter::new(file),
+                pcap::OutputFormat::Pcap,
+            )?)
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    smol::block_on(pcap::demux(
+        Compression::from_path(path).decoder(loader),
+        &mut writers,
+        route,
+    ))
+    .with_context(|| format!("Failed to demux '{}'", path))?;
+
+    for writer in &mut writers {
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::io::AsyncReadExt;
+
+    fn non_monotonic_stream() -> impl futures::stream::Stream<Item = (u64, Bytes)> {
+        futures::stream::iter(
+            [10, 20, 15, 30, 25, 40]
+                .iter()
+                .map(|ts| (*ts, Bytes::from(vec![*ts as u8]))),
+        )
+    }
+
+    #[test]
+    fn test_enforce_monotonic_clamps_backwards_timestamps() {
+        let clamped: Vec<_> = smol::block_on(
+            enforce_monotonic(non_monotonic_stream(), OnBackwards::Clamp)
+                .map(|packet| {
+                    let (ts, record) = packet.unwrap();
+                    (ts, record[0])
+                })
+                .collect(),
+        );
+        assert_eq!(
+            clamped,
+            vec![(10, 10), (20, 20), (20, 15), (30, 30), (30, 25), (40, 40)]
+        );
+    }
+
+    #[test]
+    fn test_enforce_monotonic_errors_on_backwards_timestamp() {
+        let checked: Vec<_> =
+            smol::block_on(enforce_monotonic(non_monotonic_stream(), OnBackwards::Error).collect());
+        assert_eq!(checked.len(), 3); // the stream ends at the error
+        assert_eq!(checked[0].as_ref().unwrap().0, 10);
+        assert_eq!(checked[1].as_ref().unwrap().0, 20);
+        let error = checked[2].as_ref().unwrap_err().to_string();
+        assert!(
+            error.contains("15 ns is before the previous packet's 20 ns"),
+            "{}",
+            error
+        );
+    }
+
+    /// Merge the local pcaps at `paths`, each decoded with `config`
+    fn merge_decoded_with(paths: &[String], config: &s3::DownloadConfig) -> Vec<(u64, Bytes)> {
+        let packet_streams = paths
+            .iter()
+            .map(|path| {
+                let (packets, decode_task) = stream_and_decode_packets_as(
+                    path.clone(),
+                    pcap::InputFormat::Pcap,
+                    config.clone(),
+                );
+                decode_task.detach();
+                tournament_tree::PacketStream::new(smol::stream::block_on(packets))
+            })
+            .collect();
+        let mut merger = tournament_tree::OwnedTree::new(packet_streams);
+        std::iter::from_fn(|| merger.pop()).collect()
+    }
+
+    #[test]
+    fn test_spilling_decoded_packets_leaves_the_merge_unchanged_and_memory_bounded() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths: Vec<String> = (0..4u64)
+            .map(|input| {
+                let mut bytes = pcap::PCAP_HDR_NSEC.to_vec();
+                for i in 0..5000u64 {
+                    let payload = vec![input as u8; 1 + (i % 50) as usize];
+                    let ts = i * 4 + input;
+                    for field in [0, ts as u32, payload.len() as u32, payload.len() as u32].iter() {
+                        bytes.extend_from_slice(&field.to_le_bytes());
+                    }
+                    bytes.extend_from_slice(&payload);
+                }
+                let path = dir.path().join(format!("{}.pcap", input));
+                std::fs::write(&path, bytes).unwrap();
+                path.to_str().unwrap().to_string()
+            })
+            .collect();
+        let unspilled = merge_decoded_with(&paths, &s3::DownloadConfig::default());
+        assert_eq!(unspilled.len(), 4 * 5000);
+
+        for in_memory_limit_n_bytes in [0, 64 * 1024].iter() {
+            let spill_dir = tempfile::tempdir().unwrap();
+            let spill = spill::Spill::new(spill_dir.path().to_path_buf(), *in_memory_limit_n_bytes);
+            let config = s3::DownloadConfig {
+                spill: Some(spill.clone()),
+                ..Default::default()
+            };
+            assert!(merge_decoded_with(&paths, &config) == unspilled);
+            assert!(spill.max_n_bytes_in_memory() <= *in_memory_limit_n_bytes);
+            if *in_memory_limit_n_bytes == 0 {
+                assert!(spill.n_batches_spilled() > 0);
+            }
+        }
+    }
+
+    /// [s3::ObjectStore] serving `object`, recording the most chunk downloads ever in flight at once
+    struct ConcurrencyTrackingStore {
+        object: Bytes,
+        n_in_flight: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+        max_n_in_flight: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    impl s3::ObjectStore for ConcurrencyTrackingStore {
+        fn content_length(
+            &self,
+            _bucket: &str,
+            _key: &str,
+        ) -> futures::future::BoxFuture<'static, std::io::Result<usize>> {
+            futures::future::ready(Ok(self.object.len())).boxed()
+        }
+
+        fn get_range(
+            &self,
+            _bucket: &str,
+            _key: &str,
+            start: usize,
+            end: usize,
+        ) -> futures::future::BoxFuture<'static, std::io::Result<Bytes>> {
+            use std::sync::atomic::Ordering;
+            let n_in_flight = self.n_in_flight.clone();
+            let n = n_in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_n_in_flight.fetch_max(n, Ordering::SeqCst);
+            let chunk = self.object.slice(start..(end + 1).min(self.object.len()));
+            async move {
+                smol::future::yield_now().await; // let the rest of the read-ahead be requested first
+                n_in_flight.fetch_sub(1, Ordering::SeqCst);
+                Ok(chunk)
+            }
+            .boxed()
+        }
+
+        fn list_keys(
+            &self,
+            _bucket: &str,
+            _prefix: &str,
+        ) -> futures::future::BoxFuture<'static, std::io::Result<Vec<String>>> {
+            futures::future::ready(Ok(Vec::new())).boxed()
+        }
+    }
+
+    #[test]
+    fn test_per_input_override_sets_buffering_depth() {
+        let base = s3::DownloadConfig {
+            chunk_size: 100,
+            ..Default::default()
+        };
+        let big = config::InputConfig {
+            max_n_buffered: Some(8),
+            ..config::InputConfig::new("s3://bucket/big.pcap.zst".to_string())
+        };
+        let small = config::InputConfig::new("s3://bucket/small.pcap.zst".to_string());
+
+        let max_n_in_flight = |input: &config::InputConfig| {
+            let store = std::sync::Arc::new(ConcurrencyTrackingStore {
+                object: vec![3u8; 10_000].into(),
+                n_in_flight: Default::default(),
+                max_n_in_flight: Default::default(),
+            });
+            let config = input.download_config(&base);
+            let mut reader = download_object_chunks_in_parallel(
+                s3::ObjectChunks::with_store(&input.path, config.chunk_size, store.clone())
+                    .unwrap(),
+                &config,
+            );
+            let mut read = Vec::new();
+            smol::block_on(reader.read_to_end(&mut read)).unwrap();
+            assert_eq!(read.len(), 10_000);
+            store
+                .max_n_in_flight
+                .load(std::sync::atomic::Ordering::SeqCst)
+        };
+        assert_eq!(max_n_in_flight(&big), 8);
+        assert_eq!(max_n_in_flight(&small), base.max_n_buffered);
+    }
+
+    #[test]
+    fn test_rate_limited_download_stays_under_the_cap() {
+        const BYTES_PER_SECOND: u64 = 10_000;
+        let object: Bytes = vec![7u8; 100_000].into();
+        let clock = std::sync::Arc::new(util::SimulatedClock::default());
+        let config = s3::DownloadConfig {
+            chunk_size: 1000,
+            max_n_buffered: 8,
+            rate_limiter: Some(RateLimiter::with_clock(BYTES_PER_SECOND, clock.clone())),
+            ..Default::default()
+        };
+
+        let store = std::sync::Arc::new(s3::InMemoryStore(object.clone()));
+        let mut reader = download_object_chunks_in_parallel(
+            s3::ObjectChunks::with_store("s3://bucket/key.pcap", config.chunk_size, store).unwrap(),
+            &config,
+        );
+        let mut read = Vec::new();
+        smol::block_on(reader.read_to_end(&mut read)).unwrap();
+        assert!(read == object);
 
-    receiver.map(futures::stream::iter).flatten() // hide the vector-batching we used to minimize atomic operations w/ inter-thread communication
+        // beyond the initial one-second burst, bytes are read no faster than the cap
+        let elapsed = clock.now().as_secs_f64();
+        let rate = (read.len() as u64 - BYTES_PER_SECOND) as f64 / elapsed;
+        assert!(rate <= BYTES_PER_SECOND as f64, "{} bytes/s", rate);
+        assert!(rate > BYTES_PER_SECOND as f64 * 0.9, "{} bytes/s", rate);
+    }
 }
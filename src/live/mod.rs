@@ -0,0 +1,183 @@
+//! Live packet capture from a network interface
+//!
+//! An `iface:<name>` input (e.g. `iface:eth0`) captures every packet seen on the interface from a Linux `AF_PACKET`
+//! socket, so that live traffic can be merged with recorded pcaps. Capturing usually requires root or the
+//! `CAP_NET_RAW` capability.
+
+use crate::pcap::{PacketError, RECORD_HEADER_LEN};
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::stream::Stream;
+use pcap_parser::PcapError;
+use std::ffi::CString;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::time::{Duration, SystemTime};
+
+/// Prefix of the input paths which name an interface to capture from rather than a file
+pub const URI_PREFIX: &str = "iface:";
+
+const NANOSECONDS_PER_SECOND: u64 = 1_000_000_000;
+
+/// Largest packet captured in full. Longer packets are truncated, keeping their original length in the record header.
+const SNAPLEN: usize = 65535;
+
+/// How often the capture thread checks whether the capture's stream has been dropped while no packets arrive
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// `AF_PACKET` socket bound to a single interface, receiving every packet with a kernel timestamp
+struct Socket(RawFd);
+
+impl Socket {
+    fn bind(interface: &str) -> io::Result<Socket> {
+        let name = CString::new(interface)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Invalid interface name"))?;
+        let ifindex = unsafe { libc::if_nametoindex(name.as_ptr()) };
+        if ifindex == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No interface named '{}'", interface),
+            ));
+        }
+        let protocol = (libc::ETH_P_ALL as u16).to_be();
+        let fd = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_RAW, protocol as libc::c_int) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let socket = Socket(fd); // closed on drop from here on
+
+        let mut address: libc::sockaddr_ll = unsafe { std::mem::zeroed() };
+        address.sll_family = libc::AF_PACKET as u16;
+        address.sll_protocol = protocol;
+        address.sll_ifindex = ifindex as i32;
+        let result = unsafe {
+            libc::bind(
+                fd,
+                &address as *const libc::sockaddr_ll as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        socket.set_option(libc::SO_TIMESTAMPNS, &(1 as libc::c_int))?;
+        let timeout = libc::timeval {
+            tv_sec: 0,
+            tv_usec: POLL_INTERVAL.as_micros() as libc::suseconds_t,
+        };
+        socket.set_option(libc::SO_RCVTIMEO, &timeout)?;
+        Ok(socket)
+    }
+
+    fn set_option<T>(&self, option: libc::c_int, value: &T) -> io::Result<()> {
+        let result = unsafe {
+            libc::setsockopt(
+                self.0,
+                libc::SOL_SOCKET,
+                option,
+                value as *const T as *const libc::c_void,
+                std::mem::size_of::<T>() as libc::socklen_t,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Receive the next packet into `buffer`, returning its nanosecond timestamp and original length, or [None] if no
+    /// packet arrived within the [POLL_INTERVAL]
+    fn recv(&self, buffer: &mut [u8]) -> io::Result<Option<(u64, usize)>> {
+        let mut iov = libc::iovec {
+            iov_base: buffer.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buffer.len(),
+        };
+        let mut control = [0u64; 8]; // room for one aligned SCM_TIMESTAMPNS control message
+        let mut message: libc::msghdr = unsafe { std::mem::zeroed() };
+        message.msg_iov = &mut iov;
+        message.msg_iovlen = 1;
+        message.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        message.msg_controllen = std::mem::size_of_val(&control) as _;
+        let len = unsafe { libc::recvmsg(self.0, &mut message, libc::MSG_TRUNC) };
+        if len < 0 {
+            let error = io::Error::last_os_error();
+            return match error.kind() {
+                io::ErrorKind::WouldBlock
+                | io::ErrorKind::TimedOut
+                | io::ErrorKind::Interrupted => Ok(None),
+                _ => Err(error),
+            };
+        }
+
+        let mut ts = None;
+        let mut header = unsafe { libc::CMSG_FIRSTHDR(&message) };
+        while !header.is_null() {
+            let cmsg = unsafe { &*header };
+            if cmsg.cmsg_level == libc::SOL_SOCKET && cmsg.cmsg_type == libc::SCM_TIMESTAMPNS {
+                let timestamp = unsafe {
+                    std::ptr::read_unaligned(libc::CMSG_DATA(header) as *const libc::timespec)
+                };
+                ts = Some(
+                    timestamp.tv_sec as u64 * NANOSECONDS_PER_SECOND + timestamp.tv_nsec as u64,
+                );
+            }
+            header = unsafe { libc::CMSG_NXTHDR(&message, header) };
+        }
+        // fall back to the time of receipt if the kernel didn't timestamp the packet
+        let ts = ts.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |since_epoch| since_epoch.as_nanos() as u64)
+        });
+        Ok(Some((ts, len as usize)))
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
+    }
+}
+
+/// Capture packets from `interface` on a dedicated thread, as a [Stream] of `(timestamp, record)` tuples like that of a
+/// [crate::pcap::Packets]. Each record has a little-endian, nanosecond-precision pcap record header. Timestamps never
+/// decrease: a packet timestamped before the packet captured ahead of it (e.g. after the clock is stepped back) takes
+/// the earlier packet's timestamp. The stream ends with an error if receiving fails, and otherwise never ends; the
+/// capture stops once it is dropped.
+pub fn capture(
+    interface: &str,
+) -> io::Result<impl Stream<Item = Result<(u64, Bytes), PacketError>> + Send + Unpin> {
+    let socket = Socket::bind(interface)?;
+    let (sender, receiver) = async_channel::bounded(1024);
+    std::thread::Builder::new()
+        .name(format!("capture-{}", interface))
+        .spawn(move || {
+            let mut buffer = vec![0; SNAPLEN];
+            let mut prev_ts = 0;
+            while !sender.is_closed() {
+                let packet = match socket.recv(&mut buffer) {
+                    Ok(Some((ts, len))) => {
+                        let ts = ts.max(prev_ts);
+                        prev_ts = ts;
+                        let caplen = len.min(buffer.len());
+                        let mut record = BytesMut::with_capacity(RECORD_HEADER_LEN + caplen);
+                        record.put_u32_le((ts / NANOSECONDS_PER_SECOND) as u32);
+                        record.put_u32_le((ts % NANOSECONDS_PER_SECOND) as u32);
+                        record.put_u32_le(caplen as u32);
+                        record.put_u32_le(len as u32);
+                        record.extend_from_slice(&buffer[..caplen]);
+                        Ok((ts, record.freeze()))
+                    }
+                    Ok(None) => continue,
+                    Err(error) => {
+                        tracing::event!(tracing::Level::ERROR, ?error, "Live capture failed");
+                        Err(PacketError::Pcap(PcapError::ReadError))
+                    }
+                };
+                let failed = packet.is_err();
+                if smol::block_on(sender.send(packet)).is_err() || failed {
+                    break;
+                }
+            }
+        })?;
+    Ok(receiver)
+}
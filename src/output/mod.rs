@@ -0,0 +1,413 @@
+//! Destinations for merged output
+//!
+//! Outputs are written in full and then [Output::commit]ted. An [AtomicFile] writes to a temporary file next to its
+//! destination and only renames it into place on commit, so readers never observe a partially-written output, and an
+//! error or crash mid-merge leaves any existing file at the destination untouched.
+//!
+//! A [PipedCommand] streams output to the stdin of a spawned command, committing it once the command has exited
+//! successfully.
+//!
+//! A [HashingOutput] wraps any other output to hash its bytes as they're written, for verifying it without reading it
+//! back.
+//!
+//! TODO: S3 output, completing the multipart upload on commit and aborting it on drop. Compressed output can be
+//! uploaded in independently decompressable parts written by a [crate::compression::FramedParts]
+
+use sha2::Digest;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+
+/// A [Write] sink for merged output which is only complete once committed
+pub trait Output: Write {
+    /// Flush everything written and make the output visible at its destination
+    fn commit(self: Box<Self>) -> std::io::Result<()>;
+}
+
+impl Output for std::io::StdoutLock<'_> {
+    fn commit(mut self: Box<Self>) -> std::io::Result<()> {
+        self.flush()
+    }
+}
+
+/// File which appears at `path` only once [Output::commit] succeeds. Dropping it uncommitted removes the temporary file.
+#[derive(Debug)]
+pub struct AtomicFile {
+    temp_file: NamedTempFile,
+    path: PathBuf,
+    fsync: bool,
+}
+
+impl AtomicFile {
+    /// Start writing to a temporary file in the same directory as `path`, so that it can be renamed over `path` in place
+    pub fn create(path: impl AsRef<Path>) -> std::io::Result<AtomicFile> {
+        let path = path.as_ref().to_path_buf();
+        let dir = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let temp_file = tempfile::Builder::new()
+            .prefix(&format!(".{}.", file_name))
+            .suffix(".partial")
+            .tempfile_in(dir)?;
+        Ok(AtomicFile {
+            temp_file,
+            path,
+            fsync: false,
+        })
+    }
+
+    /// Whether [Output::commit] waits for the file's contents, and its rename into place, to be durably stored on disk
+    pub fn fsync(mut self, fsync: bool) -> AtomicFile {
+        self.fsync = fsync;
+        self
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.temp_file.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.temp_file.flush()
+    }
+}
+
+impl Output for AtomicFile {
+    fn commit(mut self: Box<Self>) -> std::io::Result<()> {
+        self.temp_file.flush()?;
+        if self.fsync {
+            self.temp_file.as_file().sync_all()?;
+        }
+        let AtomicFile {
+            temp_file,
+            path,
+            fsync,
+        } = *self;
+        temp_file.persist(&path).map_err(|e| e.error)?;
+        #[cfg(unix)]
+        if fsync {
+            // the rename is only durable once the directory holding the file is too
+            if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                std::fs::File::open(dir)?.sync_all()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Command which output is written to the stdin of, as a shell pipe would. Writes block while the command falls behind
+/// reading them, so the merge only runs as fast as the command consumes it. Dropping it uncommitted kills the command,
+/// which mustn't take the partial output as complete.
+#[derive(Debug)]
+pub struct PipedCommand {
+    command: String,
+    child: std::process::Child,
+    stdin: Option<std::process::ChildStdin>,
+}
+
+impl PipedCommand {
+    /// Spawn `command`, a program followed by its whitespace-separated arguments (which aren't otherwise parsed as a
+    /// shell would), inheriting stdout and stderr
+    pub fn spawn(command: &str) -> std::io::Result<PipedCommand> {
+        let mut words = command.split_whitespace();
+        let program = words.next().ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "No command to pipe to")
+        })?;
+        let mut child = std::process::Command::new(program)
+            .args(words)
+            .stdin(std::process::Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take();
+        Ok(PipedCommand {
+            command: command.to_string(),
+            child,
+            stdin,
+        })
+    }
+
+    /// The error to report in place of `error` writing to the command: a command which stopped reading its stdin has
+    /// exited, or soon will, and if it failed that's the cause
+    fn write_error(&mut self, error: std::io::Error) -> std::io::Error {
+        if error.kind() != std::io::ErrorKind::BrokenPipe {
+            return error;
+        }
+        self.stdin = None;
+        match self.child.wait() {
+            Ok(status) if !status.success() => CommandFailed {
+                command: self.command.clone(),
+                status,
+            }
+            .into(),
+            _ => std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                format!("'{}' exited before reading all of the output", self.command),
+            ),
+        }
+    }
+}
+
+impl Write for PipedCommand {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let result = match &mut self.stdin {
+            Some(stdin) => stdin.write(buf),
+            None => return Err(std::io::ErrorKind::BrokenPipe.into()),
+        };
+        result.map_err(|error| self.write_error(error))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let result = match &mut self.stdin {
+            Some(stdin) => stdin.flush(),
+            None => return Err(std::io::ErrorKind::BrokenPipe.into()),
+        };
+        result.map_err(|error| self.write_error(error))
+    }
+}
+
+impl Output for PipedCommand {
+    /// Close the command's stdin and wait for it to exit, failing with [CommandFailed] unless it succeeds
+    fn commit(mut self: Box<Self>) -> std::io::Result<()> {
+        self.flush()?;
+        self.stdin = None;
+        let status = self.child.wait()?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(CommandFailed {
+                command: self.command.clone(),
+                status,
+            }
+            .into())
+        }
+    }
+}
+
+impl Drop for PipedCommand {
+    fn drop(&mut self) {
+        if self.stdin.take().is_some() {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+/// Error of a [PipedCommand] which exited unsuccessfully, carried by the [std::io::Error] its output fails with
+#[derive(Debug)]
+pub struct CommandFailed {
+    pub command: String,
+    pub status: std::process::ExitStatus,
+}
+
+impl CommandFailed {
+    /// The [CommandFailed] which caused `error`, if any
+    pub fn of(error: &anyhow::Error) -> Option<&CommandFailed> {
+        error.chain().find_map(|cause| {
+            cause
+                .downcast_ref::<std::io::Error>()
+                .and_then(|error| error.get_ref())
+                .and_then(|error| error.downcast_ref::<CommandFailed>())
+        })
+    }
+}
+
+impl std::fmt::Display for CommandFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "'{}' failed with {}", self.command, self.status)
+    }
+}
+
+impl std::error::Error for CommandFailed {}
+
+impl From<CommandFailed> for std::io::Error {
+    fn from(failed: CommandFailed) -> std::io::Error {
+        std::io::Error::other(failed)
+    }
+}
+
+/// Algorithm with which a [HashingOutput] hashes its output
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Sha256,
+    /// the 64-bit XXH3 hash, much faster than a cryptographic one, whose digest is its 16 lowercase hex digits
+    Xxh3,
+}
+
+impl std::str::FromStr for HashAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            "xxh3" => Ok(HashAlgorithm::Xxh3),
+            _ => anyhow::bail!(
+                "Unknown hash algorithm '{}'. Expected one of: sha256, xxh3",
+                s
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Xxh3 => "xxh3",
+        })
+    }
+}
+
+/// An [Output] which hashes every byte written to it on the way to the wrapped output, handing the lowercase hex
+/// digest of them all to `on_commit` once the wrapped output has been committed
+pub struct HashingOutput<'a> {
+    inner: Box<dyn Output + 'a>,
+    hasher: Hasher,
+    on_commit: Box<dyn FnOnce(String) + 'a>,
+}
+
+/// The running hash of a [HashingOutput]'s bytes
+enum Hasher {
+    Sha256(sha2::Sha256),
+    Xxh3(Box<xxhash_rust::xxh3::Xxh3>),
+}
+
+impl<'a> HashingOutput<'a> {
+    pub fn new(
+        inner: Box<dyn Output + 'a>,
+        algorithm: HashAlgorithm,
+        on_commit: impl FnOnce(String) + 'a,
+    ) -> HashingOutput<'a> {
+        let hasher = match algorithm {
+            HashAlgorithm::Sha256 => Hasher::Sha256(sha2::Sha256::new()),
+            HashAlgorithm::Xxh3 => Hasher::Xxh3(Box::default()),
+        };
+        HashingOutput {
+            inner,
+            hasher,
+            on_commit: Box::new(on_commit),
+        }
+    }
+}
+
+impl Write for HashingOutput<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n_written = self.inner.write(buf)?;
+        match &mut self.hasher {
+            Hasher::Sha256(hasher) => hasher.update(&buf[..n_written]),
+            Hasher::Xxh3(hasher) => hasher.update(&buf[..n_written]),
+        }
+        Ok(n_written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Output for HashingOutput<'_> {
+    fn commit(self: Box<Self>) -> std::io::Result<()> {
+        let HashingOutput {
+            inner,
+            hasher,
+            on_commit,
+        } = *self;
+        inner.commit()?;
+        on_commit(match hasher {
+            Hasher::Sha256(hasher) => format!("{:x}", hasher.finalize()),
+            Hasher::Xxh3(hasher) => format!("{:016x}", hasher.digest()),
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dir_entries(dir: &Path) -> Vec<PathBuf> {
+        let mut entries: Vec<_> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        entries.sort();
+        entries
+    }
+
+    #[test]
+    fn test_commit_replaces_destination() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("merged.pcap");
+        std::fs::write(&path, b"previous output").unwrap();
+
+        let mut file = Box::new(AtomicFile::create(&path).unwrap());
+        file.write_all(b"merged output").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"previous output");
+        file.commit().unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"merged output");
+        assert_eq!(dir_entries(dir.path()), vec![path]);
+    }
+
+    #[test]
+    fn test_hashing_output_reports_the_digest_of_everything_written() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("merged.pcap");
+        let digest = std::cell::RefCell::new(None);
+
+        let file = Box::new(AtomicFile::create(&path).unwrap());
+        let mut output = Box::new(HashingOutput::new(file, HashAlgorithm::Sha256, |hex| {
+            *digest.borrow_mut() = Some(hex)
+        }));
+        output.write_all(b"a").unwrap();
+        output.write_all(b"bc").unwrap();
+        assert_eq!(*digest.borrow(), None);
+        output.commit().unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"abc");
+        assert_eq!(
+            digest.into_inner().unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_commit_with_fsync() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("merged.pcap");
+
+        let mut file = Box::new(AtomicFile::create(&path).unwrap().fsync(true));
+        file.write_all(b"merged output").unwrap();
+        file.commit().unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"merged output");
+    }
+
+    #[test]
+    fn test_failure_mid_write_leaves_destination_untouched() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("merged.pcap");
+        std::fs::write(&path, b"previous output").unwrap();
+
+        let result = std::panic::catch_unwind(|| {
+            let mut file = AtomicFile::create(&path).unwrap();
+            file.write_all(b"partial merged out").unwrap();
+            panic!("simulated failure mid-write");
+        });
+        assert!(result.is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"previous output");
+        assert_eq!(dir_entries(dir.path()), vec![path.clone()]);
+
+        // an error returned before commit behaves the same
+        let write_then_fail = || -> std::io::Result<()> {
+            let mut file = Box::new(AtomicFile::create(&path)?);
+            file.write_all(b"partial merged out")?;
+            Err(std::io::Error::other("simulated failure mid-write"))?;
+            file.commit()
+        };
+        assert!(write_then_fail().is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"previous output");
+        assert_eq!(dir_entries(dir.path()), vec![path]);
+    }
+}
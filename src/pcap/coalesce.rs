@@ -0,0 +1,133 @@
+use super::RECORD_HEADER_LEN;
+use bytes::{BufMut, Bytes, BytesMut};
+
+const NANOSECONDS_PER_SECOND: u64 = 1_000_000_000;
+
+/// Most bytes captured for a coalesced unit, the snaplen of [super::PCAP_HDR_NSEC]. A unit is closed early rather
+/// than grow past it, unless it holds a single packet.
+const MAX_UNIT_LEN: usize = 262_144;
+
+/// Groups time-ordered packets whose timestamps fall within a window of the first packet of their group into a single
+/// unit, written in place of its packets.
+///
+/// A unit is a pcap record stamped with the timestamp of its first packet. Its captured bytes are the little-endian
+/// `u32` number of packets in the unit, followed by each packet's own nanosecond-precision pcap record (header and
+/// captured bytes), in merged order. A packet `window_ns` or more after the first packet of the open unit closes it
+/// and opens the next.
+#[derive(Debug)]
+pub struct Coalescer {
+    window_ns: u64,
+    first_ts: Option<u64>,
+    n_packets: u32,
+    unit: BytesMut,
+}
+
+impl Coalescer {
+    pub fn new(window_ns: u64) -> Coalescer {
+        Coalescer {
+            window_ns,
+            first_ts: None,
+            n_packets: 0,
+            unit: BytesMut::new(),
+        }
+    }
+
+    /// Add the packet `record`, with timestamp `ts`, to the open unit. Returns the previous unit as `(timestamp,
+    /// record)` if this packet closed it.
+    pub fn push(&mut self, ts: u64, record: &[u8]) -> Option<(u64, Bytes)> {
+        let closed = match self.first_ts {
+            Some(first_ts)
+                if ts.saturating_sub(first_ts) >= self.window_ns
+                    || self.unit.len() - RECORD_HEADER_LEN + record.len() > MAX_UNIT_LEN =>
+            {
+                self.finish()
+            }
+            _ => None,
+        };
+        if self.first_ts.is_none() {
+            self.first_ts = Some(ts);
+            self.unit.reserve(RECORD_HEADER_LEN + 4 + record.len());
+            self.unit.put_slice(&[0; RECORD_HEADER_LEN + 4]); // the lengths and count are filled in once it closes
+        }
+        self.n_packets += 1;
+        self.unit.put_u32_le((ts / NANOSECONDS_PER_SECOND) as u32);
+        self.unit.put_u32_le((ts % NANOSECONDS_PER_SECOND) as u32);
+        self.unit.put_slice(&record[8..]); // lengths and captured bytes
+        closed
+    }
+
+    /// Close the open unit, if any packets have been added since the last one closed, returning it as `(timestamp,
+    /// record)`
+    pub fn finish(&mut self) -> Option<(u64, Bytes)> {
+        let first_ts = self.first_ts.take()?;
+        let mut unit = std::mem::take(&mut self.unit);
+        let captured_len = (unit.len() - RECORD_HEADER_LEN) as u32;
+        unit[8..12].copy_from_slice(&captured_len.to_le_bytes());
+        unit[12..16].copy_from_slice(&captured_len.to_le_bytes());
+        unit[16..20].copy_from_slice(&self.n_packets.to_le_bytes());
+        self.n_packets = 0;
+        Some((first_ts, unit.freeze()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(payload: &[u8]) -> Vec<u8> {
+        let mut record = vec![0xEE; 8]; // stale timestamp, which each packet of a unit is restamped over
+        record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        record.extend_from_slice(&(payload.len() as u32 + 10).to_le_bytes());
+        record.extend_from_slice(payload);
+        record
+    }
+
+    #[test]
+    fn packets_within_the_window_of_a_units_first_packet_share_it() {
+        let mut coalescer = Coalescer::new(500);
+        assert_eq!(coalescer.push(1_000, &record(&[1; 4])), None);
+        assert_eq!(coalescer.push(1_000, &record(&[2; 2])), None);
+        assert_eq!(coalescer.push(1_499, &record(&[3; 1])), None);
+        let (ts, unit) = coalescer.push(1_500, &record(&[4; 3])).unwrap();
+        assert_eq!(ts, 1_000);
+
+        let mut expected_payload = 3u32.to_le_bytes().to_vec();
+        for &(ts, payload) in &[
+            (1_000u32, &[1u8; 4][..]),
+            (1_000, &[2; 2]),
+            (1_499, &[3; 1]),
+        ] {
+            expected_payload.extend_from_slice(&0u32.to_le_bytes());
+            expected_payload.extend_from_slice(&ts.to_le_bytes());
+            expected_payload.extend_from_slice(&record(payload)[8..]);
+        }
+        assert_eq!(
+            unit[8..12],
+            (expected_payload.len() as u32).to_le_bytes()[..]
+        );
+        assert_eq!(unit[12..16], unit[8..12]);
+        assert_eq!(unit[RECORD_HEADER_LEN..], expected_payload[..]);
+
+        // the packet which closed the unit opens the next, alone once nothing else falls within its window
+        let (ts, unit) = coalescer.finish().unwrap();
+        assert_eq!(ts, 1_500);
+        assert_eq!(
+            unit[RECORD_HEADER_LEN..RECORD_HEADER_LEN + 4],
+            1u32.to_le_bytes()[..]
+        );
+        assert_eq!(coalescer.finish(), None);
+    }
+
+    #[test]
+    fn units_are_closed_before_outgrowing_the_snaplen() {
+        let mut coalescer = Coalescer::new(u64::MAX);
+        let big = record(&vec![0; MAX_UNIT_LEN / 2]);
+        assert_eq!(coalescer.push(0, &big), None);
+        let (_, unit) = coalescer.push(1, &big).unwrap();
+        assert!(unit.len() - RECORD_HEADER_LEN <= MAX_UNIT_LEN);
+        assert_eq!(
+            unit[RECORD_HEADER_LEN..RECORD_HEADER_LEN + 4],
+            1u32.to_le_bytes()[..]
+        );
+    }
+}
@@ -0,0 +1,85 @@
+use super::{Packets, RECORD_HEADER_LEN};
+use anyhow::{anyhow, bail, Result};
+use futures::stream::TryStreamExt;
+
+/// Check that the pcaps `a` and `b` hold the same packets in the same time order, up to the order of packets sharing
+/// a timestamp: for every timestamp, both hold the same multiset of packets. Merges which only differ in how they break
+/// ties between equal timestamps are equivalent, unlike their bytes. Timestamps are compared at nanosecond precision,
+/// so a microsecond-precision pcap may be compared with a nanosecond-precision one.
+pub fn equivalent_ignoring_ties(a: &[u8], b: &[u8]) -> Result<()> {
+    let a = read_packets(a).map_err(|e| e.context("Failed to read the first pcap"))?;
+    let b = read_packets(b).map_err(|e| e.context("Failed to read the second pcap"))?;
+    let (mut a_rest, mut b_rest) = (&a[..], &b[..]);
+    loop {
+        let (a_ts, b_ts) = match (a_rest.first(), b_rest.first()) {
+            (None, None) => return Ok(()),
+            (Some((ts, _)), None) => bail!(
+                "The first pcap has {} more packets, from {} ns",
+                a_rest.len(),
+                ts
+            ),
+            (None, Some((ts, _))) => bail!(
+                "The second pcap has {} more packets, from {} ns",
+                b_rest.len(),
+                ts
+            ),
+            (Some((a_ts, _)), Some((b_ts, _))) => (*a_ts, *b_ts),
+        };
+        if a_ts != b_ts {
+            bail!(
+                "The pcaps' next packets are at different timestamps: {} ns and {} ns",
+                a_ts,
+                b_ts
+            );
+        }
+        let (mut a_tied, rest) = split_tied(a_rest);
+        a_rest = rest;
+        let (mut b_tied, rest) = split_tied(b_rest);
+        b_rest = rest;
+        a_tied.sort_unstable();
+        b_tied.sort_unstable();
+        if a_tied != b_tied {
+            bail!(
+                "The pcaps hold different packets at {} ns ({} and {} packets)",
+                a_ts,
+                a_tied.len(),
+                b_tied.len()
+            );
+        }
+    }
+}
+
+/// Panic, describing the first difference, unless the pcaps `a` and `b` are [equivalent_ignoring_ties]
+pub fn assert_equivalent_ignoring_ties(a: &[u8], b: &[u8]) {
+    if let Err(error) = equivalent_ignoring_ties(a, b) {
+        panic!("pcaps are not equivalent ignoring ties: {:#}", error);
+    }
+}
+
+/// A packet's `(timestamp, captured bytes)`
+type Packet = (u64, Vec<u8>);
+
+/// Every packet of the pcap `file`, in file order
+fn read_packets(file: &[u8]) -> Result<Vec<Packet>> {
+    smol::block_on(async {
+        Packets::new(1024 * 64, file)
+            .await
+            .map_err(|e| anyhow!("Invalid pcap header: {}", e))?
+            .strict(true)
+            .map_ok(|(ts, record)| (ts, record[RECORD_HEADER_LEN..].to_vec()))
+            .map_err(|e| anyhow!("Failed to parse packet: {}", e))
+            .try_collect()
+            .await
+    })
+}
+
+/// Split the captured bytes of the leading packets of `packets` which share the first packet's timestamp from the rest
+fn split_tied(packets: &[Packet]) -> (Vec<&[u8]>, &[Packet]) {
+    let ts = packets[0].0;
+    let n_tied = packets
+        .iter()
+        .take_while(|(tied_ts, _)| *tied_ts == ts)
+        .count();
+    let (tied, rest) = packets.split_at(n_tied);
+    (tied.iter().map(|(_, data)| &data[..]).collect(), rest)
+}
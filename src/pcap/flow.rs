@@ -0,0 +1,112 @@
+//! Stable per-flow hashing of Ethernet frames, used to shard merged output so that every packet of a flow lands in
+//! the same shard.
+
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86DD;
+const IP_PROTOCOL_TCP: u8 = 6;
+const IP_PROTOCOL_UDP: u8 = 17;
+
+/// Number of leading bytes hashed for frames which are not TCP or UDP over IPv4 or IPv6
+pub const FALLBACK_HASH_N_BYTES: usize = 64;
+
+/// Hash the 5-tuple (source and destination address, protocol, source and destination port) of an Ethernet frame
+/// carrying IPv4 or IPv6. Frames without a recognizable 5-tuple hash their first [FALLBACK_HASH_N_BYTES] bytes instead.
+/// The hash is stable across runs, machines and versions of Rust.
+pub fn flow_hash(frame: &[u8]) -> u64 {
+    match five_tuple(frame) {
+        Some(fields) => fields
+            .iter()
+            .fold(FNV_OFFSET_BASIS, |hash, field| fnv1a(hash, field)),
+        None => fnv1a(
+            FNV_OFFSET_BASIS,
+            &frame[..frame.len().min(FALLBACK_HASH_N_BYTES)],
+        ),
+    }
+}
+
+/// The address, protocol and port fields of a frame's 5-tuple, or `None` if it has none (or is truncated)
+fn five_tuple(frame: &[u8]) -> Option<[&[u8]; 5]> {
+    let ethertype = |offset: usize| {
+        Some(u16::from_be_bytes([
+            *frame.get(offset)?,
+            *frame.get(offset + 1)?,
+        ]))
+    };
+    let (ethertype, ip_offset) = match ethertype(12)? {
+        ETHERTYPE_VLAN => (ethertype(16)?, 18),
+        ethertype => (ethertype, 14),
+    };
+    let ip = frame.get(ip_offset..)?;
+    let (src, dst, protocol, ports) = match ethertype {
+        ETHERTYPE_IPV4 => {
+            let header_len = (*ip.first()? & 0x0F) as usize * 4;
+            (
+                ip.get(12..16)?,
+                ip.get(16..20)?,
+                ip.get(9..10)?,
+                ip.get(header_len..header_len + 4)?,
+            )
+        }
+        ETHERTYPE_IPV6 => (
+            ip.get(8..24)?,
+            ip.get(24..40)?,
+            ip.get(6..7)?,
+            ip.get(40..44)?,
+        ),
+        _ => return None,
+    };
+    match protocol[0] {
+        IP_PROTOCOL_TCP | IP_PROTOCOL_UDP => Some([src, dst, protocol, &ports[..2], &ports[2..]]),
+        _ => None,
+    }
+}
+
+pub(super) const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// `hash` extended with `bytes` by the 64-bit FNV-1a hash, stable across runs
+pub(super) fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn udp_ipv4_frame(src_port: u16, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0u8; 12];
+        frame.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        frame.extend_from_slice(&[0x45, 0, 0, 0, 0, 0, 0, 0, 64, IP_PROTOCOL_UDP, 0, 0]);
+        frame.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2]);
+        frame.extend_from_slice(&src_port.to_be_bytes());
+        frame.extend_from_slice(&53u16.to_be_bytes());
+        frame.extend_from_slice(&[0, 0, 0, 0]); // udp length and checksum
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn packets_of_the_same_flow_hash_equally() {
+        assert_eq!(
+            flow_hash(&udp_ipv4_frame(1000, &[1; 10])),
+            flow_hash(&udp_ipv4_frame(1000, &[2; 500]))
+        );
+        assert_ne!(
+            flow_hash(&udp_ipv4_frame(1000, &[1; 10])),
+            flow_hash(&udp_ipv4_frame(1001, &[1; 10]))
+        );
+    }
+
+    #[test]
+    fn non_ip_frames_hash_their_leading_bytes() {
+        let mut frame = vec![7u8; FALLBACK_HASH_N_BYTES];
+        frame[12..14].copy_from_slice(&0x0806u16.to_be_bytes()); // ARP
+        let mut longer = frame.clone();
+        longer.extend_from_slice(&[9; 100]);
+        assert_eq!(flow_hash(&frame), flow_hash(&longer));
+        assert_eq!(flow_hash(&[]), FNV_OFFSET_BASIS);
+    }
+}
@@ -0,0 +1,109 @@
+use anyhow::{bail, Context as _};
+
+/// Integer field type of a [KeyExpr]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FieldType {
+    U8,
+    U16Be,
+    U16Le,
+    U32Be,
+    U32Le,
+    U64Be,
+    U64Le,
+}
+
+impl FieldType {
+    fn len(self) -> usize {
+        match self {
+            FieldType::U8 => 1,
+            FieldType::U16Be | FieldType::U16Le => 2,
+            FieldType::U32Be | FieldType::U32Le => 4,
+            FieldType::U64Be | FieldType::U64Le => 8,
+        }
+    }
+}
+
+/// Expression for a merge key read from an integer field of each packet's captured bytes (excluding the record
+/// header), written `<type>@<offset>`: e.g. `u32be@42` reads a big-endian `u32` 42 bytes into the packet. The type is
+/// one of `u8`, `u16be`, `u16le`, `u32be`, `u32le`, `u64be` or `u64le`. Used with [super::Packets::with_key_fn].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyExpr {
+    field: FieldType,
+    offset: usize,
+}
+
+impl KeyExpr {
+    /// The key of `packet`, or [None] if it is too short to hold the field
+    pub fn key(&self, packet: &[u8]) -> Option<u64> {
+        let end = self.offset.checked_add(self.field.len())?;
+        let bytes = packet.get(self.offset..end)?;
+        let mut be = [0; 8];
+        be[8 - bytes.len()..].copy_from_slice(bytes);
+        let mut le = [0; 8];
+        le[..bytes.len()].copy_from_slice(bytes);
+        Some(match self.field {
+            FieldType::U8 | FieldType::U16Be | FieldType::U32Be | FieldType::U64Be => {
+                u64::from_be_bytes(be)
+            }
+            FieldType::U16Le | FieldType::U32Le | FieldType::U64Le => u64::from_le_bytes(le),
+        })
+    }
+}
+
+impl std::str::FromStr for KeyExpr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (field, offset) = match s.find('@') {
+            Some(at) => (&s[..at], &s[at + 1..]),
+            None => bail!(
+                "Invalid key expression '{}'. Expected <type>@<offset>, e.g. u32be@42",
+                s
+            ),
+        };
+        let field = match field {
+            "u8" => FieldType::U8,
+            "u16be" => FieldType::U16Be,
+            "u16le" => FieldType::U16Le,
+            "u32be" => FieldType::U32Be,
+            "u32le" => FieldType::U32Le,
+            "u64be" => FieldType::U64Be,
+            "u64le" => FieldType::U64Le,
+            _ => bail!(
+                "Unknown key type '{}' in '{}'. Expected one of: u8, u16be, u16le, u32be, u32le, u64be, u64le",
+                field,
+                s
+            ),
+        };
+        let offset = offset
+            .parse()
+            .with_context(|| format!("Invalid key offset '{}' in '{}'", offset, s))?;
+        Ok(KeyExpr { field, offset })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_evaluates_key_expressions() {
+        let packet: Vec<u8> = (0..16).collect();
+        let key = |expr: &str| expr.parse::<KeyExpr>().unwrap().key(&packet);
+        assert_eq!(key("u8@3"), Some(3));
+        assert_eq!(key("u16be@1"), Some(0x0102));
+        assert_eq!(key("u16le@1"), Some(0x0201));
+        assert_eq!(key("u32be@12"), Some(0x0C0D0E0F));
+        assert_eq!(key("u32le@0"), Some(0x03020100));
+        assert_eq!(key("u64be@8"), Some(0x08090A0B0C0D0E0F));
+        assert_eq!(key("u64le@0"), Some(0x0706050403020100));
+        assert_eq!(key("u32be@13"), None); // runs past the end of the packet
+    }
+
+    #[test]
+    fn rejects_invalid_key_expressions() {
+        for expr in ["u32be", "u32@4", "u16be@", "u16be@-1", "@4"].iter() {
+            assert!(expr.parse::<KeyExpr>().is_err(), "{}", expr);
+        }
+    }
+}
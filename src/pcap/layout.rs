@@ -0,0 +1,115 @@
+use super::{Precision, RECORD_HEADER_LEN};
+use bytes::buf::BufMut;
+use bytes::{Bytes, BytesMut};
+
+const NANOSECONDS_PER_SECOND: u64 = 1_000_000_000;
+
+/// Size in bytes of every [TimestampFormat]
+const TIMESTAMP_LEN: usize = 8;
+
+/// Size in bytes of the captured length field
+const CAPLEN_LEN: usize = 4;
+
+/// Encoding of the timestamp in a [RecordLayout]'s record header. Fields share the byte order of the file header's
+/// magic number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimestampFormat {
+    /// `u32` seconds followed by `u32` microseconds, as in a standard pcap record header
+    SecondsMicroseconds,
+    /// `u32` seconds followed by `u32` nanoseconds, as in a nanosecond-precision pcap record header
+    SecondsNanoseconds,
+    /// a single `u64` count of microseconds since the epoch
+    Microseconds,
+    /// a single `u64` count of nanoseconds since the epoch
+    Nanoseconds,
+}
+
+impl TimestampFormat {
+    pub(super) fn precision(self) -> Precision {
+        match self {
+            TimestampFormat::SecondsMicroseconds | TimestampFormat::Microseconds => {
+                Precision::Microsecond
+            }
+            TimestampFormat::SecondsNanoseconds | TimestampFormat::Nanoseconds => {
+                Precision::Nanosecond
+            }
+        }
+    }
+}
+
+/// Fixed per-record header layout of a pcap variant which [super::Packets::new] doesn't recognize, such as a vendor's
+/// proprietary capture format, parsed by [super::Packets::with_record_layout]. Each record is `header_len` bytes of
+/// header followed by its captured bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RecordLayout {
+    /// size in bytes of each record's header, up to the first captured byte
+    pub header_len: usize,
+    /// offset into the record header of the timestamp
+    pub ts_offset: usize,
+    pub ts_format: TimestampFormat,
+    /// offset into the record header of the `u32` number of captured bytes following the header
+    pub caplen_offset: usize,
+}
+
+impl RecordLayout {
+    /// Whether the timestamp and captured length fields both lie within the record header
+    pub(super) fn is_valid(&self) -> bool {
+        self.ts_offset + TIMESTAMP_LEN <= self.header_len
+            && self.caplen_offset + CAPLEN_LEN <= self.header_len
+    }
+
+    /// Total size of the record at the start of `buffer`, or [None] if `buffer` is too short to hold its header
+    pub(super) fn record_len(&self, buffer: &[u8], is_bigendian: bool) -> Option<usize> {
+        if buffer.len() < self.header_len {
+            return None;
+        }
+        let caplen = read_u32(&buffer[self.caplen_offset..], is_bigendian);
+        Some(self.header_len + caplen as usize)
+    }
+
+    /// Nanosecond timestamp of the complete `record`, along with the record normalized to a standard little-endian,
+    /// nanosecond-precision pcap record header followed by its captured bytes
+    pub(super) fn normalize(&self, record: &[u8], is_bigendian: bool) -> (u64, Bytes) {
+        let ts = &record[self.ts_offset..];
+        let ts = match self.ts_format {
+            TimestampFormat::SecondsMicroseconds => {
+                read_u32(ts, is_bigendian) as u64 * NANOSECONDS_PER_SECOND
+                    + read_u32(&ts[4..], is_bigendian) as u64 * 1000
+            }
+            TimestampFormat::SecondsNanoseconds => {
+                read_u32(ts, is_bigendian) as u64 * NANOSECONDS_PER_SECOND
+                    + read_u32(&ts[4..], is_bigendian) as u64
+            }
+            TimestampFormat::Microseconds => read_u64(ts, is_bigendian) * 1000,
+            TimestampFormat::Nanoseconds => read_u64(ts, is_bigendian),
+        };
+        let data = &record[self.header_len..];
+        let mut normalized = BytesMut::with_capacity(RECORD_HEADER_LEN + data.len());
+        normalized.put_u32_le((ts / NANOSECONDS_PER_SECOND) as u32);
+        normalized.put_u32_le((ts % NANOSECONDS_PER_SECOND) as u32);
+        normalized.put_u32_le(data.len() as u32);
+        normalized.put_u32_le(data.len() as u32); // the original length isn't part of the layout
+        normalized.extend_from_slice(data);
+        (ts, normalized.freeze())
+    }
+}
+
+fn read_u32(bytes: &[u8], is_bigendian: bool) -> u32 {
+    let mut field = [0; 4];
+    field.copy_from_slice(&bytes[..4]);
+    if is_bigendian {
+        u32::from_be_bytes(field)
+    } else {
+        u32::from_le_bytes(field)
+    }
+}
+
+fn read_u64(bytes: &[u8], is_bigendian: bool) -> u64 {
+    let mut field = [0; 8];
+    field.copy_from_slice(&bytes[..8]);
+    if is_bigendian {
+        u64::from_be_bytes(field)
+    } else {
+        u64::from_le_bytes(field)
+    }
+}
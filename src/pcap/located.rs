@@ -0,0 +1,72 @@
+use super::PacketError;
+use futures::stream::{Stream, StreamExt};
+use futures::task::{Context, Poll};
+use std::pin::Pin;
+
+/// A stream of packets parsed from an input, such as a [super::Packets], which knows how far into the input it has
+/// parsed
+pub trait ParseOffset {
+    /// Offset of the header or record to be parsed next, counted in the input's decompressed bytes. Once the stream
+    /// has yielded an error, the offset of the header or record which failed to parse.
+    fn offset(&self) -> u64;
+
+    /// This stream, with each error located at its [offset](ParseOffset::offset) in the input at `path`
+    fn located(self, path: &str) -> Located<Self>
+    where
+        Self: Sized,
+    {
+        Located {
+            packets: self,
+            path: path.to_string(),
+        }
+    }
+}
+
+/// A [PacketError] located in the input it was parsed from: the `path` of the input, and the `offset` of the header or
+/// record which failed to parse, counted in the input's decompressed bytes
+#[derive(Debug)]
+pub struct ParseError {
+    pub path: String,
+    pub offset: u64,
+    pub kind: PacketError,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        // the path is left to the context, which names the input whatever failed, and the kind to the source
+        write!(f, "error at byte {}", self.offset)
+    }
+}
+
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.kind)
+    }
+}
+
+/// Stream returned by [ParseOffset::located]
+#[must_use = "streams do nothing unless polled"]
+pub struct Located<St> {
+    packets: St,
+    path: String,
+}
+
+impl<St, T> Stream for Located<St>
+where
+    St: Stream<Item = Result<T, PacketError>> + ParseOffset + Unpin,
+{
+    type Item = Result<T, ParseError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        this.packets.poll_next_unpin(cx).map(|packet| {
+            packet.map(|packet| {
+                packet.map_err(|kind| ParseError {
+                    path: this.path.clone(),
+                    offset: this.packets.offset(),
+                    kind,
+                })
+            })
+        })
+    }
+}
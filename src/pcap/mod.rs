@@ -3,11 +3,11 @@
 //! Asynchronously parse uncompressed pcap bytes as a `futures::stream::Stream<Item=(u64, Bytes)>` of `(timestamp, packet)` tuples.
 //!
 
-use anyhow::Result;
+use anyhow::{anyhow, Context as _, Result};
 use bytes::buf::BufMut;
-use bytes::{Bytes, BytesMut};
+use bytes::{Buf, Bytes, BytesMut};
 use futures::io::{AsyncRead, AsyncReadExt};
-use futures::stream::Stream;
+use futures::stream::{Stream, StreamExt, TryStreamExt};
 use futures::task::Poll;
 use nom::{self, IResult};
 use pcap_parser::pcap::{
@@ -17,9 +17,135 @@ use pcap_parser::PcapError;
 use std::pin::Pin;
 use std::task::Context;
 
+mod coalesce;
+mod equivalence;
+pub mod flow;
+mod key_expr;
+mod layout;
+mod located;
+pub mod pcapng;
+mod raw;
+mod reorder;
+mod sample;
+mod sidecar;
+mod transcode;
+mod writer;
+pub use coalesce::Coalescer;
+pub use equivalence::{assert_equivalent_ignoring_ties, equivalent_ignoring_ties};
+pub use key_expr::KeyExpr;
+pub use layout::{RecordLayout, TimestampFormat};
+pub use located::{Located, ParseError, ParseOffset};
+pub use raw::{InputFormat, RawFramed};
+pub use reorder::Reorder;
+pub use sample::{SampleMode, Sampler};
+pub use sidecar::SidecarTimestamps;
+pub use transcode::{Transcode, LINKTYPE_LINUX_SLL, LINKTYPE_RAW};
+pub use writer::{HeaderFields, OutputFormat, Writer, PCAP_HDR_NSEC, RECORD_HEADER_LEN};
+
+/// Error yielded by a [Packets], [RawFramed] or [pcapng::PcapngPackets] stream. The stream should not be polled again after an error.
+#[derive(Debug)]
+pub enum PacketError {
+    /// The bytes read could not be parsed as a file header or record
+    Pcap(PcapError),
+    /// The underlying reader failed
+    Read(std::io::Error),
+    /// In [strict](Packets::strict) mode, the input ended part way through a record, leaving `remaining_bytes` which
+    /// don't form a complete record
+    TruncatedRecord { remaining_bytes: usize },
+    /// A record's header claims `record_len` bytes, more than the `buffered_len` bytes parsed for it. Only an
+    /// inconsistency between parsing and buffering could cause this, which is reported rather than splitting a record
+    /// past the end of the buffer.
+    RecordExceedsBuffer {
+        record_len: usize,
+        buffered_len: usize,
+    },
+    /// A pcapng block is malformed, as described
+    InvalidPcapng(&'static str),
+    /// A record's (or frame's, or block's) length field claims `record_len` bytes, more than the `max_len` any
+    /// plausible one has, so the input is corrupt or misdetected. Reported before buffering any of it.
+    RecordTooLong { record_len: usize, max_len: usize },
+}
+
+impl std::fmt::Display for PacketError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PacketError::Pcap(e) => write!(f, "{:?}", e),
+            PacketError::Read(e) => write!(f, "failed to read: {}", e),
+            PacketError::TruncatedRecord { remaining_bytes } => write!(
+                f,
+                "input ends part way through a record, {} bytes in",
+                remaining_bytes
+            ),
+            PacketError::RecordExceedsBuffer {
+                record_len,
+                buffered_len,
+            } => write!(
+                f,
+                "record claims {} bytes but only {} are buffered",
+                record_len, buffered_len
+            ),
+            PacketError::InvalidPcapng(reason) => write!(f, "invalid pcapng: {}", reason),
+            PacketError::RecordTooLong {
+                record_len,
+                max_len,
+            } => write!(
+                f,
+                "record claims {} bytes, more than the {} of any plausible record",
+                record_len, max_len
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PacketError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PacketError::Read(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Resolution of the timestamps stored in a pcap. [Packets] always yields nanosecond timestamps, scaling up those of
+/// microsecond-precision files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Precision {
+    Microsecond,
+    Nanosecond,
+}
+
+impl std::fmt::Display for Precision {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Precision::Microsecond => "us",
+            Precision::Nanosecond => "ns",
+        })
+    }
+}
+
+impl std::str::FromStr for Precision {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "us" => Ok(Precision::Microsecond),
+            "ns" => Ok(Precision::Nanosecond),
+            _ => Err(anyhow!(
+                "Unknown timestamp precision '{}'. Expected us or ns",
+                s
+            )),
+        }
+    }
+}
+
 #[pin_project::pin_project(project = PacketsProj)]
 /// [AsyncRead] combinator type for parsing pcap files into a [Stream] of timestamped [Bytes] for each packet present in the file.
 ///
+/// Each yielded [Bytes] is a complete record: the standard 16-byte record header followed by the captured packet data.
+/// Records from "modified" (ss991029) format files have their extended header trimmed to the standard 16 bytes, and
+/// records parsed with a custom [RecordLayout] are given a standard header in place of their own. Record headers are
+/// always little-endian, as [Writer] expects, whatever the byte order of the file.
+///
 /// Wrapped types implementing [AsyncRead] are expected to yield uncompressed data in .pcap form with packet timestamps which never decrease (i.e. the file is already time-ordered). If the file is detected to be unordered or corrupt,
 /// an error will be returned and TODO: define and test error return behavior for corrupt or unordered files.
 pub struct Packets<R> {
@@ -29,112 +155,1138 @@ pub struct Packets<R> {
     reader: R,
     buffer: BytesMut,
     reader_exhausted: bool,
-    parse: LegacyParseFn,
+    framing: RecordFraming,
+    link_type: u16,
+    header_fields: HeaderFields,
+    record_header_extra_len: usize, // bytes between the standard 16-byte record header and the packet data
+    strict: bool,
+    fix_wraparound: bool,
+    prev_ts_sec: Option<u32>, // 32-bit seconds of the previous record, to detect wraparound
+    n_wraparounds: u64,
+    key_fn: Option<KeyFn>,
+    prev_key: u64,
+    processors: Processors,
+    offset: u64, // of the record at the front of the buffer, in the file's decompressed bytes
 }
 
+/// Merge key of a packet's captured bytes, for [Packets::with_key_fn]
+type KeyFn = Box<dyn Fn(&[u8]) -> Option<u64> + Send + Sync>;
+
+/// Per-packet hook of [Packets::with_processor]
+type ProcessFn = Box<dyn FnMut(u64, Bytes) -> PacketAction + Send + Sync>;
+
+/// A chain of [Packets::with_processor] processors, which can also be run on packets from elsewhere, e.g. those
+/// written by a merge
+#[derive(Default)]
+pub struct Processors(Vec<ProcessFn>);
+
+impl Processors {
+    /// Run `processor` on each packet after the processors already added
+    pub fn push(
+        &mut self,
+        processor: impl FnMut(u64, Bytes) -> PacketAction + Send + Sync + 'static,
+    ) {
+        self.0.push(Box::new(processor));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The packet the processors turn the packet at `ts` with `record` into, or [None] if one drops it. Processors run
+    /// in the order they were added, a dropped packet going no further. A timestamp they change is only returned
+    /// alongside the record, whose header is left as it was: [Writer] writes the header from the returned timestamp,
+    /// and [Packets::with_key_fn] restamps the records it yields.
+    pub fn process(&mut self, ts: u64, record: Bytes) -> Option<(u64, Bytes)> {
+        self.0
+            .iter_mut()
+            .try_fold((ts, record), |(ts, record), processor| {
+                match processor(ts, record.clone()) {
+                    PacketAction::Drop => None,
+                    PacketAction::Keep => Some((ts, record)),
+                    PacketAction::Replace(ts, record) => Some((ts, record)),
+                }
+            })
+    }
+}
+
+/// Processor shifting each packet's timestamp by the signed `offset_ns`, saturating at the bounds of `u64` (see
+/// [crate::config::offset_timestamp])
+pub fn offset_processor(offset_ns: i64) -> impl FnMut(u64, Bytes) -> PacketAction + Send + Sync {
+    move |ts, record| PacketAction::Replace(crate::config::offset_timestamp(ts, offset_ns), record)
+}
+
+/// What a [Packets::with_processor] processor does with a packet
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PacketAction {
+    /// leave it out of the stream
+    Drop,
+    /// yield it unchanged
+    Keep,
+    /// yield this timestamp and record in its place
+    Replace(u64, Bytes),
+}
+
+/// Length of a pcap file header, which its first record follows
+const FILE_HEADER_LEN: usize = 24;
+
+/// Most captured bytes a record found by [Packets::last_timestamp_in] may have: libpcap's largest snapshot length
+pub const MAX_PLAUSIBLE_CAPLEN: usize = 262_144;
+
+/// Most bytes a record found by [Packets::last_timestamp_in] may have, its header included
+pub const MAX_PLAUSIBLE_RECORD_LEN: usize = RECORD_HEADER_LEN + MAX_PLAUSIBLE_CAPLEN;
+
+/// Fewest records in a chain found by [Packets::last_timestamp_in] which doesn't start at a known record
+pub const MIN_TAIL_CHAIN_LEN: usize = 4;
+
+/// Seconds added to timestamps each time a file's 32-bit seconds wrap around
+const WRAPAROUND_SECONDS: u64 = 1 << 32;
+
 type LegacyParseFn = fn(&[u8]) -> IResult<&[u8], LegacyPcapBlock, PcapError>;
 
+/// How [Packets] splits its input into records
+#[derive(Clone, Copy)]
+enum RecordFraming {
+    /// with a nom parser for one of the standard or modified record header layouts, in big-endian byte order if
+    /// `is_bigendian`
+    Legacy {
+        parse: LegacyParseFn,
+        is_bigendian: bool,
+    },
+    /// with a custom layout, reading fields in big-endian byte order if `is_bigendian`
+    Layout {
+        layout: RecordLayout,
+        is_bigendian: bool,
+    },
+}
+
+/// Magic number of the "modified" (ss991029) pcap format, as its bytes appear in little-endian and big-endian files
+const MODIFIED_MAGIC_LE: [u8; 4] = [0x34, 0xCD, 0xB2, 0xA1];
+const MODIFIED_MAGIC_BE: [u8; 4] = [0xA1, 0xB2, 0xCD, 0x34];
+/// Standard microsecond-precision magic number, as its bytes appear in little-endian and big-endian files
+const MICROSECOND_MAGIC_LE: [u8; 4] = [0xD4, 0xC3, 0xB2, 0xA1];
+const MICROSECOND_MAGIC_BE: [u8; 4] = [0xA1, 0xB2, 0xC3, 0xD4];
+
+/// The modified format appends an interface index (`u32`), protocol (`u16`), packet type (`u8`) and a byte of padding
+/// to the standard record header
+const MODIFIED_RECORD_HEADER_EXTRA_LEN: usize = 8;
+
+fn parse_modified_pcap_frame(i: &[u8]) -> IResult<&[u8], LegacyPcapBlock<'_>, PcapError> {
+    parse_modified_frame(i, u32::from_le_bytes)
+}
+
+fn parse_modified_pcap_frame_be(i: &[u8]) -> IResult<&[u8], LegacyPcapBlock<'_>, PcapError> {
+    parse_modified_frame(i, u32::from_be_bytes)
+}
+
+fn parse_modified_frame(
+    i: &[u8],
+    read_u32: fn([u8; 4]) -> u32,
+) -> IResult<&[u8], LegacyPcapBlock<'_>, PcapError> {
+    const HEADER_LEN: usize = RECORD_HEADER_LEN + MODIFIED_RECORD_HEADER_EXTRA_LEN;
+    if i.len() < HEADER_LEN {
+        return Err(nom::Err::Incomplete(nom::Needed::Size(
+            HEADER_LEN - i.len(),
+        )));
+    }
+    let field = |offset: usize| read_u32([i[offset], i[offset + 1], i[offset + 2], i[offset + 3]]);
+    let caplen = field(8);
+    let record_len = HEADER_LEN + caplen as usize;
+    if i.len() < record_len {
+        return Err(nom::Err::Incomplete(nom::Needed::Size(
+            record_len - i.len(),
+        )));
+    }
+    Ok((
+        &i[record_len..],
+        LegacyPcapBlock {
+            ts_sec: field(0),
+            ts_usec: field(4),
+            caplen,
+            origlen: field(12),
+            data: &i[HEADER_LEN..record_len],
+        },
+    ))
+}
+
 impl<R> Packets<R>
 where
     R: AsyncRead + std::marker::Unpin,
 {
     /// Given an internal buffer `capacity` and an [AsyncRead] reader which yields bytes in uncompressed .pcap format, validate
     /// the pcap file header and, on success, construct a [`Packets<R>`].
-    pub async fn new(capacity: usize, mut reader: R) -> Result<Packets<R>, PcapError> {
-        let mut header_bytes = [0; 24];
-        let mut n_header_bytes_read = 0;
-        let is_bigendian: bool;
-        let ts_usec_multiplier;
-        loop {
-            n_header_bytes_read += reader
-                .read(&mut header_bytes[n_header_bytes_read..])
-                .await
-                .or(Err(PcapError::ReadError))?;
-            // TODO: handle getting less data than a pcap header??
-            let (_, header) = match parse_pcap_header(&header_bytes) {
-                Ok((r, h)) => Ok((r, h)),
-                Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => Err(e),
-                Err(_) => continue, //incomplete. TODO: bail if we continue to fail our read
-            }?;
-            ts_usec_multiplier = if header.is_nanosecond_precision() {
-                1
-            } else {
-                1000
-            };
-            is_bigendian = header.is_bigendian();
-            break;
+    pub async fn new(capacity: usize, mut reader: R) -> Result<Packets<R>, PacketError> {
+        let mut header_bytes = read_file_header(&mut reader).await?;
+
+        // the "modified" format shares the standard microsecond-precision header layout, but uses larger record headers
+        let mut magic = [0; 4];
+        magic.copy_from_slice(&header_bytes[..4]);
+        let is_modified_format = magic == MODIFIED_MAGIC_LE || magic == MODIFIED_MAGIC_BE;
+        if magic == MODIFIED_MAGIC_LE {
+            header_bytes[..4].copy_from_slice(&MICROSECOND_MAGIC_LE);
+        } else if magic == MODIFIED_MAGIC_BE {
+            header_bytes[..4].copy_from_slice(&MICROSECOND_MAGIC_BE);
+        }
+
+        let (_, header) = match parse_pcap_header(&header_bytes) {
+            Ok((r, h)) => Ok((r, h)),
+            Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => Err(e),
+            Err(nom::Err::Incomplete(_)) => Err(PcapError::Incomplete),
         }
-        let parse = if is_bigendian {
-            parse_pcap_frame_be
+        .map_err(PacketError::Pcap)?;
+        let ts_usec_multiplier = if header.is_nanosecond_precision() {
+            1
         } else {
-            parse_pcap_frame
+            1000
+        };
+        let parse = match (is_modified_format, header.is_bigendian()) {
+            (false, false) => parse_pcap_frame,
+            (false, true) => parse_pcap_frame_be,
+            (true, false) => parse_modified_pcap_frame,
+            (true, true) => parse_modified_pcap_frame_be,
         };
         Ok(Packets {
             ts_usec_multiplier,
             reader,
             buffer: BytesMut::with_capacity(capacity),
             reader_exhausted: false,
-            parse,
+            framing: RecordFraming::Legacy {
+                parse,
+                is_bigendian: header.is_bigendian(),
+            },
+            link_type: header.network.0 as u16,
+            header_fields: HeaderFields {
+                thiszone: header.thiszone,
+                sigfigs: header.sigfigs,
+            },
+            record_header_extra_len: if is_modified_format {
+                MODIFIED_RECORD_HEADER_EXTRA_LEN
+            } else {
+                0
+            },
+            strict: false,
+            fix_wraparound: false,
+            prev_ts_sec: None,
+            n_wraparounds: 0,
+            key_fn: None,
+            processors: Processors::default(),
+            prev_key: 0,
+            offset: FILE_HEADER_LEN as u64,
+        })
+    }
+
+    /// Like [Packets::new], for pcap variants whose records have the non-standard header `layout`. The file header is
+    /// only used for the byte order of its magic number, which the layout's fields share. Timestamp precision comes
+    /// from the layout's [TimestampFormat] rather than the magic number.
+    ///
+    /// # Panics
+    ///
+    /// If the layout's timestamp or captured length lie outside of its `header_len`
+    pub async fn with_record_layout(
+        capacity: usize,
+        mut reader: R,
+        layout: RecordLayout,
+    ) -> Result<Packets<R>, PacketError> {
+        assert!(
+            layout.is_valid(),
+            "Record layout fields must lie within its header: {:?}",
+            layout
+        );
+        let header_bytes = read_file_header(&mut reader).await?;
+        let is_bigendian = header_bytes[0] == MICROSECOND_MAGIC_BE[0];
+        let field = |offset: usize| {
+            let mut field = [0; 4];
+            field.copy_from_slice(&header_bytes[offset..offset + 4]);
+            if is_bigendian {
+                u32::from_be_bytes(field)
+            } else {
+                u32::from_le_bytes(field)
+            }
+        };
+        let link_type = field(20);
+        Ok(Packets {
+            ts_usec_multiplier: 1000, // unused: the layout determines the precision
+            reader,
+            buffer: BytesMut::with_capacity(capacity),
+            reader_exhausted: false,
+            framing: RecordFraming::Layout {
+                layout,
+                is_bigendian,
+            },
+            link_type: link_type as u16,
+            header_fields: HeaderFields {
+                thiszone: field(8) as i32,
+                sigfigs: field(12),
+            },
+            record_header_extra_len: 0,
+            strict: false,
+            fix_wraparound: false,
+            prev_ts_sec: None,
+            n_wraparounds: 0,
+            key_fn: None,
+            processors: Processors::default(),
+            prev_key: 0,
+            offset: FILE_HEADER_LEN as u64,
+        })
+    }
+}
+
+/// Split the `record_len` bytes of the record at the front of `buffer` from it, failing with
+/// [PacketError::RecordExceedsBuffer] rather than panicking if fewer bytes are buffered
+fn split_record(buffer: &mut BytesMut, record_len: usize) -> Result<BytesMut, PacketError> {
+    if record_len > buffer.len() {
+        return Err(PacketError::RecordExceedsBuffer {
+            record_len,
+            buffered_len: buffer.len(),
+        });
+    }
+    Ok(buffer.split_to(record_len))
+}
+
+/// Read the pcap file header from the start of `reader`
+async fn read_file_header<R: AsyncRead + std::marker::Unpin>(
+    reader: &mut R,
+) -> Result<[u8; FILE_HEADER_LEN], PacketError> {
+    let mut header_bytes = [0; FILE_HEADER_LEN];
+    let mut n_header_bytes_read = 0;
+    while n_header_bytes_read < header_bytes.len() {
+        let n_bytes_read = reader
+            .read(&mut header_bytes[n_header_bytes_read..])
+            .await
+            .map_err(PacketError::Read)?;
+        if n_bytes_read == 0 {
+            return Err(PacketError::Pcap(PcapError::Eof)); // the file is shorter than a pcap header
+        }
+        n_header_bytes_read += n_bytes_read;
+    }
+    Ok(header_bytes)
+}
+
+impl<R> Packets<R> {
+    /// Split the next complete record from the front of the buffer, or [None] if more input is needed
+    fn next_record(self: Pin<&mut Self>) -> Option<Result<(u64, Bytes), PacketError>> {
+        let this = self.project();
+        match *this.framing {
+            RecordFraming::Legacy {
+                parse,
+                is_bigendian,
+            } => match parse(this.buffer) {
+                Ok((_rem, packet)) => {
+                    if *this.fix_wraparound {
+                        // a backward jump of over half the range of the seconds can only be sensibly explained by them
+                        // having wrapped around
+                        if let Some(prev_ts_sec) = *this.prev_ts_sec {
+                            if prev_ts_sec - packet.ts_sec.min(prev_ts_sec) > u32::MAX / 2 {
+                                *this.n_wraparounds += 1;
+                            }
+                        }
+                        *this.prev_ts_sec = Some(packet.ts_sec);
+                    }
+                    // TODO: write the nanosecond timestamp into the data??
+                    let nanosecond_ts = (packet.ts_sec as u64
+                        + *this.n_wraparounds * WRAPAROUND_SECONDS)
+                        * 1000000000
+                        + packet.ts_usec as u64 * *this.ts_usec_multiplier as u64;
+                    let extra_len = *this.record_header_extra_len;
+                    let packet_n_bytes = RECORD_HEADER_LEN + extra_len + packet.caplen as usize;
+                    let mut record = match split_record(this.buffer, packet_n_bytes) {
+                        Ok(record) => record,
+                        Err(error) => return Some(Err(error)),
+                    };
+                    *this.offset += packet_n_bytes as u64;
+                    if extra_len > 0 {
+                        // normalize to a standard record: shift the standard header fields over the extra ones
+                        record.copy_within(..RECORD_HEADER_LEN, extra_len);
+                        record.advance(extra_len);
+                    }
+                    if is_bigendian {
+                        // records are merged from files of either byte order into little-endian output
+                        for field in record[..RECORD_HEADER_LEN].chunks_exact_mut(4) {
+                            field.reverse();
+                        }
+                    }
+                    if this.key_fn.is_some() {
+                        // the yielded key is no longer the timestamp, so carry it in the header
+                        let seconds = (nanosecond_ts / 1000000000) as u32;
+                        let nanoseconds = (nanosecond_ts % 1000000000) as u32;
+                        record[..4].copy_from_slice(&seconds.to_le_bytes());
+                        record[4..8].copy_from_slice(&nanoseconds.to_le_bytes());
+                    }
+                    Some(Ok((nanosecond_ts, record.freeze())))
+                }
+                Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
+                    Some(Err(PacketError::Pcap(e)))
+                }
+                Err(nom::Err::Incomplete(_)) => None,
+            },
+            RecordFraming::Layout {
+                layout,
+                is_bigendian,
+            } => {
+                let record_len = layout.record_len(this.buffer, is_bigendian)?;
+                let max_len = layout.header_len + MAX_PLAUSIBLE_CAPLEN;
+                if record_len > max_len {
+                    // end the stream here, at the record's offset, rather than reading the rest of the input into it
+                    this.buffer.clear();
+                    *this.reader_exhausted = true;
+                    return Some(Err(PacketError::RecordTooLong {
+                        record_len,
+                        max_len,
+                    }));
+                }
+                if this.buffer.len() < record_len {
+                    this.buffer.reserve(record_len - this.buffer.len()); // make room for the rest of a large record
+                    return None;
+                }
+                let record = match split_record(this.buffer, record_len) {
+                    Ok(record) => record,
+                    Err(error) => return Some(Err(error)),
+                };
+                *this.offset += record_len as u64;
+                Some(Ok(layout.normalize(&record, is_bigendian)))
+            }
+        }
+    }
+
+    /// Precision of the timestamps stored in the file, as given by its magic number or custom [RecordLayout]
+    pub fn precision(&self) -> Precision {
+        match self.framing {
+            RecordFraming::Legacy { .. } if self.ts_usec_multiplier == 1 => Precision::Nanosecond,
+            RecordFraming::Legacy { .. } => Precision::Microsecond,
+            RecordFraming::Layout { layout, .. } => layout.ts_format.precision(),
+        }
+    }
+
+    /// Link-layer header type of the file's packets, as given by its header (e.g. 1 for Ethernet)
+    pub fn link_type(&self) -> u16 {
+        self.link_type
+    }
+
+    /// The legacy `thiszone` and `sigfigs` fields of the file's header, for merged output to carry over
+    pub fn header_fields(&self) -> HeaderFields {
+        self.header_fields
+    }
+
+    /// In strict mode, input ending part way through a record is yielded as a [PacketError::TruncatedRecord] error.
+    /// Otherwise (the default) the partial record is silently dropped, as when reading a file which is still being written.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Yield each packet keyed by `key_fn` of its captured bytes (excluding the record header) rather than by its
+    /// timestamp, so that a [crate::tournament_tree::Tree] merges by that key instead. The file must already be
+    /// ordered by the key. A packet `key_fn` returns [None] for (e.g. one too short to hold the key's field) takes the
+    /// key of the packet before it, or 0 if it is the first. The nanosecond timestamp is carried in each yielded record's
+    /// header, in little-endian byte order, to be read back with [record_timestamp].
+    pub fn with_key_fn(
+        mut self,
+        key_fn: impl Fn(&[u8]) -> Option<u64> + Send + Sync + 'static,
+    ) -> Self {
+        self.key_fn = Some(Box::new(key_fn));
+        self
+    }
+
+    /// Yield each packet with its captured bytes (excluding the record header) rewritten by `transform` before it's
+    /// merged or keyed, e.g. to reframe its link-layer header with a [Transcode]. The record's captured length is
+    /// rewritten to match, and its original length changed by as many bytes. A [Packets::with_processor] processor.
+    pub fn with_transform(
+        self,
+        transform: impl Fn(&[u8]) -> Bytes + Send + Sync + 'static,
+    ) -> Self {
+        self.with_processor(move |ts, record| {
+            let captured = transform(&record[RECORD_HEADER_LEN..]);
+            PacketAction::Replace(ts, transform_record(&record, captured))
+        })
+    }
+
+    /// Pass each packet's timestamp and record to `processor` before it's merged or keyed, to drop it, keep it or
+    /// replace it with another timestamp and record: a single hook for filtering, restamping and rewriting packets.
+    /// Processors run in the order they're added, a dropped packet going no further (see [Processors::process]).
+    /// Replacement timestamps must stay in ascending order, as the merge relies on, and replacement records must start
+    /// with a little-endian record header, though its timestamp needn't match (see [Processors::process]).
+    pub fn with_processor(
+        mut self,
+        processor: impl FnMut(u64, Bytes) -> PacketAction + Send + Sync + 'static,
+    ) -> Self {
+        self.processors.push(processor);
+        self
+    }
+
+    /// Whether to correct for the 32-bit seconds of standard and modified format records wrapping around (e.g. past
+    /// 2038, or on a relative clock). When a record's seconds are more than 2^31 below the previous record's, `2^32`
+    /// seconds are added to its timestamp and every later one, keeping them in ascending order. The seconds in yielded
+    /// records' headers are left as-is.
+    pub fn fix_wraparound(mut self, fix_wraparound: bool) -> Self {
+        self.fix_wraparound = fix_wraparound;
+        self
+    }
+
+    /// Timestamp of the last record of the file, found in `tail`, its last bytes, without parsing any before them.
+    /// With nothing marking where a record starts, `tail` is searched for the earliest offset from which a chain of
+    /// plausible records (with a valid fraction of a second, no more than [MAX_PLAUSIBLE_CAPLEN] captured bytes and
+    /// timestamps which never decrease) ends exactly at the end of the file. Unless it starts where `tail` is known to
+    /// begin with a record (`starts_with_record`, e.g. as every byte after the file header does), the chain must be
+    /// at least [MIN_TAIL_CHAIN_LEN] records long, so that packet bytes are unlikely to pass for records. Only the
+    /// offsets of the first [MAX_PLAUSIBLE_RECORD_LEN] bytes are tried, as one of them must start a record.
+    ///
+    /// [None] if there's no such chain, as when `tail` holds no whole record, or if the file's records have a custom
+    /// [RecordLayout]. The timestamp isn't corrected for wraparound.
+    pub fn last_timestamp_in(&self, tail: &[u8], starts_with_record: bool) -> Option<u64> {
+        let parse = match self.framing {
+            RecordFraming::Legacy { parse, .. } => parse,
+            RecordFraming::Layout { .. } => return None,
+        };
+        let ts_fraction_multiplier = self.ts_usec_multiplier as u64;
+        let max_fraction = 1_000_000_000 / ts_fraction_multiplier;
+        (0..tail.len().min(MAX_PLAUSIBLE_RECORD_LEN)).find_map(|start| {
+            let mut rest = &tail[start..];
+            let (mut n_records, mut last_ts) = (0, None);
+            while !rest.is_empty() {
+                let (remaining, record) = parse(rest).ok()?;
+                if record.ts_usec as u64 >= max_fraction
+                    || record.caplen as usize > MAX_PLAUSIBLE_CAPLEN
+                {
+                    return None;
+                }
+                let ts = record.ts_sec as u64 * 1_000_000_000
+                    + record.ts_usec as u64 * ts_fraction_multiplier;
+                if last_ts.is_some_and(|last_ts| ts < last_ts) {
+                    return None;
+                }
+                last_ts = Some(ts);
+                n_records += 1;
+                rest = remaining;
+            }
+            last_ts
+                .filter(|_| n_records >= MIN_TAIL_CHAIN_LEN || (start == 0 && starts_with_record))
         })
     }
 }
 
+impl<R> ParseOffset for Packets<R> {
+    fn offset(&self) -> u64 {
+        self.offset
+    }
+}
+
 impl<R: AsyncRead> Stream for Packets<R>
 where
     R: AsyncRead,
 {
-    type Item = Result<(u64, Bytes), nom::Err<PcapError>>;
+    type Item = Result<(u64, Bytes), PacketError>;
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         if self.buffer.is_empty() && self.reader_exhausted {
             return Poll::Ready(None); // EOF
         }
 
         loop {
-            match (self.as_mut().parse)(&self.buffer) {
-                Ok((rem, packet)) => {
-                    // TODO: write the nanosecond timestamp into the data??
-                    let nanosecond_ts = packet.ts_sec as u64 * 1000000000
-                        + packet.ts_usec as u64 * self.ts_usec_multiplier as u64;
-                    let packet_n_bytes = self.buffer.len() - rem.len();
-                    return Poll::Ready(Some(Ok((
-                        nanosecond_ts,
-                        self.as_mut()
-                            .project()
-                            .buffer
-                            .split_to(packet_n_bytes)
-                            .freeze(),
-                    ))));
-                }
-                Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
-                    return Poll::Ready(Some(Err(nom::Err::Error(e))))
+            match self.as_mut().next_record() {
+                Some(Ok((ts, record))) => {
+                    let this = self.as_mut().project();
+                    let (ts, mut record) = match this.processors.process(ts, record) {
+                        Some(packet) => packet,
+                        None => continue,
+                    };
+                    let key = match this.key_fn {
+                        Some(key_fn) => {
+                            // even a replacement record at the same timestamp must carry it
+                            if record_timestamp(&record) != ts {
+                                record = restamp_record(&record, ts);
+                            }
+                            *this.prev_key =
+                                key_fn(&record[RECORD_HEADER_LEN..]).unwrap_or(*this.prev_key);
+                            *this.prev_key
+                        }
+                        None => ts,
+                    };
+                    return Poll::Ready(Some(Ok((key, record))));
                 }
-                Err(_) => {
+                Some(Err(error)) => return Poll::Ready(Some(Err(error))),
+                None => {
                     // incomplete. get some more data from our underlying reader
                     let PacketsProj {
                         ts_usec_multiplier: _,
                         reader,
                         buffer,
-                        reader_exhausted: _,
-                        parse: _,
+                        reader_exhausted,
+                        framing: _,
+                        link_type: _,
+                        header_fields: _,
+                        record_header_extra_len: _,
+                        strict,
+                        fix_wraparound: _,
+                        prev_ts_sec: _,
+                        n_wraparounds: _,
+                        key_fn: _,
+                        prev_key: _,
+                        processors: _,
+                        offset: _,
                     } = self.as_mut().project();
 
                     let to_read = unsafe {
                         &mut *(buffer.bytes_mut() as *mut [std::mem::MaybeUninit<u8>]
                             as *mut [u8])
                     };
-                    if let Poll::Ready(Ok(n_bytes_read)) = reader.poll_read(cx, to_read) {
-                        // got more data! loop around to see whether we now have a complete packet
-                        if n_bytes_read == 0 {
-                            return Poll::Ready(None);
+                    match reader.poll_read(cx, to_read) {
+                        Poll::Ready(Ok(n_bytes_read)) => {
+                            // got more data! loop around to see whether we now have a complete packet
+                            if n_bytes_read == 0 {
+                                *reader_exhausted = true;
+                                let remaining_bytes = buffer.len();
+                                buffer.clear();
+                                if *strict && remaining_bytes > 0 {
+                                    return Poll::Ready(Some(Err(PacketError::TruncatedRecord {
+                                        remaining_bytes,
+                                    })));
+                                }
+                                return Poll::Ready(None);
+                            }
+                            unsafe {
+                                self.as_mut().project().buffer.advance_mut(n_bytes_read);
+                            }
                         }
-                        unsafe {
-                            self.as_mut().project().buffer.advance_mut(n_bytes_read);
+                        Poll::Ready(Err(error)) => {
+                            return Poll::Ready(Some(Err(PacketError::Read(error))))
                         }
-                    } else {
-                        return Poll::Pending; // our poll_read call will have scheduled our next wakeup for us
+                        Poll::Pending => return Poll::Pending, // our poll_read call will have scheduled our next wakeup for us
                     }
                 }
             }
         }
     }
 }
+
+/// Whether `bytes` begin with the magic number of a pcap file (of either byte order and timestamp precision, or the
+/// "modified" format) or the block type of a pcapng file's leading section header
+pub fn has_capture_magic(bytes: &[u8]) -> bool {
+    let nanosecond_magic_le = &PCAP_HDR_NSEC[..4];
+    let nanosecond_magic_be = [0xA1, 0xB2, 0x3C, 0x4D];
+    let pcapng_section_header = [0x0A, 0x0D, 0x0D, 0x0A];
+    [
+        nanosecond_magic_le,
+        &nanosecond_magic_be,
+        &MICROSECOND_MAGIC_LE,
+        &MICROSECOND_MAGIC_BE,
+        &MODIFIED_MAGIC_LE,
+        &MODIFIED_MAGIC_BE,
+        &pcapng_section_header,
+    ]
+    .iter()
+    .any(|magic| bytes.starts_with(magic))
+}
+
+/// Nanosecond timestamp in the little-endian, nanosecond-precision header of `record`, such as those yielded by
+/// [Packets::with_key_fn], [RawFramed] or a custom [RecordLayout]
+pub fn record_timestamp(record: &[u8]) -> u64 {
+    let mut seconds = [0; 4];
+    seconds.copy_from_slice(&record[..4]);
+    let mut nanoseconds = [0; 4];
+    nanoseconds.copy_from_slice(&record[4..8]);
+    u32::from_le_bytes(seconds) as u64 * 1000000000 + u32::from_le_bytes(nanoseconds) as u64
+}
+
+/// `record`, a little-endian record header and captured bytes, with its header's timestamp rewritten to `ts`
+fn restamp_record(record: &[u8], ts: u64) -> Bytes {
+    let mut restamped = BytesMut::from(record);
+    restamped[..4].copy_from_slice(&((ts / 1000000000) as u32).to_le_bytes());
+    restamped[4..8].copy_from_slice(&((ts % 1000000000) as u32).to_le_bytes());
+    restamped.freeze()
+}
+
+/// `record`, a little-endian record header and captured bytes, with `captured` in place of its captured bytes and both
+/// lengths in its header changed by the difference in length
+fn transform_record(record: &[u8], captured: Bytes) -> Bytes {
+    let original_len =
+        u32::from_le_bytes([record[12], record[13], record[14], record[15]]) as usize;
+    let original_len = (original_len + captured.len())
+        .saturating_sub(record.len() - RECORD_HEADER_LEN)
+        .max(captured.len());
+    let mut transformed = BytesMut::with_capacity(RECORD_HEADER_LEN + captured.len());
+    transformed.put_slice(&record[..8]);
+    transformed.put_u32_le(captured.len() as u32);
+    transformed.put_u32_le(original_len as u32);
+    transformed.put_slice(&captured);
+    transformed.freeze()
+}
+
+/// `record` (such as one yielded by [Packets]) with its captured bytes truncated to at most `snaplen`, and the captured
+/// length in its header rewritten to match. Its original length is kept, still giving the packet's length on the wire.
+pub fn truncate_record(record: Bytes, snaplen: usize) -> Bytes {
+    if record.len() - RECORD_HEADER_LEN <= snaplen {
+        return record;
+    }
+    let mut truncated = BytesMut::from(&record[..RECORD_HEADER_LEN + snaplen]);
+    truncated[8..12].copy_from_slice(&(snaplen as u32).to_le_bytes());
+    truncated.freeze()
+}
+
+/// The records of the pcap `file`, if they are already exactly as a merge would write them: `file` is a
+/// little-endian, nanosecond-precision pcap holding only complete records, in ascending time order. A merge of `file`
+/// alone could then write these bytes verbatim after its own global header, without parsing each packet.
+pub fn sorted_nanosecond_records(file: &[u8]) -> Option<&[u8]> {
+    const FILE_HEADER_LEN: usize = 24;
+    if file.len() < FILE_HEADER_LEN || file[..4] != PCAP_HDR_NSEC[..4] {
+        return None;
+    }
+    let records = &file[FILE_HEADER_LEN..];
+    let mut rest = records;
+    let mut prev_ts = 0;
+    while !rest.is_empty() {
+        if rest.len() < RECORD_HEADER_LEN {
+            return None;
+        }
+        let ts = record_timestamp(rest);
+        let mut caplen = [0; 4];
+        caplen.copy_from_slice(&rest[8..12]);
+        let record_len = RECORD_HEADER_LEN + u32::from_le_bytes(caplen) as usize;
+        if ts < prev_ts || rest.len() < record_len {
+            return None;
+        }
+        prev_ts = ts;
+        rest = &rest[record_len..];
+    }
+    Some(records)
+}
+
+/// Split the pcap read from `reader` into `outputs`: the inverse of a merge. `route` is called with the captured bytes
+/// of each packet (excluding the record header) and returns the index of the output the packet belongs to. Packets keep
+/// their relative order within each output.
+pub async fn demux<R, W, F>(reader: R, outputs: &mut [Writer<W>], mut route: F) -> Result<()>
+where
+    R: AsyncRead + std::marker::Unpin,
+    W: std::io::Write,
+    F: FnMut(&[u8]) -> usize,
+{
+    let mut packets = Packets::new(1024 * 64, reader)
+        .await
+        .map_err(|e| anyhow!("Invalid pcap header: {}", e))?;
+    while let Some(packet) = packets.next().await {
+        let (ts, record) = packet.map_err(|e| anyhow!("Failed to parse packet: {:?}", e))?;
+        let index = route(&record[RECORD_HEADER_LEN..]);
+        let n_outputs = outputs.len();
+        let output = outputs.get_mut(index).ok_or_else(|| {
+            anyhow!(
+                "Demux route returned output index {} but there are only {} outputs",
+                index,
+                n_outputs
+            )
+        })?;
+        output.write_packet(ts, &record)?;
+    }
+    Ok(())
+}
+
+/// Open the local (and optionally .gz or .zst compressed) pcap at `path` and parse it as a stream of `(timestamp, record)`
+/// tuples, like [crate::stream_and_decode_pcap_packets] but on the polling task itself rather than a spawned one. See
+/// [crate::stream_and_decode_reader]. Failing to open the file, read its header or parse a packet is yielded as an
+/// error item.
+pub fn stream_file(path: &str) -> impl Stream<Item = Result<(u64, Bytes)>> {
+    let path = path.to_string();
+    futures::stream::once(async move {
+        let file =
+            std::fs::File::open(&path).with_context(|| format!("Failed to open '{}'", path))?;
+        let packets = crate::stream_and_decode_reader(
+            smol::Unblock::with_capacity(1024 * 128, file),
+            crate::compression::Compression::from_path(&path),
+        );
+        Ok::<_, anyhow::Error>(
+            packets.map_err(move |e| e.context(format!("Failed to decode '{}'", path))),
+        )
+    })
+    .try_flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn modified_pcap(is_bigendian: bool, packets: &[(u32, u32, &[u8])]) -> Vec<u8> {
+        let u32_bytes = |value: u32| {
+            if is_bigendian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            }
+        };
+        let mut bytes = if is_bigendian {
+            MODIFIED_MAGIC_BE.to_vec()
+        } else {
+            MODIFIED_MAGIC_LE.to_vec()
+        };
+        for (major, minor) in [(2u16, 4u16)].iter() {
+            if is_bigendian {
+                bytes.extend_from_slice(&major.to_be_bytes());
+                bytes.extend_from_slice(&minor.to_be_bytes());
+            } else {
+                bytes.extend_from_slice(&major.to_le_bytes());
+                bytes.extend_from_slice(&minor.to_le_bytes());
+            }
+        }
+        for field in [0, 0, 65535, 1].iter() {
+            bytes.extend_from_slice(&u32_bytes(*field)); // thiszone, sigfigs, snaplen, linktype
+        }
+        for (ts_sec, ts_usec, data) in packets {
+            for field in [*ts_sec, *ts_usec, data.len() as u32, data.len() as u32].iter() {
+                bytes.extend_from_slice(&u32_bytes(*field));
+            }
+            bytes.extend_from_slice(&u32_bytes(7)); // ifindex
+            bytes.extend_from_slice(&[0x08, 0x00, 4, 0]); // protocol, pkt_type, pad
+            bytes.extend_from_slice(data);
+        }
+        bytes
+    }
+
+    #[test]
+    fn parses_modified_format_records() {
+        for is_bigendian in [false, true].iter() {
+            let packets: [(u32, u32, &[u8]); 3] = [
+                (10, 1, &[1; 60]),
+                (10, 999_999, &[2; 1]),
+                (12, 5, &[3; 1500]),
+            ];
+            let bytes = modified_pcap(*is_bigendian, &packets);
+            let parsed: Vec<_> = futures::executor::block_on(async {
+                Packets::new(64, &bytes[..])
+                    .await
+                    .unwrap()
+                    .map(Result::unwrap)
+                    .collect()
+                    .await
+            });
+
+            assert_eq!(parsed.len(), packets.len());
+            for ((ts, record), (ts_sec, ts_usec, data)) in parsed.iter().zip(packets.iter()) {
+                assert_eq!(*ts, *ts_sec as u64 * 1_000_000_000 + *ts_usec as u64 * 1000);
+                assert_eq!(record.len(), RECORD_HEADER_LEN + data.len());
+                assert_eq!(record[8..12], (data.len() as u32).to_le_bytes()); // in either byte order
+                assert_eq!(&record[RECORD_HEADER_LEN..], *data);
+            }
+        }
+    }
+
+    #[test]
+    fn parses_records_with_a_custom_layout() {
+        // a 24-byte vendor header: a u32 of flags, a u64 nanosecond timestamp, a u32 port, the u32 caplen and padding
+        let layout = RecordLayout {
+            header_len: 24,
+            ts_offset: 4,
+            ts_format: TimestampFormat::Nanoseconds,
+            caplen_offset: 16,
+        };
+        let packets: [(u64, &[u8]); 3] = [
+            (1_637_796_620_123_456_789, &[1; 60]),
+            (1_637_796_620_123_456_790, &[]),
+            (1_637_796_621_000_000_001, &[3; 1500]),
+        ];
+        for is_bigendian in [false, true].iter() {
+            let mut bytes = PCAP_HDR_NSEC.to_vec();
+            if *is_bigendian {
+                bytes[..4].copy_from_slice(&MICROSECOND_MAGIC_BE);
+            }
+            for (ts, data) in packets.iter() {
+                let (ts, caplen) = if *is_bigendian {
+                    (ts.to_be_bytes(), (data.len() as u32).to_be_bytes())
+                } else {
+                    (ts.to_le_bytes(), (data.len() as u32).to_le_bytes())
+                };
+                bytes.extend_from_slice(&[0xFF; 4]);
+                bytes.extend_from_slice(&ts);
+                bytes.extend_from_slice(&[0xEE; 4]);
+                bytes.extend_from_slice(&caplen);
+                bytes.extend_from_slice(&[0xDD; 4]);
+                bytes.extend_from_slice(data);
+            }
+
+            let parsed: Vec<_> = futures::executor::block_on(async {
+                Packets::with_record_layout(64, &bytes[..], layout)
+                    .await
+                    .unwrap()
+                    .map(Result::unwrap)
+                    .collect()
+                    .await
+            });
+
+            assert_eq!(parsed.len(), packets.len());
+            for ((ts, record), (expected_ts, data)) in parsed.iter().zip(packets.iter()) {
+                assert_eq!(ts, expected_ts);
+                assert_eq!(&record[..4], &((ts / 1_000_000_000) as u32).to_le_bytes());
+                assert_eq!(&record[4..8], &((ts % 1_000_000_000) as u32).to_le_bytes());
+                assert_eq!(&record[8..12], &(data.len() as u32).to_le_bytes());
+                assert_eq!(&record[RECORD_HEADER_LEN..], *data);
+            }
+        }
+    }
+
+    #[test]
+    fn custom_layout_record_longer_than_any_plausible_one_is_an_error_at_its_offset() {
+        let layout = RecordLayout {
+            header_len: 16,
+            ts_offset: 0,
+            ts_format: TimestampFormat::Nanoseconds,
+            caplen_offset: 12,
+        };
+        let mut bytes = PCAP_HDR_NSEC.to_vec();
+        bytes.extend_from_slice(&1000u64.to_le_bytes());
+        bytes.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        bytes.push(1);
+        bytes.extend_from_slice(&2000u64.to_le_bytes());
+        bytes.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+        bytes.extend_from_slice(&[2; 100]);
+
+        let parsed: Vec<_> = futures::executor::block_on(async {
+            Packets::with_record_layout(64, &bytes[..], layout)
+                .await
+                .unwrap()
+                .located("a.pcap")
+                .collect()
+                .await
+        });
+
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].as_ref().unwrap().0, 1000);
+        let error = parsed[1].as_ref().unwrap_err();
+        assert_eq!(error.offset, 24 + 17);
+        assert!(matches!(
+            error.kind,
+            PacketError::RecordTooLong {
+                record_len,
+                max_len,
+            } if record_len == 16 + u32::MAX as usize && max_len == 16 + MAX_PLAUSIBLE_CAPLEN
+        ));
+    }
+
+    #[test]
+    fn last_timestamp_is_only_searched_for_from_the_first_plausible_records_length_of_the_tail() {
+        let packets = futures::executor::block_on(Packets::new(64, PCAP_HDR_NSEC)).unwrap();
+        let mut records = Vec::new();
+        for ts_nsec in 1..=MIN_TAIL_CHAIN_LEN as u32 {
+            for field in [1, ts_nsec, 1, 1].iter() {
+                records.extend_from_slice(&field.to_le_bytes());
+            }
+            records.push(0);
+        }
+        // bytes which can't start a record, as their fraction of a second is out of range
+        let tail = |n_garbage_bytes: usize| [vec![0xFF; n_garbage_bytes], records.clone()].concat();
+
+        assert_eq!(
+            packets.last_timestamp_in(&tail(MAX_PLAUSIBLE_RECORD_LEN - 1), false),
+            Some(1_000_000_000 + MIN_TAIL_CHAIN_LEN as u64)
+        );
+        assert_eq!(
+            packets.last_timestamp_in(&tail(MAX_PLAUSIBLE_RECORD_LEN), false),
+            None
+        );
+    }
+
+    #[test]
+    fn processed_packets_are_dropped_or_restamped_before_the_merge() {
+        let pcap = |timestamps: &[u32]| {
+            let mut bytes = PCAP_HDR_NSEC.to_vec();
+            for ts_nsec in timestamps {
+                for field in [0, *ts_nsec, 1, 1].iter() {
+                    bytes.extend_from_slice(&field.to_le_bytes());
+                }
+                bytes.push(*ts_nsec as u8);
+            }
+            bytes
+        };
+        let (a, b) = (pcap(&[10, 11, 30, 31, 50]), pcap(&[20, 21, 40, 41]));
+        // drops every odd-indexed packet and delays the rest by 5ns, merging by their captured byte so that their
+        // timestamps are carried in their headers
+        let processed = |bytes: &[u8]| -> Vec<(u64, Bytes)> {
+            let mut index = 0;
+            futures::executor::block_on(async {
+                Packets::new(64, bytes)
+                    .await
+                    .unwrap()
+                    .with_key_fn(|captured| Some(captured[0] as u64))
+                    .with_processor(move |ts, record| {
+                        index += 1;
+                        match index % 2 {
+                            0 => PacketAction::Drop,
+                            _ => PacketAction::Replace(ts + 5, record),
+                        }
+                    })
+                    .map(Result::unwrap)
+                    .collect()
+                    .await
+            })
+        };
+
+        let mut tree = crate::tournament_tree::OwnedTree::new(vec![
+            crate::tournament_tree::PacketStream::new(processed(&a).into_iter()),
+            crate::tournament_tree::PacketStream::new(processed(&b).into_iter()),
+        ]);
+        let mut merged = Vec::new();
+        while let Some((key, record)) = tree.pop() {
+            merged.push((key, record_timestamp(&record)));
+        }
+        assert_eq!(merged, [(10, 15), (20, 25), (30, 35), (40, 45), (50, 55)]);
+    }
+
+    #[test]
+    fn processed_timestamps_leave_records_as_read_without_a_key_fn() {
+        let mut bytes = PCAP_HDR_NSEC.to_vec();
+        bytes[..4].copy_from_slice(&MICROSECOND_MAGIC_LE);
+        for (ts_sec, ts_usec) in [(1u32, 2), (3, 4)].iter() {
+            for field in [*ts_sec, *ts_usec, 1, 1].iter() {
+                bytes.extend_from_slice(&field.to_le_bytes());
+            }
+            bytes.push(0);
+        }
+        let packets: Vec<(u64, Bytes)> = futures::executor::block_on(async {
+            Packets::new(64, &bytes[..])
+                .await
+                .unwrap()
+                .with_processor(|ts, record| match ts {
+                    1_000_002_000 => PacketAction::Keep,
+                    _ => PacketAction::Replace(ts + 5, record),
+                })
+                .map(Result::unwrap)
+                .collect()
+                .await
+        });
+        assert_eq!(packets[0].0, 1_000_002_000);
+        assert_eq!(packets[0].1[..8], bytes[24..32]);
+        // the writer, not the processor, puts the new timestamp in the header
+        assert_eq!(packets[1].0, 3_000_004_005);
+        assert_eq!(packets[1].1[..8], bytes[41..49]);
+    }
+
+    #[test]
+    fn corrects_wrapped_around_seconds() {
+        let mut bytes = PCAP_HDR_NSEC.to_vec();
+        let seconds = [u32::MAX - 1, u32::MAX, 0, 1, 0, 3];
+        for ts_sec in seconds.iter() {
+            for field in [*ts_sec, 500, 4, 4].iter() {
+                bytes.extend_from_slice(&field.to_le_bytes());
+            }
+            bytes.extend_from_slice(&[7; 4]);
+        }
+        let parse = |fix_wraparound| -> Vec<u64> {
+            futures::executor::block_on(async {
+                Packets::new(64, &bytes[..])
+                    .await
+                    .unwrap()
+                    .fix_wraparound(fix_wraparound)
+                    .map(|packet| packet.unwrap().0)
+                    .collect()
+                    .await
+            })
+        };
+
+        let wrapped = (u32::MAX as u64 + 1) * 1_000_000_000;
+        assert_eq!(
+            parse(true),
+            vec![
+                wrapped - 2_000_000_000 + 500,
+                wrapped - 1_000_000_000 + 500,
+                wrapped + 500,
+                wrapped + 1_000_000_000 + 500,
+                wrapped + 500, // a small backward jump is left for the merge to treat as out of order
+                wrapped + 3_000_000_000 + 500,
+            ]
+        );
+        assert_eq!(parse(false)[2], 500);
+    }
+
+    #[test]
+    fn record_longer_than_the_buffer_is_read_whole_or_an_error() {
+        let record = |caplen: u32, data_len: usize| {
+            let mut bytes = PCAP_HDR_NSEC.to_vec();
+            for field in [1, 0, caplen, caplen].iter() {
+                bytes.extend_from_slice(&field.to_le_bytes());
+            }
+            bytes.extend_from_slice(&vec![7; data_len]);
+            bytes
+        };
+        let parse = |bytes: Vec<u8>| -> Vec<_> {
+            futures::executor::block_on(async {
+                Packets::new(64, &bytes[..])
+                    .await
+                    .unwrap()
+                    .strict(true)
+                    .collect()
+                    .await
+            })
+        };
+
+        // far past the end of the input and the 64 byte buffer, so never split off
+        let parsed = parse(record(1_000_000, 10));
+        assert_eq!(parsed.len(), 1);
+        match &parsed[0] {
+            Err(PacketError::TruncatedRecord { remaining_bytes }) => {
+                assert_eq!(*remaining_bytes, RECORD_HEADER_LEN + 10)
+            }
+            other => panic!("expected a truncated record error, got {:?}", other),
+        }
+
+        // the buffer grows to hold a whole record longer than it
+        let parsed = parse(record(200, 200));
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].as_ref().unwrap().1.len(), RECORD_HEADER_LEN + 200);
+    }
+
+    #[test]
+    fn only_sorted_nanosecond_records_are_verbatim() {
+        let pcap = |seconds: &[u32]| {
+            let mut bytes = PCAP_HDR_NSEC.to_vec();
+            for ts_sec in seconds {
+                for field in [*ts_sec, 0, 4, 4].iter() {
+                    bytes.extend_from_slice(&field.to_le_bytes());
+                }
+                bytes.extend_from_slice(&[7; 4]);
+            }
+            bytes
+        };
+
+        let sorted = pcap(&[1, 2, 2, 3]);
+        assert_eq!(sorted_nanosecond_records(&sorted), Some(&sorted[24..]));
+        assert_eq!(sorted_nanosecond_records(&sorted[..24]), Some(&[][..]));
+        assert_eq!(sorted_nanosecond_records(&sorted[..sorted.len() - 1]), None); // truncated
+        assert_eq!(sorted_nanosecond_records(&pcap(&[1, 3, 2])), None); // unsorted
+        let mut microsecond = sorted.clone();
+        microsecond[..4].copy_from_slice(&MICROSECOND_MAGIC_LE);
+        assert_eq!(sorted_nanosecond_records(&microsecond), None);
+    }
+
+    #[test]
+    fn truncated_final_record_is_an_error_only_in_strict_mode() {
+        let mut bytes = PCAP_HDR_NSEC.to_vec();
+        for (ts, len) in [(1u32, 40u32), (2, 40)].iter() {
+            for field in [*ts, 0, *len, *len].iter() {
+                bytes.extend_from_slice(&field.to_le_bytes());
+            }
+            bytes.extend_from_slice(&vec![7; *len as usize]);
+        }
+        let truncated = &bytes[..bytes.len() - 15]; // the second record is missing its last 15 bytes
+
+        let parse = |strict| -> Vec<_> {
+            futures::executor::block_on(async {
+                Packets::new(64, truncated)
+                    .await
+                    .unwrap()
+                    .strict(strict)
+                    .collect()
+                    .await
+            })
+        };
+
+        let parsed = parse(false);
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].as_ref().unwrap().0, 1_000_000_000);
+
+        let parsed = parse(true);
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].as_ref().unwrap().0, 1_000_000_000);
+        match &parsed[1] {
+            Err(PacketError::TruncatedRecord { remaining_bytes }) => {
+                assert_eq!(*remaining_bytes, RECORD_HEADER_LEN + 40 - 15)
+            }
+            other => panic!("expected a truncated record error, got {:?}", other),
+        }
+    }
+}
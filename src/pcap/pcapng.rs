@@ -0,0 +1,928 @@
+//! The pcapng format
+//!
+//! Unlike a pcap file header, which only distinguishes microsecond from nanosecond precision, each pcapng interface
+//! declares the unit of its packets' timestamps with its `if_tsresol` option, which may be any negative power of 10 or
+//! 2. [PcapngPackets] normalizes each Enhanced Packet Block's timestamp with the [TsResolution] of the interface it
+//! was captured on, yielding the same nanosecond-precision records as [super::Packets]. The [Interface] each packet
+//! was captured on is described by an Interface Description Block, which [super::Writer] writes for each interface of
+//! its pcapng output. Each packet's [PacketMeta], decoded from its block's options, is available alongside it with
+//! [PcapngPackets::with_meta].
+
+use super::{PacketAction, PacketError, Processors, RECORD_HEADER_LEN};
+use bytes::buf::BufMut;
+use bytes::{Bytes, BytesMut};
+use futures::io::AsyncRead;
+use futures::stream::Stream;
+use futures::task::Poll;
+use pcap_parser::PcapError;
+use pin_project_lite::pin_project;
+use std::pin::Pin;
+use std::task::Context;
+
+const NANOSECONDS_PER_SECOND: u64 = 1_000_000_000;
+
+const SECTION_HEADER_BLOCK: u32 = 0x0A0D_0D0A;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 1;
+const ENHANCED_PACKET_BLOCK: u32 = 6;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+
+const OPT_ENDOFOPT: u16 = 0;
+const IF_NAME: u16 = 2;
+const IF_TSRESOL: u16 = 9;
+const EPB_FLAGS: u16 = 2;
+const EPB_DROPCOUNT: u16 = 4;
+
+/// Size of a block's type and total length, ahead of its body
+const BLOCK_HEADER_LEN: usize = 8;
+/// Size of the fields of an Enhanced Packet Block's body ahead of its packet data: the interface ID, the upper and
+/// lower 32 bits of the timestamp, and the captured and original lengths
+const EPB_FIELDS_LEN: usize = 20;
+/// Size of the header [write_enhanced_packet_header] writes ahead of a packet's data
+pub(crate) const EPB_HEADER_LEN: usize = BLOCK_HEADER_LEN + EPB_FIELDS_LEN;
+/// Most bytes a block may have before it's taken for corruption rather than buffered: far more than any packet's
+/// Enhanced Packet Block with its options, or any other block a capture writes
+const MAX_BLOCK_LEN: usize = 16 << 20;
+
+/// Link type of Ethernet, assumed for inputs which don't declare their own
+pub const LINKTYPE_ETHERNET: u16 = 1;
+
+/// Direction of a packet relative to the interface it was captured on, as given by the `epb_flags` option of its
+/// Enhanced Packet Block
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+/// What an Enhanced Packet Block records of its packet beyond its timestamp and bytes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PacketMeta {
+    /// ID of the interface the packet was captured on, indexing the section's [PcapngPackets::interfaces]
+    pub interface_id: u32,
+    /// from the direction bits of the `epb_flags` option, unless it's absent or they're unset
+    pub direction: Option<Direction>,
+    /// packets lost between this packet and the one before it on its interface, from the `epb_dropcount` option
+    pub drop_count: Option<u64>,
+}
+
+/// Length of a [PacketMeta] as [PacketMeta::encode] encodes it
+pub const PACKET_META_LEN: usize = 4 + 1 + 8;
+
+impl PacketMeta {
+    /// The little-endian `u32` interface ID, a byte giving the direction (0 if unknown, 1 inbound, 2 outbound, as in
+    /// the `epb_flags` option), then the little-endian `u64` drop count (`u64::MAX` if unknown), as written by
+    /// [super::Writer::packet_meta]
+    pub fn encode(&self) -> [u8; PACKET_META_LEN] {
+        let mut encoded = [0; PACKET_META_LEN];
+        encoded[..4].copy_from_slice(&self.interface_id.to_le_bytes());
+        encoded[4] = match self.direction {
+            None => 0,
+            Some(Direction::Inbound) => 1,
+            Some(Direction::Outbound) => 2,
+        };
+        encoded[5..].copy_from_slice(&self.drop_count.unwrap_or(u64::MAX).to_le_bytes());
+        encoded
+    }
+
+    /// `record` with this metadata [encoded](PacketMeta::encode) after its captured bytes, to be carried through a
+    /// merge alongside it and split off again with [PacketMeta::split_from]
+    pub fn append_to(&self, record: &[u8]) -> Bytes {
+        let mut appended = BytesMut::with_capacity(record.len() + PACKET_META_LEN);
+        appended.extend_from_slice(record);
+        appended.extend_from_slice(&self.encode());
+        appended.freeze()
+    }
+
+    /// The record and metadata of a `record` given its metadata by [PacketMeta::append_to]
+    pub fn split_from(mut record: Bytes) -> std::io::Result<(Bytes, PacketMeta)> {
+        if record.len() < PACKET_META_LEN {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Packet record is too short to carry its metadata",
+            ));
+        }
+        let encoded = record.split_off(record.len() - PACKET_META_LEN);
+        let direction = match encoded[4] {
+            0 => None,
+            1 => Some(Direction::Inbound),
+            2 => Some(Direction::Outbound),
+            direction => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("Packet metadata has an unknown direction {}", direction),
+                ))
+            }
+        };
+        let mut drop_count = [0; 8];
+        drop_count.copy_from_slice(&encoded[5..]);
+        let meta = PacketMeta {
+            interface_id: read_u32(&encoded, false),
+            direction,
+            drop_count: Some(u64::from_le_bytes(drop_count)).filter(|count| *count != u64::MAX),
+        };
+        Ok((record, meta))
+    }
+
+    /// Decode the `options` of an Enhanced Packet Block on the `interface_id`th interface. Unknown options, and
+    /// those of the wrong length, are ignored.
+    fn parse(interface_id: u32, options: &[u8], big_endian: bool) -> PacketMeta {
+        let mut meta = PacketMeta {
+            interface_id,
+            ..PacketMeta::default()
+        };
+        for (code, value) in Options::new(options, big_endian) {
+            match code {
+                EPB_FLAGS if value.len() == 4 => {
+                    meta.direction = match read_u32(value, big_endian) & 0b11 {
+                        1 => Some(Direction::Inbound),
+                        2 => Some(Direction::Outbound),
+                        _ => None,
+                    }
+                }
+                EPB_DROPCOUNT if value.len() == 8 => {
+                    let mut bytes = [0; 8];
+                    bytes.copy_from_slice(value);
+                    meta.drop_count = Some(match big_endian {
+                        true => u64::from_be_bytes(bytes),
+                        false => u64::from_le_bytes(bytes),
+                    });
+                }
+                _ => {}
+            }
+        }
+        meta
+    }
+}
+
+/// Unit of an interface's timestamps, as given by its `if_tsresol` option
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TsResolution {
+    /// timestamps count units of 10^-exponent seconds
+    Decimal(u8),
+    /// timestamps count units of 2^-exponent seconds
+    Binary(u8),
+}
+
+impl Default for TsResolution {
+    /// Microseconds, for an interface without an `if_tsresol` option
+    fn default() -> TsResolution {
+        TsResolution::Decimal(6)
+    }
+}
+
+impl TsResolution {
+    /// The resolution encoded by an `if_tsresol` option's value: the exponent in its low 7 bits, of 2 if its most
+    /// significant bit is set and otherwise of 10
+    pub fn from_option(value: u8) -> TsResolution {
+        let exponent = value & 0x7F;
+        if value & 0x80 == 0 {
+            TsResolution::Decimal(exponent)
+        } else {
+            TsResolution::Binary(exponent)
+        }
+    }
+
+    /// Nanoseconds since the epoch of a timestamp of `ts` units, truncating any finer precision. Saturates at
+    /// [u64::MAX] rather than overflowing.
+    pub fn to_nanoseconds(self, ts: u64) -> u64 {
+        let ns = match self {
+            TsResolution::Decimal(exponent) if exponent <= 9 => {
+                ts as u128 * 10u128.pow(9 - exponent as u32)
+            }
+            TsResolution::Decimal(exponent) => {
+                // beyond 10^-38 seconds, a u64 timestamp is always less than a nanosecond
+                10u128
+                    .checked_pow(exponent as u32 - 9)
+                    .map_or(0, |divisor| ts as u128 / divisor)
+            }
+            TsResolution::Binary(exponent) => {
+                (ts as u128 * NANOSECONDS_PER_SECOND as u128) >> exponent
+            }
+        };
+        ns.min(u64::MAX as u128) as u64
+    }
+
+    /// Nanoseconds since the epoch of an Enhanced Packet Block's timestamp, given its upper and lower 32 bits
+    pub fn epb_nanoseconds(self, ts_high: u32, ts_low: u32) -> u64 {
+        self.to_nanoseconds((ts_high as u64) << 32 | ts_low as u64)
+    }
+}
+
+/// A capture interface, as described by a pcapng Interface Description Block
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Interface {
+    /// the link-layer header type of the interface's packets (e.g. 1 for Ethernet)
+    pub link_type: u16,
+    /// the most bytes captured of each of the interface's packets, or 0 for no limit
+    pub snaplen: u32,
+    /// the interface's `if_name` option, e.g. `eth0`
+    pub name: Option<String>,
+    pub ts_resolution: TsResolution,
+}
+
+impl Interface {
+    /// An unnamed interface with the given `link_type`, microsecond timestamps and no snaplen
+    pub fn new(link_type: u16) -> Interface {
+        Interface {
+            link_type,
+            snaplen: 0,
+            name: None,
+            ts_resolution: TsResolution::default(),
+        }
+    }
+
+    /// Parse the body of an Interface Description Block, given in the section's byte order
+    fn parse(body: &[u8], big_endian: bool) -> Result<Interface, PacketError> {
+        if body.len() < 8 {
+            return Err(PacketError::InvalidPcapng(
+                "Interface Description Block is too short",
+            ));
+        }
+        let mut interface = Interface {
+            snaplen: read_u32(&body[4..], big_endian),
+            ..Interface::new(read_u16(body, big_endian))
+        };
+        for (code, value) in Options::new(&body[8..], big_endian) {
+            match code {
+                IF_NAME => interface.name = Some(String::from_utf8_lossy(value).into_owned()),
+                IF_TSRESOL if !value.is_empty() => {
+                    interface.ts_resolution = TsResolution::from_option(value[0])
+                }
+                _ => {}
+            }
+        }
+        Ok(interface)
+    }
+
+    /// Append an Interface Description Block for this interface, declaring nanosecond timestamps whatever the
+    /// interface's own [TsResolution], to `out`
+    pub(crate) fn write_description_block(&self, out: &mut Vec<u8>) {
+        let mut body = Vec::new();
+        body.extend_from_slice(&self.link_type.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes()); // reserved
+        body.extend_from_slice(&self.snaplen.to_le_bytes());
+        if let Some(name) = &self.name {
+            push_option(&mut body, IF_NAME, name.as_bytes());
+        }
+        push_option(&mut body, IF_TSRESOL, &[9]);
+        push_option(&mut body, OPT_ENDOFOPT, &[]);
+        push_block(out, INTERFACE_DESCRIPTION_BLOCK, &body);
+    }
+}
+
+/// Append a little-endian Section Header Block, of a section of unspecified length without options, to `out`
+pub(crate) fn write_section_header_block(out: &mut Vec<u8>) {
+    let mut body = Vec::new();
+    body.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+    body.extend_from_slice(&1u16.to_le_bytes()); // major version
+    body.extend_from_slice(&0u16.to_le_bytes()); // minor version
+    body.extend_from_slice(&(-1i64).to_le_bytes()); // section length
+    push_block(out, SECTION_HEADER_BLOCK, &body);
+}
+
+/// Append the header of a little-endian Enhanced Packet Block for `captured_len` bytes of data, captured on the
+/// `interface`th interface at `ts` nanoseconds, to `out`. The block is completed by the data itself, then the bytes
+/// returned by [enhanced_packet_trailer].
+pub(crate) fn write_enhanced_packet_header(
+    out: &mut Vec<u8>,
+    interface: u32,
+    ts: u64,
+    captured_len: u32,
+    original_len: u32,
+) {
+    let block_len = enhanced_packet_block_len(captured_len);
+    out.extend_from_slice(&ENHANCED_PACKET_BLOCK.to_le_bytes());
+    out.extend_from_slice(&block_len.to_le_bytes());
+    out.extend_from_slice(&interface.to_le_bytes());
+    out.extend_from_slice(&((ts >> 32) as u32).to_le_bytes());
+    out.extend_from_slice(&(ts as u32).to_le_bytes());
+    out.extend_from_slice(&captured_len.to_le_bytes());
+    out.extend_from_slice(&original_len.to_le_bytes());
+}
+
+/// The padding and trailing block length which complete an Enhanced Packet Block of `captured_len` bytes of data
+pub(crate) fn enhanced_packet_trailer(captured_len: u32) -> ([u8; 7], usize) {
+    let padding = padding_len(captured_len as usize);
+    let mut trailer = [0; 7];
+    trailer[padding..padding + 4]
+        .copy_from_slice(&enhanced_packet_block_len(captured_len).to_le_bytes());
+    (trailer, padding + 4)
+}
+
+fn enhanced_packet_block_len(captured_len: u32) -> u32 {
+    (EPB_HEADER_LEN + captured_len as usize + padding_len(captured_len as usize) + 4) as u32
+}
+
+/// Bytes of padding following `len` bytes to align them to 32 bits
+fn padding_len(len: usize) -> usize {
+    (4 - len % 4) % 4
+}
+
+/// Append an option with the given `code` and `value`, padded to 32 bits, to `out`
+fn push_option(out: &mut Vec<u8>, code: u16, value: &[u8]) {
+    out.extend_from_slice(&code.to_le_bytes());
+    out.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    out.extend_from_slice(value);
+    out.resize(out.len() + padding_len(value.len()), 0);
+}
+
+/// Append a little-endian block of type `block_type` with the given (already padded) `body` to `out`
+fn push_block(out: &mut Vec<u8>, block_type: u32, body: &[u8]) {
+    let block_len = (BLOCK_HEADER_LEN + body.len() + 4) as u32;
+    out.extend_from_slice(&block_type.to_le_bytes());
+    out.extend_from_slice(&block_len.to_le_bytes());
+    out.extend_from_slice(body);
+    out.extend_from_slice(&block_len.to_le_bytes());
+}
+
+fn read_u16(bytes: &[u8], big_endian: bool) -> u16 {
+    let bytes = [bytes[0], bytes[1]];
+    if big_endian {
+        u16::from_be_bytes(bytes)
+    } else {
+        u16::from_le_bytes(bytes)
+    }
+}
+
+fn read_u32(bytes: &[u8], big_endian: bool) -> u32 {
+    let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+    if big_endian {
+        u32::from_be_bytes(bytes)
+    } else {
+        u32::from_le_bytes(bytes)
+    }
+}
+
+/// Iterator over the `(code, value)` options of a block, up to its `opt_endofopt` or the end of its options
+struct Options<'a> {
+    options: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Options<'a> {
+    fn new(options: &'a [u8], big_endian: bool) -> Options<'a> {
+        Options {
+            options,
+            big_endian,
+        }
+    }
+}
+
+impl<'a> Iterator for Options<'a> {
+    type Item = (u16, &'a [u8]);
+
+    fn next(&mut self) -> Option<(u16, &'a [u8])> {
+        if self.options.len() < 4 {
+            return None;
+        }
+        let code = read_u16(self.options, self.big_endian);
+        let len = read_u16(&self.options[2..], self.big_endian) as usize;
+        if code == OPT_ENDOFOPT || self.options.len() < 4 + len {
+            return None;
+        }
+        let value = &self.options[4..4 + len];
+        self.options = &self.options[(4 + len + padding_len(len)).min(self.options.len())..];
+        Some((code, value))
+    }
+}
+
+pin_project! {
+    /// [AsyncRead] combinator type for parsing a pcapng file into the same [Stream] of nanosecond-precision timestamped
+    /// records as [super::Packets]. Each Enhanced Packet Block is given a synthesized little-endian pcap record
+    /// header, with its timestamp normalized by the [TsResolution] of its interface. Blocks of any other type (such as
+    /// Simple Packet Blocks, which have no timestamp) are skipped.
+    pub struct PcapngPackets<R> {
+        #[pin]
+        reader: R,
+        buffer: BytesMut,
+        reader_exhausted: bool,
+        strict: bool,
+        // byte order of the current section, once its Section Header Block has been read
+        big_endian: Option<bool>,
+        interfaces: Vec<Interface>,
+        only_interface: Option<u32>,
+        processors: Processors,
+        offset: u64,
+        // of the packet yielded last
+        meta: PacketMeta,
+    }
+}
+
+pin_project! {
+    /// Stream returned by [PcapngPackets::with_meta]
+    pub struct WithMeta<R> {
+        #[pin]
+        packets: PcapngPackets<R>,
+    }
+}
+
+impl<R: AsyncRead> PcapngPackets<R> {
+    pub fn new(capacity: usize, reader: R) -> PcapngPackets<R> {
+        PcapngPackets {
+            reader,
+            buffer: BytesMut::with_capacity(capacity),
+            reader_exhausted: false,
+            strict: false,
+            big_endian: None,
+            interfaces: Vec::new(),
+            only_interface: None,
+            processors: Processors::default(),
+            offset: 0,
+            meta: PacketMeta::default(),
+        }
+    }
+
+    /// Yield each packet's [PacketMeta] alongside its timestamp and record
+    pub fn with_meta(self) -> WithMeta<R> {
+        WithMeta { packets: self }
+    }
+
+    /// As for [super::Packets::strict], yield input ending part way through a block as a
+    /// [PacketError::TruncatedRecord]
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Only yield the packets captured on the `interface`th interface of the file's section, skipping the rest
+    pub fn only_interface(mut self, interface: Option<u32>) -> Self {
+        self.only_interface = interface;
+        self
+    }
+
+    /// As for [super::Packets::with_processor], pass each packet to `processor` to drop, keep or replace it
+    pub fn with_processor(
+        mut self,
+        processor: impl FnMut(u64, Bytes) -> PacketAction + Send + Sync + 'static,
+    ) -> Self {
+        self.processors.push(processor);
+        self
+    }
+
+    /// The interfaces described by the current section so far, indexed by interface ID. Every interface described
+    /// ahead of a packet is known once that packet has been yielded.
+    pub fn interfaces(&self) -> &[Interface] {
+        &self.interfaces
+    }
+}
+
+impl<R: AsyncRead> Stream for PcapngPackets<R> {
+    type Item = Result<(u64, Bytes), PacketError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        loop {
+            if this.buffer.len() >= BLOCK_HEADER_LEN + 4 {
+                let block_type = read_u32(this.buffer, this.big_endian.unwrap_or(false));
+                if block_type == SECTION_HEADER_BLOCK {
+                    // each section declares its own byte order, with the magic following the block's length
+                    *this.big_endian = match read_u32(&this.buffer[BLOCK_HEADER_LEN..], false) {
+                        BYTE_ORDER_MAGIC => Some(false),
+                        magic if magic.swap_bytes() == BYTE_ORDER_MAGIC => Some(true),
+                        _ => {
+                            return Poll::Ready(Some(Err(PacketError::Pcap(
+                                PcapError::HeaderNotRecognized,
+                            ))))
+                        }
+                    };
+                    this.interfaces.clear();
+                }
+                let big_endian = match *this.big_endian {
+                    Some(big_endian) => big_endian,
+                    None => {
+                        return Poll::Ready(Some(Err(PacketError::Pcap(
+                            PcapError::HeaderNotRecognized,
+                        ))))
+                    }
+                };
+                let block_len = read_u32(&this.buffer[4..], big_endian) as usize;
+                if block_len < BLOCK_HEADER_LEN + 4 || !block_len.is_multiple_of(4) {
+                    return Poll::Ready(Some(Err(PacketError::InvalidPcapng(
+                        "block length is not a multiple of 4 bytes of at least 12",
+                    ))));
+                }
+                if block_len > MAX_BLOCK_LEN {
+                    // end the stream here, at the block's offset, rather than reading the rest of the input into it
+                    this.buffer.clear();
+                    *this.reader_exhausted = true;
+                    return Poll::Ready(Some(Err(PacketError::RecordTooLong {
+                        record_len: block_len,
+                        max_len: MAX_BLOCK_LEN,
+                    })));
+                }
+                if this.buffer.len() >= block_len {
+                    let block = this.buffer.split_to(block_len).freeze();
+                    let body = block.slice(BLOCK_HEADER_LEN..block_len - 4);
+                    let packet = match block_type {
+                        INTERFACE_DESCRIPTION_BLOCK => {
+                            this.interfaces.push(Interface::parse(&body, big_endian)?);
+                            None
+                        }
+                        ENHANCED_PACKET_BLOCK => enhanced_packet(
+                            &body,
+                            big_endian,
+                            this.interfaces,
+                            *this.only_interface,
+                        )?
+                        .and_then(|(ts, record, meta)| {
+                            *this.meta = meta;
+                            this.processors.process(ts, record)
+                        }),
+                        _ => None, // no packet with a timestamp to merge
+                    };
+                    *this.offset += block_len as u64;
+                    match packet {
+                        Some(packet) => return Poll::Ready(Some(Ok(packet))),
+                        None => continue,
+                    }
+                }
+                this.buffer.reserve(block_len - this.buffer.len()); // make room for the rest of a large block
+            }
+            if *this.reader_exhausted {
+                let remaining_bytes = this.buffer.len();
+                this.buffer.clear();
+                if *this.strict && remaining_bytes > 0 {
+                    return Poll::Ready(Some(Err(PacketError::TruncatedRecord {
+                        remaining_bytes,
+                    })));
+                }
+                return Poll::Ready(None); // EOF
+            }
+
+            // incomplete. get some more data from our underlying reader
+            let to_read = unsafe {
+                &mut *(this.buffer.bytes_mut() as *mut [std::mem::MaybeUninit<u8>] as *mut [u8])
+            };
+            match this.reader.as_mut().poll_read(cx, to_read) {
+                Poll::Ready(Ok(0)) => *this.reader_exhausted = true,
+                Poll::Ready(Ok(n_bytes_read)) => unsafe { this.buffer.advance_mut(n_bytes_read) },
+                Poll::Ready(Err(error)) => return Poll::Ready(Some(Err(PacketError::Read(error)))),
+                Poll::Pending => return Poll::Pending, // our poll_read call will have scheduled our next wakeup for us
+            }
+        }
+    }
+}
+
+impl<R> super::ParseOffset for PcapngPackets<R> {
+    fn offset(&self) -> u64 {
+        self.offset
+    }
+}
+
+impl<R> super::ParseOffset for WithMeta<R> {
+    fn offset(&self) -> u64 {
+        self.packets.offset
+    }
+}
+
+impl<R: AsyncRead> Stream for WithMeta<R> {
+    type Item = Result<(u64, Bytes, PacketMeta), PacketError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut packets = self.project().packets;
+        packets.as_mut().poll_next(cx).map(|packet| {
+            packet.map(|packet| packet.map(|(ts, record)| (ts, record, packets.meta)))
+        })
+    }
+}
+
+/// The timestamp, record and [PacketMeta] of the Enhanced Packet Block with the given `body`, unless it was
+/// captured on an interface other than `only_interface`
+fn enhanced_packet(
+    body: &[u8],
+    big_endian: bool,
+    interfaces: &[Interface],
+    only_interface: Option<u32>,
+) -> Result<Option<(u64, Bytes, PacketMeta)>, PacketError> {
+    if body.len() < EPB_FIELDS_LEN {
+        return Err(PacketError::InvalidPcapng(
+            "Enhanced Packet Block is too short",
+        ));
+    }
+    let field = |index: usize| read_u32(&body[index * 4..], big_endian);
+    let interface_id = field(0);
+    if only_interface.is_some_and(|only_interface| only_interface != interface_id) {
+        return Ok(None);
+    }
+    let interface = interfaces
+        .get(interface_id as usize)
+        .ok_or(PacketError::InvalidPcapng(
+            "Enhanced Packet Block refers to an undescribed interface",
+        ))?;
+    let ts = interface.ts_resolution.epb_nanoseconds(field(1), field(2));
+    let (captured_len, original_len) = (field(3), field(4));
+    let data = body
+        .get(EPB_FIELDS_LEN..EPB_FIELDS_LEN + captured_len as usize)
+        .ok_or(PacketError::InvalidPcapng(
+            "Enhanced Packet Block's captured length exceeds the block",
+        ))?;
+    let mut record = BytesMut::with_capacity(RECORD_HEADER_LEN + data.len());
+    record.put_u32_le((ts / NANOSECONDS_PER_SECOND) as u32);
+    record.put_u32_le((ts % NANOSECONDS_PER_SECOND) as u32);
+    record.put_u32_le(captured_len);
+    record.put_u32_le(original_len);
+    record.extend_from_slice(data);
+    let options = &body[(EPB_FIELDS_LEN + data.len() + padding_len(data.len())).min(body.len())..];
+    let meta = PacketMeta::parse(interface_id, options, big_endian);
+    Ok(Some((ts, record.freeze(), meta)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pcap::{OutputFormat, ParseOffset, Writer};
+
+    #[test]
+    fn interfaces_at_different_resolutions_normalize_to_the_same_nanoseconds() {
+        // 1600000000.123 seconds since the epoch, at each interface's resolution
+        let interfaces = [
+            (TsResolution::from_option(3), 1_600_000_000_123u64),
+            (TsResolution::from_option(6), 1_600_000_000_123_000),
+            (TsResolution::from_option(9), 1_600_000_000_123_000_000),
+        ];
+        for (resolution, ts) in interfaces.iter() {
+            let (ts_high, ts_low) = ((ts >> 32) as u32, *ts as u32);
+            assert_eq!(
+                resolution.epb_nanoseconds(ts_high, ts_low),
+                1_600_000_000_123_000_000,
+                "{:?}",
+                resolution
+            );
+        }
+        assert_eq!(TsResolution::default(), TsResolution::from_option(6));
+    }
+
+    #[test]
+    fn finer_and_binary_resolutions() {
+        assert_eq!(
+            TsResolution::from_option(12).to_nanoseconds(5_000_999),
+            5_000
+        );
+        assert_eq!(TsResolution::from_option(127).to_nanoseconds(u64::MAX), 0);
+
+        // 2^-10 seconds, so 1024 units per second
+        let binary = TsResolution::from_option(0x80 | 10);
+        assert_eq!(binary, TsResolution::Binary(10));
+        assert_eq!(binary.to_nanoseconds(1024 * 7 + 512), 7_500_000_000);
+        assert_eq!(
+            TsResolution::from_option(0x80).to_nanoseconds(3),
+            3 * NANOSECONDS_PER_SECOND
+        );
+
+        // seconds since the epoch overflow u64 nanoseconds long after they'd fit in a timestamp
+        assert_eq!(
+            TsResolution::from_option(0).to_nanoseconds(u64::MAX),
+            u64::MAX
+        );
+    }
+
+    /// Append a block of type `block_type` with the given `body`, in the given byte order, to `out`
+    fn push_block_in(out: &mut Vec<u8>, block_type: u32, body: &[u8], big_endian: bool) {
+        let u32_bytes = |value: u32| {
+            if big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            }
+        };
+        let block_len = (BLOCK_HEADER_LEN + body.len() + 4) as u32;
+        out.extend_from_slice(&u32_bytes(block_type));
+        out.extend_from_slice(&u32_bytes(block_len));
+        out.extend_from_slice(body);
+        out.extend_from_slice(&u32_bytes(block_len));
+    }
+
+    /// A big-endian section with a microsecond interface named `eth0` and a nanosecond one, then a packet on each: an
+    /// inbound one with 7 packets dropped ahead of it, then an outbound one
+    fn big_endian_section() -> Vec<u8> {
+        let mut file = Vec::new();
+        let mut shb = BYTE_ORDER_MAGIC.to_be_bytes().to_vec();
+        shb.extend_from_slice(&[0, 1, 0, 0]);
+        shb.extend_from_slice(&(-1i64).to_be_bytes());
+        push_block_in(&mut file, SECTION_HEADER_BLOCK, &shb, true);
+
+        let mut eth0 = vec![0, 1, 0, 0, 0, 0, 0xFF, 0xFF]; // Ethernet, 65535-byte snaplen
+        eth0.extend_from_slice(&[0, 2, 0, 4]);
+        eth0.extend_from_slice(b"eth0");
+        eth0.extend_from_slice(&[0, 0, 0, 0]);
+        push_block_in(&mut file, INTERFACE_DESCRIPTION_BLOCK, &eth0, true);
+        let mut raw = vec![0, 101, 0, 0, 0, 0, 0, 0]; // raw IP, no snaplen
+        raw.extend_from_slice(&[0, 9, 0, 1, 9, 0, 0, 0]);
+        push_block_in(&mut file, INTERFACE_DESCRIPTION_BLOCK, &raw, true);
+
+        // a block of an unknown type is skipped
+        push_block_in(&mut file, 0x0BAD, &[0; 4], true);
+
+        let mut inbound_dropped = vec![0, 2, 0, 4, 0, 0, 0, 1, 0, 4, 0, 8];
+        inbound_dropped.extend_from_slice(&7u64.to_be_bytes());
+        inbound_dropped.extend_from_slice(&[0, 0, 0, 0]);
+        let outbound = vec![0, 2, 0, 4, 0, 0, 0, 2];
+        for (interface, ts, data, options) in &[
+            (0u32, 1_500_000u64, &b"abc"[..], inbound_dropped),
+            (1, 2_000_000_000, b"defgh", outbound),
+        ] {
+            let mut epb = Vec::new();
+            for field in &[
+                *interface,
+                (ts >> 32) as u32,
+                *ts as u32,
+                data.len() as u32,
+                data.len() as u32 + 100,
+            ] {
+                epb.extend_from_slice(&field.to_be_bytes());
+            }
+            epb.extend_from_slice(data);
+            epb.resize(epb.len() + padding_len(data.len()), 0);
+            epb.extend_from_slice(options);
+            push_block_in(&mut file, ENHANCED_PACKET_BLOCK, &epb, true);
+        }
+        file
+    }
+
+    fn read(file: &[u8], only_interface: Option<u32>) -> (Vec<(u64, Bytes)>, Vec<Interface>) {
+        futures::executor::block_on(async {
+            use futures::stream::StreamExt;
+            let mut packets = PcapngPackets::new(16, file)
+                .strict(true)
+                .only_interface(only_interface);
+            let mut read = Vec::new();
+            while let Some(packet) = packets.next().await {
+                read.push(packet.unwrap());
+            }
+            (read, packets.interfaces().to_vec())
+        })
+    }
+
+    #[test]
+    fn reads_a_big_endian_section_with_interfaces_at_different_resolutions() {
+        let (packets, interfaces) = read(&big_endian_section(), None);
+        assert_eq!(
+            interfaces,
+            vec![
+                Interface {
+                    snaplen: 65535,
+                    name: Some("eth0".to_string()),
+                    ..Interface::new(LINKTYPE_ETHERNET)
+                },
+                Interface {
+                    ts_resolution: TsResolution::Decimal(9),
+                    ..Interface::new(101)
+                },
+            ]
+        );
+
+        let timestamps: Vec<u64> = packets.iter().map(|(ts, _)| *ts).collect();
+        assert_eq!(timestamps, vec![1_500_000_000, 2_000_000_000]);
+        let (_, record) = &packets[0];
+        assert_eq!(record[..4], 1u32.to_le_bytes()[..]);
+        assert_eq!(record[4..8], 500_000_000u32.to_le_bytes()[..]);
+        assert_eq!(record[8..12], 3u32.to_le_bytes()[..]);
+        assert_eq!(record[12..16], 103u32.to_le_bytes()[..]);
+        assert_eq!(record[RECORD_HEADER_LEN..], b"abc"[..]);
+        assert_eq!(packets[1].1[RECORD_HEADER_LEN..], b"defgh"[..]);
+
+        let (packets, _) = read(&big_endian_section(), Some(1));
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].1[RECORD_HEADER_LEN..], b"defgh"[..]);
+    }
+
+    #[test]
+    fn block_longer_than_any_plausible_one_is_an_error_at_its_offset() {
+        use futures::stream::StreamExt;
+        let mut file = big_endian_section();
+        let block_offset = file.len();
+        file.extend_from_slice(&ENHANCED_PACKET_BLOCK.to_be_bytes());
+        file.extend_from_slice(&0xFFFF_FFFCu32.to_be_bytes());
+        file.extend_from_slice(&[0; 100]);
+
+        let packets: Vec<_> = futures::executor::block_on(
+            PcapngPackets::new(16, &file[..])
+                .located("a.pcapng")
+                .collect(),
+        );
+        assert_eq!(packets.len(), 3);
+        assert!(packets[..2].iter().all(Result::is_ok));
+        let error = packets[2].as_ref().unwrap_err();
+        assert_eq!(error.offset, block_offset as u64);
+        assert!(matches!(
+            error.kind,
+            PacketError::RecordTooLong {
+                record_len: 0xFFFF_FFFC,
+                max_len: MAX_BLOCK_LEN,
+            }
+        ));
+    }
+
+    #[test]
+    fn packet_metadata_is_read_from_enhanced_packet_block_options() {
+        use futures::stream::StreamExt;
+        let file = big_endian_section();
+        let packets: Vec<_> = futures::executor::block_on(
+            PcapngPackets::new(16, &file[..])
+                .strict(true)
+                .with_meta()
+                .map(Result::unwrap)
+                .collect(),
+        );
+        let metas: Vec<PacketMeta> = packets.iter().map(|(_, _, meta)| *meta).collect();
+        assert_eq!(
+            metas,
+            vec![
+                PacketMeta {
+                    interface_id: 0,
+                    direction: Some(Direction::Inbound),
+                    drop_count: Some(7),
+                },
+                PacketMeta {
+                    interface_id: 1,
+                    direction: Some(Direction::Outbound),
+                    drop_count: None,
+                },
+            ]
+        );
+        assert_eq!(packets[1].2.interface_id, 1);
+        assert_eq!(packets[1].1[RECORD_HEADER_LEN..], b"defgh"[..]);
+
+        // carried through a merge after the record, and split off again
+        for (_, record, meta) in &packets {
+            let appended = meta.append_to(record);
+            assert_eq!(
+                PacketMeta::split_from(appended).unwrap(),
+                (record.clone(), *meta)
+            );
+        }
+        assert!(PacketMeta::split_from(Bytes::from_static(&[0; 4])).is_err());
+
+        // packets written without options have none
+        let mut writer = Writer::new(Vec::new(), OutputFormat::Pcapng).unwrap();
+        writer.write_packet(1, &[0; RECORD_HEADER_LEN]).unwrap();
+        let file = writer.into_inner();
+        let packets: Vec<_> =
+            futures::executor::block_on(PcapngPackets::new(16, &file[..]).with_meta().collect());
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].as_ref().unwrap().2, PacketMeta::default());
+    }
+
+    #[test]
+    fn written_blocks_read_back() {
+        let interfaces = vec![
+            Interface {
+                name: Some("capture port 1".to_string()),
+                snaplen: 9000,
+                ..Interface::new(LINKTYPE_ETHERNET)
+            },
+            Interface::new(101),
+        ];
+        let record = |payload: &[u8]| {
+            let mut record = vec![0; 8];
+            record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+            record.extend_from_slice(&(payload.len() as u32 + 1).to_le_bytes());
+            record.extend_from_slice(payload);
+            record
+        };
+        let mut writer = Writer::new(Vec::new(), OutputFormat::Pcapng)
+            .unwrap()
+            .interfaces(interfaces.clone());
+        let written: Vec<(u64, Vec<u8>, u32)> = (0..6u8)
+            .map(|i| {
+                (
+                    1_600_000_000_000_000_000 + i as u64 * 3,
+                    record(&vec![i; i as usize]),
+                    i as u32 % 2,
+                )
+            })
+            .collect();
+        for (ts, record, interface) in &written {
+            writer.write_packet_on(*ts, record, *interface).unwrap();
+        }
+        assert!(writer.write_packet_on(0, &record(&[]), 2).is_err());
+        let file = writer.into_inner();
+        assert_eq!(file.len() % 4, 0);
+
+        // written at nanosecond resolution, whatever the interfaces' own
+        let nanosecond = |interface: &Interface| Interface {
+            ts_resolution: TsResolution::Decimal(9),
+            ..interface.clone()
+        };
+        for interface in 0..2 {
+            let (packets, read_interfaces) = read(&file, Some(interface));
+            assert_eq!(
+                read_interfaces,
+                interfaces.iter().map(nanosecond).collect::<Vec<_>>()
+            );
+            let expected: Vec<(u64, Bytes)> = written
+                .iter()
+                .filter(|(_, _, written_interface)| *written_interface == interface)
+                .map(|(ts, record, _)| {
+                    let mut expected = record.clone();
+                    expected[..4]
+                        .copy_from_slice(&((ts / NANOSECONDS_PER_SECOND) as u32).to_le_bytes());
+                    expected[4..8]
+                        .copy_from_slice(&((ts % NANOSECONDS_PER_SECOND) as u32).to_le_bytes());
+                    (*ts, Bytes::from(expected))
+                })
+                .collect();
+            assert_eq!(packets, expected);
+        }
+    }
+}
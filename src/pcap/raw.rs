@@ -0,0 +1,272 @@
+use super::{PacketAction, PacketError, Processors, MAX_PLAUSIBLE_CAPLEN, RECORD_HEADER_LEN};
+use bytes::buf::BufMut;
+use bytes::{Bytes, BytesMut};
+use futures::io::AsyncRead;
+use futures::stream::Stream;
+use futures::task::Poll;
+use serde::Deserialize;
+use std::pin::Pin;
+use std::task::Context;
+
+use pin_project_lite::pin_project;
+
+/// Size of the header preceding each raw framed payload: a `u64` nanosecond timestamp and `u32` payload length
+const RAW_HEADER_LEN: usize = 12;
+
+/// Most bytes a frame may have, header included, before it's taken for corruption rather than buffered
+const MAX_FRAME_LEN: usize = RAW_HEADER_LEN + MAX_PLAUSIBLE_CAPLEN;
+
+const NANOSECONDS_PER_SECOND: u64 = 1_000_000_000;
+
+/// Framing of an input file's packets
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum InputFormat {
+    /// A .pcap file, parsed by [super::Packets]
+    Pcap,
+    /// Headerless records, each a little-endian `u64` nanosecond timestamp, a little-endian `u32` payload length, then
+    /// the payload bytes (i.e. [super::OutputFormat::LengthPrefixed]). Parsed by [RawFramed].
+    Raw,
+    /// A .pcapng file, parsed by [super::pcapng::PcapngPackets]
+    Pcapng,
+}
+
+impl InputFormat {
+    /// Detect the format of a file from its extension, ignoring any compression extension (e.g. `.raw.zst` is [InputFormat::Raw])
+    pub fn from_path(path: &str) -> InputFormat {
+        let path = path.trim_end_matches(".gz").trim_end_matches(".zst");
+        if path.ends_with(".raw") {
+            InputFormat::Raw
+        } else if path.ends_with(".pcapng") {
+            InputFormat::Pcapng
+        } else {
+            InputFormat::Pcap
+        }
+    }
+}
+
+impl std::str::FromStr for InputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "pcap" => Ok(InputFormat::Pcap),
+            "raw" => Ok(InputFormat::Raw),
+            "pcapng" => Ok(InputFormat::Pcapng),
+            _ => anyhow::bail!(
+                "Unknown input format '{}'. Expected one of: pcap, raw, pcapng",
+                s
+            ),
+        }
+    }
+}
+
+pin_project! {
+    /// [AsyncRead] combinator type for parsing [InputFormat::Raw] files into the same [Stream] of timestamped records
+    /// as [super::Packets]. Each payload is given a synthesized little-endian, nanosecond-precision pcap record header
+    /// so that raw and pcap inputs can be merged into the same output.
+    pub struct RawFramed<R> {
+        #[pin]
+        reader: R,
+        buffer: BytesMut,
+        reader_exhausted: bool,
+        strict: bool,
+        processors: Processors,
+        offset: u64,
+    }
+}
+
+impl<R: AsyncRead> RawFramed<R> {
+    pub fn new(capacity: usize, reader: R) -> RawFramed<R> {
+        RawFramed {
+            reader,
+            buffer: BytesMut::with_capacity(capacity),
+            reader_exhausted: false,
+            strict: false,
+            processors: Processors::default(),
+            offset: 0,
+        }
+    }
+
+    /// As for [super::Packets::strict], yield input ending part way through a frame as a [PacketError::TruncatedRecord]
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// As for [super::Packets::with_processor], pass each packet to `processor` to drop, keep or replace it
+    pub fn with_processor(
+        mut self,
+        processor: impl FnMut(u64, Bytes) -> PacketAction + Send + Sync + 'static,
+    ) -> Self {
+        self.processors.push(processor);
+        self
+    }
+}
+
+impl<R: AsyncRead> Stream for RawFramed<R> {
+    type Item = Result<(u64, Bytes), PacketError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        loop {
+            if this.buffer.len() >= RAW_HEADER_LEN {
+                let mut ts = [0; 8];
+                ts.copy_from_slice(&this.buffer[..8]);
+                let ts = u64::from_le_bytes(ts);
+                let mut len = [0; 4];
+                len.copy_from_slice(&this.buffer[8..RAW_HEADER_LEN]);
+                let len = u32::from_le_bytes(len);
+                let frame_n_bytes = RAW_HEADER_LEN + len as usize;
+                if frame_n_bytes > MAX_FRAME_LEN {
+                    // end the stream here, at the frame's offset, rather than reading the rest of the input into it
+                    this.buffer.clear();
+                    *this.reader_exhausted = true;
+                    return Poll::Ready(Some(Err(PacketError::RecordTooLong {
+                        record_len: frame_n_bytes,
+                        max_len: MAX_FRAME_LEN,
+                    })));
+                }
+                if this.buffer.len() >= frame_n_bytes {
+                    let frame = this.buffer.split_to(frame_n_bytes);
+                    *this.offset += frame_n_bytes as u64;
+                    let mut record = BytesMut::with_capacity(RECORD_HEADER_LEN + len as usize);
+                    record.put_u32_le((ts / NANOSECONDS_PER_SECOND) as u32);
+                    record.put_u32_le((ts % NANOSECONDS_PER_SECOND) as u32);
+                    record.put_u32_le(len);
+                    record.put_u32_le(len);
+                    record.extend_from_slice(&frame[RAW_HEADER_LEN..]);
+                    match this.processors.process(ts, record.freeze()) {
+                        Some(packet) => return Poll::Ready(Some(Ok(packet))),
+                        None => continue,
+                    }
+                }
+                this.buffer.reserve(frame_n_bytes - this.buffer.len()); // make room for the rest of a large frame
+            }
+            if *this.reader_exhausted {
+                let remaining_bytes = this.buffer.len();
+                this.buffer.clear();
+                if *this.strict && remaining_bytes > 0 {
+                    return Poll::Ready(Some(Err(PacketError::TruncatedRecord {
+                        remaining_bytes,
+                    })));
+                }
+                return Poll::Ready(None); // EOF
+            }
+
+            // incomplete. get some more data from our underlying reader
+            let to_read = unsafe {
+                &mut *(this.buffer.bytes_mut() as *mut [std::mem::MaybeUninit<u8>] as *mut [u8])
+            };
+            match this.reader.as_mut().poll_read(cx, to_read) {
+                Poll::Ready(Ok(0)) => *this.reader_exhausted = true,
+                Poll::Ready(Ok(n_bytes_read)) => unsafe { this.buffer.advance_mut(n_bytes_read) },
+                Poll::Ready(Err(error)) => return Poll::Ready(Some(Err(PacketError::Read(error)))),
+                Poll::Pending => return Poll::Pending, // our poll_read call will have scheduled our next wakeup for us
+            }
+        }
+    }
+}
+
+impl<R> super::ParseOffset for RawFramed<R> {
+    fn offset(&self) -> u64 {
+        self.offset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pcap::ParseOffset;
+    use futures::stream::StreamExt;
+
+    #[test]
+    fn parses_records_across_small_reads() {
+        let payloads: Vec<Vec<u8>> = (0..50u8).map(|i| vec![i; i as usize * 7]).collect();
+        let mut bytes = Vec::new();
+        for (i, payload) in payloads.iter().enumerate() {
+            bytes.extend_from_slice(&(i as u64 * 1_500_000_000).to_le_bytes());
+            bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(payload);
+        }
+
+        let parsed: Vec<_> = futures::executor::block_on(
+            RawFramed::new(16, &bytes[..]).map(Result::unwrap).collect(),
+        );
+
+        assert_eq!(parsed.len(), payloads.len());
+        for (i, ((ts, record), payload)) in parsed.iter().zip(payloads.iter()).enumerate() {
+            assert_eq!(*ts, i as u64 * 1_500_000_000);
+            assert_eq!(&record[..4], &((i as u32 * 3) / 2).to_le_bytes());
+            assert_eq!(&record[RECORD_HEADER_LEN..], &payload[..]);
+        }
+    }
+
+    #[test]
+    fn trailing_partial_frame_is_an_error_only_when_strict() {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&1_000u64.to_le_bytes());
+        bytes.extend_from_slice(&3u32.to_le_bytes());
+        bytes.extend_from_slice(&[1, 2, 3]);
+        let complete_n_bytes = bytes.len();
+        bytes.extend_from_slice(&2_000u64.to_le_bytes());
+        bytes.extend_from_slice(&10u32.to_le_bytes());
+        bytes.extend_from_slice(&[4, 5]);
+
+        let parse = |bytes: &[u8], strict: bool| -> Vec<_> {
+            futures::executor::block_on(RawFramed::new(16, bytes).strict(strict).collect())
+        };
+        let lenient = parse(&bytes, false);
+        assert_eq!(lenient.len(), 1);
+        assert_eq!(lenient[0].as_ref().unwrap().0, 1_000);
+
+        let strict = parse(&bytes, true);
+        assert_eq!(strict.len(), 2);
+        assert!(matches!(
+            strict[1],
+            Err(PacketError::TruncatedRecord {
+                remaining_bytes: 14
+            })
+        ));
+        // ending part way through a frame's header is as much a truncated frame
+        let strict = parse(&bytes[..complete_n_bytes + 5], true);
+        assert!(matches!(
+            strict[1],
+            Err(PacketError::TruncatedRecord { remaining_bytes: 5 })
+        ));
+        assert_eq!(parse(&bytes[..complete_n_bytes], true).len(), 1);
+    }
+
+    #[test]
+    fn frame_longer_than_any_plausible_one_is_an_error_at_its_offset() {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&1_000u64.to_le_bytes());
+        bytes.extend_from_slice(&3u32.to_le_bytes());
+        bytes.extend_from_slice(&[1, 2, 3]);
+        bytes.extend_from_slice(&2_000u64.to_le_bytes());
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+        bytes.extend_from_slice(&[4; 100]);
+
+        let parsed: Vec<_> =
+            futures::executor::block_on(RawFramed::new(16, &bytes[..]).located("a.raw").collect());
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].as_ref().unwrap().0, 1_000);
+        let error = parsed[1].as_ref().unwrap_err();
+        assert_eq!(error.offset, 15);
+        assert!(matches!(
+            error.kind,
+            PacketError::RecordTooLong {
+                record_len,
+                max_len: MAX_FRAME_LEN,
+            } if record_len == RAW_HEADER_LEN + u32::MAX as usize
+        ));
+    }
+
+    #[test]
+    fn detects_format_from_path() {
+        assert_eq!(InputFormat::from_path("a.raw"), InputFormat::Raw);
+        assert_eq!(InputFormat::from_path("s3://b/a.raw.zst"), InputFormat::Raw);
+        assert_eq!(InputFormat::from_path("a.pcap.gz"), InputFormat::Pcap);
+        assert_eq!(InputFormat::from_path("a.pcapng.zst"), InputFormat::Pcapng);
+    }
+}
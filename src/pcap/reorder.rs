@@ -0,0 +1,154 @@
+use bytes::Bytes;
+use futures::stream::{Stream, StreamExt};
+use futures::task::{Context, Poll};
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::pin::Pin;
+
+/// Sorts the packets of a stream (such as [super::Packets]) which is out of timestamp order by no more than a window,
+/// as captures whose packets were locally reordered are. Each packet is held until a packet `window_ns` or more after
+/// it has been read (or the stream ends), then released in timestamp order, ties in the order they were read. A packet
+/// further out of order than the window still follows those released before it was read, so is left out of order.
+///
+/// An error ends the stream, once the packets read before it have been released.
+pub struct Reorder<St, E> {
+    packets: St,
+    window_ns: u64,
+    held: BinaryHeap<Reverse<Held>>,
+    n_read: u64,
+    latest_ts: u64,
+    ended: bool,
+    error: Option<E>,
+}
+
+/// A packet held by [Reorder], ordered by timestamp then by when it was read
+struct Held {
+    ts: u64,
+    sequence_number: u64,
+    packet: Bytes,
+}
+
+impl PartialEq for Held {
+    fn eq(&self, other: &Held) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Held {}
+
+impl PartialOrd for Held {
+    fn partial_cmp(&self, other: &Held) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Held {
+    fn cmp(&self, other: &Held) -> Ordering {
+        (self.ts, self.sequence_number).cmp(&(other.ts, other.sequence_number))
+    }
+}
+
+impl<St, E> Reorder<St, E> {
+    pub fn new(packets: St, window_ns: u64) -> Reorder<St, E> {
+        Reorder {
+            packets,
+            window_ns,
+            held: BinaryHeap::new(),
+            n_read: 0,
+            latest_ts: 0,
+            ended: false,
+            error: None,
+        }
+    }
+}
+
+impl<St, E> Stream for Reorder<St, E>
+where
+    St: Stream<Item = Result<(u64, Bytes), E>> + Unpin,
+    E: Unpin,
+{
+    type Item = Result<(u64, Bytes), E>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        loop {
+            if let Some(Reverse(earliest)) = this.held.peek() {
+                // no packet yet to be read is expected before one the window or more before the latest
+                if this.ended || earliest.ts.saturating_add(this.window_ns) <= this.latest_ts {
+                    let Reverse(earliest) = this.held.pop().unwrap();
+                    return Poll::Ready(Some(Ok((earliest.ts, earliest.packet))));
+                }
+            }
+            if this.ended {
+                return Poll::Ready(this.error.take().map(Err));
+            }
+            match this.packets.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok((ts, packet)))) => {
+                    this.latest_ts = this.latest_ts.max(ts);
+                    this.held.push(Reverse(Held {
+                        ts,
+                        sequence_number: this.n_read,
+                        packet,
+                    }));
+                    this.n_read += 1;
+                }
+                Poll::Ready(Some(Err(error))) => {
+                    this.error = Some(error);
+                    this.ended = true;
+                }
+                Poll::Ready(None) => this.ended = true,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reorder(timestamps: &[u64], window_ns: u64) -> Vec<Result<u64, &'static str>> {
+        let packets = futures::stream::iter(
+            timestamps
+                .iter()
+                .map(|ts| Ok((*ts, Bytes::from(ts.to_string())))),
+        );
+        let reordered = Reorder::new(
+            packets.chain(futures::stream::iter([Err("ended")])),
+            window_ns,
+        );
+        smol::block_on(reordered.collect::<Vec<_>>())
+            .into_iter()
+            .map(|packet| {
+                packet.map(|(ts, packet)| {
+                    assert_eq!(packet, ts.to_string());
+                    ts
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn packets_out_of_order_within_the_window_are_sorted() {
+        assert_eq!(
+            reorder(&[10, 30, 20, 25, 40, 35, 60, 55, 100], 10),
+            [10, 20, 25, 30, 35, 40, 55, 60, 100]
+                .iter()
+                .map(|ts| Ok(*ts))
+                .chain([Err("ended")])
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn packets_out_of_order_beyond_the_window_stay_out_of_order() {
+        assert_eq!(
+            reorder(&[10, 30, 50, 15, 60], 10),
+            [10, 30, 15, 50, 60]
+                .iter()
+                .map(|ts| Ok(*ts))
+                .chain([Err("ended")])
+                .collect::<Vec<_>>()
+        );
+    }
+}
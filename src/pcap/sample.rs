@@ -0,0 +1,122 @@
+use super::flow::{fnv1a, FNV_OFFSET_BASIS};
+use super::RECORD_HEADER_LEN;
+
+/// How a [Sampler] chooses the packets it keeps
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SampleMode {
+    /// the first of every `n` packets, by position in the merged output
+    Every,
+    /// the packets whose timestamp and captured bytes hash to a multiple of `n`, so that the same packets are kept
+    /// whichever other packets they're merged with
+    Hash,
+}
+
+impl std::str::FromStr for SampleMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "every" => Ok(SampleMode::Every),
+            "hash" => Ok(SampleMode::Hash),
+            _ => anyhow::bail!("Unknown sample mode '{}'. Expected one of: every, hash", s),
+        }
+    }
+}
+
+impl std::fmt::Display for SampleMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            SampleMode::Every => "every",
+            SampleMode::Hash => "hash",
+        })
+    }
+}
+
+/// Deterministically keeps about one in every `n` merged packets, e.g. to cut a lightweight test fixture from a real
+/// capture. The same input always yields the same sample.
+#[derive(Clone, Debug)]
+pub struct Sampler {
+    n: u64,
+    mode: SampleMode,
+    n_seen: u64,
+}
+
+impl Sampler {
+    pub fn new(n: u64, mode: SampleMode) -> Sampler {
+        assert!(n > 0, "Can't sample one in every 0 packets");
+        Sampler { n, mode, n_seen: 0 }
+    }
+
+    /// Whether to keep the packet `record`, a pcap record (header and captured bytes) merged with timestamp `ts`
+    pub fn keep(&mut self, ts: u64, record: &[u8]) -> bool {
+        match self.mode {
+            SampleMode::Every => {
+                let keep = self.n_seen.is_multiple_of(self.n);
+                self.n_seen += 1;
+                keep
+            }
+            SampleMode::Hash => {
+                let hash = fnv1a(FNV_OFFSET_BASIS, &ts.to_le_bytes());
+                finalize(fnv1a(hash, &record[RECORD_HEADER_LEN..])).is_multiple_of(self.n)
+            }
+        }
+    }
+}
+
+/// Mix every bit of an FNV-1a `hash` into its low bits, which a modulus keeps. Those of FNV-1a itself barely depend on
+/// the high bits of the bytes hashed (the lowest, not at all), so small moduli would sample unevenly. MurmurHash3's
+/// 64-bit finalizer.
+fn finalize(mut hash: u64) -> u64 {
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(payload: &[u8]) -> Vec<u8> {
+        let mut record = vec![0; RECORD_HEADER_LEN];
+        record.extend_from_slice(payload);
+        record
+    }
+
+    #[test]
+    fn every_nth_packet_is_kept_starting_with_the_first() {
+        let mut sampler = Sampler::new(3, SampleMode::Every);
+        let kept: Vec<u64> = (0..10)
+            .filter(|ts| sampler.keep(*ts, &record(&[1])))
+            .collect();
+        assert_eq!(kept, vec![0, 3, 6, 9]);
+    }
+
+    #[test]
+    fn hashed_samples_depend_only_on_each_packet() {
+        let packets: Vec<(u64, Vec<u8>)> = (0..1000u64)
+            .map(|ts| (ts, record(&ts.to_be_bytes())))
+            .collect();
+        let mut sampler = Sampler::new(10, SampleMode::Hash);
+        let kept: Vec<u64> = packets
+            .iter()
+            .filter(|(ts, record)| sampler.keep(*ts, record))
+            .map(|(ts, _)| *ts)
+            .collect();
+        assert!((50..150).contains(&kept.len()), "kept {}", kept.len());
+
+        // the same packets are kept from a subset of them, in a different order
+        let mut sampler = Sampler::new(10, SampleMode::Hash);
+        let mut kept_of_odd: Vec<u64> = packets
+            .iter()
+            .rev()
+            .filter(|(ts, _)| ts % 2 == 1)
+            .filter(|(ts, record)| sampler.keep(*ts, record))
+            .map(|(ts, _)| *ts)
+            .collect();
+        kept_of_odd.reverse();
+        let odd: Vec<u64> = kept.into_iter().filter(|ts| ts % 2 == 1).collect();
+        assert_eq!(kept_of_odd, odd);
+    }
+}
@@ -0,0 +1,92 @@
+use anyhow::{Context, Result};
+use std::io::{BufReader, Read};
+
+/// Timestamps read from a sidecar file, e.g. one logged by capture hardware alongside a pcap whose own timestamps are
+/// less precise: consecutive little-endian `u64` nanosecond timestamps, one per packet of the pcap, in the same order.
+/// See [crate::tournament_tree::SidecarKeyed]. Ends early, logging an error, if the file can't be read or ends part way
+/// through a timestamp.
+pub struct SidecarTimestamps<R: Read> {
+    reader: R,
+    path: String,
+    ended: bool,
+}
+
+impl SidecarTimestamps<BufReader<std::fs::File>> {
+    /// Open the sidecar at the local `path`
+    pub fn open(path: &str) -> Result<Self> {
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("Failed to open timestamp sidecar '{}'", path))?;
+        Ok(SidecarTimestamps::new(
+            BufReader::with_capacity(1024 * 64, file),
+            path.to_string(),
+        ))
+    }
+}
+
+impl<R: Read> SidecarTimestamps<R> {
+    /// Read timestamps from `reader`, naming it `path` in any error
+    pub fn new(reader: R, path: String) -> Self {
+        SidecarTimestamps {
+            reader,
+            path,
+            ended: false,
+        }
+    }
+}
+
+impl<R: Read> Iterator for SidecarTimestamps<R> {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        if self.ended {
+            return None;
+        }
+        let mut ts = [0; 8];
+        let mut n_read = 0;
+        while n_read < ts.len() {
+            match self.reader.read(&mut ts[n_read..]) {
+                Ok(0) => break,
+                Ok(n) => n_read += n,
+                Err(error) if error.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(error) => {
+                    tracing::event!(
+                        tracing::Level::ERROR,
+                        path = self.path.as_str(),
+                        error = %error,
+                        "Failed to read timestamp sidecar"
+                    );
+                    n_read = 0;
+                    break;
+                }
+            }
+        }
+        if n_read < ts.len() {
+            self.ended = true;
+            if n_read > 0 {
+                tracing::event!(
+                    tracing::Level::ERROR,
+                    path = self.path.as_str(),
+                    "Timestamp sidecar ends part way through a timestamp"
+                );
+            }
+            return None;
+        }
+        Some(u64::from_le_bytes(ts))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_little_endian_nanosecond_timestamps_up_to_a_partial_one() {
+        let mut bytes = Vec::new();
+        for ts in &[1u64, 1_637_796_620_000_000_001, u64::MAX - 1] {
+            bytes.extend_from_slice(&ts.to_le_bytes());
+        }
+        bytes.extend_from_slice(&[1, 2, 3]);
+        let timestamps: Vec<u64> = SidecarTimestamps::new(&bytes[..], "test".to_string()).collect();
+        assert_eq!(timestamps, vec![1, 1_637_796_620_000_000_001, u64::MAX - 1]);
+    }
+}
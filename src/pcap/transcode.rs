@@ -0,0 +1,119 @@
+use super::pcapng::LINKTYPE_ETHERNET;
+use bytes::{BufMut, Bytes, BytesMut};
+use serde::Deserialize;
+
+/// Link-layer header type of Linux "cooked" captures (e.g. `tcpdump -i any`)
+pub const LINKTYPE_LINUX_SLL: u16 = 113;
+
+/// Link-layer header type of packets which begin with their IPv4 or IPv6 header
+pub const LINKTYPE_RAW: u16 = 101;
+
+const SLL_HEADER_LEN: usize = 16;
+const SLL_PACKET_TYPE_BROADCAST: u16 = 1;
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86DD;
+
+/// Built-in rewrites of a packet's link-layer framing into Ethernet, for merging captures of the same traffic taken with
+/// different encapsulations into uniform output. Applied by [super::Packets::with_transform] to the packets of files of
+/// the [Transcode::from_link_type]. MAC addresses a capture doesn't record are written as zeroes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Transcode {
+    /// Linux "cooked" (SLL) headers become Ethernet headers with the same EtherType, the sender's address as the
+    /// source MAC and, for broadcast packets, the broadcast destination MAC
+    SllToEthernet,
+    /// raw IPv4 and IPv6 packets gain an Ethernet header of the EtherType of their IP version
+    RawIpToEthernet,
+}
+
+impl Transcode {
+    /// Link-layer header type of the packets this transcodes
+    pub fn from_link_type(self) -> u16 {
+        match self {
+            Transcode::SllToEthernet => LINKTYPE_LINUX_SLL,
+            Transcode::RawIpToEthernet => LINKTYPE_RAW,
+        }
+    }
+
+    /// Link-layer header type of the transcoded packets
+    pub fn to_link_type(self) -> u16 {
+        LINKTYPE_ETHERNET
+    }
+
+    /// The captured bytes of `packet` reframed. A packet too short, or of an IP version too unfamiliar, to reframe is
+    /// left as it is.
+    pub fn transcode(self, packet: &[u8]) -> Bytes {
+        let (destination, source, ethertype, payload) = match self {
+            Transcode::SllToEthernet if packet.len() >= SLL_HEADER_LEN => {
+                let field =
+                    |offset: usize| u16::from_be_bytes([packet[offset], packet[offset + 1]]);
+                let destination = if field(0) == SLL_PACKET_TYPE_BROADCAST {
+                    [0xFF; 6]
+                } else {
+                    [0; 6]
+                };
+                let mut source = [0; 6];
+                if field(4) == 6 {
+                    source.copy_from_slice(&packet[6..12]);
+                }
+                (destination, source, field(14), &packet[SLL_HEADER_LEN..])
+            }
+            Transcode::RawIpToEthernet => match packet.first().map(|byte| byte >> 4) {
+                Some(4) => ([0; 6], [0; 6], ETHERTYPE_IPV4, packet),
+                Some(6) => ([0; 6], [0; 6], ETHERTYPE_IPV6, packet),
+                _ => return Bytes::copy_from_slice(packet),
+            },
+            _ => return Bytes::copy_from_slice(packet),
+        };
+        let mut ethernet = BytesMut::with_capacity(14 + payload.len());
+        ethernet.put_slice(&destination);
+        ethernet.put_slice(&source);
+        ethernet.put_u16(ethertype);
+        ethernet.put_slice(payload);
+        ethernet.freeze()
+    }
+}
+
+impl std::str::FromStr for Transcode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "sll-to-ethernet" => Ok(Transcode::SllToEthernet),
+            "raw-ip-to-ethernet" => Ok(Transcode::RawIpToEthernet),
+            _ => anyhow::bail!(
+                "Unknown transcode '{}'. Expected one of: sll-to-ethernet, raw-ip-to-ethernet",
+                s
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for Transcode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Transcode::SllToEthernet => "sll-to-ethernet",
+            Transcode::RawIpToEthernet => "raw-ip-to-ethernet",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raw_ip_packets_gain_the_ethertype_of_their_version() {
+        let ipv6 = [0x60, 0, 0, 0, 1, 2];
+        let mut expected = vec![0; 12];
+        expected.extend_from_slice(&[0x86, 0xDD]);
+        expected.extend_from_slice(&ipv6);
+        assert_eq!(Transcode::RawIpToEthernet.transcode(&ipv6), expected);
+
+        // neither IPv4 nor IPv6, so left alone
+        assert_eq!(
+            Transcode::RawIpToEthernet.transcode(&[0x10, 1]),
+            vec![0x10, 1]
+        );
+    }
+}
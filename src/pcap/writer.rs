@@ -0,0 +1,509 @@
+use super::pcapng::{self, Interface, PacketMeta};
+use hex_literal::hex;
+use serde::Deserialize;
+use std::io::Write;
+
+/// Global header for a little-endian, nanosecond-precision .pcap file with a 262144-byte snaplen and Ethernet link type
+pub const PCAP_HDR_NSEC: &[u8] = &hex!(
+    "4D 3C B2 A1 02 00 04 00 00 00 00 00 00 00 00 00
+    00 00 04 00 01 00 00 00"
+);
+
+/// Byte range of the snaplen within [PCAP_HDR_NSEC]
+const SNAPLEN_RANGE: std::ops::Range<usize> = 16..20;
+
+/// Byte ranges of the `thiszone` and `sigfigs` fields within [PCAP_HDR_NSEC]
+const THISZONE_RANGE: std::ops::Range<usize> = 8..12;
+const SIGFIGS_RANGE: std::ops::Range<usize> = 12..16;
+
+/// The legacy fields of a pcap global header, which few tools read but some do: `thiszone`, the offset in seconds of
+/// the capture's local time from UTC, and `sigfigs`, the accuracy of its timestamps. Both are usually 0, as in
+/// [PCAP_HDR_NSEC].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HeaderFields {
+    pub thiszone: i32,
+    pub sigfigs: u32,
+}
+
+const NANOSECONDS_PER_SECOND: u64 = 1_000_000_000;
+
+/// Size in bytes of the per-packet record header which prefixes each packet yielded by [super::Packets]
+pub const RECORD_HEADER_LEN: usize = 16;
+
+/// Encodings in which [Writer] can emit merged packets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// A nanosecond-precision .pcap file: the global header followed by each packet's record.
+    Pcap,
+    /// No global header. Each packet is framed as a little-endian `u64` nanosecond timestamp, a little-endian `u32`
+    /// payload length, then the payload bytes (without the pcap record header). Simple to consume from scripting languages.
+    /// With [Writer::tag_sources], each frame is prefixed by the little-endian `u32` id of the source it was merged from,
+    /// and with [Writer::packet_meta], then by the packet's pcapng [PacketMeta].
+    LengthPrefixed,
+    /// A nanosecond-precision pcapng file: a section header, an Interface Description Block for each of the
+    /// [Writer::interfaces], then an Enhanced Packet Block for each packet referring to the interface it was captured on.
+    Pcapng,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "pcap" => Ok(OutputFormat::Pcap),
+            "length-prefixed" => Ok(OutputFormat::LengthPrefixed),
+            "pcapng" => Ok(OutputFormat::Pcapng),
+            _ => anyhow::bail!(
+                "Unknown output format '{}'. Expected one of: pcap, length-prefixed, pcapng",
+                s
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let text = match *self {
+            OutputFormat::Pcap => "pcap",
+            OutputFormat::LengthPrefixed => "length-prefixed",
+            OutputFormat::Pcapng => "pcapng",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+/// Encode a time-ordered sequence of `(timestamp, record)` packets, as yielded by [super::Packets], to a [Write]r
+/// in the chosen [OutputFormat].
+pub struct Writer<W: Write> {
+    writer: W,
+    format: OutputFormat,
+    header: Vec<u8>, // scratch space for each packet's rewritten header, reused rather than allocated per packet
+    max_gap_ns: Option<u64>,
+    last_ts: Option<u64>,
+    n_markers: u64,
+    interfaces: Vec<Interface>,
+    interfaces_described: bool,
+    tag_sources: bool,
+    packet_meta: bool,
+}
+
+/// Longest header [Writer] writes ahead of a packet's bytes: that of a length-prefixed frame with its source tag and
+/// [PacketMeta], a byte longer than that of a pcapng Enhanced Packet Block
+const MAX_HEADER_LEN: usize = 4 + pcapng::PACKET_META_LEN + (8 + 4);
+
+impl<W: Write> Writer<W> {
+    /// Wrap `writer`, emitting any global header required by `format` immediately.
+    pub fn new(writer: W, format: OutputFormat) -> std::io::Result<Writer<W>> {
+        Writer::with_snaplen(writer, format, None)
+    }
+
+    /// Like [Writer::new], with pcap output's global header giving `snaplen` as the most bytes captured of any packet,
+    /// if given, rather than that of [PCAP_HDR_NSEC]. Packets are written as given, so should be truncated to fit
+    /// (see [super::truncate_record]).
+    pub fn with_snaplen(
+        writer: W,
+        format: OutputFormat,
+        snaplen: Option<u32>,
+    ) -> std::io::Result<Writer<W>> {
+        Writer::with_header(writer, format, snaplen, HeaderFields::default())
+    }
+
+    /// Like [Writer::with_snaplen], with pcap output's global header also carrying `fields`
+    pub fn with_header(
+        mut writer: W,
+        format: OutputFormat,
+        snaplen: Option<u32>,
+        fields: HeaderFields,
+    ) -> std::io::Result<Writer<W>> {
+        match format {
+            OutputFormat::Pcap => {
+                let mut header = PCAP_HDR_NSEC.to_vec();
+                if let Some(snaplen) = snaplen {
+                    header[SNAPLEN_RANGE].copy_from_slice(&snaplen.to_le_bytes());
+                }
+                header[THISZONE_RANGE].copy_from_slice(&fields.thiszone.to_le_bytes());
+                header[SIGFIGS_RANGE].copy_from_slice(&fields.sigfigs.to_le_bytes());
+                writer.write_all(&header)?
+            }
+            OutputFormat::LengthPrefixed => {}
+            OutputFormat::Pcapng => {
+                let mut header = Vec::new();
+                pcapng::write_section_header_block(&mut header);
+                writer.write_all(&header)?;
+            }
+        }
+        Ok(Writer {
+            writer,
+            format,
+            header: Vec::with_capacity(MAX_HEADER_LEN),
+            max_gap_ns: None,
+            last_ts: None,
+            n_markers: 0,
+            interfaces: vec![Interface::new(pcapng::LINKTYPE_ETHERNET)],
+            interfaces_described: false,
+            tag_sources: false,
+            packet_meta: false,
+        })
+    }
+
+    /// The interfaces packets are captured on, which [Writer::write_packet_on] refers to by index. Only pcapng output
+    /// describes them, ahead of its first packet. By default, there's a single unnamed Ethernet interface.
+    pub fn interfaces(mut self, interfaces: Vec<Interface>) -> Writer<W> {
+        self.interfaces = interfaces;
+        self
+    }
+
+    /// Prefix each length-prefixed frame with the id of the source its packet was merged from, given as the `interface`
+    /// of [Writer::write_packet_on], so that merged lanes can be told apart. Sources needn't be [Writer::interfaces].
+    pub fn tag_sources(mut self) -> Writer<W> {
+        assert_eq!(
+            self.format,
+            OutputFormat::LengthPrefixed,
+            "Only length-prefixed output tags sources"
+        );
+        self.tag_sources = true;
+        self
+    }
+
+    /// Include the [PacketMeta] given to [Writer::write_packet_with_meta] in each length-prefixed frame, ahead of its
+    /// timestamp (and following any source tag): the little-endian `u32` interface ID, a byte giving the direction (0 if
+    /// unknown, 1 inbound, 2 outbound, as in the `epb_flags` option), then the little-endian `u64` drop count
+    /// (`u64::MAX` if unknown). Packets written without any, and markers, have the default [PacketMeta].
+    pub fn packet_meta(mut self) -> Writer<W> {
+        assert_eq!(
+            self.format,
+            OutputFormat::LengthPrefixed,
+            "Only length-prefixed output includes packet metadata"
+        );
+        self.packet_meta = true;
+        self
+    }
+
+    /// Keep the written timeline dense: wherever more than `max_gap_ns` would pass between consecutive packets, write
+    /// marker packets every `max_gap_ns` after the earlier one. A marker has no captured bytes and an original length of
+    /// 0 (an empty payload, when length-prefixed), which no captured packet has.
+    pub fn max_gap(mut self, max_gap_ns: u64) -> Writer<W> {
+        assert!(max_gap_ns > 0, "The maximum gap must be at least 1ns");
+        self.max_gap_ns = Some(max_gap_ns);
+        self
+    }
+
+    /// Number of marker packets written to fill gaps longer than the [Writer::max_gap]
+    pub fn n_markers(&self) -> u64 {
+        self.n_markers
+    }
+
+    /// Write a single packet. `record` is the pcap record (header and captured bytes) and `ts` its nanosecond timestamp.
+    /// The written record's timestamp is always taken from `ts`, so adjustments made to the merge key (e.g. per-file
+    /// offsets) are reflected in the output, and its captured length from the captured bytes (see
+    /// [Writer::write_captured_on]).
+    pub fn write_packet(&mut self, ts: u64, record: &[u8]) -> std::io::Result<()> {
+        self.write_packet_on(ts, record, 0)
+    }
+
+    /// Like [Writer::write_packet], for a packet captured on the `interface`th of the [Writer::interfaces], or merged from
+    /// that source when [Writer::tag_sources]. Any marker packets inserted ahead of it are attributed to the same one.
+    pub fn write_packet_on(
+        &mut self,
+        ts: u64,
+        record: &[u8],
+        interface: u32,
+    ) -> std::io::Result<()> {
+        self.write_packet_with_meta(ts, record, interface, &PacketMeta::default())
+    }
+
+    /// Like [Writer::write_packet_on], for a packet's `captured` bytes alone, with its length on the wire given as
+    /// `original_len` rather than read from a record header. The written captured length is always that of `captured`,
+    /// so that packets transformed after being read stay consistent with their headers, and the original length is
+    /// raised to at least it.
+    pub fn write_captured_on(
+        &mut self,
+        ts: u64,
+        captured: &[u8],
+        original_len: u32,
+        interface: u32,
+    ) -> std::io::Result<()> {
+        self.write_captured(
+            ts,
+            captured,
+            original_len,
+            interface,
+            &PacketMeta::default(),
+        )
+    }
+
+    /// Like [Writer::write_packet_on], for a packet read with its `meta` (see [super::pcapng::PcapngPackets::with_meta]),
+    /// which is written when [Writer::packet_meta]
+    pub fn write_packet_with_meta(
+        &mut self,
+        ts: u64,
+        record: &[u8],
+        interface: u32,
+        meta: &PacketMeta,
+    ) -> std::io::Result<()> {
+        if record.len() < RECORD_HEADER_LEN {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "Packet record of {} bytes is shorter than its header",
+                    record.len()
+                ),
+            ));
+        }
+        let original_len = u32::from_le_bytes([record[12], record[13], record[14], record[15]]);
+        self.write_captured(
+            ts,
+            &record[RECORD_HEADER_LEN..],
+            original_len,
+            interface,
+            meta,
+        )
+    }
+
+    fn write_captured(
+        &mut self,
+        ts: u64,
+        captured: &[u8],
+        original_len: u32,
+        interface: u32,
+        meta: &PacketMeta,
+    ) -> std::io::Result<()> {
+        if !self.tag_sources && interface as usize >= self.interfaces.len() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "Packet captured on interface {} of {}",
+                    interface,
+                    self.interfaces.len()
+                ),
+            ));
+        }
+        if let (Some(max_gap_ns), Some(last_ts)) = (self.max_gap_ns, self.last_ts) {
+            let mut marker_ts = last_ts;
+            while ts.saturating_sub(marker_ts) > max_gap_ns {
+                marker_ts += max_gap_ns;
+                self.write_record(marker_ts, &[], 0, interface, &PacketMeta::default())?;
+                self.n_markers += 1;
+            }
+        }
+        self.last_ts = Some(ts);
+        let original_len = original_len.max(captured.len() as u32);
+        self.write_record(ts, captured, original_len, interface, meta)
+    }
+
+    fn write_record(
+        &mut self,
+        ts: u64,
+        captured: &[u8],
+        original_len: u32,
+        interface: u32,
+        meta: &PacketMeta,
+    ) -> std::io::Result<()> {
+        if self.format == OutputFormat::Pcapng && !self.interfaces_described {
+            let mut descriptions = Vec::new();
+            for interface in &self.interfaces {
+                interface.write_description_block(&mut descriptions);
+            }
+            self.writer.write_all(&descriptions)?;
+            self.interfaces_described = true;
+        }
+        self.header.clear();
+        match self.format {
+            OutputFormat::Pcap => {
+                let seconds = (ts / NANOSECONDS_PER_SECOND) as u32;
+                let nanoseconds = (ts % NANOSECONDS_PER_SECOND) as u32;
+                self.header.extend_from_slice(&seconds.to_le_bytes());
+                self.header.extend_from_slice(&nanoseconds.to_le_bytes());
+                self.header
+                    .extend_from_slice(&(captured.len() as u32).to_le_bytes());
+                self.header.extend_from_slice(&original_len.to_le_bytes());
+            }
+            OutputFormat::LengthPrefixed => {
+                if self.tag_sources {
+                    self.header.extend_from_slice(&interface.to_le_bytes());
+                }
+                if self.packet_meta {
+                    self.header.extend_from_slice(&meta.encode());
+                }
+                self.header.extend_from_slice(&ts.to_le_bytes());
+                self.header
+                    .extend_from_slice(&(captured.len() as u32).to_le_bytes());
+            }
+            OutputFormat::Pcapng => {
+                pcapng::write_enhanced_packet_header(
+                    &mut self.header,
+                    interface,
+                    ts,
+                    captured.len() as u32,
+                    original_len,
+                );
+                let (trailer, trailer_len) = pcapng::enhanced_packet_trailer(captured.len() as u32);
+                self.writer.write_all(&self.header)?;
+                self.writer.write_all(captured)?;
+                return self.writer.write_all(&trailer[..trailer_len]);
+            }
+        }
+        self.writer.write_all(&self.header)?;
+        self.writer.write_all(captured)
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::pcapng::Direction;
+    use super::*;
+
+    /// Encode `ts` and `record` as [Writer::write_packet] does, in a newly allocated buffer
+    fn encode_naively(format: OutputFormat, ts: u64, record: &[u8]) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        match format {
+            OutputFormat::Pcap => {
+                encoded.extend_from_slice(&((ts / NANOSECONDS_PER_SECOND) as u32).to_le_bytes());
+                encoded.extend_from_slice(&((ts % NANOSECONDS_PER_SECOND) as u32).to_le_bytes());
+                encoded.extend_from_slice(&record[8..]);
+            }
+            OutputFormat::LengthPrefixed => {
+                let payload = &record[RECORD_HEADER_LEN..];
+                encoded.extend_from_slice(&ts.to_le_bytes());
+                encoded.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+                encoded.extend_from_slice(payload);
+            }
+            OutputFormat::Pcapng => {
+                unreachable!("pcapng blocks are tested alongside their parsing")
+            }
+        }
+        encoded
+    }
+
+    #[test]
+    fn reused_header_buffer_matches_per_packet_encoding() {
+        let packets: Vec<(u64, Vec<u8>)> = (0..100u64)
+            .map(|i| {
+                let payload = vec![i as u8; (i as usize * 7) % 90];
+                let mut record = vec![0xEE; 8]; // stale timestamp, which is always rewritten
+                record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+                record.extend_from_slice(&(payload.len() as u32 + 4).to_le_bytes());
+                record.extend_from_slice(&payload);
+                (i * 123_456_789, record)
+            })
+            .collect();
+        for &format in &[OutputFormat::Pcap, OutputFormat::LengthPrefixed] {
+            let mut writer = Writer::new(Vec::new(), format).unwrap();
+            let mut expected = writer.writer.clone();
+            for (ts, record) in &packets {
+                writer.write_packet(*ts, record).unwrap();
+                expected.extend(encode_naively(format, *ts, record));
+            }
+            assert_eq!(writer.header.capacity(), MAX_HEADER_LEN, "{}", format);
+            assert_eq!(writer.into_inner(), expected, "{}", format);
+        }
+    }
+
+    #[test]
+    fn markers_are_written_every_max_gap_within_longer_gaps() {
+        let record = |payload: &[u8]| {
+            let mut record = vec![0; 8];
+            record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+            record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+            record.extend_from_slice(payload);
+            record
+        };
+        let mut writer = Writer::new(Vec::new(), OutputFormat::LengthPrefixed)
+            .unwrap()
+            .max_gap(100);
+        for &ts in &[1_000, 1_100, 1_350, 1_360] {
+            writer.write_packet(ts, &record(&[7; 3])).unwrap();
+        }
+        // a gap of exactly the maximum needs no marker, while one of 250 needs two
+        assert_eq!(writer.n_markers(), 2);
+
+        let mut expected = Vec::new();
+        for &(ts, payload) in &[
+            (1_000u64, &[7u8; 3][..]),
+            (1_100, &[7; 3]),
+            (1_200, &[]),
+            (1_300, &[]),
+            (1_350, &[7; 3]),
+            (1_360, &[7; 3]),
+        ] {
+            expected.extend(encode_naively(
+                OutputFormat::LengthPrefixed,
+                ts,
+                &record(payload),
+            ));
+        }
+        assert_eq!(writer.into_inner(), expected);
+    }
+
+    #[test]
+    fn captured_lengths_are_those_of_the_written_bytes() {
+        let payload: Vec<u8> = (0..100).collect();
+        let mut record = vec![0; 8];
+        record.extend_from_slice(&100u32.to_le_bytes());
+        record.extend_from_slice(&100u32.to_le_bytes());
+        record.extend_from_slice(&payload);
+        // truncated without rewriting its header, which still claims all 100 bytes were captured
+        let truncated = &record[..RECORD_HEADER_LEN + 40];
+
+        let mut writer = Writer::new(Vec::new(), OutputFormat::Pcap).unwrap();
+        writer.write_packet(1, truncated).unwrap();
+        writer.write_captured_on(2, &payload[..10], 60, 0).unwrap();
+        // an original length shorter than what was captured is raised to it
+        writer.write_captured_on(3, &payload[..10], 4, 0).unwrap();
+
+        let mut expected = PCAP_HDR_NSEC.to_vec();
+        for &(ts, captured_len, original_len) in &[(1u32, 40u32, 100u32), (2, 10, 60), (3, 10, 10)]
+        {
+            expected.extend_from_slice(&0u32.to_le_bytes());
+            expected.extend_from_slice(&ts.to_le_bytes());
+            expected.extend_from_slice(&captured_len.to_le_bytes());
+            expected.extend_from_slice(&original_len.to_le_bytes());
+            expected.extend_from_slice(&payload[..captured_len as usize]);
+        }
+        assert_eq!(writer.into_inner(), expected);
+    }
+
+    #[test]
+    fn length_prefixed_frames_carry_packet_metadata_after_their_source() {
+        let mut record = vec![0; RECORD_HEADER_LEN];
+        record.extend_from_slice(b"xy");
+        let meta = PacketMeta {
+            interface_id: 3,
+            direction: Some(Direction::Outbound),
+            drop_count: Some(9),
+        };
+        let mut writer = Writer::new(Vec::new(), OutputFormat::LengthPrefixed)
+            .unwrap()
+            .tag_sources()
+            .packet_meta();
+        writer.write_packet_with_meta(5, &record, 1, &meta).unwrap();
+        writer.write_packet_on(6, &record, 2).unwrap();
+        let error = writer
+            .write_packet_with_meta(7, &record[..4], 1, &meta)
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput); // and nothing is written
+
+        let mut expected = Vec::new();
+        for (ts, source, interface_id, direction, drop_count) in
+            [(5u64, 1u32, 3u32, 2u8, 9u64), (6, 2, 0, 0, u64::MAX)]
+        {
+            expected.extend_from_slice(&source.to_le_bytes());
+            expected.extend_from_slice(&interface_id.to_le_bytes());
+            expected.push(direction);
+            expected.extend_from_slice(&drop_count.to_le_bytes());
+            expected.extend_from_slice(&ts.to_le_bytes());
+            expected.extend_from_slice(&2u32.to_le_bytes());
+            expected.extend_from_slice(b"xy");
+        }
+        assert_eq!(writer.into_inner(), expected);
+    }
+}
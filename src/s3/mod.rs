@@ -4,20 +4,31 @@
 //!
 //! TODO gate compilation behind some sort of feature flag like features = "s3"
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context as _, Result};
 use async_compat::CompatExt;
 use bytes::{Bytes, BytesMut};
+use futures::future::BoxFuture;
 use futures::stream::{Stream, StreamExt};
 use futures::task::Poll;
 use futures::Future;
-use futures::{ready, FutureExt};
+use futures::{ready, FutureExt, TryFutureExt};
+use rusoto_core::credential::{
+    AutoRefreshingProvider, AwsCredentials, DefaultCredentialsProvider, ProfileProvider,
+    ProvideAwsCredentials,
+};
 use rusoto_core::request::{HttpClient, HttpConfig};
-use rusoto_core::{credential::DefaultCredentialsProvider, Region};
-use rusoto_s3::{GetObjectRequest, HeadObjectOutput, HeadObjectRequest, S3Client, S3};
+use rusoto_core::{Region, RusotoError};
+use rusoto_s3::{
+    GetObjectRequest, HeadObjectRequest, ListObjectsV2Request, RestoreObjectRequest,
+    RestoreRequest, S3Client, S3,
+};
 use std::convert::TryInto;
 use std::pin::Pin;
 use std::task::Context;
 
+mod preflight;
+pub use preflight::{preflight, Preflight, PreflightProblem};
+
 #[pin_project::pin_project(project = ObjectChunksProj)]
 /// [Stream] a file from Amazon S3 in `chunk_size` chunks by providing a byte `range` to the HTTP [GetObjectRequest].
 ///
@@ -30,26 +41,832 @@ pub struct ObjectChunks {
     chunk_size: usize,
     bucket: String,
     key: String,
-    client: std::sync::Arc<S3Client>, // TODO: share a client?
-    file_size: Option<usize>,         // set on first stream call??
-    head_object_request: Option<
-        Pin<
-            std::boxed::Box<
-                dyn Future<
-                        Output = std::result::Result<
-                            HeadObjectOutput,
-                            rusoto_core::RusotoError<rusoto_s3::HeadObjectError>,
-                        >,
-                    > + std::marker::Send,
-            >,
-        >,
-    >,
+    client: std::sync::Arc<dyn ObjectStore>, // TODO: share a client?
+    file_size: Option<usize>,                // set on first stream call??
+    // learns the object's size: a HeadObject request, or the response to the first chunk's request
+    file_size_request: Option<BoxFuture<'static, std::io::Result<usize>>>,
+    head_first: bool,
+    restore_days: Option<u32>,
+    // once read to its known end, how long to wait before asking whether the object has grown
+    follow: Option<std::time::Duration>,
+    // waits out the follow interval, then learns the object's size again
+    growth_request: Option<BoxFuture<'static, std::io::Result<usize>>>,
+}
+
+/// What a HeadObject request reports about an object
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ObjectHead {
+    /// size of the object in bytes
+    pub content_length: usize,
+    /// the object's storage class (e.g. `GLACIER`), if not `STANDARD`
+    pub storage_class: Option<String>,
+    /// the `x-amz-restore` header of an archived object which has been restored, or is being restored
+    pub restore: Option<String>,
+}
+
+impl ObjectHead {
+    /// Whether the object's storage class archives its contents, so they must be restored before they can be read
+    pub fn is_archived(&self) -> bool {
+        matches!(
+            self.storage_class.as_deref(),
+            Some("GLACIER") | Some("DEEP_ARCHIVE")
+        )
+    }
+
+    /// Whether the object is archived and its contents can't currently be read: it hasn't been restored, or its
+    /// restore is still in progress
+    pub fn needs_restore(&self) -> bool {
+        self.is_archived()
+            && !self
+                .restore
+                .as_deref()
+                .is_some_and(|restore| restore.contains("ongoing-request=\"false\""))
+    }
+
+    /// Whether a restore of the object has been requested and is still in progress
+    pub fn is_restoring(&self) -> bool {
+        self.restore
+            .as_deref()
+            .is_some_and(|restore| restore.contains("ongoing-request=\"true\""))
+    }
+}
+
+/// The object storage operations [ObjectChunks] relies upon. Implemented for Amazon S3 by [S3Store], and by in-memory
+/// stores in tests.
+pub trait ObjectStore: Send + Sync {
+    /// Size in bytes of the object at `bucket`/`key`. By default, unsupported, for stores which only answer
+    /// [ObjectStore::head].
+    fn content_length(
+        &self,
+        _bucket: &str,
+        _key: &str,
+    ) -> BoxFuture<'static, std::io::Result<usize>> {
+        unsupported("This store can't report the size of objects")
+    }
+
+    /// The object's bytes from `start` up to and including `end`. As with HTTP ranges, `end` may lie past the end of the object.
+    fn get_range(
+        &self,
+        bucket: &str,
+        key: &str,
+        start: usize,
+        end: usize,
+    ) -> BoxFuture<'static, std::io::Result<Bytes>>;
+
+    /// Like [ObjectStore::get_range], along with the size in bytes of the whole object. By default, the size is
+    /// requested at the same time with [ObjectStore::head], for stores whose responses don't report it.
+    fn get_range_and_length(
+        &self,
+        bucket: &str,
+        key: &str,
+        start: usize,
+        end: usize,
+    ) -> BoxFuture<'static, std::io::Result<(Bytes, usize)>> {
+        let head = self.head(bucket, key).map_ok(|head| head.content_length);
+        futures::future::try_join(self.get_range(bucket, key, start, end), head).boxed()
+    }
+
+    /// Keys of every object in `bucket` beginning with `prefix`, in ascending order. By default, unsupported.
+    fn list_keys(
+        &self,
+        _bucket: &str,
+        _prefix: &str,
+    ) -> BoxFuture<'static, std::io::Result<Vec<String>>> {
+        unsupported("This store can't list objects")
+    }
+
+    /// The [ObjectHead] of the object at `bucket`/`key`. By default, only its [ObjectStore::content_length] is
+    /// known, as for a store which doesn't archive objects.
+    fn head(&self, bucket: &str, key: &str) -> BoxFuture<'static, std::io::Result<ObjectHead>> {
+        self.content_length(bucket, key)
+            .map(|content_length| {
+                content_length.map(|content_length| ObjectHead {
+                    content_length,
+                    ..Default::default()
+                })
+            })
+            .boxed()
+    }
+
+    /// Request that the archived object at `bucket`/`key` be restored, keeping the restored copy readable for `days`
+    fn restore(
+        &self,
+        _bucket: &str,
+        _key: &str,
+        _days: u32,
+    ) -> BoxFuture<'static, std::io::Result<()>> {
+        unsupported("This store can't restore archived objects")
+    }
+}
+
+/// An [std::io::ErrorKind::Unsupported] error, for what an [ObjectStore] can't do
+fn unsupported<T: Send + 'static>(message: &'static str) -> BoxFuture<'static, std::io::Result<T>> {
+    futures::future::ready(Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        message,
+    )))
+    .boxed()
+}
+
+/// Why a request to S3 failed, so far as it affects what can be done about it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FailureKind {
+    /// the credentials were rejected, or aren't allowed to make the request (e.g. lacking `s3:GetObject`)
+    AccessDenied,
+    /// the credentials (e.g. a session token) have expired
+    ExpiredCredentials,
+    /// no credentials could be found
+    NoCredentials,
+    /// the object is archived (e.g. in Glacier) and hasn't been restored
+    Archived,
+    /// the object is archived, and a restore which has been requested is still in progress
+    Restoring,
+    Other,
+}
+
+impl FailureKind {
+    /// Classify a request which failed with the HTTP `status` and response `body`
+    pub fn from_response(status: u16, body: &str) -> FailureKind {
+        if ["ExpiredToken", "TokenRefreshRequired", "RequestExpired"]
+            .iter()
+            .any(|code| body.contains(code))
+        {
+            FailureKind::ExpiredCredentials
+        } else if status == 403 || body.contains("AccessDenied") {
+            FailureKind::AccessDenied
+        } else {
+            FailureKind::Other
+        }
+    }
+
+    /// The kind of failure behind an error returned by an [ObjectStore], or [FailureKind::Other] if it wasn't
+    /// classified
+    pub fn of(error: &std::io::Error) -> FailureKind {
+        FailureKind::of_request(error).unwrap_or(FailureKind::Other)
+    }
+
+    /// Like [FailureKind::of], or `None` if `error` doesn't describe a failed request, such as those reported by
+    /// [ObjectChunks] and [http_error]
+    pub fn of_request(error: &std::io::Error) -> Option<FailureKind> {
+        error
+            .get_ref()
+            .and_then(|error| error.downcast_ref::<RequestError>())
+            .map(|error| error.kind)
+    }
+
+    /// What the user can do about this kind of failure
+    fn advice(self) -> Option<&'static str> {
+        match self {
+            FailureKind::AccessDenied => Some(
+                "Access was denied: check that the AWS credentials in use allow s3:GetObject on the object \
+                 (and s3:ListBucket on its bucket), and pass --requester-pays for requester-pays buckets",
+            ),
+            FailureKind::ExpiredCredentials => {
+                Some("The AWS credentials have expired: refresh them (e.g. with aws sso login) and retry")
+            }
+            FailureKind::NoCredentials => Some(
+                "No AWS credentials were found: set AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY, set AWS_PROFILE, \
+                 or run with an instance or task role",
+            ),
+            FailureKind::Archived => Some(
+                "Archived objects must be restored before they can be read: pass --restore <DAYS> to request a \
+                 restore, and retry once it completes",
+            ),
+            FailureKind::Restoring => {
+                Some("Retry once the restore completes, which may take several hours")
+            }
+            FailureKind::Other => None,
+        }
+    }
+}
+
+/// Error of a failed request, classified by its [FailureKind]
+#[derive(Clone, Debug)]
+struct RequestError {
+    kind: FailureKind,
+    message: String,
+}
+
+impl std::fmt::Display for RequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for RequestError {}
+
+impl From<RequestError> for std::io::Error {
+    fn from(error: RequestError) -> std::io::Error {
+        let kind = match error.kind {
+            FailureKind::AccessDenied
+            | FailureKind::ExpiredCredentials
+            | FailureKind::NoCredentials => std::io::ErrorKind::PermissionDenied,
+            FailureKind::Archived | FailureKind::Restoring | FailureKind::Other => {
+                std::io::ErrorKind::Other
+            }
+        };
+        std::io::Error::new(kind, error)
+    }
+}
+
+/// Error for a request which failed with the HTTP `status` and response `body`, classified by
+/// [FailureKind::from_response]. For [ObjectStore] implementations to report failed requests with.
+pub fn http_error(status: u16, body: &str) -> std::io::Error {
+    let body = body.trim();
+    RequestError {
+        kind: FailureKind::from_response(status, body),
+        message: if body.is_empty() {
+            format!("HTTP {}", status)
+        } else {
+            format!("HTTP {}: {}", status, body)
+        },
+    }
+    .into()
+}
+
+/// Convert the error of a failed [S3Client] request, classifying credential and HTTP errors
+fn request_error<E: std::fmt::Display>(error: RusotoError<E>) -> std::io::Error {
+    match error {
+        RusotoError::Credentials(error) => RequestError {
+            kind: if error.message.to_ascii_lowercase().contains("expired") {
+                FailureKind::ExpiredCredentials
+            } else {
+                FailureKind::NoCredentials
+            },
+            message: error.message,
+        }
+        .into(),
+        RusotoError::Unknown(response) => {
+            http_error(response.status.as_u16(), response.body_as_str())
+        }
+        error => std::io::Error::other(error.to_string()),
+    }
+}
+
+/// `error` from a request for `s3://bucket/key`, annotated with the object's URI and what can be done about the
+/// failure
+fn annotate(error: std::io::Error, bucket: &str, key: &str) -> std::io::Error {
+    annotated(error, bucket, key).into()
+}
+
+/// Like [annotate], as a [RequestError] which can be cloned
+fn annotated(error: std::io::Error, bucket: &str, key: &str) -> RequestError {
+    let kind = FailureKind::of(&error);
+    let mut message = format!("Failed to read {}{}/{}: {}", URI_PREFIX, bucket, key, error);
+    if let Some(advice) = kind.advice() {
+        message = format!("{}. {}", message, advice);
+    }
+    RequestError { kind, message }
+}
+
+/// [ObjectStore] backed by an [S3Client]
+#[derive(Clone)]
+pub struct S3Store {
+    client: S3Client,
+    requester_pays: bool,
+}
+
+impl S3Store {
+    pub fn new(client: S3Client) -> S3Store {
+        S3Store {
+            client,
+            requester_pays: false,
+        }
+    }
+
+    /// Whether requests acknowledge that the requester is charged for them, as required to read from (and list)
+    /// requester-pays buckets. Those buckets reject requests without it with a `403`
+    pub fn requester_pays(mut self, requester_pays: bool) -> S3Store {
+        self.requester_pays = requester_pays;
+        self
+    }
+
+    /// The `x-amz-request-payer` header value sent with every request
+    fn request_payer(&self) -> Option<String> {
+        if self.requester_pays {
+            Some("requester".into())
+        } else {
+            None
+        }
+    }
+
+    fn head_object_request(&self, bucket: &str, key: &str) -> HeadObjectRequest {
+        HeadObjectRequest {
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            request_payer: self.request_payer(),
+            ..Default::default()
+        }
+    }
+
+    fn restore_object_request(&self, bucket: &str, key: &str, days: u32) -> RestoreObjectRequest {
+        RestoreObjectRequest {
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            restore_request: Some(RestoreRequest {
+                days: Some(days.into()),
+                ..Default::default()
+            }),
+            request_payer: self.request_payer(),
+            ..Default::default()
+        }
+    }
+
+    fn get_object_request(
+        &self,
+        bucket: &str,
+        key: &str,
+        start: usize,
+        end: usize,
+    ) -> GetObjectRequest {
+        GetObjectRequest {
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            range: Some(format!("bytes={}-{}", start, end)),
+            request_payer: self.request_payer(),
+            ..Default::default()
+        }
+    }
+
+    fn list_objects_request(&self, bucket: &str, prefix: &str) -> ListObjectsV2Request {
+        ListObjectsV2Request {
+            bucket: bucket.to_string(),
+            prefix: Some(prefix.to_string()),
+            request_payer: self.request_payer(),
+            ..Default::default()
+        }
+    }
+}
+
+impl ObjectStore for S3Store {
+    fn content_length(
+        &self,
+        bucket: &str,
+        key: &str,
+    ) -> BoxFuture<'static, std::io::Result<usize>> {
+        self.head(bucket, key)
+            .map(|head| head.map(|head| head.content_length))
+            .boxed()
+    }
+
+    fn head(&self, bucket: &str, key: &str) -> BoxFuture<'static, std::io::Result<ObjectHead>> {
+        let client = self.client.clone();
+        let request = self.head_object_request(bucket, key);
+        async move {
+            let object_metadata = client
+                .head_object(request)
+                .compat()
+                .await
+                .map_err(request_error)?;
+            let content_length = object_metadata
+                .content_length
+                .and_then(|content_length| content_length.try_into().ok())
+                .ok_or_else(|| {
+                    std::io::Error::other("HeadObject response is missing a valid content length")
+                })?;
+            Ok(ObjectHead {
+                content_length,
+                storage_class: object_metadata.storage_class,
+                restore: object_metadata.restore,
+            })
+        }
+        .boxed()
+    }
+
+    fn restore(
+        &self,
+        bucket: &str,
+        key: &str,
+        days: u32,
+    ) -> BoxFuture<'static, std::io::Result<()>> {
+        let client = self.client.clone();
+        let request = self.restore_object_request(bucket, key, days);
+        async move {
+            client
+                .restore_object(request)
+                .compat()
+                .await
+                .map_err(request_error)?;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn get_range(
+        &self,
+        bucket: &str,
+        key: &str,
+        start: usize,
+        end: usize,
+    ) -> BoxFuture<'static, std::io::Result<Bytes>> {
+        self.get_range_and_length(bucket, key, start, end)
+            .map_ok(|(body, _)| body)
+            .boxed()
+    }
+
+    fn get_range_and_length(
+        &self,
+        bucket: &str,
+        key: &str,
+        start: usize,
+        end: usize,
+    ) -> BoxFuture<'static, std::io::Result<(Bytes, usize)>> {
+        let client = self.client.clone();
+        let request = self.get_object_request(bucket, key, start, end);
+        async move {
+            let mut object = client
+                .get_object(request)
+                .compat()
+                .await
+                .map_err(request_error)?;
+            let content_length = object
+                .content_range
+                .as_deref()
+                .and_then(content_range_length)
+                .ok_or_else(|| {
+                    std::io::Error::other("GetObject response is missing a valid content range")
+                })?;
+            let mut chunk_content_byte_stream = object.body.take().expect("No body");
+            let mut body = BytesMut::with_capacity(end + 1 - start);
+            while let Some(data) = chunk_content_byte_stream.next().await {
+                body.extend_from_slice(&data?);
+            }
+            Ok((body.freeze(), content_length))
+        }
+        .boxed()
+    }
+
+    fn list_keys(
+        &self,
+        bucket: &str,
+        prefix: &str,
+    ) -> BoxFuture<'static, std::io::Result<Vec<String>>> {
+        let client = self.client.clone();
+        let mut request = self.list_objects_request(bucket, prefix);
+        async move {
+            let mut keys = Vec::new();
+            loop {
+                let page = client
+                    .list_objects_v2(request.clone())
+                    .compat()
+                    .await
+                    .map_err(request_error)?;
+                keys.extend(
+                    page.contents
+                        .unwrap_or_default()
+                        .into_iter()
+                        .filter_map(|object| object.key),
+                );
+                match page.next_continuation_token {
+                    Some(token) if page.is_truncated == Some(true) => {
+                        request.continuation_token = Some(token)
+                    }
+                    _ => return Ok(keys),
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+/// Size of the whole object given by the `Content-Range` header of a ranged response, e.g. 1000 for `bytes 0-99/1000`
+fn content_range_length(content_range: &str) -> Option<usize> {
+    content_range.rsplit('/').next()?.parse().ok()
+}
+
+/// [ObjectStore] holding a single object in memory
+#[cfg(test)]
+pub(crate) struct InMemoryStore(pub Bytes);
+
+#[cfg(test)]
+impl ObjectStore for InMemoryStore {
+    fn content_length(
+        &self,
+        _bucket: &str,
+        _key: &str,
+    ) -> BoxFuture<'static, std::io::Result<usize>> {
+        futures::future::ready(Ok(self.0.len())).boxed()
+    }
+
+    fn get_range(
+        &self,
+        _bucket: &str,
+        _key: &str,
+        start: usize,
+        end: usize,
+    ) -> BoxFuture<'static, std::io::Result<Bytes>> {
+        if start >= self.0.len() {
+            // as S3 responds to a range starting past the end of the object
+            let body = "<Error><Code>InvalidRange</Code></Error>";
+            return futures::future::ready(Err(http_error(416, body))).boxed();
+        }
+        let end = (end + 1).min(self.0.len());
+        futures::future::ready(Ok(self.0.slice(start..end))).boxed()
+    }
+
+    fn list_keys(
+        &self,
+        _bucket: &str,
+        _prefix: &str,
+    ) -> BoxFuture<'static, std::io::Result<Vec<String>>> {
+        futures::future::ready(Ok(Vec::new())).boxed()
+    }
 }
 
 const URI_PREFIX: &str = "s3://";
 
+/// Controls how each S3 object is split into chunks and how many chunk downloads are in flight at once.
+#[derive(Clone, Debug)]
+pub struct DownloadConfig {
+    /// number of bytes requested by each ranged GetObject
+    pub chunk_size: usize,
+    /// number of chunks downloaded one-at-a-time before a file is considered "active" and read-ahead begins
+    pub take_n_serially: usize,
+    /// maximum number of concurrent chunk downloads per active file
+    pub max_n_buffered: usize,
+    /// shared cap on the bytes of read-ahead chunks in flight across every file using this budget
+    pub memory_budget: Option<crate::MemoryBudget>,
+    /// run each file's download, decompression and parsing on these dedicated threads instead of the global executor
+    pub decode_pool: Option<crate::DecodePool>,
+    /// decode each file ahead of the merge, writing its packets which are waiting to be merged to disk once too many
+    /// bytes are waiting in memory. See [crate::spill]
+    pub spill: Option<crate::spill::Spill>,
+    /// shared cap on the aggregate rate at which chunks are downloaded across every file using this limiter
+    pub rate_limiter: Option<crate::RateLimiter>,
+    /// shared cap on the number of local files open at once. When set, each local file is opened once its stream
+    /// first reads from it and closed again while its packets wait to be merged
+    pub open_file_limit: Option<crate::OpenFileLimit>,
+    /// report a file ending part way through a record as an error rather than silently dropping the partial record.
+    /// See [crate::pcap::Packets::strict]
+    pub strict: bool,
+    /// correct pcap timestamps for their 32-bit seconds wrapping around. See [crate::pcap::Packets::fix_wraparound]
+    pub fix_wraparound: bool,
+    /// merge pcap packets by this key of their captured bytes rather than their timestamps. See
+    /// [crate::pcap::Packets::with_key_fn]
+    pub key_expr: Option<crate::pcap::KeyExpr>,
+    /// only read the packets of this interface of a pcapng file. See [crate::pcap::pcapng::PcapngPackets::only_interface]
+    pub pcapng_interface: Option<u32>,
+    /// read `s3://` files from requester-pays buckets. See [S3Store::requester_pays]
+    pub requester_pays: bool,
+    /// read `s3://` files with this profile's credentials and region rather than the defaults. See [default_store]
+    pub aws_profile: Option<AwsProfile>,
+    /// request that archived `s3://` files be restored for this many days, rather than only failing to read them. See
+    /// [ObjectChunks::restore_archived]
+    pub restore_days: Option<u32>,
+    /// learn the size of each `s3://` file before requesting its first chunk. See [ObjectChunks::head_first]
+    pub head_first: bool,
+    /// keep streaming each `s3://` file as it grows, learning its size again at this interval once it has been read
+    /// to its known end. See [ObjectChunks::follow]
+    pub follow: Option<std::time::Duration>,
+    /// decode `.zst` files with this dictionary. See [crate::compression::ZstdDictionaryDecoder]
+    pub zstd_dictionary: Option<std::sync::Arc<Vec<u8>>>,
+    /// reframe the packets of pcap files of its link type. See [crate::pcap::Transcode]
+    pub transcode: Option<crate::pcap::Transcode>,
+    /// sort each file's packets which are out of timestamp order by no more than this many nanoseconds. See
+    /// [crate::pcap::Reorder]
+    pub reorder_window_ns: Option<u64>,
+    /// shift each packet's timestamp by this signed number of nanoseconds. See [crate::pcap::offset_processor]
+    pub offset_ns: i64,
+    /// append each packet's pcapng metadata to its record, to be split off with
+    /// [crate::pcap::pcapng::PacketMeta::split_from]. Packets of other formats are given the default metadata
+    pub packet_meta: bool,
+}
+
+impl Default for DownloadConfig {
+    fn default() -> DownloadConfig {
+        // 128kb * 4_simultaneous_downloads == 128kB * 4000 files == 512MB for one chunk per-file. If decompression rate requires > 1 parallel chunk to achieve goal throughput,
+        // let's say 4 to start are needed, our expected max memory footprint will be 512MB + (512MB/12) * 4 == 512MB + 170.6666MB == 682.6666MB when 1/12th of the files are "active" at a time.
+        DownloadConfig {
+            chunk_size: 1024 * 128,
+            take_n_serially: 1,
+            max_n_buffered: 4,
+            memory_budget: None,
+            decode_pool: None,
+            spill: None,
+            rate_limiter: None,
+            open_file_limit: None,
+            strict: false,
+            fix_wraparound: false,
+            key_expr: None,
+            pcapng_interface: None,
+            requester_pays: false,
+            aws_profile: None,
+            restore_days: None,
+            head_first: false,
+            follow: None,
+            zstd_dictionary: None,
+            transcode: None,
+            reorder_window_ns: None,
+            offset_ns: 0,
+            packet_meta: false,
+        }
+    }
+}
+
+/// A named profile of the shared AWS credentials and config files, as selected by `AWS_PROFILE` or `--profile` for
+/// other AWS tools
+#[derive(Clone, Debug)]
+pub struct AwsProfile {
+    provider: ProfileProvider,
+    // read for the profile's region rather than the default config file, if given
+    config_file: Option<std::path::PathBuf>,
+}
+
+impl AwsProfile {
+    /// The profile `name` of the default credentials file (`AWS_SHARED_CREDENTIALS_FILE`, or `~/.aws/credentials`)
+    pub fn named(name: &str) -> Result<AwsProfile> {
+        let provider = ProfileProvider::with_default_credentials(name).with_context(|| {
+            format!(
+                "Failed to find the AWS credentials file for profile '{}'",
+                name
+            )
+        })?;
+        Ok(AwsProfile {
+            provider,
+            config_file: None,
+        })
+    }
+
+    /// The profile `name` of the credentials file at `path`
+    pub fn with_credentials_file(path: impl AsRef<std::path::Path>, name: &str) -> AwsProfile {
+        AwsProfile {
+            provider: ProfileProvider::with_configuration(path, name),
+            config_file: None,
+        }
+    }
+
+    /// Read this profile's region from the config file at `path` rather than the default config file
+    pub fn config_file(mut self, path: impl Into<std::path::PathBuf>) -> AwsProfile {
+        self.config_file = Some(path.into());
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        self.provider.profile()
+    }
+
+    /// The region set by this profile in its config file (see [AwsProfile::config_file], otherwise `AWS_CONFIG_FILE`,
+    /// or `~/.aws/config`), if any
+    pub fn region(&self) -> Result<Option<Region>> {
+        let region = match &self.config_file {
+            Some(path) => profile_region(path, self.name()),
+            None => self.provider.region_from_profile().map_err(Into::into),
+        }
+        .with_context(|| format!("Failed to read the region of AWS profile '{}'", self.name()))?;
+        region
+            .map(|region| {
+                region.parse().with_context(|| {
+                    format!(
+                        "Invalid region '{}' in AWS profile '{}'",
+                        region,
+                        self.name()
+                    )
+                })
+            })
+            .transpose()
+    }
+
+    /// This profile's credentials from its credentials file
+    pub async fn credentials(&self) -> Result<AwsCredentials> {
+        self.provider.credentials().await.with_context(|| {
+            format!(
+                "Failed to read the credentials of AWS profile '{}'",
+                self.name()
+            )
+        })
+    }
+}
+
+/// The `region` of the `name`d profile's section of the AWS config file at `config_file`, if it sets one
+fn profile_region(config_file: &std::path::Path, name: &str) -> Result<Option<String>> {
+    let config = std::fs::read_to_string(config_file)
+        .with_context(|| format!("Failed to read '{}'", config_file.display()))?;
+    // every profile but the default is named with a prefix in the config file, unlike the credentials file
+    let section = match name {
+        "default" => "default".to_string(),
+        name => format!("profile {}", name),
+    };
+    let mut in_section = false;
+    for line in config.lines().map(str::trim) {
+        if let Some(header) = line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            in_section = header.trim() == section;
+        } else if let Some((key, value)) = line.split_once('=').filter(|_| in_section) {
+            if key.trim() == "region" {
+                return Ok(Some(value.trim().to_string()));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// The [S3Store] used for `s3://` URIs unless another [ObjectStore] is provided. Requests are signed with the
+/// credentials and sent to the region of `profile` if one is given. Otherwise, they're found from the environment
+/// (where `AWS_PROFILE` selects a profile), the shared credentials and config files, then the instance's role.
+///
+/// Without a profile, or with one which sets no region, requests are sent to [Region::default]: the region named by
+/// `AWS_DEFAULT_REGION` or `AWS_REGION`, otherwise that of the default profile of the config file, falling back to
+/// us-east-1.
+pub fn default_store(
+    requester_pays: bool,
+    profile: Option<&AwsProfile>,
+) -> std::sync::Arc<S3Store> {
+    // attempt to use a 8mb HTTP request buffer for better performance?
+    let mut http_config_with_bigger_buffer = HttpConfig::new();
+    http_config_with_bigger_buffer.read_buf_size(1024 * 1024 * 8);
+    let http_provider = HttpClient::new_with_config(http_config_with_bigger_buffer).unwrap();
+    let client = match profile {
+        Some(profile) => {
+            let region = profile.region().unwrap_or_else(|error| {
+                tracing::event!(tracing::Level::WARN, ?error, "Using the default region");
+                None
+            });
+            S3Client::new_with(
+                http_provider,
+                AutoRefreshingProvider::new(profile.provider.clone()).unwrap(),
+                region.unwrap_or_default(),
+            )
+        }
+        None => S3Client::new_with(
+            http_provider,
+            DefaultCredentialsProvider::new().unwrap(),
+            Region::default(),
+        ),
+    };
+    std::sync::Arc::new(S3Store::new(client).requester_pays(requester_pays))
+}
+
+/// Split `s3://bucket/key` (the `s3://` being optional) into its bucket and key
+fn split_uri(uri: &str) -> Result<(&str, &str)> {
+    let uri = uri.trim_start_matches(URI_PREFIX);
+    match uri.find('/') {
+        Some(bucket_delimiter_index) => Ok((
+            &uri[..bucket_delimiter_index],
+            &uri[bucket_delimiter_index + 1..],
+        )),
+        None => bail!("Invalid S3 URI: '{}'. Missing '/' bucket delimiter", uri),
+    }
+}
+
+/// Size in bytes of the object at the `s3://` `uri`, looked up with the [default_store] of `config`
+pub async fn object_len(uri: &str, config: &DownloadConfig) -> Result<usize> {
+    let (bucket, key) = split_uri(uri)?;
+    default_store(config.requester_pays, config.aws_profile.as_ref())
+        .content_length(bucket, key)
+        .await
+        .with_context(|| format!("Failed to look up '{}'", uri))
+}
+
+/// The bytes of the object at the `s3://` `uri` from `start` up to and including `end` (or the end of the object),
+/// requested from the [default_store] of `config`
+pub async fn object_range(
+    uri: &str,
+    config: &DownloadConfig,
+    start: usize,
+    end: usize,
+) -> Result<Bytes> {
+    let (bucket, key) = split_uri(uri)?;
+    default_store(config.requester_pays, config.aws_profile.as_ref())
+        .get_range(bucket, key, start, end)
+        .await
+        .with_context(|| format!("Failed to read bytes {}-{} of '{}'", start, end, uri))
+}
+
+/// `s3://` URIs of every object under the `s3://bucket/prefix/` URI `prefix_uri`, in ascending order, listed with a
+/// [default_store]
+pub fn list_prefix(
+    prefix_uri: &str,
+    requester_pays: bool,
+    profile: Option<&AwsProfile>,
+) -> Result<Vec<String>> {
+    list_prefix_with_store(prefix_uri, &*default_store(requester_pays, profile))
+}
+
+/// Like [list_prefix], listing objects from `store`
+pub fn list_prefix_with_store(prefix_uri: &str, store: &dyn ObjectStore) -> Result<Vec<String>> {
+    let (bucket, prefix) = split_uri(prefix_uri)?;
+    let mut keys = smol::block_on(store.list_keys(bucket, prefix))
+        .with_context(|| format!("Failed to list '{}'", prefix_uri))?;
+    keys.sort();
+    Ok(keys
+        .into_iter()
+        .map(|key| format!("{}{}/{}", URI_PREFIX, bucket, key))
+        .collect())
+}
+
 impl ObjectChunks {
     pub fn new(uri: &str, chunk_size: usize) -> Result<Pin<Box<ObjectChunks>>> {
+        ObjectChunks::with_store(uri, chunk_size, default_store(false, None))
+    }
+
+    /// Like [ObjectChunks::new], reading the object from `store` rather than a default [S3Store]
+    pub fn with_store(
+        uri: &str,
+        chunk_size: usize,
+        store: std::sync::Arc<dyn ObjectStore>,
+    ) -> Result<Pin<Box<ObjectChunks>>> {
         let uri = uri.trim_start_matches(URI_PREFIX);
         if let Some(bucket_delimiter_index) = uri.find('/') {
             let (bucket, key) = uri.split_at(bucket_delimiter_index);
@@ -60,28 +877,20 @@ impl ObjectChunks {
                 );
             }
 
-            // attempt to use a 8mb HTTP request buffer for better performance?
-            let cred_provider = DefaultCredentialsProvider::new().unwrap();
-            let mut http_config_with_bigger_buffer = HttpConfig::new();
-            http_config_with_bigger_buffer.read_buf_size(1024 * 1024 * 8);
-            let http_provider =
-                HttpClient::new_with_config(http_config_with_bigger_buffer).unwrap();
-            let client = std::sync::Arc::new(S3Client::new_with(
-                http_provider,
-                cred_provider,
-                Region::UsEast1,
-            ));
-
             let bucket = String::from(bucket);
             let key = String::from(&key[1..]);
             let stream = Box::pin(ObjectChunks {
                 next_chunk_start: 0,
                 chunk_size,
-                client,
+                client: store,
                 bucket,
                 key,
                 file_size: None,
-                head_object_request: None,
+                file_size_request: None,
+                head_first: false,
+                restore_days: None,
+                follow: None,
+                growth_request: None,
             });
 
             Ok(stream)
@@ -89,6 +898,103 @@ impl ObjectChunks {
             bail!("Invalid S3 URI: '{}'. Missing '/' bucket delimiter", uri);
         }
     }
+
+    /// If the object is archived and hasn't been restored, request that it be restored for `days` before failing to
+    /// read it. Either way, the stream fails with a [FailureKind::Archived] or [FailureKind::Restoring] error naming
+    /// the object until its restore completes.
+    pub fn restore_archived(&mut self, days: u32) {
+        self.restore_days = Some(days);
+    }
+
+    /// Learn the object's size with a HeadObject request before requesting its first chunk, rather than from the
+    /// response to its first chunk's request. This costs a round trip before any of the object is read, but no
+    /// chunk is requested of an object which can't be read (e.g. because it is archived).
+    pub fn head_first(&mut self, head_first: bool) {
+        self.head_first = head_first;
+    }
+
+    /// Once the object has been read to the end of its known size, wait `interval`, then learn its size again with a
+    /// HeadObject request, rather than ending the stream. If it has grown, the stream continues with its new bytes,
+    /// like `tail -f`. Otherwise, it waits out another interval. For objects still being written, whose size when the
+    /// stream began would otherwise end it early. The stream never ends of its own accord while following.
+    pub fn follow(&mut self, interval: Option<std::time::Duration>) {
+        self.follow = interval;
+    }
+
+    /// Start the next chunk at the absolute byte `offset` into the object, clamped to the object's size. Chunk futures
+    /// already yielded by the stream are unaffected, so this is only meaningful before they are polled in earnest
+    /// (e.g. before the stream is wrapped in [crate::util::TakeThenBuffered]), or when the caller discards them.
+    pub fn seek_to(&mut self, offset: usize) {
+        self.next_chunk_start = match self.file_size {
+            Some(file_size) => offset.min(file_size),
+            None => offset, // clamped once the size is known
+        };
+    }
+}
+
+/// Size in bytes of the object at `bucket`/`key`, failing if it is archived and hasn't been restored. A restore for
+/// `restore_days` is requested first, if given and none is already in progress.
+async fn readable_length(
+    store: std::sync::Arc<dyn ObjectStore>,
+    bucket: String,
+    key: String,
+    restore_days: Option<u32>,
+) -> std::io::Result<usize> {
+    let head = store.head(&bucket, &key).await?;
+    if !head.needs_restore() {
+        return Ok(head.content_length);
+    }
+    let storage_class = head.storage_class.as_deref().unwrap_or_default();
+    let error = if head.is_restoring() {
+        RequestError {
+            kind: FailureKind::Restoring,
+            message: format!(
+                "The object is archived in the {} storage class, and is being restored",
+                storage_class
+            ),
+        }
+    } else if let Some(days) = restore_days {
+        store.restore(&bucket, &key, days).await?;
+        tracing::event!(tracing::Level::INFO, %bucket, %key, days, "Requested restore of archived object");
+        RequestError {
+            kind: FailureKind::Restoring,
+            message: format!(
+                "The object is archived in the {} storage class. Requested that it be restored for {} days",
+                storage_class, days
+            ),
+        }
+    } else {
+        RequestError {
+            kind: FailureKind::Archived,
+            message: format!(
+                "The object is archived in the {} storage class, and hasn't been restored",
+                storage_class
+            ),
+        }
+    };
+    Err(error.into())
+}
+
+/// The object's bytes from `start` up to and including `end`, along with its size. Should the request fail, the
+/// object's HEAD tells whether it did so because the object is empty or shorter than `start`, so the chunk is empty,
+/// or can't be read as it is, which is reported as by [readable_length].
+async fn first_chunk(
+    store: std::sync::Arc<dyn ObjectStore>,
+    bucket: String,
+    key: String,
+    (start, end): (usize, usize),
+    restore_days: Option<u32>,
+) -> std::io::Result<(Bytes, usize)> {
+    let error = match store.get_range_and_length(&bucket, &key, start, end).await {
+        Ok(chunk) => return Ok(chunk),
+        Err(error) => error,
+    };
+    let size = readable_length(store, bucket, key, restore_days).await?;
+    if start >= size {
+        Ok((Bytes::new(), size))
+    } else {
+        Err(error)
+    }
 }
 
 // TODO: reimplement with TryStream in mind to propagate errors?
@@ -105,78 +1011,664 @@ impl Stream for ObjectChunks {
             key,
             client,
             file_size,
-            head_object_request,
+            file_size_request,
+            head_first,
+            restore_days,
+            follow,
+            growth_request,
         } = self.as_mut().project();
 
         if file_size.is_none() {
-            if head_object_request.is_none() {
-                let other_client = client.clone();
-                let other_bucket = bucket.clone();
-                let other_key = key.clone();
-                *head_object_request = Some(
-                    async move {
-                        other_client
-                            .head_object(HeadObjectRequest {
-                                bucket: other_bucket,
-                                key: other_key,
-                                ..Default::default()
-                            })
-                            .compat()
-                            .await
+            if file_size_request.is_none() {
+                if *head_first {
+                    *file_size_request = Some(
+                        readable_length(client.clone(), bucket.clone(), key.clone(), *restore_days)
+                            .boxed(),
+                    );
+                } else {
+                    // request the first chunk without waiting to learn the object's size, which its response reports
+                    let range = (*next_chunk_start, *next_chunk_start + (*chunk_size - 1));
+                    let (chunk_bucket, chunk_key) = (bucket.clone(), key.clone());
+                    let chunk = first_chunk(
+                        client.clone(),
+                        bucket.clone(),
+                        key.clone(),
+                        range,
+                        *restore_days,
+                    )
+                    .map_err(move |error| annotated(error, &chunk_bucket, &chunk_key))
+                    .boxed()
+                    .shared();
+                    *file_size_request =
+                        Some(chunk.clone().map_ok(|(_, size)| size).err_into().boxed());
+                    *next_chunk_start += *chunk_size;
+                    return Poll::Ready(Some(Box::pin(
+                        chunk.map_ok(|(chunk, _)| chunk).err_into(),
+                    )));
+                }
+            }
+            if let Some(request) = file_size_request {
+                // return Poll::Pending until the object's size is known
+                let size = match ready!(request.as_mut().poll(cx)) {
+                    Ok(size) => size,
+                    Err(_) if !*head_first => {
+                        // the first chunk has already failed with the same error, so end the stream
+                        *file_size_request = None;
+                        *file_size = Some(0);
+                        return Poll::Ready(None);
                     }
-                    .boxed(),
-                );
+                    Err(error) => {
+                        // yield the failure in place of the first chunk, then end the stream
+                        *file_size_request = None;
+                        *file_size = Some(0);
+                        let error = annotate(error, bucket, key);
+                        return Poll::Ready(Some(Box::pin(futures::future::ready(Err(error)))));
+                    }
+                };
+                *file_size = Some(size);
+                *next_chunk_start = (*next_chunk_start).min(size); // apply any seek_to() made before the size was known
             }
-            if let Some(request) = head_object_request {
-                // return Poll::Pending until the saved HeadObjectRequest is ready
-                let object_metadata = ready!(request.as_mut().poll(cx));
-                *file_size = Some(
-                    object_metadata
-                        .unwrap()
-                        .content_length
-                        .unwrap()
-                        .try_into()
-                        .unwrap(),
-                ); // TODO: graceful error vs unwrap
+        }
+
+        if let Some(known_size) = *file_size {
+            if *next_chunk_start >= known_size {
+                let interval = match *follow {
+                    Some(interval) => interval,
+                    None => return Poll::Ready(None), // done streaming the file
+                };
+                loop {
+                    let request = growth_request.get_or_insert_with(|| {
+                        let (client, bucket, key) = (client.clone(), bucket.clone(), key.clone());
+                        async move {
+                            smol::Timer::after(interval).await;
+                            client.content_length(&bucket, &key).await
+                        }
+                        .boxed()
+                    });
+                    let size = ready!(request.as_mut().poll(cx));
+                    *growth_request = None;
+                    match size {
+                        Ok(size) if size > known_size => {
+                            *file_size = Some(size);
+                            break;
+                        }
+                        Ok(_) => {} // not yet grown, so wait out another interval
+                        Err(error) => {
+                            // yield the failure in place of the next chunk, then end the stream
+                            *follow = None;
+                            let error = annotate(error, bucket, key);
+                            return Poll::Ready(Some(Box::pin(futures::future::ready(Err(error)))));
+                        }
+                    }
+                }
             }
         }
 
-        if let Some(file_size) = *file_size {
-            if *next_chunk_start > file_size {
-                // done streaming the file
-                return Poll::Ready(None);
+        // request the next chunk. While following, no chunk reaches past the known size, so that any bytes appended
+        // since are left to the chunks which follow it
+        let start = *next_chunk_start;
+        let mut end = start + (*chunk_size - 1);
+        if let (Some(_), Some(file_size)) = (*follow, *file_size) {
+            end = end.min(file_size - 1);
+        }
+        let next_chunk = client.get_range(bucket, key, start, end);
+        *next_chunk_start = end + 1;
+        let (bucket, key) = (bucket.clone(), key.clone());
+        Poll::Ready(Some(Box::pin(next_chunk.map(move |chunk| {
+            chunk.map_err(|error| annotate(error, &bucket, &key))
+        }))))
+    }
+}
+
+/* TODO: add S3 file download tests which confirm downloads happen in parallel when wrapped with TakeThenBuffered? */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn read_all(chunks: impl Stream<Item = <ObjectChunks as Stream>::Item>) -> Vec<u8> {
+        futures::executor::block_on(chunks.then(|chunk| chunk).collect::<Vec<_>>())
+            .into_iter()
+            .flat_map(|chunk| chunk.unwrap())
+            .collect()
+    }
+
+    /// [ObjectStore] which only lists a fixed set of keys
+    struct ListingStore(Vec<&'static str>);
+
+    impl ObjectStore for ListingStore {
+        fn get_range(
+            &self,
+            _bucket: &str,
+            _key: &str,
+            _start: usize,
+            _end: usize,
+        ) -> BoxFuture<'static, std::io::Result<Bytes>> {
+            unsupported("ListingStore only lists keys")
+        }
+
+        fn list_keys(
+            &self,
+            _bucket: &str,
+            prefix: &str,
+        ) -> BoxFuture<'static, std::io::Result<Vec<String>>> {
+            let keys = self
+                .0
+                .iter()
+                .filter(|key| key.starts_with(prefix))
+                .map(|key| key.to_string())
+                .collect();
+            futures::future::ready(Ok(keys)).boxed()
+        }
+    }
+
+    #[test]
+    fn test_list_prefix_returns_sorted_uris() {
+        let store = ListingStore(vec![
+            "captures/b/2.pcap",
+            "captures/a.pcap.gz",
+            "other/c.pcap",
+            "captures/b/1.pcap.zst",
+        ]);
+        assert_eq!(
+            list_prefix_with_store("s3://bucket/captures/", &store).unwrap(),
+            vec![
+                "s3://bucket/captures/a.pcap.gz",
+                "s3://bucket/captures/b/1.pcap.zst",
+                "s3://bucket/captures/b/2.pcap",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_requester_pays_sets_request_payer() {
+        let client = S3Client::new(Region::UsEast1);
+        let store = S3Store::new(client.clone());
+        assert_eq!(
+            store.head_object_request("bucket", "key").request_payer,
+            None
+        );
+        assert_eq!(
+            store
+                .get_object_request("bucket", "key", 0, 9)
+                .request_payer,
+            None
+        );
+
+        let store = S3Store::new(client).requester_pays(true);
+        let requester = Some("requester".to_string());
+        assert_eq!(
+            store.head_object_request("bucket", "key").request_payer,
+            requester
+        );
+        assert_eq!(
+            store
+                .get_object_request("bucket", "key", 0, 9)
+                .request_payer,
+            requester
+        );
+        assert_eq!(
+            store
+                .list_objects_request("bucket", "prefix/")
+                .request_payer,
+            requester
+        );
+    }
+
+    #[test]
+    fn test_seek_to_reads_from_offset() {
+        let object: Bytes = (0..1000u32)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>()
+            .into();
+        let store = Arc::new(InMemoryStore(object.clone()));
+
+        let mut chunks =
+            ObjectChunks::with_store("s3://bucket/key.pcap", 100, store.clone()).unwrap();
+        chunks.seek_to(250);
+        assert_eq!(read_all(chunks), object[250..]);
+
+        let mut chunks =
+            ObjectChunks::with_store("s3://bucket/key.pcap", 100, store.clone()).unwrap();
+        assert_eq!(read_all(chunks.as_mut()), object[..]);
+        chunks.seek_to(990); // the size is known by now, and rewinding allows a retry from that offset
+        assert_eq!(read_all(chunks), object[990..]);
+
+        let mut chunks = ObjectChunks::with_store("s3://bucket/key.pcap", 100, store).unwrap();
+        chunks.seek_to(5000);
+        assert!(read_all(chunks).is_empty());
+    }
+
+    /// [ObjectStore] which rejects every request with a `403 AccessDenied` response
+    struct DenyingStore;
+
+    impl DenyingStore {
+        fn deny<T: Send + 'static>() -> BoxFuture<'static, std::io::Result<T>> {
+            let body = "<Error><Code>AccessDenied</Code><Message>Access Denied</Message></Error>";
+            futures::future::ready(Err(http_error(403, body))).boxed()
+        }
+    }
+
+    impl ObjectStore for DenyingStore {
+        fn content_length(
+            &self,
+            _bucket: &str,
+            _key: &str,
+        ) -> BoxFuture<'static, std::io::Result<usize>> {
+            DenyingStore::deny()
+        }
+
+        fn get_range(
+            &self,
+            _bucket: &str,
+            _key: &str,
+            _start: usize,
+            _end: usize,
+        ) -> BoxFuture<'static, std::io::Result<Bytes>> {
+            DenyingStore::deny()
+        }
+    }
+
+    #[test]
+    fn test_access_denied_is_classified_and_names_the_object() {
+        let chunks =
+            ObjectChunks::with_store("s3://bucket/key.pcap", 100, Arc::new(DenyingStore)).unwrap();
+        let results: Vec<_> = futures::executor::block_on(chunks.then(|chunk| chunk).collect());
+        assert_eq!(results.len(), 1); // the stream ends at the failure
+        let error = results.into_iter().next().unwrap().unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::PermissionDenied);
+        assert_eq!(FailureKind::of(&error), FailureKind::AccessDenied);
+        let message = error.to_string();
+        assert!(
+            message.starts_with("Failed to read s3://bucket/key.pcap: HTTP 403"),
+            "{}",
+            message
+        );
+        assert!(message.contains("allow s3:GetObject"), "{}", message);
+    }
+
+    #[test]
+    fn test_classifies_failed_responses() {
+        assert_eq!(
+            FailureKind::from_response(400, "<Error><Code>ExpiredToken</Code></Error>"),
+            FailureKind::ExpiredCredentials
+        );
+        assert_eq!(
+            FailureKind::from_response(403, ""),
+            FailureKind::AccessDenied
+        );
+        assert_eq!(
+            FailureKind::from_response(500, "<Error><Code>InternalError</Code></Error>"),
+            FailureKind::Other
+        );
+        assert_eq!(
+            FailureKind::of(&std::io::Error::other("unclassified")),
+            FailureKind::Other
+        );
+    }
+
+    /// [ObjectStore] holding a single object in the `GLACIER` storage class, with the given `x-amz-restore` header,
+    /// which records the restores requested of it
+    struct ArchivedStore {
+        object: Bytes,
+        restore: Option<&'static str>,
+        restores: std::sync::Mutex<Vec<(String, String, u32)>>,
+    }
+
+    impl ArchivedStore {
+        fn new(restore: Option<&'static str>) -> ArchivedStore {
+            ArchivedStore {
+                object: Bytes::from_static(b"archived packets"),
+                restore,
+                restores: Default::default(),
             }
         }
 
-        // request the next chunk
-        let chunk_request = GetObjectRequest {
-            bucket: bucket.clone(),
-            key: key.clone(),
-            range: Some(format!(
-                "bytes={}-{}",
-                next_chunk_start,
-                *next_chunk_start + (*chunk_size - 1)
-            )),
-            ..Default::default()
-        };
+        fn object_head(&self) -> ObjectHead {
+            ObjectHead {
+                content_length: self.object.len(),
+                storage_class: Some("GLACIER".into()),
+                restore: self.restore.map(String::from),
+            }
+        }
+    }
 
-        let chunk_size = *chunk_size;
-        let client = client.clone();
-        let object = async move { client.get_object(chunk_request).compat().await };
-        let next_chunk = object
-            .map(|chunk_content| chunk_content.unwrap().body.take().expect("No body"))
-            .then(move |mut chunk_content_byte_stream| async move {
-                let mut body = BytesMut::with_capacity(chunk_size);
-                while let Some(data) = chunk_content_byte_stream.next().await {
-                    body.extend_from_slice(&data.unwrap());
-                }
-                std::io::Result::Ok(body.freeze())
-            })
-            .boxed();
+    impl ObjectStore for Arc<ArchivedStore> {
+        fn head(
+            &self,
+            _bucket: &str,
+            _key: &str,
+        ) -> BoxFuture<'static, std::io::Result<ObjectHead>> {
+            futures::future::ready(Ok(self.object_head())).boxed()
+        }
+
+        fn restore(
+            &self,
+            bucket: &str,
+            key: &str,
+            days: u32,
+        ) -> BoxFuture<'static, std::io::Result<()>> {
+            let request = (bucket.to_string(), key.to_string(), days);
+            self.restores.lock().unwrap().push(request);
+            futures::future::ready(Ok(())).boxed()
+        }
+
+        fn get_range(
+            &self,
+            _bucket: &str,
+            _key: &str,
+            start: usize,
+            end: usize,
+        ) -> BoxFuture<'static, std::io::Result<Bytes>> {
+            if self.object_head().needs_restore() {
+                // as S3 responds to a GetObject of an archived object
+                let body = "<Error><Code>InvalidObjectState</Code></Error>";
+                return futures::future::ready(Err(http_error(403, body))).boxed();
+            }
+            InMemoryStore(self.object.clone()).get_range("", "", start, end)
+        }
+    }
 
-        *next_chunk_start = *next_chunk_start + chunk_size;
-        Poll::Ready(Some(Box::pin(next_chunk)))
+    /// The error with which reading `s3://bucket/archived.pcap` from `store` fails
+    fn read_error(store: &Arc<ArchivedStore>, restore_days: Option<u32>) -> std::io::Error {
+        let mut chunks =
+            ObjectChunks::with_store("s3://bucket/archived.pcap", 100, Arc::new(store.clone()))
+                .unwrap();
+        if let Some(days) = restore_days {
+            chunks.restore_archived(days);
+        }
+        let results: Vec<_> = futures::executor::block_on(chunks.then(|chunk| chunk).collect());
+        assert_eq!(results.len(), 1); // the stream ends at the failure
+        results.into_iter().next().unwrap().unwrap_err()
     }
-}
 
-/* TODO: add S3 file download tests which confirm downloads happen in parallel when wrapped with TakeThenBuffered? */
+    #[test]
+    fn test_archived_objects_fail_clearly_or_are_restored() {
+        let store = Arc::new(ArchivedStore::new(None));
+        let error = read_error(&store, None);
+        assert_eq!(FailureKind::of(&error), FailureKind::Archived);
+        let message = error.to_string();
+        assert!(
+            message.starts_with(
+                "Failed to read s3://bucket/archived.pcap: The object is archived in the GLACIER storage class"
+            ),
+            "{}",
+            message
+        );
+        assert!(message.contains("pass --restore <DAYS>"), "{}", message);
+        assert!(store.restores.lock().unwrap().is_empty());
+
+        let error = read_error(&store, Some(7));
+        assert_eq!(FailureKind::of(&error), FailureKind::Restoring);
+        assert!(
+            error
+                .to_string()
+                .contains("Requested that it be restored for 7 days"),
+            "{}",
+            error
+        );
+        assert_eq!(
+            *store.restores.lock().unwrap(),
+            vec![("bucket".to_string(), "archived.pcap".to_string(), 7)]
+        );
+
+        // a restore in progress isn't requested again
+        let store = Arc::new(ArchivedStore::new(Some("ongoing-request=\"true\"")));
+        let error = read_error(&store, Some(7));
+        assert_eq!(FailureKind::of(&error), FailureKind::Restoring);
+        assert!(error.to_string().contains("is being restored"), "{}", error);
+        assert!(store.restores.lock().unwrap().is_empty());
+
+        // once restored, the object is read as usual
+        let store = Arc::new(ArchivedStore::new(Some(
+            "ongoing-request=\"false\", expiry-date=\"Fri, 23 Dec 2022 00:00:00 GMT\"",
+        )));
+        let mut chunks =
+            ObjectChunks::with_store("s3://bucket/archived.pcap", 5, Arc::new(store.clone()))
+                .unwrap();
+        chunks.restore_archived(7);
+        assert_eq!(read_all(chunks), store.object[..]);
+        assert!(store.restores.lock().unwrap().is_empty());
+
+        let request = S3Store::new(S3Client::new(Region::UsEast1))
+            .requester_pays(true)
+            .restore_object_request("bucket", "archived.pcap", 7);
+        assert_eq!(request.restore_request.unwrap().days, Some(7));
+        assert_eq!(request.request_payer, Some("requester".to_string()));
+    }
+
+    /// [ObjectStore] holding a single object in memory, whose HEAD only completes once its gate is opened, which
+    /// records the requests made of it
+    struct GatedStore {
+        object: Bytes,
+        gate: (async_channel::Sender<()>, async_channel::Receiver<()>),
+        requests: std::sync::Mutex<Vec<String>>,
+    }
+
+    impl GatedStore {
+        fn new(object: Bytes) -> GatedStore {
+            GatedStore {
+                object,
+                gate: async_channel::bounded(1),
+                requests: Default::default(),
+            }
+        }
+
+        fn open_gate(&self) {
+            self.gate.0.close();
+        }
+
+        fn requests(&self) -> Vec<String> {
+            self.requests.lock().unwrap().clone()
+        }
+    }
+
+    impl ObjectStore for Arc<GatedStore> {
+        fn content_length(
+            &self,
+            bucket: &str,
+            key: &str,
+        ) -> BoxFuture<'static, std::io::Result<usize>> {
+            self.head(bucket, key)
+                .map_ok(|head| head.content_length)
+                .boxed()
+        }
+
+        fn head(
+            &self,
+            _bucket: &str,
+            _key: &str,
+        ) -> BoxFuture<'static, std::io::Result<ObjectHead>> {
+            self.requests.lock().unwrap().push("HEAD".to_string());
+            let (gate, content_length) = (self.gate.1.clone(), self.object.len());
+            async move {
+                let _ = gate.recv().await; // fails once the gate is closed, i.e. opened
+                Ok(ObjectHead {
+                    content_length,
+                    ..Default::default()
+                })
+            }
+            .boxed()
+        }
+
+        fn get_range(
+            &self,
+            bucket: &str,
+            key: &str,
+            start: usize,
+            end: usize,
+        ) -> BoxFuture<'static, std::io::Result<Bytes>> {
+            self.get_range_and_length(bucket, key, start, end)
+                .map_ok(|(chunk, _)| chunk)
+                .boxed()
+        }
+
+        fn get_range_and_length(
+            &self,
+            _bucket: &str,
+            _key: &str,
+            start: usize,
+            end: usize,
+        ) -> BoxFuture<'static, std::io::Result<(Bytes, usize)>> {
+            let request = format!("GET {}-{}", start, end);
+            self.requests.lock().unwrap().push(request);
+            let content_length = self.object.len();
+            InMemoryStore(self.object.clone())
+                .get_range("", "", start, end)
+                .map_ok(move |chunk| (chunk, content_length))
+                .boxed()
+        }
+    }
+
+    #[test]
+    fn test_first_chunk_is_requested_without_waiting_on_the_head() {
+        let object: Bytes = (0..250u32).map(|i| i as u8).collect::<Vec<_>>().into();
+        let gets = vec!["GET 0-99", "GET 100-199", "GET 200-299"];
+
+        // the object is read in full without its HEAD ever completing
+        let store = Arc::new(GatedStore::new(object.clone()));
+        let chunks =
+            ObjectChunks::with_store("s3://bucket/key.pcap", 100, Arc::new(store.clone())).unwrap();
+        assert_eq!(read_all(chunks), object[..]);
+        assert_eq!(store.requests(), gets);
+
+        // unless the HEAD is to come first, when no chunk is requested until it has completed
+        let store = Arc::new(GatedStore::new(object.clone()));
+        let mut chunks =
+            ObjectChunks::with_store("s3://bucket/key.pcap", 100, Arc::new(store.clone())).unwrap();
+        chunks.head_first(true);
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        assert!(chunks.as_mut().poll_next(&mut cx).is_pending());
+        assert!(chunks.as_mut().poll_next(&mut cx).is_pending());
+        assert_eq!(store.requests(), vec!["HEAD"]);
+        store.open_gate();
+        assert_eq!(read_all(chunks), object[..]);
+        assert_eq!(store.requests(), [&["HEAD"][..], &gets].concat());
+    }
+
+    /// [ObjectStore] holding a single object in memory which is still being written: each HEAD reports the next of
+    /// `sizes` (then the last, once they run out), while GETs see every byte which will ever be written
+    struct GrowingStore {
+        object: Bytes,
+        sizes: std::sync::Mutex<std::collections::VecDeque<usize>>,
+        requests: std::sync::Mutex<Vec<String>>,
+    }
+
+    impl ObjectStore for Arc<GrowingStore> {
+        fn content_length(
+            &self,
+            _bucket: &str,
+            _key: &str,
+        ) -> BoxFuture<'static, std::io::Result<usize>> {
+            self.requests.lock().unwrap().push("HEAD".to_string());
+            let mut sizes = self.sizes.lock().unwrap();
+            let size = match sizes.len() {
+                1 => sizes[0],
+                _ => sizes.pop_front().unwrap(),
+            };
+            futures::future::ready(Ok(size)).boxed()
+        }
+
+        fn get_range(
+            &self,
+            bucket: &str,
+            key: &str,
+            start: usize,
+            end: usize,
+        ) -> BoxFuture<'static, std::io::Result<Bytes>> {
+            self.requests
+                .lock()
+                .unwrap()
+                .push(format!("GET {}-{}", start, end));
+            InMemoryStore(self.object.clone()).get_range(bucket, key, start, end)
+        }
+    }
+
+    #[test]
+    fn test_followed_object_is_streamed_as_it_grows() {
+        let object: Bytes = (0..250u32).map(|i| i as u8).collect::<Vec<_>>().into();
+        let store = Arc::new(GrowingStore {
+            object: object.clone(),
+            sizes: std::sync::Mutex::new(vec![120, 120, 250].into()),
+            requests: Default::default(),
+        });
+        let mut chunks =
+            ObjectChunks::with_store("s3://bucket/key.pcap", 100, Arc::new(store.clone())).unwrap();
+        chunks.head_first(true);
+        chunks.follow(Some(std::time::Duration::from_millis(1)));
+
+        // the stream doesn't end while following, so read until every byte which was written has been
+        let mut chunks = chunks.then(|chunk| chunk);
+        let streamed = futures::executor::block_on(async {
+            let mut streamed = Vec::new();
+            while streamed.len() < object.len() {
+                streamed.extend_from_slice(&chunks.next().await.unwrap().unwrap());
+            }
+            streamed
+        });
+        assert_eq!(streamed, object[..]);
+        // no chunk reaches past the size known when it was requested, so none of the later bytes are read twice
+        assert_eq!(
+            store.requests.lock().unwrap()[..],
+            [
+                "HEAD",
+                "GET 0-99",
+                "GET 100-119",
+                "HEAD",
+                "HEAD",
+                "GET 120-219",
+                "GET 220-249"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_content_range_gives_the_objects_size() {
+        assert_eq!(content_range_length("bytes 0-99/1000"), Some(1000));
+        assert_eq!(content_range_length("bytes 0-0/1"), Some(1));
+        assert_eq!(content_range_length("bytes */1000"), Some(1000));
+        assert_eq!(content_range_length("bytes 0-99/*"), None);
+    }
+
+    #[test]
+    fn test_aws_profile_selects_its_region_and_credentials() {
+        let dir = tempfile::tempdir().unwrap();
+        let credentials = dir.path().join("credentials");
+        std::fs::write(
+            &credentials,
+            "[default]\naws_access_key_id = DEFAULTKEY\naws_secret_access_key = defaultsecret\n\n\
+             [analysis]\naws_access_key_id = ANALYSISKEY\naws_secret_access_key = analysissecret\n",
+        )
+        .unwrap();
+        let config = dir.path().join("config");
+        std::fs::write(
+            &config,
+            "[default]\nregion = us-east-1\n\n[profile analysis]\nregion = us-west-2\n\n[profile bare]\n",
+        )
+        .unwrap();
+
+        let profile =
+            AwsProfile::with_credentials_file(&credentials, "analysis").config_file(&config);
+        assert_eq!(profile.name(), "analysis");
+        assert_eq!(profile.region().unwrap(), Some(Region::UsWest2));
+        let selected = futures::executor::block_on(profile.credentials()).unwrap();
+        assert_eq!(selected.aws_access_key_id(), "ANALYSISKEY");
+        assert_eq!(selected.aws_secret_access_key(), "analysissecret");
+
+        let default =
+            AwsProfile::with_credentials_file(&credentials, "default").config_file(&config);
+        assert_eq!(default.region().unwrap(), Some(Region::UsEast1));
+        let selected = futures::executor::block_on(default.credentials()).unwrap();
+        assert_eq!(selected.aws_access_key_id(), "DEFAULTKEY");
+
+        let bare = AwsProfile::with_credentials_file(&credentials, "bare").config_file(&config);
+        assert_eq!(bare.region().unwrap(), None);
+        let error = futures::executor::block_on(bare.credentials()).unwrap_err();
+        assert!(
+            format!("{:#}", error).contains("AWS profile 'bare'"),
+            "{:#}",
+            error
+        );
+    }
+}
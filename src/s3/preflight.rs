@@ -0,0 +1,250 @@
+use super::{default_store, split_uri, DownloadConfig, ObjectStore};
+use crate::compression::Compression;
+use crate::pcap::has_capture_magic;
+use anyhow::{bail, Context as _, Result};
+use futures::stream::StreamExt;
+
+/// Bytes fetched from the start of each object to check its magic number
+const MAGIC_LEN: usize = 8;
+
+/// Objects checked at once by [preflight]
+const MAX_CONCURRENT: usize = 64;
+
+/// Cheap checks of `s3://` inputs ahead of a merge, so that every missing, empty or unrestored object is reported at
+/// once, rather than failing the merge when it's reached, perhaps hours in. Each object is looked up with a HeadObject
+/// request and, if `check_magic`, its first bytes are fetched to check that they begin as its name says: with the
+/// magic number of its compression format or, for an uncompressed `.pcap` or `.pcapng` object, that of a pcap or
+/// pcapng file.
+#[derive(Clone, Copy, Debug)]
+pub struct Preflight {
+    pub check_magic: bool,
+    /// whether archived objects are to be restored (see [DownloadConfig::restore_days]), so aren't problems
+    pub restoring: bool,
+    /// most objects checked at once
+    pub max_concurrent: usize,
+}
+
+/// An object which failed its [Preflight] checks, and why
+#[derive(Debug)]
+pub struct PreflightProblem {
+    pub uri: String,
+    pub error: anyhow::Error,
+}
+
+impl std::fmt::Display for PreflightProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "'{}': {:#}", self.uri, self.error)
+    }
+}
+
+impl Preflight {
+    /// Check each of the objects at `uris` in `store`, returning the problems found in the order of `uris`
+    pub async fn check(&self, uris: &[String], store: &dyn ObjectStore) -> Vec<PreflightProblem> {
+        futures::stream::iter(uris)
+            .map(|uri| async move {
+                self.check_object(uri, store)
+                    .await
+                    .err()
+                    .map(|error| PreflightProblem {
+                        uri: uri.clone(),
+                        error,
+                    })
+            })
+            .buffered(self.max_concurrent.max(1))
+            .filter_map(futures::future::ready)
+            .collect()
+            .await
+    }
+
+    async fn check_object(&self, uri: &str, store: &dyn ObjectStore) -> Result<()> {
+        let (bucket, key) = split_uri(uri)?;
+        let head = store
+            .head(bucket, key)
+            .await
+            .context("Failed to look up the object")?;
+        if head.content_length == 0 {
+            bail!("The object is empty");
+        }
+        if head.needs_restore() {
+            if self.restoring {
+                return Ok(()); // can't be read until it has been restored
+            }
+            bail!(
+                "The object is archived in {} and hasn't been restored. See --restore",
+                head.storage_class.as_deref().unwrap_or_default()
+            );
+        }
+        if !self.check_magic {
+            return Ok(());
+        }
+        let start = store
+            .get_range(bucket, key, 0, MAGIC_LEN - 1)
+            .await
+            .context("Failed to read the start of the object")?;
+        match Compression::from_path(uri).magic() {
+            Some(magic) if !start.starts_with(magic) => {
+                bail!("The object doesn't begin with the magic number of its compression format")
+            }
+            None if (uri.ends_with(".pcap") || uri.ends_with(".pcapng"))
+                && !has_capture_magic(&start) =>
+            {
+                bail!("The object doesn't begin with the magic number of a pcap or pcapng file")
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Run a [Preflight] of the objects at `uris`, requested from the [default_store] of `config`, returning every
+/// problem found
+pub fn preflight(
+    uris: &[String],
+    config: &DownloadConfig,
+    check_magic: bool,
+) -> Vec<PreflightProblem> {
+    let store = default_store(config.requester_pays, config.aws_profile.as_ref());
+    let preflight = Preflight {
+        check_magic,
+        restoring: config.restore_days.is_some(),
+        max_concurrent: MAX_CONCURRENT,
+    };
+    smol::block_on(preflight.check(uris, &*store))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{http_error, ObjectHead};
+    use super::*;
+    use bytes::Bytes;
+    use futures::future::BoxFuture;
+    use futures::FutureExt;
+    use std::collections::HashMap;
+
+    /// [ObjectStore] of objects in memory by key, counting the requests for their bytes
+    #[derive(Default)]
+    struct ObjectsStore {
+        objects: HashMap<&'static str, ObjectHead>,
+        bytes: HashMap<&'static str, &'static [u8]>,
+        n_gets: std::sync::atomic::AtomicUsize,
+    }
+
+    impl ObjectsStore {
+        fn with(mut self, key: &'static str, bytes: &'static [u8]) -> ObjectsStore {
+            let head = ObjectHead {
+                content_length: bytes.len(),
+                ..Default::default()
+            };
+            self.objects.insert(key, head);
+            self.bytes.insert(key, bytes);
+            self
+        }
+    }
+
+    impl ObjectStore for ObjectsStore {
+        fn head(
+            &self,
+            _bucket: &str,
+            key: &str,
+        ) -> BoxFuture<'static, std::io::Result<ObjectHead>> {
+            let head = self
+                .objects
+                .get(key)
+                .cloned()
+                .ok_or_else(|| http_error(404, "<Error><Code>NoSuchKey</Code></Error>"));
+            futures::future::ready(head).boxed()
+        }
+
+        fn get_range(
+            &self,
+            _bucket: &str,
+            key: &str,
+            start: usize,
+            end: usize,
+        ) -> BoxFuture<'static, std::io::Result<Bytes>> {
+            self.n_gets
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            let bytes = self.bytes[key];
+            let range = start.min(bytes.len())..(end + 1).min(bytes.len());
+            futures::future::ready(Ok(Bytes::copy_from_slice(&bytes[range]))).boxed()
+        }
+    }
+
+    fn uris(keys: &[&str]) -> Vec<String> {
+        keys.iter()
+            .map(|key| format!("s3://bucket/{}", key))
+            .collect()
+    }
+
+    #[test]
+    fn every_problem_is_reported_together() {
+        let mut store = ObjectsStore::default()
+            .with("good.pcap", crate::pcap::PCAP_HDR_NSEC)
+            .with("good.pcap.zst", &[0x28, 0xB5, 0x2F, 0xFD, 0, 0])
+            .with("good.raw", &[1, 2, 3])
+            .with("empty.pcap", &[])
+            .with("misnamed.pcap.gz", &[0x28, 0xB5, 0x2F, 0xFD])
+            .with("text.pcap", b"not a capture");
+        store.objects.insert(
+            "archived.pcap",
+            ObjectHead {
+                content_length: 100,
+                storage_class: Some("GLACIER".into()),
+                restore: None,
+            },
+        );
+        let keys = [
+            "good.pcap",
+            "missing.pcap",
+            "good.pcap.zst",
+            "empty.pcap",
+            "archived.pcap",
+            "misnamed.pcap.gz",
+            "good.raw",
+            "text.pcap",
+        ];
+        let preflight = Preflight {
+            check_magic: true,
+            restoring: false,
+            max_concurrent: 3,
+        };
+        let problems = smol::block_on(preflight.check(&uris(&keys), &store));
+        let failed: Vec<&str> = problems
+            .iter()
+            .map(|problem| problem.uri.as_str())
+            .collect();
+        assert_eq!(
+            failed,
+            uris(&[
+                "missing.pcap",
+                "empty.pcap",
+                "archived.pcap",
+                "misnamed.pcap.gz",
+                "text.pcap"
+            ])
+        );
+        assert!(
+            problems[0].to_string().contains("NoSuchKey"),
+            "{}",
+            problems[0]
+        );
+        assert!(
+            problems[2].to_string().contains("GLACIER"),
+            "{}",
+            problems[2]
+        );
+
+        // without the magic checks, only the objects' heads are requested
+        let preflight = Preflight {
+            check_magic: false,
+            restoring: true,
+            max_concurrent: 3,
+        };
+        let n_gets = store.n_gets.load(std::sync::atomic::Ordering::Relaxed);
+        let problems = smol::block_on(preflight.check(&uris(&keys), &store));
+        assert_eq!(problems.len(), 2);
+        assert_eq!(
+            store.n_gets.load(std::sync::atomic::Ordering::Relaxed),
+            n_gets
+        );
+    }
+}
@@ -0,0 +1,262 @@
+//! Spill decoded packets waiting to be merged to disk
+//!
+//! Each input is normally decoded no more than a batch of packets ahead of the merge, so that the packets of inputs the
+//! merge hasn't reached yet don't pile up in memory. With a [Spill], each input is instead decoded as fast as it can be
+//! read, its batches of packets waiting in memory while no more than `in_memory_limit_n_bytes` of every input's packets
+//! are, and otherwise appended to a spill file of the input's own in the spill directory, to be read back once the
+//! merge reaches them. Memory then holds a bounded number of decoded packets however far ahead of the merge inputs run.
+
+use bytes::Bytes;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Bytes of each spilled packet's framing: its u64 timestamp and u32 record length
+const SPILLED_PACKET_HEADER_LEN: usize = 8 + 4;
+
+/// Shared spill policy and accounting for every input decoded with the same [Spill]. Cheap to clone.
+#[derive(Clone, Debug)]
+pub struct Spill(Arc<SpillInner>);
+
+#[derive(Debug)]
+struct SpillInner {
+    dir: PathBuf,
+    in_memory_limit_n_bytes: usize,
+    n_bytes_in_memory: AtomicUsize,
+    max_n_bytes_in_memory: AtomicUsize,
+    n_batches_spilled: AtomicUsize,
+}
+
+impl Spill {
+    pub fn new(dir: PathBuf, in_memory_limit_n_bytes: usize) -> Spill {
+        Spill(Arc::new(SpillInner {
+            dir,
+            in_memory_limit_n_bytes,
+            n_bytes_in_memory: AtomicUsize::new(0),
+            max_n_bytes_in_memory: AtomicUsize::new(0),
+            n_batches_spilled: AtomicUsize::new(0),
+        }))
+    }
+
+    /// Number of batches of packets which have been written to spill files so far
+    pub fn n_batches_spilled(&self) -> usize {
+        self.0.n_batches_spilled.load(Ordering::Relaxed)
+    }
+
+    /// Most bytes of packets ever waiting in memory at once, which never exceeds the in-memory limit
+    pub fn max_n_bytes_in_memory(&self) -> usize {
+        self.0.max_n_bytes_in_memory.load(Ordering::Relaxed)
+    }
+
+    /// The writing and reading ends of a new input's spill file, which is only created once a batch is spilled to it
+    pub fn input(&self) -> (SpillWriter, SpillReader) {
+        let writer = SpillWriter {
+            spill: self.clone(),
+            file: None,
+        };
+        (writer, SpillReader { file: None })
+    }
+
+    /// Account for `n_bytes` more waiting in memory, unless that would exceed the limit
+    fn reserve(&self, n_bytes: usize) -> Option<InMemoryGuard> {
+        let reserved = self.0.n_bytes_in_memory.fetch_update(
+            Ordering::AcqRel,
+            Ordering::Acquire,
+            |n_bytes_in_memory| {
+                Some(n_bytes_in_memory + n_bytes)
+                    .filter(|total| *total <= self.0.in_memory_limit_n_bytes)
+            },
+        );
+        let n_bytes_in_memory = reserved.ok()? + n_bytes;
+        self.0
+            .max_n_bytes_in_memory
+            .fetch_max(n_bytes_in_memory, Ordering::Relaxed);
+        Some(InMemoryGuard {
+            spill: self.clone(),
+            n_bytes,
+        })
+    }
+}
+
+/// Holds the batches of packets decoded from an input until the merge reads them, through [Spill::input]
+pub struct SpillWriter {
+    spill: Spill,
+    // unlinked once created, so its blocks are freed once both ends are dropped
+    file: Option<BufWriter<File>>,
+}
+
+impl SpillWriter {
+    /// Hold `packets` until they're read: in memory while under the in-memory limit, otherwise appended to the input's
+    /// spill file. Performs blocking file IO when spilling.
+    pub fn stash(&mut self, packets: Vec<(u64, Bytes)>) -> std::io::Result<Batch> {
+        let n_bytes = packets.iter().map(|(_, record)| record.len()).sum();
+        if let Some(guard) = self.spill.reserve(n_bytes) {
+            return Ok(Batch::InMemory(packets, Some(guard)));
+        }
+        let reader = match &self.file {
+            Some(_) => None,
+            None => {
+                // reopened for its own offset to read from, then unlinked
+                let file = tempfile::NamedTempFile::new_in(&self.spill.0.dir)?;
+                let reader = file.reopen()?;
+                self.file = Some(BufWriter::new(file.into_file()));
+                Some(reader)
+            }
+        };
+        let file = self.file.as_mut().unwrap();
+        for (ts, record) in &packets {
+            file.write_all(&ts.to_le_bytes())?;
+            file.write_all(&(record.len() as u32).to_le_bytes())?;
+            file.write_all(record)?;
+        }
+        file.flush()?;
+        self.spill
+            .0
+            .n_batches_spilled
+            .fetch_add(1, Ordering::Relaxed);
+        Ok(Batch::Spilled {
+            n_packets: packets.len(),
+            reader,
+        })
+    }
+}
+
+/// A batch of packets held by [SpillWriter::stash], to be read with the input's [SpillReader]
+pub enum Batch {
+    InMemory(Vec<(u64, Bytes)>, Option<InMemoryGuard>),
+    Spilled {
+        n_packets: usize,
+        /// the reading end of the spill file, sent with the first batch spilled to it
+        reader: Option<File>,
+    },
+}
+
+impl From<Vec<(u64, Bytes)>> for Batch {
+    /// Hold `packets` in memory without spill accounting
+    fn from(packets: Vec<(u64, Bytes)>) -> Batch {
+        Batch::InMemory(packets, None)
+    }
+}
+
+/// Reads back the batches of an input held by its [SpillWriter], in the order they were stashed. By default, one which
+/// only reads batches held in memory.
+#[derive(Default)]
+pub struct SpillReader {
+    file: Option<BufReader<File>>,
+}
+
+impl SpillReader {
+    /// The packets of `batch`, which keep their bytes accounted as waiting in memory until all have been taken
+    pub fn read(&mut self, batch: Batch) -> std::io::Result<BatchPackets> {
+        let (n_packets, reader) = match batch {
+            Batch::InMemory(packets, guard) => {
+                return Ok(BatchPackets {
+                    packets: packets.into_iter(),
+                    _guard: guard,
+                })
+            }
+            Batch::Spilled { n_packets, reader } => (n_packets, reader),
+        };
+        if let Some(reader) = reader {
+            self.file = Some(BufReader::new(reader));
+        }
+        let file = self.file.as_mut().ok_or_else(|| {
+            std::io::Error::other("read a spilled batch before the spill file was created")
+        })?;
+        let mut packets = Vec::with_capacity(n_packets);
+        for _ in 0..n_packets {
+            let mut header = [0; SPILLED_PACKET_HEADER_LEN];
+            file.read_exact(&mut header)?;
+            let mut ts = [0; 8];
+            ts.copy_from_slice(&header[..8]);
+            let mut len = [0; 4];
+            len.copy_from_slice(&header[8..]);
+            let mut record = vec![0; u32::from_le_bytes(len) as usize];
+            file.read_exact(&mut record)?;
+            packets.push((u64::from_le_bytes(ts), Bytes::from(record)));
+        }
+        Ok(BatchPackets {
+            packets: packets.into_iter(),
+            _guard: None,
+        })
+    }
+}
+
+/// The packets of a [Batch] read by [SpillReader::read]
+pub struct BatchPackets {
+    packets: std::vec::IntoIter<(u64, Bytes)>,
+    _guard: Option<InMemoryGuard>,
+}
+
+impl Iterator for BatchPackets {
+    type Item = (u64, Bytes);
+
+    fn next(&mut self) -> Option<(u64, Bytes)> {
+        self.packets.next()
+    }
+}
+
+/// Releases a batch's bytes from its [Spill]'s accounting once its packets are taken
+pub struct InMemoryGuard {
+    spill: Spill,
+    n_bytes: usize,
+}
+
+impl Drop for InMemoryGuard {
+    fn drop(&mut self) {
+        self.spill
+            .0
+            .n_bytes_in_memory
+            .fetch_sub(self.n_bytes, Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packets(timestamps: &[u64]) -> Vec<(u64, Bytes)> {
+        timestamps
+            .iter()
+            .map(|ts| (*ts, Bytes::from(vec![*ts as u8; 5])))
+            .collect()
+    }
+
+    #[test]
+    fn batches_over_the_in_memory_limit_are_spilled_to_the_inputs_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let spill = Spill::new(dir.path().to_path_buf(), 10);
+        let (mut writer, mut reader) = spill.input();
+
+        let first = writer.stash(packets(&[1, 2])).unwrap();
+        assert!(matches!(first, Batch::InMemory(..)));
+        let second = writer.stash(packets(&[3])).unwrap();
+        assert!(matches!(
+            second,
+            Batch::Spilled {
+                reader: Some(_),
+                ..
+            }
+        ));
+        let third = writer.stash(packets(&[4, 5])).unwrap();
+        assert!(matches!(third, Batch::Spilled { reader: None, .. }));
+        assert_eq!(spill.n_batches_spilled(), 2);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0); // unlinked
+
+        let read = reader.read(first).unwrap();
+        let fourth = writer.stash(packets(&[6])).unwrap();
+        assert!(matches!(fourth, Batch::Spilled { .. })); // the first batch's packets are still waiting
+        assert_eq!(read.collect::<Vec<_>>(), packets(&[1, 2]));
+        let fifth = writer.stash(packets(&[7])).unwrap();
+        assert!(matches!(fifth, Batch::InMemory(..))); // taking them made room again
+
+        let mut read = Vec::new();
+        for batch in [second, third, fourth, fifth] {
+            read.extend(reader.read(batch).unwrap());
+        }
+        assert_eq!(read, packets(&[3, 4, 5, 6, 7]));
+        assert_eq!(spill.max_n_bytes_in_memory(), 10);
+    }
+}
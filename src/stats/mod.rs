@@ -0,0 +1,279 @@
+//! Statistics gathered about inputs over the course of a merge
+
+mod progress;
+#[cfg(unix)]
+mod socket;
+pub use progress::{InputProgress, InputSnapshot, Progress, ProgressSnapshot};
+#[cfg(unix)]
+pub use socket::StatsSocket;
+
+/// Inclusive range of the nanosecond timestamps seen in an input
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeRange {
+    pub first_ns: u64,
+    pub last_ns: u64,
+}
+
+impl TimeRange {
+    /// Extend `range` (`None` before the first packet) to include `ts`
+    pub fn observe(range: Option<TimeRange>, ts: u64) -> TimeRange {
+        match range {
+            Some(range) => TimeRange {
+                first_ns: range.first_ns.min(ts),
+                last_ns: range.last_ns.max(ts),
+            },
+            None => TimeRange {
+                first_ns: ts,
+                last_ns: ts,
+            },
+        }
+    }
+}
+
+/// Overlap between the time ranges of two inputs, identified by their indices
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Overlap {
+    pub earlier: usize,
+    pub later: usize,
+    pub overlap_ns: u64,
+}
+
+/// Order the inputs with packets by their first timestamp and report how much each overlaps the next. Inputs with no
+/// packets (`None`) are skipped. Ranges which merely touch (one ends at the nanosecond the next starts) overlap by 0ns
+/// and are not reported.
+pub fn adjacent_overlaps(ranges: &[Option<TimeRange>]) -> Vec<Overlap> {
+    let mut ordered: Vec<(usize, TimeRange)> = ranges
+        .iter()
+        .enumerate()
+        .filter_map(|(i, range)| range.map(|range| (i, range)))
+        .collect();
+    ordered.sort_by_key(|(i, range)| (range.first_ns, *i));
+    ordered
+        .windows(2)
+        .filter_map(|pair| {
+            let ((earlier, a), (later, b)) = (pair[0], pair[1]);
+            let overlap_ns = a.last_ns.min(b.last_ns).saturating_sub(b.first_ns);
+            if overlap_ns > 0 {
+                Some(Overlap {
+                    earlier,
+                    later,
+                    overlap_ns,
+                })
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// How the time ranges of two inputs adjacent in time fail to abut, by more than a tolerance
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Discontinuity {
+    /// nanoseconds from the earlier input's last timestamp to the later's first
+    Gap(u64),
+    /// nanoseconds for which the later input's range overlaps the earlier's, as by [adjacent_overlaps]
+    Overlap(u64),
+}
+
+/// A [Discontinuity] between two inputs adjacent in time, identified by their indices
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Adjacency {
+    pub earlier: usize,
+    pub later: usize,
+    pub discontinuity: Discontinuity,
+}
+
+/// Order the inputs with packets by their first timestamp, as [adjacent_overlaps] does, and report each which doesn't
+/// abut the next: where the later's first timestamp is more than `tolerance_ns` after the earlier's last (a gap), or
+/// the two ranges overlap by more than `tolerance_ns`. For rolling captures which should tile the timeline.
+pub fn discontinuities(ranges: &[Option<TimeRange>], tolerance_ns: u64) -> Vec<Adjacency> {
+    let mut ordered: Vec<(usize, TimeRange)> = ranges
+        .iter()
+        .enumerate()
+        .filter_map(|(i, range)| range.map(|range| (i, range)))
+        .collect();
+    ordered.sort_by_key(|(i, range)| (range.first_ns, *i));
+    ordered
+        .windows(2)
+        .filter_map(|pair| {
+            let ((earlier, a), (later, b)) = (pair[0], pair[1]);
+            let discontinuity = if b.first_ns > a.last_ns {
+                Discontinuity::Gap(b.first_ns - a.last_ns)
+            } else {
+                Discontinuity::Overlap(a.last_ns.min(b.last_ns) - b.first_ns)
+            };
+            match discontinuity {
+                Discontinuity::Gap(ns) | Discontinuity::Overlap(ns) if ns > tolerance_ns => {
+                    Some(Adjacency {
+                        earlier,
+                        later,
+                        discontinuity,
+                    })
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Streams a time series of how many packets (and captured bytes) fall into each `bucket_ns`-long bucket of time, as
+/// CSV rows of `bucket_start_ns,packet_count,byte_count`. Buckets start at multiples of `bucket_ns` since the epoch.
+/// Packets must be observed in time order, so that each bucket can be written as soon as a packet falls beyond it.
+/// Buckets between the first and last packets which hold no packets are written with counts of 0.
+pub struct RateSeries<W: std::io::Write> {
+    writer: W,
+    bucket_ns: u64,
+    bucket: Option<Bucket>,
+}
+
+#[derive(Clone, Copy)]
+struct Bucket {
+    start_ns: u64,
+    packet_count: u64,
+    byte_count: u64,
+}
+
+impl<W: std::io::Write> RateSeries<W> {
+    /// Write the CSV header to `writer`
+    pub fn new(mut writer: W, bucket_ns: u64) -> std::io::Result<RateSeries<W>> {
+        assert!(bucket_ns > 0, "Rate buckets must be at least 1ns long");
+        writeln!(writer, "bucket_start_ns,packet_count,byte_count")?;
+        Ok(RateSeries {
+            writer,
+            bucket_ns,
+            bucket: None,
+        })
+    }
+
+    /// Count a packet of `n_bytes` captured bytes at `ts`, writing out any buckets which end before it
+    pub fn observe(&mut self, ts: u64, n_bytes: usize) -> std::io::Result<()> {
+        let start_ns = ts - ts % self.bucket_ns;
+        let mut bucket = match self.bucket {
+            Some(bucket) if bucket.start_ns >= start_ns => bucket,
+            Some(mut bucket) => {
+                while bucket.start_ns < start_ns {
+                    self.write(bucket)?;
+                    bucket = Bucket {
+                        start_ns: bucket.start_ns + self.bucket_ns,
+                        packet_count: 0,
+                        byte_count: 0,
+                    };
+                }
+                bucket
+            }
+            None => Bucket {
+                start_ns,
+                packet_count: 0,
+                byte_count: 0,
+            },
+        };
+        bucket.packet_count += 1;
+        bucket.byte_count += n_bytes as u64;
+        self.bucket = Some(bucket);
+        Ok(())
+    }
+
+    /// Write the final bucket, returning the flushed writer
+    pub fn finish(mut self) -> std::io::Result<W> {
+        if let Some(bucket) = self.bucket.take() {
+            self.write(bucket)?;
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write(&mut self, bucket: Bucket) -> std::io::Result<()> {
+        writeln!(
+            self.writer,
+            "{},{},{}",
+            bucket.start_ns, bucket.packet_count, bucket.byte_count
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_series_writes_every_bucket_in_order() {
+        let mut series = RateSeries::new(Vec::new(), 10).unwrap();
+        for (ts, n_bytes) in [(12, 100), (15, 50), (19, 1), (41, 7), (49, 3)].iter() {
+            series.observe(*ts, *n_bytes).unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(series.finish().unwrap()).unwrap(),
+            "bucket_start_ns,packet_count,byte_count\n10,3,151\n20,0,0\n30,0,0\n40,2,10\n"
+        );
+    }
+
+    fn range(first_ns: u64, last_ns: u64) -> Option<TimeRange> {
+        Some(TimeRange { first_ns, last_ns })
+    }
+
+    #[test]
+    fn reports_overlap_between_files_adjacent_in_time() {
+        let ranges = [
+            range(200, 300),
+            None,
+            range(0, 100),
+            range(90, 250),
+            range(300, 400),
+        ];
+        assert_eq!(
+            adjacent_overlaps(&ranges),
+            vec![
+                Overlap {
+                    earlier: 2,
+                    later: 3,
+                    overlap_ns: 10
+                },
+                Overlap {
+                    earlier: 3,
+                    later: 0,
+                    overlap_ns: 50
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn contained_range_overlaps_by_its_own_length() {
+        assert_eq!(
+            adjacent_overlaps(&[range(0, 1000), range(100, 200)]),
+            vec![Overlap {
+                earlier: 0,
+                later: 1,
+                overlap_ns: 100
+            }]
+        );
+    }
+
+    #[test]
+    fn reports_gaps_and_overlaps_beyond_the_tolerance() {
+        let ranges = [
+            range(0, 100),
+            range(105, 200), // abuts within the tolerance
+            range(300, 400),
+            None,
+            range(380, 500),
+            range(495, 600),
+        ];
+        assert_eq!(
+            discontinuities(&ranges, 10),
+            vec![
+                Adjacency {
+                    earlier: 1,
+                    later: 2,
+                    discontinuity: Discontinuity::Gap(100)
+                },
+                Adjacency {
+                    earlier: 2,
+                    later: 4,
+                    discontinuity: Discontinuity::Overlap(20)
+                },
+            ]
+        );
+        assert_eq!(discontinuities(&ranges, 100), vec![]);
+    }
+}
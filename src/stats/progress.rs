@@ -0,0 +1,149 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Progress of a merge, updated by the merge as it reads each packet and [snapshotted](Progress::snapshot) by any
+/// other thread wanting to report it (e.g. a [super::StatsSocket]). Observing a packet is a few relaxed atomic writes.
+pub struct Progress {
+    started: Instant,
+    n_packets: AtomicU64,
+    merge_ts: AtomicU64,
+    inputs: Mutex<Vec<Arc<InputProgress>>>,
+}
+
+/// Progress of the merge through one of its inputs, added with [Progress::add_input]
+pub struct InputProgress {
+    path: String,
+    size_bytes: Option<u64>,
+    n_packets: AtomicU64,
+    n_bytes: AtomicU64,
+}
+
+/// The [Progress] of a merge at one moment, as reported in JSON
+#[derive(Clone, Debug, Serialize)]
+pub struct ProgressSnapshot {
+    pub packets_merged: u64,
+    /// timestamp of the packet merged last, once there is one
+    pub merge_ts_ns: Option<u64>,
+    pub elapsed_secs: f64,
+    /// estimated time until every input has been merged, only known if the size of every input is
+    pub eta_secs: Option<f64>,
+    pub inputs: Vec<InputSnapshot>,
+}
+
+/// The [InputProgress] of a merge at one moment
+#[derive(Clone, Debug, Serialize)]
+pub struct InputSnapshot {
+    pub path: String,
+    pub packets_merged: u64,
+    /// bytes of the records merged from the input
+    pub bytes_merged: u64,
+    /// bytes of records in the input, if known
+    pub size_bytes: Option<u64>,
+    /// `bytes_merged` as a fraction of `size_bytes`
+    pub fraction: Option<f64>,
+}
+
+impl Default for Progress {
+    fn default() -> Progress {
+        Progress {
+            started: Instant::now(),
+            n_packets: AtomicU64::new(0),
+            merge_ts: AtomicU64::new(0),
+            inputs: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl Progress {
+    /// Track the progress of the merge through another input, whose records are `size_bytes` long in total if known
+    pub fn add_input(&self, path: String, size_bytes: Option<u64>) -> Arc<InputProgress> {
+        let input = Arc::new(InputProgress {
+            path,
+            size_bytes,
+            n_packets: AtomicU64::new(0),
+            n_bytes: AtomicU64::new(0),
+        });
+        self.inputs.lock().unwrap().push(input.clone());
+        input
+    }
+
+    /// Record that the merge read a packet with timestamp `ts` and a record `record_len` bytes long from `input`
+    pub fn observe(&self, input: &InputProgress, ts: u64, record_len: usize) {
+        self.merge_ts.store(ts, Ordering::Relaxed);
+        self.n_packets.fetch_add(1, Ordering::Relaxed);
+        input.n_packets.fetch_add(1, Ordering::Relaxed);
+        input
+            .n_bytes
+            .fetch_add(record_len as u64, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> ProgressSnapshot {
+        let elapsed_secs = self.started.elapsed().as_secs_f64();
+        let inputs: Vec<InputSnapshot> = self
+            .inputs
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|input| {
+                let bytes_merged = input.n_bytes.load(Ordering::Relaxed);
+                InputSnapshot {
+                    path: input.path.clone(),
+                    packets_merged: input.n_packets.load(Ordering::Relaxed),
+                    bytes_merged,
+                    size_bytes: input.size_bytes,
+                    fraction: input
+                        .size_bytes
+                        .filter(|size| *size > 0)
+                        .map(|size| (bytes_merged as f64 / size as f64).min(1.0)),
+                }
+            })
+            .collect();
+        // extrapolate from the fraction of all the inputs' bytes merged so far
+        let sizes = inputs
+            .iter()
+            .map(|input| input.size_bytes)
+            .sum::<Option<u64>>();
+        let merged: u64 = inputs.iter().map(|input| input.bytes_merged).sum();
+        let eta_secs = sizes.filter(|_| merged > 0).map(|size| {
+            let fraction = (merged as f64 / size.max(1) as f64).min(1.0);
+            elapsed_secs * (1.0 - fraction) / fraction
+        });
+        let packets_merged = self.n_packets.load(Ordering::Relaxed);
+        ProgressSnapshot {
+            packets_merged,
+            merge_ts_ns: Some(self.merge_ts.load(Ordering::Relaxed)).filter(|_| packets_merged > 0),
+            elapsed_secs,
+            eta_secs,
+            inputs,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eta_is_extrapolated_from_the_bytes_merged() {
+        let progress = Progress::default();
+        let sized = progress.add_input("sized.pcap".into(), Some(400));
+        assert!(progress.snapshot().eta_secs.is_none());
+        progress.observe(&sized, 10, 100);
+        progress.observe(&sized, 20, 100);
+        let snapshot = progress.snapshot();
+        assert_eq!(snapshot.packets_merged, 2);
+        assert_eq!(snapshot.merge_ts_ns, Some(20));
+        assert_eq!(snapshot.inputs[0].fraction, Some(0.5));
+        let eta_secs = snapshot.eta_secs.unwrap();
+        assert!((eta_secs - snapshot.elapsed_secs).abs() < 1e-3 * snapshot.elapsed_secs.max(1.0));
+
+        // one input of unknown size leaves the whole merge's unknown
+        let compressed = progress.add_input("compressed.pcap.gz".into(), None);
+        progress.observe(&compressed, 30, 100);
+        let snapshot = progress.snapshot();
+        assert_eq!(snapshot.inputs[1].packets_merged, 1);
+        assert!(snapshot.inputs[1].fraction.is_none() && snapshot.eta_secs.is_none());
+    }
+}
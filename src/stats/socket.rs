@@ -0,0 +1,47 @@
+use super::Progress;
+use std::io::Write;
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Serves the [Progress] of a merge on a Unix domain socket, for it to be monitored while it runs. Each connection is
+/// sent a JSON [super::ProgressSnapshot] on a single line, then closed. Connections are served on a background thread,
+/// and the socket file removed when this is dropped.
+pub struct StatsSocket {
+    path: PathBuf,
+}
+
+impl StatsSocket {
+    /// Listen on a new socket at `path`, failing if anything exists there already
+    pub fn bind(path: &Path, progress: Arc<Progress>) -> std::io::Result<StatsSocket> {
+        let listener = UnixListener::bind(path)?;
+        // log through the subscriber of the thread binding the socket rather than only the global default
+        let dispatch = tracing::dispatcher::get_default(|dispatch| dispatch.clone());
+        std::thread::Builder::new()
+            .name("stream-merge-stats-socket".to_string())
+            .spawn(move || {
+                tracing::dispatcher::with_default(&dispatch, || {
+                    for connection in listener.incoming() {
+                        let sent = connection.and_then(|mut connection| {
+                            let mut stats = serde_json::to_vec(&progress.snapshot())?;
+                            stats.push(b'\n');
+                            connection.write_all(&stats)
+                        });
+                        if let Err(error) = sent {
+                            tracing::event!(tracing::Level::WARN, ?error, "Failed to send stats");
+                        }
+                    }
+                })
+            })?;
+        Ok(StatsSocket {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for StatsSocket {
+    fn drop(&mut self) {
+        // the listening thread is left blocked on the unlinked socket until the process exits
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
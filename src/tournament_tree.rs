@@ -1,34 +1,188 @@
+use bytes::Bytes;
+use futures::stream::{Stream, StreamExt};
+use futures::task::{Context, Poll};
+use std::cell::{Cell, RefCell};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
 /* TODO: is there a more idiomatic way to express this? Maybe there is some standard trait for a comparable/orderable key which can be produced from data and saved within the tree */
-pub trait Mergeable {
+/// An input to a [Tree], merged by a key of type `K` (a `u64` timestamp by default). Once exhausted, an input must
+/// report the [KeyOrdering::exhausted] key of the tree's ordering.
+pub trait Mergeable<K = u64> {
     type Data: ?Sized;
 
-    fn peek_timestamp(&mut self) -> u64; // TODO: make this any "copy, sortable key?"
+    fn peek_timestamp(&mut self) -> K;
     fn pop(&mut self) -> Option<&Self::Data>;
+
+    /// Breaks ties between inputs whose keys are equal, the lower secondary key being merged first (e.g. a sequence
+    /// number). Read along with each key. Inputs which tie on both are merged in the [Tree]'s own tie order.
+    fn peek_secondary(&mut self) -> u64 {
+        0
+    }
+}
+
+/// An input to an [OwnedTree]: like [Mergeable], for inputs which hand over ownership of each item they pop rather than
+/// holding it to be borrowed, such as iterators of cheaply moved `(timestamp, Bytes)` tuples
+pub trait MergeableOwned<K = u64> {
+    type Data;
+
+    fn peek_timestamp(&mut self) -> K;
+    fn pop(&mut self) -> Option<Self::Data>;
+
+    /// See [Mergeable::peek_secondary]
+    fn peek_secondary(&mut self) -> u64 {
+        0
+    }
+}
+
+/// Determines the order in which a [Tree] merges keys
+pub trait KeyOrdering {
+    type Key: Clone + PartialEq;
+
+    /// Whether `a` must be merged strictly before `b`
+    fn precedes(&self, a: &Self::Key, b: &Self::Key) -> bool;
+
+    /// The key reported by exhausted inputs. No key should be ordered after it.
+    fn exhausted(&self) -> Self::Key;
+}
+
+/// Smallest `u64` first, with `u64::MAX` marking exhausted inputs. The default [Tree] ordering.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Ascending;
+
+impl KeyOrdering for Ascending {
+    type Key = u64;
+
+    #[inline]
+    fn precedes(&self, a: &u64, b: &u64) -> bool {
+        a < b
+    }
+
+    fn exhausted(&self) -> u64 {
+        std::u64::MAX
+    }
+}
+
+/// Largest `u64` first, with `u64::MIN` marking exhausted inputs
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Descending;
+
+impl KeyOrdering for Descending {
+    type Key = u64;
+
+    #[inline]
+    fn precedes(&self, a: &u64, b: &u64) -> bool {
+        a > b
+    }
+
+    fn exhausted(&self) -> u64 {
+        std::u64::MIN
+    }
 }
-pub struct Tree<T: Mergeable> {
+
+/// Tournament tree merging any number of [Mergeable] inputs by their keys, in the order defined by a [KeyOrdering]
+///
+/// Memory use is linear in the number of inputs `n`: the tree's nodes take a `u32` per leaf of a complete binary tree
+/// (`n` rounded up to a power of two), and each input's current key and the input itself are stored once. Leaves
+/// beyond the last input take no storage, reading as the ordering's [KeyOrdering::exhausted] key, so that merging
+/// just over a power of two inputs doesn't double the space taken by keys. Each input's
+/// [secondary key](Mergeable::peek_secondary) takes a further `u64`. Inputs are held inline along with any
+/// data they keep for their last pop, so for enormous input counts it pays to keep that state small (e.g. boxing
+/// rarely used parts of it).
+pub struct Tree<T: Mergeable<O::Key>, O: KeyOrdering = Ascending> {
     needs_updating: bool,
     winning_value_index: usize,
-    nodes: Vec<u16>,
-    values: Vec<u64>,
+    nodes: Vec<u32>,
+    values: Vec<O::Key>, // one per input: see [Tree::value] for the leaves beyond the last input
+    secondaries: Vec<u64>, // one per input, breaking ties between values
     input_streams: Vec<T>, // each input stream is held in memory next to its last popped data
+    ordering: O,
+    exhausted: O::Key,
+    publish: Option<PublishKey<O::Key>>, // see [Tree::publish_current_key]
+}
+
+/// Hook given each new [Tree::current_key]
+type PublishKey<K> = Box<dyn Fn(&K) + Send + Sync>;
+
+/// Most inputs a [Tree] can merge, since its nodes index their winning leaves with a `u32`
+pub const MAX_N_INPUTS: u64 = 1 << 32;
+
+/// Error constructing a [Tree] from more than [MAX_N_INPUTS] inputs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TooManyInputs {
+    pub n_inputs: usize,
+}
+
+impl std::fmt::Display for TooManyInputs {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Can't merge {} inputs: a tournament tree merges at most {}",
+            self.n_inputs, MAX_N_INPUTS
+        )
+    }
+}
+
+impl std::error::Error for TooManyInputs {}
+
+/// Number of leaves of a tree of `n_inputs`: the next power of two, unless that's more than `max_n_inputs`
+fn n_leaf_nodes(n_inputs: usize, max_n_inputs: u64) -> Result<usize, TooManyInputs> {
+    n_inputs
+        .max(1)
+        .checked_next_power_of_two()
+        .filter(|n_leaf_nodes| *n_leaf_nodes as u64 <= max_n_inputs)
+        .ok_or(TooManyInputs { n_inputs })
 }
+
 impl<T: Mergeable> Tree<T> {
     // TODO: rather than taking an explict vector, maybe take anything iterable? Might need to solicit some help from the rust users forum
+    /// # Panics
+    ///
+    /// If there are more than [MAX_N_INPUTS] `input_streams`. See [Tree::try_new]
     pub fn new(input_streams: Vec<T>) -> Tree<T> {
-        let n_leaf_nodes = if input_streams.len() == 1 {
-            1
-        } else {
-            input_streams.len().next_power_of_two()
-        };
+        Tree::with_ordering(input_streams, Ascending)
+    }
+
+    /// Like [Tree::new], failing rather than panicking if there are more than [MAX_N_INPUTS] `input_streams`
+    pub fn try_new(input_streams: Vec<T>) -> Result<Tree<T>, TooManyInputs> {
+        Tree::try_with_ordering(input_streams, Ascending)
+    }
+}
+impl<T: Mergeable<O::Key>, O: KeyOrdering> Tree<T, O> {
+    /// Merge `input_streams` in the order defined by `ordering`
+    ///
+    /// # Panics
+    ///
+    /// If there are more than [MAX_N_INPUTS] `input_streams`. See [Tree::try_with_ordering]
+    pub fn with_ordering(input_streams: Vec<T>, ordering: O) -> Tree<T, O> {
+        Tree::try_with_ordering(input_streams, ordering).unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Like [Tree::with_ordering], failing rather than panicking if there are more than [MAX_N_INPUTS] `input_streams`
+    pub fn try_with_ordering(
+        input_streams: Vec<T>,
+        ordering: O,
+    ) -> Result<Tree<T, O>, TooManyInputs> {
+        // with at most MAX_N_INPUTS leaves, the last leaf's index fits in a u32
+        let n_leaf_nodes = n_leaf_nodes(input_streams.len(), MAX_N_INPUTS)?;
 
-        let values = vec![std::u64::MAX; n_leaf_nodes];
-        let nodes = vec![(n_leaf_nodes - 1) as u16; n_leaf_nodes];
-        let mut tree = Tree::<T> {
+        let exhausted = ordering.exhausted();
+        let values = Vec::with_capacity(input_streams.len());
+        let nodes = vec![(n_leaf_nodes - 1) as u32; n_leaf_nodes];
+        let mut tree = Tree::<T, O> {
             needs_updating: true,
             winning_value_index: 0,
             nodes,
             values,
+            secondaries: Vec::with_capacity(input_streams.len()),
             input_streams: Vec::<T>::with_capacity(input_streams.len()),
+            ordering,
+            exhausted,
+            publish: None,
         };
 
         // iterate through input streams and build the tree
@@ -38,19 +192,16 @@ impl<T: Mergeable> Tree<T> {
         for i in 0..n_leaf_nodes as usize {
             if let Some(mut stream) = streams.next() {
                 let value = stream.peek_timestamp();
-                tree.values[i] = value;
+                tree.values.push(value);
+                tree.secondaries.push(stream.peek_secondary());
                 tree.input_streams.push(stream);
             }
 
             if i % 2 != 0 {
                 // compute the winner and propagate it up the tree
-                let winning_value_index = if tree.values[i] < tree.values[i - 1] {
-                    i
-                } else {
-                    i - 1
-                };
+                let winning_value_index = if tree.precedes(i, i - 1) { i } else { i - 1 };
                 let parent = (tree.nodes.len() >> 1) + (i >> 1);
-                tree.nodes[parent] = winning_value_index as u16;
+                tree.nodes[parent] = winning_value_index as u32;
             }
         }
 
@@ -60,86 +211,1039 @@ impl<T: Mergeable> Tree<T> {
             for i in level_start..(2 * level_start) {
                 let left_child = tree.nodes[2 * i];
                 let right_child = tree.nodes[2 * i + 1];
-                tree.nodes[i] =
-                    if tree.values[left_child as usize] < tree.values[right_child as usize] {
-                        left_child
-                    } else {
-                        right_child
-                    };
+                tree.nodes[i] = if tree.precedes(left_child as usize, right_child as usize) {
+                    left_child
+                } else {
+                    right_child
+                };
                 //println!("updated {} = {}", i, tree.nodes[i]);
 
-                //println!("updating {} -> {}, {}", level_start, level_start >> 1, i);
-            }
-            level_start >>= 1; // move on to populating the next parent
+                //println!("updating {} -> {}, {}", level_start, level_start >> 1, i);
+            }
+            level_start >>= 1; // move on to populating the next parent
+        }
+        if tree.nodes.len() > 1 {
+            tree.winning_value_index = tree.nodes[1] as usize;
+        }
+        tree.needs_updating = false;
+        Ok(tree)
+    }
+
+    /// Key of the leaf at `index`: an input's key, or the exhausted key for the leaves beyond the last input
+    #[inline]
+    fn value(&self, index: usize) -> &O::Key {
+        self.values.get(index).unwrap_or(&self.exhausted)
+    }
+
+    /// Whether the leaf at `a` must be merged strictly before the leaf at `b`: by key, then by secondary key
+    #[inline]
+    fn precedes(&self, a: usize, b: usize) -> bool {
+        let (key_a, key_b) = (self.value(a), self.value(b));
+        self.ordering.precedes(key_a, key_b)
+            || (key_a == key_b
+                && self.secondaries.get(a).unwrap_or(&0) < self.secondaries.get(b).unwrap_or(&0))
+    }
+
+    // TODO: make this faster
+    fn update_winner(&mut self, changed_value_index: u32) {
+        //let mut parent = self.nodes.len() - 1 - (changed_value_index + 1 >> 1) as usize;        //let mut parent = self.nodes.len() - 1 - ((self.nodes.len()>>1) - ((changed_value_index as usize) >> 1));
+        if self.nodes.len() > 1 {
+            let parent = (self.nodes.len() >> 1) + (changed_value_index >> 1) as usize;
+            // the index that was changed was our previous winner
+            let mut winning_value_index = changed_value_index;
+            let sibling_value_index = winning_value_index ^ 1;
+
+            //println!("winning {} sibling {}", winning_value, sibling_value);
+            if self.precedes(sibling_value_index as usize, winning_value_index as usize) {
+                winning_value_index = sibling_value_index;
+            }
+
+            if parent > 1 {
+                self.nodes[parent] = winning_value_index;
+
+                let mut changed_index = parent;
+                while changed_index > 3 {
+                    let parent = changed_index >> 1;
+                    let sibling_value_index = self.nodes[changed_index ^ 1];
+
+                    // only need to update winning_value_index if it has changed
+                    if self.precedes(sibling_value_index as usize, winning_value_index as usize) {
+                        winning_value_index = sibling_value_index;
+                    }
+
+                    self.nodes[parent] = winning_value_index;
+                    changed_index = parent;
+                }
+                let sibling_value_index = self.nodes[changed_index ^ 1];
+                if self.precedes(sibling_value_index as usize, winning_value_index as usize) {
+                    winning_value_index = sibling_value_index;
+                }
+            }
+
+            self.winning_value_index = winning_value_index as usize;
+        }
+    }
+
+    /// Re-read the key of the input most recently popped from
+    fn update_popped(&mut self) {
+        if self.needs_updating {
+            let winner_stream_index = self.winning_value_index;
+            let input = &mut self.input_streams[winner_stream_index];
+            self.values[winner_stream_index] = input.peek_timestamp();
+            self.secondaries[winner_stream_index] = input.peek_secondary();
+            self.update_winner(winner_stream_index as u32);
+            self.needs_updating = false;
+            self.publish_key();
+        }
+    }
+
+    fn publish_key(&self) {
+        if let Some(publish) = &self.publish {
+            publish(self.value(self.winning_value_index));
+        }
+    }
+
+    /// The merge frontier: the key of the winning input, which no key yet to be merged precedes. Unlike the key of the
+    /// item last popped, this moves on as soon as that input's next key is known, and is the exhausted key once every
+    /// input is.
+    pub fn current_key(&mut self) -> &O::Key {
+        self.update_popped();
+        self.value(self.winning_value_index)
+    }
+
+    /// Re-read the key of the input at `input_index`, for inputs whose key can change without being popped (such as
+    /// [ArrivalOrdered] inputs)
+    pub fn refresh(&mut self, input_index: usize) {
+        self.update_popped();
+        let input = &mut self.input_streams[input_index];
+        self.values[input_index] = input.peek_timestamp();
+        self.secondaries[input_index] = input.peek_secondary();
+        self.update_winner(input_index as u32);
+        self.publish_key();
+    }
+
+    /// The input at `input_index`, in the order the inputs were given to the tree
+    pub fn input_mut(&mut self, input_index: usize) -> &mut T {
+        &mut self.input_streams[input_index]
+    }
+
+    /// Merge `input` alongside the others from here on, returning its index (after those of every other input). Any of
+    /// its keys which precede the keys already merged are merged next.
+    ///
+    /// # Panics
+    ///
+    /// If the tree already merges [MAX_N_INPUTS] inputs
+    pub fn add_input(&mut self, mut input: T) -> usize {
+        self.update_popped();
+        let input_index = self.input_streams.len();
+        self.values.push(input.peek_timestamp());
+        self.secondaries.push(input.peek_secondary());
+        self.input_streams.push(input);
+        if input_index < self.nodes.len() {
+            self.update_winner(input_index as u32);
+        } else {
+            // every leaf is taken, so double them
+            let n_leaf_nodes = 2 * self.nodes.len();
+            assert!(
+                n_leaf_nodes as u64 <= MAX_N_INPUTS,
+                "{}",
+                TooManyInputs {
+                    n_inputs: input_index + 1
+                }
+            );
+            self.nodes = vec![0; n_leaf_nodes];
+            self.build_nodes();
+        }
+        self.publish_key();
+        input_index
+    }
+
+    /// Recompute every internal node, and the winner, from the leaf values, as [Tree::try_with_ordering] does
+    fn build_nodes(&mut self) {
+        let n_leaf_nodes = self.nodes.len();
+        for parent in (1..n_leaf_nodes).rev() {
+            let (left, right) = (2 * parent, 2 * parent + 1);
+            self.nodes[parent] = if left >= n_leaf_nodes {
+                // the parent of two leaves, preferring the left on a tie
+                let (left, right) = ((left - n_leaf_nodes) as u32, (right - n_leaf_nodes) as u32);
+                if self.precedes(right as usize, left as usize) {
+                    right
+                } else {
+                    left
+                }
+            } else {
+                let (left, right) = (self.nodes[left], self.nodes[right]);
+                if self.precedes(left as usize, right as usize) {
+                    left
+                } else {
+                    right
+                }
+            };
+        }
+        self.winning_value_index = if n_leaf_nodes > 1 {
+            self.nodes[1] as usize
+        } else {
+            0
+        };
+    }
+
+    pub fn pop(&mut self) -> std::option::Option<&<T>::Data> {
+        self.update_popped();
+
+        if *self.value(self.winning_value_index) == self.exhausted {
+            None
+        } else {
+            let winner_stream_index = self.winning_value_index;
+            self.needs_updating = true; // from here on out, we will always need to call peek on the last
+            self.input_streams[winner_stream_index].pop()
+        }
+    }
+}
+
+impl<T: Mergeable, O: KeyOrdering<Key = u64>> Tree<T, O> {
+    /// Store each new [Tree::current_key] to `frontier` for other subsystems (e.g. threads deciding how far ahead to
+    /// read) to follow. Since the tree only re-reads the input it popped from on its next pop, the published key
+    /// trails the frontier by the item last popped.
+    pub fn publish_current_key(mut self, frontier: MergeFrontier) -> Tree<T, O> {
+        frontier.set(*self.value(self.winning_value_index));
+        self.publish = Some(Box::new(move |key: &u64| frontier.set(*key)));
+        self
+    }
+}
+
+#[cfg(any(test, feature = "debug-tree"))]
+impl<T: Mergeable<O::Key>, O: KeyOrdering> Tree<T, O>
+where
+    O::Key: std::fmt::Debug,
+{
+    /// Render the internal `nodes` (indices of each subtree's winning value, from the root at index 1) and leaf `values`
+    /// arrays, along with the current winner. Only the winner is kept up to date for the root once merging begins.
+    pub fn dump(&self) -> String {
+        format!(
+            "nodes: {:?}\nvalues: {:?}\nwinner: values[{}] = {:?}{}",
+            self.nodes.get(1..).unwrap_or_default(),
+            self.values,
+            self.winning_value_index,
+            self.value(self.winning_value_index),
+            if self.needs_updating {
+                " (popped; refreshed on the next pop)"
+            } else {
+                ""
+            }
+        )
+    }
+
+    /// Panic, printing [Tree::dump], unless every internal node (and the current winner, for the root) points at a value
+    /// within its subtree which no other value in that subtree precedes
+    pub fn assert_invariants(&self) {
+        let n_leaf_nodes = self.nodes.len();
+        for node in 1..self.nodes.len() {
+            // the subtree under `node` spans leaves [first_leaf, last_leaf]
+            let (mut first_leaf, mut last_leaf) = (node, node);
+            while first_leaf < n_leaf_nodes {
+                first_leaf *= 2;
+                last_leaf = 2 * last_leaf + 1;
+            }
+            let leaves = (first_leaf - n_leaf_nodes)..=(last_leaf - n_leaf_nodes);
+            // pops only maintain the root's winner in winning_value_index, leaving nodes[1] as built
+            let winner = if node == 1 {
+                self.winning_value_index
+            } else {
+                self.nodes[node] as usize
+            };
+            assert!(
+                leaves.contains(&winner),
+                "node {} points at values[{}], outside its subtree {:?}\n{}",
+                node,
+                winner,
+                leaves,
+                self.dump()
+            );
+            for leaf in leaves {
+                assert!(
+                    !self.precedes(leaf, winner),
+                    "node {} points at values[{}] but values[{}] precedes it\n{}",
+                    node,
+                    winner,
+                    leaf,
+                    self.dump()
+                );
+            }
+        }
+    }
+}
+
+/// [Mergeable] wrapper of a [MergeableOwned] input, holding its popped item until the [OwnedTree] takes it
+struct Owned<T: MergeableOwned<K>, K> {
+    input: T,
+    popped: Option<T::Data>,
+}
+
+impl<T: MergeableOwned<K>, K> Mergeable<K> for Owned<T, K> {
+    type Data = T::Data;
+
+    fn peek_timestamp(&mut self) -> K {
+        self.input.peek_timestamp()
+    }
+
+    fn peek_secondary(&mut self) -> u64 {
+        self.input.peek_secondary()
+    }
+
+    fn pop(&mut self) -> Option<&T::Data> {
+        self.popped = self.input.pop();
+        self.popped.as_ref()
+    }
+}
+
+/// [Tree] of [MergeableOwned] inputs, yielding each merged item by value
+pub struct OwnedTree<T: MergeableOwned<O::Key>, O: KeyOrdering = Ascending> {
+    tree: Tree<Owned<T, O::Key>, O>,
+}
+
+impl<T: MergeableOwned> OwnedTree<T> {
+    /// # Panics
+    ///
+    /// If there are more than [MAX_N_INPUTS] `inputs`. See [Tree::try_new]
+    pub fn new(inputs: Vec<T>) -> OwnedTree<T> {
+        OwnedTree::with_ordering(inputs, Ascending)
+    }
+}
+
+impl<T: MergeableOwned<O::Key>, O: KeyOrdering> OwnedTree<T, O> {
+    /// Merge `inputs` in the order defined by `ordering`
+    ///
+    /// # Panics
+    ///
+    /// If there are more than [MAX_N_INPUTS] `inputs`. See [Tree::try_with_ordering]
+    pub fn with_ordering(inputs: Vec<T>, ordering: O) -> OwnedTree<T, O> {
+        let inputs = inputs
+            .into_iter()
+            .map(|input| Owned {
+                input,
+                popped: None,
+            })
+            .collect();
+        OwnedTree {
+            tree: Tree::with_ordering(inputs, ordering),
+        }
+    }
+
+    /// Re-read the key of the input at `input_index`. See [Tree::refresh]
+    pub fn refresh(&mut self, input_index: usize) {
+        self.tree.refresh(input_index)
+    }
+
+    /// The input at `input_index`, in the order the inputs were given to the tree
+    pub fn input_mut(&mut self, input_index: usize) -> &mut T {
+        &mut self.tree.input_mut(input_index).input
+    }
+
+    pub fn pop(&mut self) -> Option<T::Data> {
+        self.pop_with_index().map(|(_, data)| data)
+    }
+
+    /// Merge `input` alongside the others from here on. See [Tree::add_input]
+    pub fn add_input(&mut self, input: T) -> usize {
+        self.tree.add_input(Owned {
+            input,
+            popped: None,
+        })
+    }
+
+    /// The merge frontier. See [Tree::current_key]
+    pub fn current_key(&mut self) -> &O::Key {
+        self.tree.current_key()
+    }
+
+    /// Like [OwnedTree::pop], alongside the index of the input the item was popped from
+    pub fn pop_with_index(&mut self) -> Option<(usize, T::Data)> {
+        self.tree.pop()?;
+        // the tree only moves on from the input it popped from on its next pop
+        let input_index = self.tree.winning_value_index;
+        let data = self.tree.input_streams[input_index].popped.take()?;
+        Some((input_index, data))
+    }
+}
+
+impl<T: MergeableOwned, O: KeyOrdering<Key = u64>> OwnedTree<T, O> {
+    /// See [Tree::publish_current_key]
+    pub fn publish_current_key(self, frontier: MergeFrontier) -> OwnedTree<T, O> {
+        OwnedTree {
+            tree: self.tree.publish_current_key(frontier),
+        }
+    }
+}
+
+/// [MergeableOwned] adapter for an iterator of time-ordered `(timestamp, packet)` tuples, such as a blocking iterator
+/// over the stream returned by [crate::stream_and_decode_pcap_packets]
+pub struct PacketStream<T: Iterator<Item = (u64, Bytes)>> {
+    iterator: std::iter::Peekable<T>,
+}
+impl<T: Iterator<Item = (u64, Bytes)>> PacketStream<T> {
+    pub fn new(iterator: T) -> PacketStream<T> {
+        PacketStream {
+            iterator: iterator.peekable(),
+        }
+    }
+}
+
+impl<T: Iterator<Item = (u64, Bytes)>> MergeableOwned for PacketStream<T> {
+    type Data = (u64, Bytes);
+
+    fn pop(&mut self) -> Option<(u64, Bytes)> {
+        self.iterator.next()
+    }
+
+    fn peek_timestamp(&mut self) -> u64 {
+        match self.iterator.peek() {
+            Some((ts, _bytes)) => *ts,
+            None => std::u64::MAX,
+        }
+    }
+}
+
+/// [Mergeable] input over a slice of `(key, data)` tuples already sorted by key, for tests and quick merges of
+/// in-memory data without an adapter of their own. Popped tuples are borrowed from the slice.
+///
+/// ```
+/// use stream_merge::tournament_tree::{SliceStream, Tree};
+///
+/// let odd = vec![(1, "a"), (3, "c")];
+/// let even = vec![(2, "b"), (4, "d")];
+/// let mut tree = Tree::new(vec![SliceStream::new(&odd), SliceStream::new(&even)]);
+/// let mut merged = Vec::new();
+/// while let Some((key, data)) = tree.pop() {
+///     merged.push((*key, *data));
+/// }
+/// assert_eq!(merged, vec![(1, "a"), (2, "b"), (3, "c"), (4, "d")]);
+/// ```
+pub struct SliceStream<'a, K, D> {
+    items: &'a [(K, D)],
+    next_index: usize,
+    exhausted: K,
+}
+
+impl<'a, D> SliceStream<'a, u64, D> {
+    /// Merge `items`, sorted by ascending `u64` key, in a [Tree] of the default [Ascending] ordering
+    pub fn new(items: &'a [(u64, D)]) -> SliceStream<'a, u64, D> {
+        SliceStream::with_ordering(items, &Ascending)
+    }
+}
+
+impl<'a, K, D> SliceStream<'a, K, D> {
+    /// Merge `items`, sorted in the order defined by `ordering`, in a [Tree] of that ordering
+    pub fn with_ordering<O: KeyOrdering<Key = K>>(
+        items: &'a [(K, D)],
+        ordering: &O,
+    ) -> SliceStream<'a, K, D> {
+        SliceStream {
+            items,
+            next_index: 0,
+            exhausted: ordering.exhausted(),
+        }
+    }
+}
+
+impl<K: Clone, D> Mergeable<K> for SliceStream<'_, K, D> {
+    type Data = (K, D);
+
+    fn peek_timestamp(&mut self) -> K {
+        match self.items.get(self.next_index) {
+            Some((key, _data)) => key.clone(),
+            None => self.exhausted.clone(),
+        }
+    }
+
+    fn pop(&mut self) -> Option<&(K, D)> {
+        let item = self.items.get(self.next_index)?;
+        self.next_index += 1;
+        Some(item)
+    }
+}
+
+/// [MergeableOwned] adapter which keys an iterator of `(timestamp, packet)` tuples by a second iterator of timestamps
+/// rather than by their own, matched positionally: the nth packet is merged, and restamped, with the nth timestamp. The
+/// `timestamps` must be in ascending order, whatever the order of the packets' own timestamps. The input ends once
+/// either iterator does (see [SidecarKeyed::mismatch]).
+pub struct SidecarKeyed<T: Iterator<Item = (u64, Bytes)>, S: Iterator<Item = u64>> {
+    packets: T,
+    timestamps: S,
+    next_value: Option<Option<(u64, Bytes)>>, // the next keyed packet (or None, at the end) once it has been read
+    mismatch: Option<SidecarMismatch>,
+}
+
+/// Which of a [SidecarKeyed] input's iterators had items left over once the other ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SidecarMismatch {
+    /// packets were left without a timestamp, and were not merged
+    MorePackets,
+    /// timestamps were left without a packet
+    MoreTimestamps,
+}
+
+impl<T: Iterator<Item = (u64, Bytes)>, S: Iterator<Item = u64>> SidecarKeyed<T, S> {
+    pub fn new(packets: T, timestamps: S) -> SidecarKeyed<T, S> {
+        SidecarKeyed {
+            packets,
+            timestamps,
+            next_value: None,
+            mismatch: None,
+        }
+    }
+
+    /// Whether the packets and timestamps differed in number, once the input has ended
+    pub fn mismatch(&self) -> Option<SidecarMismatch> {
+        self.mismatch
+    }
+
+    fn peek(&mut self) -> &Option<(u64, Bytes)> {
+        if self.next_value.is_none() {
+            let next = match (self.packets.next(), self.timestamps.next()) {
+                (Some((_ts, packet)), Some(ts)) => Some((ts, packet)),
+                (Some(_), None) => {
+                    self.mismatch = Some(SidecarMismatch::MorePackets);
+                    None
+                }
+                (None, Some(_)) => {
+                    self.mismatch = Some(SidecarMismatch::MoreTimestamps);
+                    None
+                }
+                (None, None) => None,
+            };
+            self.next_value = Some(next);
+        }
+        self.next_value.as_ref().unwrap()
+    }
+}
+
+impl<T: Iterator<Item = (u64, Bytes)>, S: Iterator<Item = u64>> MergeableOwned
+    for SidecarKeyed<T, S>
+{
+    type Data = (u64, Bytes);
+
+    fn pop(&mut self) -> Option<(u64, Bytes)> {
+        self.peek();
+        let next = self.next_value.take().flatten();
+        if next.is_none() {
+            self.next_value = Some(None); // stay ended, rather than read on past a mismatch
+        }
+        next
+    }
+
+    fn peek_timestamp(&mut self) -> u64 {
+        match self.peek() {
+            Some((ts, _packet)) => *ts,
+            None => std::u64::MAX,
+        }
+    }
+}
+
+/// [MergeableOwned] adapter which calls `on_exhausted` once its timestamp-ordered `input` is first found exhausted: when
+/// the tree moves on from its last item (or as the tree is built, if it has none). Inputs are drained in the order
+/// their callbacks are called.
+pub struct OnExhausted<T: MergeableOwned, F: FnOnce()> {
+    input: T,
+    on_exhausted: Option<F>,
+}
+
+impl<T: MergeableOwned, F: FnOnce()> OnExhausted<T, F> {
+    pub fn new(input: T, on_exhausted: F) -> OnExhausted<T, F> {
+        OnExhausted {
+            input,
+            on_exhausted: Some(on_exhausted),
+        }
+    }
+}
+
+impl<T: MergeableOwned, F: FnOnce()> MergeableOwned for OnExhausted<T, F> {
+    type Data = T::Data;
+
+    fn pop(&mut self) -> Option<T::Data> {
+        self.input.pop()
+    }
+
+    fn peek_timestamp(&mut self) -> u64 {
+        let ts = self.input.peek_timestamp();
+        if ts == std::u64::MAX {
+            if let Some(on_exhausted) = self.on_exhausted.take() {
+                on_exhausted();
+            }
+        }
+        ts
+    }
+
+    fn peek_secondary(&mut self) -> u64 {
+        self.input.peek_secondary()
+    }
+}
+
+/// Virtual time advanced by each pop of a [Weighted] input of weight 1. Divisible by every weight up to 16, and small
+/// enough that the virtual time can't overflow within any merge
+const WEIGHT_STRIDE: u64 = 720_720;
+
+/// Weighted round-robin between the [Weighted] inputs of one tree, which relaxes timestamp order: of the inputs tied
+/// on timestamp, each is merged in proportion to its weight, rather than in the [Tree]'s tie order. With a non-zero
+/// `window_ns`, timestamps are compared by the `window_ns`-long interval they fall in, so that near-ties are
+/// interleaved by weight too, and packets may be merged up to a window out of order. Cheap to clone.
+#[derive(Clone, Debug, Default)]
+pub struct WeightedTies {
+    virtual_time: Rc<Cell<u64>>,
+    window_ns: u64,
+}
+
+impl WeightedTies {
+    pub fn new(window_ns: u64) -> WeightedTies {
+        WeightedTies {
+            virtual_time: Rc::default(),
+            window_ns,
+        }
+    }
+
+    /// `input`, interleaved by `weight` with the other inputs of these ties. A weight of 0 is taken as 1, and one above
+    /// 720720 as 720720, the most by which weights can be told apart.
+    pub fn weighted<T: MergeableOwned>(&self, input: T, weight: u32) -> Weighted<T> {
+        Weighted {
+            input,
+            ties: self.clone(),
+            stride: (WEIGHT_STRIDE / u64::from(weight.max(1))).max(1),
+            pass: 0,
+        }
+    }
+}
+
+/// [MergeableOwned] adapter which breaks ties by [weighted round-robin](WeightedTies), in place of `input`'s own
+/// secondary key. Each pop advances the input's pass by a stride inversely proportional to its weight, and ties go to
+/// the input with the lowest pass. An input which hasn't tied for a while is caught up to the ties' virtual time as it's
+/// popped, so it doesn't then win every tie until the others catch up.
+pub struct Weighted<T: MergeableOwned> {
+    input: T,
+    ties: WeightedTies,
+    stride: u64,
+    pass: u64,
+}
+
+impl<T: MergeableOwned> MergeableOwned for Weighted<T> {
+    type Data = T::Data;
+
+    fn pop(&mut self) -> Option<T::Data> {
+        let virtual_time = &self.ties.virtual_time;
+        let start = self.pass.max(virtual_time.get());
+        virtual_time.set(start);
+        self.pass = start + self.stride;
+        self.input.pop()
+    }
+
+    fn peek_timestamp(&mut self) -> u64 {
+        let ts = self.input.peek_timestamp();
+        match self.ties.window_ns {
+            0 => ts,
+            _ if ts == std::u64::MAX => ts,
+            window_ns => ts - ts % window_ns,
+        }
+    }
+
+    fn peek_secondary(&mut self) -> u64 {
+        self.pass
+    }
+}
+
+/// Merges more inputs than can all be open at once, such as a very large number of files, keeping only about
+/// `max_active` of them open in an [OwnedTree] at a time. The rest wait in a priority queue by the key of their first
+/// item, known (e.g. cheaply probed) up front, and are only opened as the earlier ones are exhausted, each opened input
+/// taking the tree leaf of an exhausted one. Inputs are opened in order of their first keys, so once the tree is full
+/// one is only opened early if its first key precedes every key left in the tree, as it must be for the merge to stay
+/// in order: more than `max_active` inputs are open only while more than that many overlap in time.
+pub struct ActiveSetMerge<P, T: MergeableOwned, F: FnMut(P) -> T> {
+    tree: OwnedTree<ActiveSlot<T>>,
+    pending: BinaryHeap<Reverse<PendingInput<P>>>,
+    open: F,
+    max_active: usize,
+    // indices of the leaves whose inputs are exhausted, free for the next input opened
+    freed: Rc<RefCell<Vec<usize>>>,
+}
+
+/// An input of an [ActiveSetMerge] yet to be opened, ordered by its first key then by the order it was given in
+struct PendingInput<P> {
+    first_key: u64,
+    sequence_number: usize,
+    input: P,
+}
+
+impl<P> PartialEq for PendingInput<P> {
+    fn eq(&self, other: &PendingInput<P>) -> bool {
+        self.cmp(other) == std::cmp::Ordering::Equal
+    }
+}
+
+impl<P> Eq for PendingInput<P> {}
+
+impl<P> PartialOrd for PendingInput<P> {
+    fn partial_cmp(&self, other: &PendingInput<P>) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<P> Ord for PendingInput<P> {
+    fn cmp(&self, other: &PendingInput<P>) -> std::cmp::Ordering {
+        (self.first_key, self.sequence_number).cmp(&(other.first_key, other.sequence_number))
+    }
+}
+
+/// A leaf of an [ActiveSetMerge]'s tree, dropping its input as soon as it's found exhausted, so that whatever the input
+/// holds open is released, and freeing the leaf for the next input opened
+struct ActiveSlot<T> {
+    input: Option<T>,
+    index: usize,
+    freed: Rc<RefCell<Vec<usize>>>,
+}
+
+impl<T: MergeableOwned> MergeableOwned for ActiveSlot<T> {
+    type Data = T::Data;
+
+    fn pop(&mut self) -> Option<T::Data> {
+        self.input.as_mut()?.pop()
+    }
+
+    fn peek_timestamp(&mut self) -> u64 {
+        let ts = match &mut self.input {
+            Some(input) => input.peek_timestamp(),
+            None => return std::u64::MAX,
+        };
+        if ts == std::u64::MAX {
+            self.input = None;
+            self.freed.borrow_mut().push(self.index);
+        }
+        ts
+    }
+
+    fn peek_secondary(&mut self) -> u64 {
+        self.input
+            .as_mut()
+            .map_or(0, |input| input.peek_secondary())
+    }
+}
+
+impl<P, T: MergeableOwned, F: FnMut(P) -> T> ActiveSetMerge<P, T, F> {
+    /// Merge `inputs`, each given with the key of its first item (or any key at or before it, such as 0 for an input
+    /// whose first key is unknown), opening each with `open` once it's to be merged
+    pub fn new(inputs: Vec<(u64, P)>, max_active: usize, open: F) -> ActiveSetMerge<P, T, F> {
+        let pending = inputs
+            .into_iter()
+            .enumerate()
+            .map(|(sequence_number, (first_key, input))| {
+                Reverse(PendingInput {
+                    first_key,
+                    sequence_number,
+                    input,
+                })
+            })
+            .collect();
+        ActiveSetMerge {
+            tree: OwnedTree::new(Vec::new()),
+            pending,
+            open,
+            max_active: max_active.max(1),
+            freed: Rc::default(),
+        }
+    }
+
+    /// Number of inputs open in the tree, not yet found exhausted
+    pub fn n_active(&self) -> usize {
+        self.tree.tree.input_streams.len() - self.freed.borrow().len()
+    }
+
+    pub fn pop(&mut self) -> Option<T::Data> {
+        self.open_pending();
+        self.tree.pop()
+    }
+
+    /// Open pending inputs while there is room for them in the tree, or while the next would be merged before any
+    /// input in the tree
+    fn open_pending(&mut self) {
+        while let Some(Reverse(next)) = self.pending.peek() {
+            // read first, as it finds any input popped last exhausted
+            let frontier = *self.tree.current_key();
+            if self.n_active() >= self.max_active && next.first_key >= frontier {
+                break;
+            }
+            let Reverse(next) = self.pending.pop().unwrap();
+            let input = (self.open)(next.input);
+            let freed = self.freed.borrow_mut().pop();
+            match freed {
+                Some(index) => {
+                    self.tree.input_mut(index).input = Some(input);
+                    self.tree.refresh(index);
+                }
+                None => {
+                    let index = self.tree.tree.input_streams.len();
+                    self.tree.add_input(ActiveSlot {
+                        input: Some(input),
+                        index,
+                        freed: self.freed.clone(),
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// The timestamp of the packet most recently merged, shared with [ArrivalOrdered] inputs. Cheap to clone.
+#[derive(Clone, Debug, Default)]
+pub struct MergeClock(Rc<Cell<u64>>);
+
+impl MergeClock {
+    pub fn get(&self) -> u64 {
+        self.0.get()
+    }
+
+    /// Advance the clock to `ts`, the timestamp of a merged packet
+    pub fn set(&self, ts: u64) {
+        self.0.set(ts)
+    }
+}
+
+/// The latest [Tree::current_key] of a tree merging `u64` keys, as [published](Tree::publish_current_key) for other
+/// threads to read. Cheap to clone.
+#[derive(Clone, Debug, Default)]
+pub struct MergeFrontier(Arc<AtomicU64>);
+
+impl MergeFrontier {
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn set(&self, key: u64) {
+        self.0.store(key, Ordering::Relaxed)
+    }
+}
+
+/// [MergeableOwned] adapter which merges a stream of packets in arrival order rather than by timestamp: a packet which has
+/// arrived is keyed, and restamped, with the current [MergeClock] time, so that it is merged next. An input with no
+/// packet ready reports the exhausted key until one arrives, so the [OwnedTree] must be [refreshed](OwnedTree::refresh) to see
+/// it, and a merge whose inputs are all exhausted should wait on any arrival-ordered inputs which are still
+/// [open](ArrivalOrdered::is_open) (see [ArrivalOrdered::poll_ready]).
+pub struct ArrivalOrdered<St> {
+    stream: St,
+    clock: MergeClock,
+    next_value: Option<(u64, Bytes)>,
+    ended: bool,
+}
+
+impl<St: Stream<Item = (u64, Bytes)> + Unpin> ArrivalOrdered<St> {
+    pub fn new(stream: St, clock: MergeClock) -> ArrivalOrdered<St> {
+        ArrivalOrdered {
+            stream,
+            clock,
+            next_value: None,
+            ended: false,
+        }
+    }
+
+    /// Whether more packets may still arrive
+    pub fn is_open(&self) -> bool {
+        self.next_value.is_some() || !self.ended
+    }
+
+    /// Ready once a packet has arrived or the stream has ended, otherwise scheduling a wakeup for when either happens
+    pub fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        if self.next_value.is_none() && !self.ended {
+            match self.stream.poll_next_unpin(cx) {
+                Poll::Ready(Some(packet)) => self.next_value = Some(packet),
+                Poll::Ready(None) => self.ended = true,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(())
+    }
+}
+
+impl<St: Stream<Item = (u64, Bytes)> + Unpin> MergeableOwned for ArrivalOrdered<St> {
+    type Data = (u64, Bytes);
+
+    fn pop(&mut self) -> Option<(u64, Bytes)> {
+        let ts = self.clock.get();
+        self.next_value.take().map(|(_ts, packet)| (ts, packet))
+    }
+
+    fn peek_timestamp(&mut self) -> u64 {
+        let _ = self.poll_ready(&mut Context::from_waker(futures::task::noop_waker_ref()));
+        if self.next_value.is_some() {
+            self.clock.get()
+        } else {
+            std::u64::MAX
+        }
+    }
+}
+
+/// An input to an [AsyncTree]: like [Mergeable], for inputs whose next key may not be known yet, such as a [Stream]
+/// of packets still being decoded. See [AsyncStream].
+pub trait AsyncMergeable<K = u64> {
+    type Data;
+
+    /// The key of the next item, without removing it, once it is known. Otherwise, schedules a wakeup for when it may
+    /// be. Once exhausted, an input must report the [KeyOrdering::exhausted] key of the tree's ordering.
+    fn poll_peek_key(&mut self, cx: &mut Context<'_>) -> Poll<K>;
+
+    /// Remove the next item, whose key [AsyncMergeable::poll_peek_key] has just reported
+    fn pop(&mut self) -> Option<Self::Data>;
+}
+
+/// [AsyncMergeable] adapter for a [Stream] of time-ordered `(timestamp, data)` tuples, such as the stream returned by
+/// [crate::stream_and_decode_pcap_packets] (once pinned)
+pub struct AsyncStream<St: Stream> {
+    stream: St,
+    next_value: Option<Option<St::Item>>, // the next item (or None, at the end of the stream) once it has been polled
+}
+
+impl<St: Stream> AsyncStream<St> {
+    pub fn new(stream: St) -> AsyncStream<St> {
+        AsyncStream {
+            stream,
+            next_value: None,
         }
-        if tree.nodes.len() > 1 {
-            tree.winning_value_index = tree.nodes[1] as usize;
+    }
+}
+
+impl<D, St: Stream<Item = (u64, D)> + Unpin> AsyncMergeable for AsyncStream<St> {
+    type Data = (u64, D);
+
+    fn poll_peek_key(&mut self, cx: &mut Context<'_>) -> Poll<u64> {
+        if self.next_value.is_none() {
+            self.next_value = Some(futures::ready!(self.stream.poll_next_unpin(cx)));
         }
-        //println!("BEGIN tree: {:?} {:?}", &tree.nodes[1..], &tree.values[..]);
-        tree.needs_updating = false;
-        tree
+        Poll::Ready(match &self.next_value {
+            Some(Some((ts, _data))) => *ts,
+            _ => std::u64::MAX,
+        })
     }
 
-    // TODO: make this faster
-    fn update_winner(&mut self, changed_value_index: u16) {
-        //println!("BEFORE tree: {:?} {:?}", &self.nodes[1..], &self.values[..]);
-        //let mut parent = self.nodes.len() - 1 - (changed_value_index + 1 >> 1) as usize;        //let mut parent = self.nodes.len() - 1 - ((self.nodes.len()>>1) - ((changed_value_index as usize) >> 1));
-        if self.nodes.len() > 1 {
-            let parent = (self.nodes.len() >> 1) + (changed_value_index >> 1) as usize;
-            // the index that was changed was our previous winner
-            let mut winning_value = self.values[changed_value_index as usize];
-            let mut winning_value_index = changed_value_index;
-            let sibling_value = self.values[winning_value_index as usize ^ 1];
+    fn pop(&mut self) -> Option<(u64, D)> {
+        self.next_value.take().flatten()
+    }
+}
 
-            //println!("winning {} sibling {}", winning_value, sibling_value);
-            if sibling_value < winning_value {
-                winning_value = sibling_value;
-                winning_value_index ^= 1;
-            } //println!("MIDDLE tree: {:?} {:?}", &self.nodes[1..], &self.values[..]);
+/// An item popped from an [AsyncTree] input by its inner [Tree], waiting to be taken by the [AsyncTree]
+struct Popped<D> {
+    input_index: usize,
+    data: Cell<Option<D>>,
+}
 
-            if parent > 1 {
-                self.nodes[parent] = winning_value_index;
+/// [Mergeable] wrapper of an [AsyncMergeable] input whose next key is always known by the time its [Tree] reads it
+struct KnownKey<T: AsyncMergeable<K>, K> {
+    input: T,
+    key: Option<K>,
+    popped: Popped<T::Data>,
+}
 
-                let mut changed_index = parent;
-                while changed_index > 3 {
-                    let parent = changed_index >> 1;
-                    let sibling_value_index = self.nodes[changed_index ^ 1];
-                    let sibling_value = self.values[sibling_value_index as usize];
+impl<T: AsyncMergeable<K>, K: Clone> Mergeable<K> for KnownKey<T, K> {
+    type Data = Popped<T::Data>;
 
-                    // only need to update winning_value_index or winning_value if they have changed
-                    if sibling_value < winning_value {
-                        winning_value_index = sibling_value_index;
-                        winning_value = sibling_value;
-                    }
+    fn peek_timestamp(&mut self) -> K {
+        self.key
+            .clone()
+            .expect("an AsyncTree input's key is polled before the tree reads it")
+    }
 
-                    self.nodes[parent] = winning_value_index;
-                    changed_index = parent;
-                }
-                let sibling_value_index = self.nodes[changed_index ^ 1];
-                let sibling_value = self.values[sibling_value_index as usize];
-                if winning_value > sibling_value {
-                    winning_value_index = sibling_value_index;
-                }
-            }
+    fn pop(&mut self) -> Option<&Popped<T::Data>> {
+        self.key = None;
+        self.popped.data.set(Some(self.input.pop()?));
+        Some(&self.popped)
+    }
+}
 
-            self.winning_value_index = winning_value_index as usize;
-        } //println!("AFTER tree: {:?} {:?}", &self.nodes[1..], &self.values[..]);
+/// [Stream] merging [AsyncMergeable] inputs in the order defined by a [KeyOrdering], driving each input as it is polled
+/// rather than blocking on it, so that a merge of asynchronously decoded inputs can run as a single task. Yields
+/// nothing until the first key of every input is known, then waits on the input most recently merged from whenever
+/// its next key isn't yet known: a [Tree] of the inputs whose keys are all known.
+pub struct AsyncTree<T: AsyncMergeable<O::Key>, O: KeyOrdering = Ascending> {
+    state: AsyncTreeState<T, O>,
+}
+
+enum AsyncTreeState<T: AsyncMergeable<O::Key>, O: KeyOrdering> {
+    /// polling every input for its first key
+    Starting {
+        inputs: Vec<KnownKey<T, O::Key>>,
+        ordering: Option<O>,
+    },
+    Merging {
+        tree: Tree<KnownKey<T, O::Key>, O>,
+        /// input whose next key must be known before the tree can pop again
+        last_popped: Option<usize>,
+    },
+}
+
+impl<T: AsyncMergeable> AsyncTree<T> {
+    pub fn new(inputs: Vec<T>) -> AsyncTree<T> {
+        AsyncTree::with_ordering(inputs, Ascending)
     }
+}
 
-    pub fn pop(&mut self) -> std::option::Option<&<T>::Data> {
-        if self.needs_updating {
-            let winner_stream_index = self.winning_value_index;
-            self.values[winner_stream_index] =
-                self.input_streams[winner_stream_index].peek_timestamp();
-            self.update_winner(winner_stream_index as u16);
+impl<T: AsyncMergeable<O::Key>, O: KeyOrdering> AsyncTree<T, O> {
+    /// Merge `inputs` in the order defined by `ordering`
+    pub fn with_ordering(inputs: Vec<T>, ordering: O) -> AsyncTree<T, O> {
+        let inputs = inputs
+            .into_iter()
+            .enumerate()
+            .map(|(input_index, input)| KnownKey {
+                input,
+                key: None,
+                popped: Popped {
+                    input_index,
+                    data: Cell::new(None),
+                },
+            })
+            .collect();
+        AsyncTree {
+            state: AsyncTreeState::Starting {
+                inputs,
+                ordering: Some(ordering),
+            },
         }
+    }
+}
 
-        if self.values[self.winning_value_index] == std::u64::MAX {
-            None
-        } else {
-            let winner_stream_index = self.winning_value_index;
-            self.needs_updating = true; // from here on out, we will always need to call peek on the last
-            self.input_streams[winner_stream_index].pop()
+// inputs are polled through `&mut`, never pinned
+impl<T: AsyncMergeable<O::Key>, O: KeyOrdering> Unpin for AsyncTree<T, O> {}
+
+impl<T: AsyncMergeable<O::Key>, O: KeyOrdering> Stream for AsyncTree<T, O> {
+    type Item = T::Data;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T::Data>> {
+        let state = &mut self.state;
+        if let AsyncTreeState::Starting { inputs, ordering } = state {
+            let mut all_known = true;
+            for input in inputs.iter_mut().filter(|input| input.key.is_none()) {
+                match input.input.poll_peek_key(cx) {
+                    Poll::Ready(key) => input.key = Some(key),
+                    Poll::Pending => all_known = false, // keep polling the rest, so that they all make progress
+                }
+            }
+            if !all_known {
+                return Poll::Pending;
+            }
+            let tree = Tree::with_ordering(std::mem::take(inputs), ordering.take().unwrap());
+            *state = AsyncTreeState::Merging {
+                tree,
+                last_popped: None,
+            };
+        }
+        match state {
+            AsyncTreeState::Merging { tree, last_popped } => {
+                if let Some(input_index) = *last_popped {
+                    let input = tree.input_mut(input_index);
+                    input.key = Some(futures::ready!(input.input.poll_peek_key(cx)));
+                    *last_popped = None;
+                }
+                Poll::Ready(tree.pop().map(|popped| {
+                    *last_popped = Some(popped.input_index);
+                    popped
+                        .data
+                        .take()
+                        .expect("a popped item is taken exactly once")
+                }))
+            }
+            AsyncTreeState::Starting { .. } => unreachable!("every input's first key is known"),
         }
     }
 }
@@ -160,8 +1264,6 @@ mod tests {
             }
         }
     }
-    // TODO: rather than taking an "InputStream", just take an iterator which returns tuples of
-    // (Value,Data) as input to tournament_tree::Tree::new?
     impl<T: Iterator<Item = u64>> Mergeable for InputStream<T> {
         type Data = <T>::Item;
 
@@ -176,6 +1278,54 @@ mod tests {
             *self.iterator.peek().unwrap_or(&std::u64::MAX)
         }
     }
+    /// Input of `(timestamp, sequence number)` items, breaking ties in timestamp by sequence number
+    struct SequencedStream(
+        std::iter::Peekable<std::vec::IntoIter<(u64, u64)>>,
+        Option<(u64, u64)>,
+    );
+
+    impl Mergeable for SequencedStream {
+        type Data = (u64, u64);
+
+        fn pop(&mut self) -> Option<&(u64, u64)> {
+            self.1 = self.0.next();
+            self.1.as_ref()
+        }
+
+        fn peek_timestamp(&mut self) -> u64 {
+            self.0.peek().map_or(std::u64::MAX, |(ts, _sequence)| *ts)
+        }
+
+        fn peek_secondary(&mut self) -> u64 {
+            self.0.peek().map_or(0, |(_ts, sequence)| *sequence)
+        }
+    }
+
+    #[test]
+    fn ties_in_timestamp_are_broken_by_secondary_key() {
+        let inputs: Vec<Vec<(u64, u64)>> = vec![
+            vec![(1, 7), (2, 2), (3, 1)],
+            vec![(1, 3), (2, 4)],
+            vec![(1, 5), (3, 0)],
+            vec![(1, 1), (2, 6), (4, 0)],
+            vec![(2, 5)],
+        ];
+        let mut expected: Vec<(u64, u64)> = inputs.iter().flatten().copied().collect();
+        expected.sort();
+        let mut tree = Tree::new(
+            inputs
+                .into_iter()
+                .map(|items| SequencedStream(items.into_iter().peekable(), None))
+                .collect(),
+        );
+        let mut merged = Vec::new();
+        while let Some(item) = tree.pop() {
+            merged.push(*item);
+            tree.assert_invariants();
+        }
+        assert_eq!(merged, expected);
+    }
+
     #[test]
     fn produces_all_output() {
         let inputs = vec![InputStream::new(vec![1, 1, 2, 6, 8, 8, 9].into_iter())];
@@ -252,4 +1402,612 @@ mod tests {
         }
         assert!(tree.pop().is_none(), "Tree should be empty but isn't");
     }
+
+    #[test]
+    fn invariants_hold_after_each_pop() {
+        let inputs = vec![
+            InputStream::new(vec![4, 5, 7].into_iter()),
+            InputStream::new(vec![4, 5, 7].into_iter()),
+            InputStream::new(vec![2, 3, 5, 7].into_iter()),
+            InputStream::new(vec![4, 5, 7].into_iter()),
+            InputStream::new(vec![1, 1, 2, 6, 8, 8, 9].into_iter()),
+        ];
+
+        let mut tree = Tree::new(inputs);
+        tree.assert_invariants();
+        let mut n_popped = 0;
+        while tree.pop().is_some() {
+            tree.assert_invariants();
+            n_popped += 1;
+        }
+        tree.assert_invariants();
+        assert_eq!(n_popped, 20);
+        assert!(tree.dump().contains("winner: values["), "{}", tree.dump());
+    }
+
+    #[test]
+    #[should_panic(expected = "precedes it")]
+    fn assert_invariants_detects_a_stale_winner() {
+        let inputs = vec![
+            InputStream::new(vec![1, 3].into_iter()),
+            InputStream::new(vec![2, 4].into_iter()),
+        ];
+        let mut tree = Tree::new(inputs);
+        tree.values[1] = 0; // change a value without propagating it up the tree
+        tree.assert_invariants();
+    }
+
+    #[test]
+    fn keys_are_only_stored_for_inputs() {
+        // e.g. 1025 inputs take 2048 leaves, but only 1025 keys
+        for &n_inputs in &[0usize, 1, 2, 3, 5, 1024, 1025] {
+            let inputs = (0..n_inputs)
+                .map(|i| InputStream::new(vec![i as u64].into_iter()))
+                .collect();
+            let tree = Tree::new(inputs);
+            let n_leaf_nodes = n_inputs.max(1).next_power_of_two();
+            assert_eq!(tree.values.capacity(), n_inputs);
+            assert_eq!(tree.nodes.len(), n_leaf_nodes);
+            assert_eq!(tree.input_streams.capacity(), n_inputs);
+            tree.assert_invariants();
+        }
+    }
+
+    #[test]
+    fn merges_more_inputs_than_u16_can_index() {
+        const N_INPUTS: u64 = 70_000;
+        // listed in reverse, so that the winners come from the last leaves
+        let inputs = (0..N_INPUTS)
+            .rev()
+            .map(|i| InputStream::new(vec![i, N_INPUTS + i].into_iter()))
+            .collect();
+        let mut tree = Tree::new(inputs);
+        let mut expected = 0;
+        while let Some(popped) = tree.pop() {
+            assert_eq!(*popped, expected);
+            expected += 1;
+        }
+        assert_eq!(expected, 2 * N_INPUTS);
+    }
+
+    /// Input with no packets, for tests of the shape of a tree
+    #[derive(Debug)]
+    struct Exhausted;
+    impl Mergeable for Exhausted {
+        type Data = u64;
+
+        fn pop(&mut self) -> Option<&u64> {
+            None
+        }
+
+        fn peek_timestamp(&mut self) -> u64 {
+            std::u64::MAX
+        }
+    }
+
+    #[test]
+    fn too_many_inputs_is_a_descriptive_error() {
+        // the same check as for MAX_N_INPUTS, at a size whose inputs can be allocated
+        assert_eq!(n_leaf_nodes(0, 4), Ok(1));
+        assert_eq!(n_leaf_nodes(3, 4), Ok(4));
+        assert_eq!(n_leaf_nodes(4, 4), Ok(4));
+        assert_eq!(n_leaf_nodes(5, 4), Err(TooManyInputs { n_inputs: 5 }));
+        assert_eq!(
+            n_leaf_nodes(usize::MAX, u64::MAX),
+            Err(TooManyInputs {
+                n_inputs: usize::MAX
+            })
+        );
+        assert_eq!(
+            n_leaf_nodes(MAX_N_INPUTS as usize + 1, MAX_N_INPUTS)
+                .unwrap_err()
+                .to_string(),
+            "Can't merge 4294967297 inputs: a tournament tree merges at most 4294967296"
+        );
+
+        let tree = Tree::try_new(vec![Exhausted, Exhausted, Exhausted]).unwrap();
+        assert_eq!(tree.nodes.len(), 4);
+    }
+
+    #[test]
+    fn owned_tree_yields_the_inputs_bytes() {
+        let packets = |timestamps: &[u64]| -> Vec<(u64, Bytes)> {
+            timestamps
+                .iter()
+                .map(|ts| (*ts, Bytes::from(vec![*ts as u8; 8])))
+                .collect()
+        };
+        let (a, b) = (packets(&[1, 4, 5]), packets(&[2, 3, 6]));
+        let mut expected: Vec<_> = a.iter().chain(b.iter()).cloned().collect();
+        expected.sort_by_key(|(ts, _)| *ts);
+
+        let mut tree = OwnedTree::new(vec![
+            PacketStream::new(a.into_iter()),
+            PacketStream::new(b.into_iter()),
+        ]);
+        let mut merged = Vec::new();
+        while let Some(packet) = tree.pop() {
+            merged.push(packet);
+        }
+        assert_eq!(merged, expected);
+        // moved out of the inputs rather than copied
+        for ((_, merged), (_, expected)) in merged.iter().zip(&expected) {
+            assert_eq!(merged.as_ptr(), expected.as_ptr());
+        }
+        assert!(tree.pop().is_none());
+    }
+
+    #[test]
+    fn pop_with_index_names_each_items_input() {
+        let packets = |timestamps: &[u64]| -> Vec<(u64, Bytes)> {
+            timestamps.iter().map(|ts| (*ts, Bytes::new())).collect()
+        };
+        let mut tree = OwnedTree::new(vec![
+            PacketStream::new(packets(&[1, 4]).into_iter()),
+            PacketStream::new(packets(&[]).into_iter()),
+            PacketStream::new(packets(&[2, 3]).into_iter()),
+        ]);
+        let mut merged = Vec::new();
+        while let Some((index, (ts, _))) = tree.pop_with_index() {
+            merged.push((index, ts));
+        }
+        assert_eq!(merged, vec![(0, 1), (2, 2), (2, 3), (0, 4)]);
+    }
+
+    /// The index of the input of each item merged from weighted inputs of the given timestamps
+    fn weighted_merge(inputs: &[(&[u64], u32)], window_ns: u64) -> Vec<usize> {
+        let ties = WeightedTies::new(window_ns);
+        let mut tree = OwnedTree::new(
+            inputs
+                .iter()
+                .map(|(timestamps, weight)| {
+                    let packets: Vec<(u64, Bytes)> =
+                        timestamps.iter().map(|ts| (*ts, Bytes::new())).collect();
+                    ties.weighted(PacketStream::new(packets.into_iter()), *weight)
+                })
+                .collect(),
+        );
+        std::iter::from_fn(|| tree.pop_with_index().map(|(index, _)| index)).collect()
+    }
+
+    #[test]
+    fn tied_inputs_are_interleaved_by_weight() {
+        let tied = [5; 30];
+        let merged = weighted_merge(&[(&tied, 2), (&tied, 1)], 0);
+        // twice as many from the first input as from the second, until it runs out
+        for round in merged[..45].chunks(3) {
+            assert_eq!(round.iter().filter(|index| **index == 0).count(), 2);
+        }
+        assert_eq!(merged[45..], [1; 15]);
+
+        // without ties, timestamp order is kept
+        let merged = weighted_merge(&[(&[1, 2, 3, 4], 2), (&[5, 6], 1)], 0);
+        assert_eq!(merged, [0, 0, 0, 0, 1, 1]);
+    }
+
+    #[test]
+    fn weights_above_the_stride_are_taken_as_it() {
+        // rather than advancing by a stride of 0, and so winning every tie
+        let tied = [5; 10];
+        let merged = weighted_merge(&[(&tied, u32::MAX), (&tied, WEIGHT_STRIDE as u32)], 0);
+        for round in merged.chunks(2) {
+            assert_eq!(round.iter().filter(|index| **index == 0).count(), 1);
+        }
+    }
+
+    #[test]
+    fn near_ties_within_the_window_are_interleaved_by_weight() {
+        let merged = weighted_merge(&[(&[10, 11, 12, 13], 1), (&[14, 15, 16, 17, 20], 1)], 10);
+        for round in merged[..8].chunks(2) {
+            assert_eq!(round.iter().filter(|index| **index == 0).count(), 1);
+        }
+        assert_eq!(merged[8], 1);
+    }
+
+    /// [MergeableOwned] input counting how many of its kind are open at once
+    struct CountedOpen {
+        packets: PacketStream<std::vec::IntoIter<(u64, Bytes)>>,
+        n_open: Rc<Cell<usize>>,
+    }
+
+    impl MergeableOwned for CountedOpen {
+        type Data = (u64, Bytes);
+
+        fn pop(&mut self) -> Option<(u64, Bytes)> {
+            self.packets.pop()
+        }
+
+        fn peek_timestamp(&mut self) -> u64 {
+            self.packets.peek_timestamp()
+        }
+    }
+
+    impl Drop for CountedOpen {
+        fn drop(&mut self) {
+            self.n_open.set(self.n_open.get() - 1);
+        }
+    }
+
+    #[test]
+    fn active_set_merge_holds_a_bounded_number_of_inputs_open() {
+        // each input overlaps the three after it, and every tenth is empty
+        let inputs: Vec<(u64, Vec<u64>)> = (0..200u64)
+            .rev()
+            .map(|i| match i % 10 {
+                9 => (i * 10, Vec::new()),
+                _ => (i * 10, (0..8).map(|j| i * 10 + j * 5).collect()),
+            })
+            .collect();
+        let mut expected: Vec<u64> = inputs.iter().flat_map(|(_, ts)| ts.clone()).collect();
+        expected.sort_unstable();
+
+        let n_open = Rc::new(Cell::new(0));
+        let max_n_open = Rc::new(Cell::new(0));
+        let open = |timestamps: Vec<u64>| {
+            n_open.set(n_open.get() + 1);
+            max_n_open.set(max_n_open.get().max(n_open.get()));
+            let packets: Vec<(u64, Bytes)> = timestamps
+                .into_iter()
+                .map(|ts| (ts, Bytes::new()))
+                .collect();
+            CountedOpen {
+                packets: PacketStream::new(packets.into_iter()),
+                n_open: n_open.clone(),
+            }
+        };
+        let mut merger = ActiveSetMerge::new(inputs, 4, open);
+        let mut merged = Vec::new();
+        while let Some((ts, _)) = merger.pop() {
+            merged.push(ts);
+            assert!(merger.n_active() <= 4);
+        }
+        assert_eq!(merged, expected);
+        assert_eq!(max_n_open.get(), 4);
+        assert_eq!(n_open.get(), 0);
+
+        // an input whose first key precedes those of every open input is opened regardless
+        let inputs = vec![(0, vec![0, 100]), (10, vec![10, 100]), (20, vec![20, 30])];
+        let mut merger = ActiveSetMerge::new(inputs, 1, |timestamps: Vec<u64>| {
+            PacketStream::new(timestamps.into_iter().map(|ts| (ts, Bytes::new())))
+        });
+        let merged: Vec<u64> = std::iter::from_fn(|| merger.pop().map(|(ts, _)| ts)).collect();
+        assert_eq!(merged, [0, 10, 20, 30, 100, 100]);
+    }
+
+    #[test]
+    fn sidecar_keyed_packets_are_merged_and_restamped_by_their_sidecar_timestamps() {
+        let packets = |timestamps: &[u64]| -> Vec<(u64, Bytes)> {
+            timestamps
+                .iter()
+                .map(|ts| (*ts, Bytes::from(vec![*ts as u8])))
+                .collect()
+        };
+        // the sidecar puts the second input's packets between the first's, rather than before them all
+        let mut tree = OwnedTree::new(vec![
+            SidecarKeyed::new(
+                packets(&[7, 8, 9]).into_iter(),
+                vec![10, 30, 50].into_iter(),
+            ),
+            SidecarKeyed::new(packets(&[1, 2]).into_iter(), vec![20, 40].into_iter()),
+        ]);
+        let mut merged = Vec::new();
+        while let Some((ts, packet)) = tree.pop() {
+            merged.push((ts, packet[0]));
+        }
+        assert_eq!(merged, vec![(10, 7), (20, 1), (30, 8), (40, 2), (50, 9)]);
+
+        let mut more_packets =
+            SidecarKeyed::new(packets(&[1, 2, 3]).into_iter(), vec![10, 20].into_iter());
+        assert_eq!(more_packets.pop().map(|(ts, _)| ts), Some(10));
+        assert_eq!(more_packets.pop().map(|(ts, _)| ts), Some(20));
+        assert_eq!(more_packets.mismatch(), None);
+        assert_eq!(more_packets.peek_timestamp(), std::u64::MAX);
+        assert!(more_packets.pop().is_none());
+        assert_eq!(more_packets.mismatch(), Some(SidecarMismatch::MorePackets));
+
+        let mut more_timestamps =
+            SidecarKeyed::new(packets(&[1]).into_iter(), vec![10, 20].into_iter());
+        assert!(more_timestamps.pop().is_some());
+        assert!(more_timestamps.pop().is_none());
+        assert_eq!(
+            more_timestamps.mismatch(),
+            Some(SidecarMismatch::MoreTimestamps)
+        );
+    }
+
+    enum MixedInput {
+        Timestamp(PacketStream<std::vec::IntoIter<(u64, Bytes)>>),
+        Arrival(ArrivalOrdered<async_channel::Receiver<(u64, Bytes)>>),
+    }
+    impl MergeableOwned for MixedInput {
+        type Data = (u64, Bytes);
+
+        fn pop(&mut self) -> Option<(u64, Bytes)> {
+            match self {
+                MixedInput::Timestamp(input) => input.pop(),
+                MixedInput::Arrival(input) => input.pop(),
+            }
+        }
+
+        fn peek_timestamp(&mut self) -> u64 {
+            match self {
+                MixedInput::Timestamp(input) => input.peek_timestamp(),
+                MixedInput::Arrival(input) => input.peek_timestamp(),
+            }
+        }
+    }
+
+    #[test]
+    fn arrival_ordered_packets_are_merged_as_they_arrive() {
+        let timestamped: Vec<_> = (1..=5u64)
+            .map(|i| (i * 10, Bytes::from(vec![i as u8])))
+            .collect();
+        let (arrivals, arrival_receiver) = async_channel::unbounded();
+        let clock = MergeClock::default();
+        let mut tree = OwnedTree::new(vec![
+            MixedInput::Timestamp(PacketStream::new(timestamped.into_iter())),
+            MixedInput::Arrival(ArrivalOrdered::new(arrival_receiver, clock.clone())),
+        ]);
+        let pop = |tree: &mut OwnedTree<MixedInput>| {
+            tree.refresh(1);
+            let (ts, packet) = tree.pop()?;
+            clock.set(ts);
+            Some((ts, packet[0]))
+        };
+
+        assert_eq!(pop(&mut tree), Some((10, 1)));
+        assert_eq!(pop(&mut tree), Some((20, 2)));
+        arrivals.try_send((0, Bytes::from(vec![100]))).unwrap();
+        assert_eq!(pop(&mut tree), Some((20, 100))); // merged next, at the merge clock
+        assert_eq!(pop(&mut tree), Some((30, 3)));
+        assert_eq!(pop(&mut tree), Some((40, 4)));
+        arrivals.try_send((7, Bytes::from(vec![101]))).unwrap();
+        arrivals.try_send((3, Bytes::from(vec![102]))).unwrap();
+        assert_eq!(pop(&mut tree), Some((40, 101)));
+        assert_eq!(pop(&mut tree), Some((40, 102)));
+        assert_eq!(pop(&mut tree), Some((50, 5)));
+
+        // the timestamped input is exhausted, but the arrival-ordered input is still open
+        assert_eq!(pop(&mut tree), None);
+        match tree.input_mut(1) {
+            MixedInput::Arrival(input) => assert!(input.is_open()),
+            MixedInput::Timestamp(_) => unreachable!(),
+        }
+        arrivals.try_send((1, Bytes::from(vec![103]))).unwrap();
+        drop(arrivals);
+        assert_eq!(pop(&mut tree), Some((50, 103)));
+        assert_eq!(pop(&mut tree), None);
+        match tree.input_mut(1) {
+            MixedInput::Arrival(input) => assert!(!input.is_open()),
+            MixedInput::Timestamp(_) => unreachable!(),
+        }
+    }
+
+    /// Highest priority first, then in arrival order (lowest sequence number first) among equal priorities
+    struct HighestPriorityFirst;
+    impl KeyOrdering for HighestPriorityFirst {
+        type Key = (u32, u64); // (priority, sequence number)
+
+        fn precedes(&self, a: &(u32, u64), b: &(u32, u64)) -> bool {
+            a.0 > b.0 || (a.0 == b.0 && a.1 < b.1)
+        }
+
+        fn exhausted(&self) -> (u32, u64) {
+            (u32::MIN, u64::MAX)
+        }
+    }
+
+    struct PriorityStream {
+        iterator: std::iter::Peekable<std::vec::IntoIter<(u32, u64)>>,
+        current_value: Option<(u32, u64)>,
+    }
+    impl Mergeable<(u32, u64)> for PriorityStream {
+        type Data = (u32, u64);
+
+        fn pop(&mut self) -> Option<&(u32, u64)> {
+            self.current_value = self.iterator.next();
+            self.current_value.as_ref()
+        }
+
+        fn peek_timestamp(&mut self) -> (u32, u64) {
+            *self
+                .iterator
+                .peek()
+                .unwrap_or(&HighestPriorityFirst.exhausted())
+        }
+    }
+
+    #[test]
+    fn merges_by_custom_reverse_ordering() {
+        let inputs: Vec<_> = vec![
+            vec![(9, 2), (5, 0), (5, 7), (1, 3)],
+            vec![(9, 1), (7, 4), (1, 1)],
+            vec![(8, 5), (5, 6), (2, 8), (0, 9)],
+        ]
+        .into_iter()
+        .map(|keys| PriorityStream {
+            iterator: keys.into_iter().peekable(),
+            current_value: None,
+        })
+        .collect();
+
+        let mut tree = Tree::with_ordering(inputs, HighestPriorityFirst);
+        let mut popped = Vec::new();
+        while let Some(key) = tree.pop() {
+            popped.push(*key);
+        }
+        assert_eq!(
+            popped,
+            vec![
+                (9, 1),
+                (9, 2),
+                (8, 5),
+                (7, 4),
+                (5, 0),
+                (5, 6),
+                (5, 7),
+                (2, 8),
+                (1, 1),
+                (1, 3),
+                (0, 9)
+            ]
+        );
+    }
+
+    #[test]
+    fn merges_descending_u64s() {
+        let inputs = vec![
+            InputStream::new(vec![9, 7, 7, 2].into_iter()),
+            InputStream::new(vec![8, 7, 3, 1].into_iter()),
+        ];
+        // a descending input stream reports u64::MIN once exhausted
+        struct DescendingStream<T: Iterator<Item = u64>>(InputStream<T>);
+        impl<T: Iterator<Item = u64>> Mergeable for DescendingStream<T> {
+            type Data = u64;
+            fn pop(&mut self) -> Option<&u64> {
+                self.0.pop()
+            }
+            fn peek_timestamp(&mut self) -> u64 {
+                *self.0.iterator.peek().unwrap_or(&std::u64::MIN)
+            }
+        }
+
+        let mut tree = Tree::with_ordering(
+            inputs.into_iter().map(DescendingStream).collect(),
+            Descending,
+        );
+        let mut popped = Vec::new();
+        while let Some(value) = tree.pop() {
+            popped.push(*value);
+        }
+        assert_eq!(popped, vec![9, 8, 7, 7, 7, 3, 2, 1]);
+    }
+
+    #[test]
+    fn slice_streams_report_the_exhausted_key_of_their_ordering() {
+        let first = [(9, 'a'), (7, 'b'), (2, 'c')];
+        let second = [(8, 'd'), (5, 'e')];
+        let mut tree = Tree::with_ordering(
+            vec![
+                SliceStream::with_ordering(&first, &Descending),
+                SliceStream::with_ordering(&second, &Descending),
+            ],
+            Descending,
+        );
+        let mut popped = Vec::new();
+        while let Some((_key, data)) = tree.pop() {
+            popped.push(*data);
+        }
+        assert_eq!(popped, vec!['a', 'd', 'b', 'e', 'c']);
+    }
+
+    #[test]
+    fn async_tree_merges_inputs_as_they_become_ready() {
+        // each input is sent from its own thread at its own pace, so the tree often finds it isn't ready yet
+        let inputs: Vec<_> = (0..4u64)
+            .map(|input| {
+                let (sender, receiver) = async_channel::bounded(2);
+                std::thread::spawn(move || {
+                    for i in 0..50u64 {
+                        if i % (input + 2) == 0 {
+                            std::thread::sleep(std::time::Duration::from_millis(1));
+                        }
+                        smol::block_on(sender.send((i * 4 + input, input))).unwrap();
+                    }
+                });
+                AsyncStream::new(receiver)
+            })
+            .collect();
+
+        let merged: Vec<_> = smol::block_on(AsyncTree::new(inputs).collect());
+        assert_eq!(
+            merged,
+            (0..200u64).map(|ts| (ts, ts % 4)).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn an_input_of_one_repeated_timestamp_is_merged_in_full() {
+        // the winner keeps winning ties, so the repeated input is drained in a run once it's the minimum, while the
+        // other input's packet of the same timestamp may come before or after that run
+        for repeated_input in 0..2 {
+            let mut inputs = vec![
+                InputStream::new(vec![1, 3, 5, 7, 9].into_iter()),
+                InputStream::new(vec![5; 100].into_iter()),
+            ];
+            if repeated_input == 0 {
+                inputs.reverse();
+            }
+            let mut tree = Tree::new(inputs);
+            let mut merged = Vec::new();
+            while let Some(popped) = tree.pop() {
+                merged.push(*popped);
+                assert!(merged.len() <= 105, "the merge doesn't end");
+            }
+            assert!(tree.pop().is_none(), "Tree should stay empty");
+            let mut expected = vec![1, 3];
+            expected.extend(vec![5; 101]);
+            expected.extend(vec![7, 9]);
+            assert_eq!(merged, expected);
+            tree.assert_invariants();
+        }
+    }
+
+    #[test]
+    fn inputs_added_part_way_through_are_merged_from_then_on() {
+        let mut tree = Tree::new(Vec::new());
+        assert!(tree.pop().is_none());
+        tree.add_input(InputStream::new(vec![1, 4, 10, 12].into_iter()));
+        assert_eq!(tree.pop(), Some(&1));
+        // growing from one leaf to two, then to four, and filling the last of them
+        tree.add_input(InputStream::new(vec![2, 6].into_iter()));
+        tree.assert_invariants();
+        assert_eq!(tree.pop(), Some(&2));
+        tree.add_input(InputStream::new(vec![3, 11].into_iter()));
+        tree.assert_invariants();
+        assert_eq!(tree.pop(), Some(&3));
+        assert_eq!(tree.add_input(InputStream::new(vec![5].into_iter())), 3);
+        tree.assert_invariants();
+        let mut merged = Vec::new();
+        while let Some(popped) = tree.pop() {
+            merged.push(*popped);
+            if *popped == 6 {
+                // keys before those already merged come next
+                tree.add_input(InputStream::new(vec![0, 7].into_iter()));
+                tree.assert_invariants();
+            }
+        }
+        assert_eq!(merged, vec![4, 5, 6, 0, 7, 10, 11, 12]);
+    }
+
+    #[test]
+    fn current_key_is_the_key_of_the_next_pop() {
+        let inputs = vec![
+            InputStream::new(vec![2, 4, 9].into_iter()),
+            InputStream::new(vec![1, 5, 7].into_iter()),
+        ];
+        let frontier = MergeFrontier::default();
+        let mut tree = Tree::new(inputs).publish_current_key(frontier.clone());
+        assert_eq!(frontier.get(), 1);
+        let mut previous = None;
+        loop {
+            let current = *tree.current_key();
+            assert_eq!(frontier.get(), current);
+            match tree.pop() {
+                Some(popped) => assert_eq!(*popped, current),
+                None => break assert_eq!(current, std::u64::MAX),
+            }
+            assert!(previous < Some(current), "the frontier only advances");
+            previous = Some(current);
+        }
+    }
+
+    #[test]
+    fn async_tree_of_no_packets_ends() {
+        let inputs = vec![
+            AsyncStream::new(futures::stream::empty::<(u64, ())>()),
+            AsyncStream::new(futures::stream::empty()),
+        ];
+        assert!(smol::block_on(AsyncTree::new(inputs).collect::<Vec<_>>()).is_empty());
+    }
 }
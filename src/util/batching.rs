@@ -0,0 +1,137 @@
+use super::MemoryBudget;
+use core::pin::Pin;
+use futures::stream::{Fuse, Stream, StreamExt};
+use futures::task::{Context, Poll};
+use pin_project_lite::pin_project;
+
+/// Most items batched together while there's no memory pressure: that of the fixed `ready_chunks` batching it replaces
+// TODO: validate the choice of 2048? is a smaller number like 1024 any better? trade off between parallelism and memory
+pub(crate) const MAX_BATCH_SIZE: usize = 2048;
+
+/// Fewest items a batch is shrunk to under memory pressure, below which batching stops paying for its channel sends
+pub(crate) const MIN_BATCH_SIZE: usize = 32;
+
+/// Target size of the batches of a [ReadyBatches], halved each batch while the bytes reserved from a [MemoryBudget]
+/// exceed three quarters of its limit, so that less is held in flight, and doubled again once they fall below half.
+/// Without a budget, the target stays at [MAX_BATCH_SIZE].
+#[derive(Debug)]
+pub(crate) struct BatchSize {
+    budget: Option<MemoryBudget>,
+    target: usize,
+}
+
+impl BatchSize {
+    pub(crate) fn new(budget: Option<MemoryBudget>) -> BatchSize {
+        BatchSize {
+            budget,
+            target: MAX_BATCH_SIZE,
+        }
+    }
+
+    pub(crate) fn target(&self) -> usize {
+        self.target
+    }
+
+    /// Shrink or grow the target for the next batch according to the budget's current pressure
+    pub(crate) fn adapt(&mut self) {
+        let budget = match &self.budget {
+            Some(budget) => budget,
+            None => return,
+        };
+        let (reserved, limit) = (budget.n_bytes_reserved(), budget.limit_n_bytes());
+        if reserved > limit / 4 * 3 {
+            self.target = (self.target / 2).max(MIN_BATCH_SIZE);
+        } else if reserved < limit / 2 {
+            self.target = (self.target * 2).min(MAX_BATCH_SIZE);
+        }
+    }
+}
+
+pin_project! {
+    /// Like [StreamExt::ready_chunks], batching the items of `stream` which are ready into vectors of at most the
+    /// [BatchSize] target, which adapts between batches to the pressure on a [MemoryBudget]
+    #[must_use = "streams do nothing unless polled"]
+    pub(crate) struct ReadyBatches<St: Stream> {
+        #[pin]
+        stream: Fuse<St>,
+        items: Vec<St::Item>,
+        batch_size: BatchSize,
+    }
+}
+
+impl<St: Stream> ReadyBatches<St> {
+    pub(crate) fn new(stream: St, batch_size: BatchSize) -> ReadyBatches<St> {
+        ReadyBatches {
+            stream: stream.fuse(),
+            items: Vec::new(),
+            batch_size,
+        }
+    }
+
+    fn take_batch(items: &mut Vec<St::Item>, batch_size: &mut BatchSize) -> Vec<St::Item> {
+        batch_size.adapt();
+        std::mem::take(items)
+    }
+}
+
+impl<St: Stream> Stream for ReadyBatches<St> {
+    type Item = Vec<St::Item>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        loop {
+            match this.stream.as_mut().poll_next(cx) {
+                Poll::Ready(Some(item)) => {
+                    if this.items.is_empty() {
+                        this.items.reserve(this.batch_size.target());
+                    }
+                    this.items.push(item);
+                    if this.items.len() >= this.batch_size.target() {
+                        return Poll::Ready(Some(Self::take_batch(this.items, this.batch_size)));
+                    }
+                }
+                Poll::Ready(None) if this.items.is_empty() => return Poll::Ready(None),
+                Poll::Pending if this.items.is_empty() => return Poll::Pending,
+                // send what's ready rather than wait for more
+                Poll::Ready(None) | Poll::Pending => {
+                    return Poll::Ready(Some(Self::take_batch(this.items, this.batch_size)))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batches_shrink_under_memory_pressure_and_grow_back_as_it_eases() {
+        let budget = MemoryBudget::new(1000);
+        let packets = futures::stream::iter(0..u64::MAX);
+        let mut batches = ReadyBatches::new(packets, BatchSize::new(Some(budget.clone())));
+        let mut next_batch_len = || smol::block_on(batches.next()).unwrap().len();
+
+        // packets read ahead for other files press on the budget
+        let pressure = budget.reserve(900).unwrap();
+        let under_pressure: Vec<usize> = (0..8).map(|_| next_batch_len()).collect();
+        assert_eq!(under_pressure, vec![2048, 1024, 512, 256, 128, 64, 32, 32]);
+
+        drop(pressure);
+        let easing: Vec<usize> = (0..8).map(|_| next_batch_len()).collect();
+        assert_eq!(easing, vec![32, 64, 128, 256, 512, 1024, 2048, 2048]);
+
+        // between half and three quarters of the budget, the target holds
+        let pressure = budget.reserve(600).unwrap();
+        assert_eq!(next_batch_len(), 2048);
+        drop(pressure);
+    }
+
+    #[test]
+    fn without_a_budget_batches_stay_at_their_largest() {
+        let packets = futures::stream::iter(0..5000);
+        let batches = ReadyBatches::new(packets, BatchSize::new(None));
+        let sizes: Vec<usize> = smol::block_on(batches.map(|batch| batch.len()).collect());
+        assert_eq!(sizes, vec![2048, 2048, 904]);
+    }
+}
@@ -0,0 +1,141 @@
+use super::{Clock, SystemClock};
+use core::pin::Pin;
+use futures::future::{BoxFuture, FutureExt};
+use futures::stream::Stream;
+use futures::task::{Context, Poll};
+use pin_project_lite::pin_project;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A wall-clock budget for a merge. Once it has passed, streams [bounded](Deadline::bound) by it end rather than wait
+/// on a slow input any longer, so that the merge can stop between packets and flush what it has written. Whether it
+/// has passed is checked with a single atomic load, so may be checked for every packet. Cheap to clone.
+#[derive(Clone)]
+pub struct Deadline(Arc<DeadlineInner>);
+
+struct DeadlineInner {
+    clock: Arc<dyn Clock>,
+    at: Duration,
+    passed: AtomicBool,
+    cut_short: AtomicBool,
+}
+
+impl DeadlineInner {
+    fn pass(&self) {
+        self.passed.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Deadline {
+    /// A deadline `budget` from now
+    pub fn new(budget: Duration) -> Deadline {
+        Deadline::with_clock(budget, Arc::new(SystemClock::default()))
+    }
+
+    /// A deadline `budget` from now by `clock`. One with no budget has passed from the start.
+    pub fn with_clock(budget: Duration, clock: Arc<dyn Clock>) -> Deadline {
+        let deadline = Deadline(Arc::new(DeadlineInner {
+            at: clock.now() + budget,
+            clock,
+            passed: AtomicBool::new(budget.is_zero()),
+            cut_short: AtomicBool::new(false),
+        }));
+        if !budget.is_zero() {
+            let inner = deadline.0.clone();
+            smol::spawn(inner.clock.sleep(budget).map(move |()| inner.pass())).detach();
+        }
+        deadline
+    }
+
+    /// Whether the deadline has passed
+    pub fn expired(&self) -> bool {
+        self.0.passed.load(Ordering::Relaxed)
+    }
+
+    /// Whether the deadline has ended any [bounded](Deadline::bound) stream before the stream itself ended
+    pub fn cut_short(&self) -> bool {
+        self.0.cut_short.load(Ordering::Relaxed)
+    }
+
+    /// `stream`, ending early if it's still waiting for its next item once the deadline has passed
+    pub fn bound<St: Stream>(&self, stream: St) -> Bounded<St> {
+        Bounded {
+            stream,
+            deadline: self.clone(),
+            timer: None,
+            ended: false,
+        }
+    }
+}
+
+pin_project! {
+    /// Stream returned by [Deadline::bound]
+    #[must_use = "streams do nothing unless polled"]
+    pub struct Bounded<St> {
+        #[pin]
+        stream: St,
+        deadline: Deadline,
+        // only started once the stream first has to be waited on
+        timer: Option<BoxFuture<'static, ()>>,
+        ended: bool,
+    }
+}
+
+impl<St: Stream> Stream for Bounded<St> {
+    type Item = St::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<St::Item>> {
+        let this = self.project();
+        if *this.ended {
+            return Poll::Ready(None);
+        }
+        if let Poll::Ready(item) = this.stream.poll_next(cx) {
+            *this.ended = item.is_none();
+            return Poll::Ready(item);
+        }
+        let deadline = &this.deadline.0;
+        let timer = this.timer.get_or_insert_with(|| {
+            let remaining = deadline.at.saturating_sub(deadline.clock.now());
+            deadline.clock.sleep(remaining)
+        });
+        match timer.poll_unpin(cx) {
+            Poll::Ready(()) => {
+                deadline.pass();
+                deadline.cut_short.store(true, Ordering::Relaxed);
+                *this.ended = true;
+                Poll::Ready(None)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::SimulatedClock;
+    use super::*;
+    use futures::stream::StreamExt;
+
+    #[test]
+    fn a_stream_waited_on_past_the_deadline_ends() {
+        let clock = Arc::new(SimulatedClock::default());
+        let deadline = Deadline::with_clock(Duration::from_secs(10), clock.clone());
+        let slow = futures::stream::iter(0..3).chain(futures::stream::pending());
+        assert_eq!(
+            smol::block_on(deadline.bound(slow).collect::<Vec<_>>()),
+            vec![0, 1, 2]
+        );
+        assert!(deadline.expired() && deadline.cut_short());
+        assert!(clock.now() >= Duration::from_secs(10));
+    }
+
+    #[test]
+    fn a_stream_which_never_waits_is_read_in_full() {
+        let clock = Arc::new(SimulatedClock::default());
+        let deadline = Deadline::with_clock(Duration::from_secs(10), clock);
+        let ready = futures::stream::iter(0..1000);
+        assert_eq!(smol::block_on(deadline.bound(ready).count()), 1000);
+        assert!(!deadline.cut_short());
+    }
+}
@@ -1,10 +1,26 @@
 use core::pin::Pin;
-use futures::future::Future;
-use futures::stream::{Fuse, FuturesOrdered, Stream, StreamExt};
-use futures::task::{Context, Poll};
+use futures::future::{CatchUnwind, Future, FutureExt};
+use futures::stream::{Fuse, FuturesOrdered, Map, Stream, StreamExt};
+use futures::task::{Context, Poll, Waker};
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
 
 use pin_project_lite::pin_project;
 
+mod batching;
+mod deadline;
+mod open_files;
+mod rate_limit;
+pub(crate) use batching::{BatchSize, ReadyBatches};
+pub use deadline::{Bounded, Deadline};
+pub use open_files::OpenFileLimit;
+pub(crate) use open_files::{LazyFile, LazyFileCloser};
+#[cfg(test)]
+pub(crate) use rate_limit::SimulatedClock;
+pub use rate_limit::{Clock, RateLimiter, SystemClock};
+
 pin_project! {
     /// [Stream] combinator structure which applies the same buffering scheme as [futures::stream::Buffered],
     /// but waits to spawn any concurrent futures (i.e. push new futures into the [FuturesOrdered] `in_progress_queue`)
@@ -30,6 +46,9 @@ pin_project! {
         in_progress_queue: FuturesOrdered<St::Item>,
         take_n_serially: usize,
         max_n_buffered: usize,
+        memory_budget: Option<MemoryBudget>,
+        item_n_bytes: usize,
+        reservations: Vec<Reservation>, // one per read-ahead future in the in_progress_queue
     }
 }
 
@@ -44,8 +63,49 @@ where
             in_progress_queue: FuturesOrdered::new(),
             take_n_serially,
             max_n_buffered,
+            memory_budget: None,
+            item_n_bytes: 0,
+            reservations: Vec::new(),
         }
     }
+
+    /// Charge `item_n_bytes` against the shared `memory_budget` for every read-ahead future, i.e. every future spawned
+    /// while another is already in progress. Read-ahead stops whenever the budget is exhausted and resumes once memory is
+    /// released by any stream sharing the budget. A stream with nothing in progress may always spawn a single future so
+    /// that every stream can make progress even when the budget is held by other streams.
+    pub(super) fn with_memory_budget(
+        mut self,
+        memory_budget: Option<MemoryBudget>,
+        item_n_bytes: usize,
+    ) -> Self {
+        self.memory_budget = memory_budget;
+        self.item_n_bytes = item_n_bytes;
+        self
+    }
+}
+
+/// Future wrapper used by [TakeThenBuffered::catch_panics] which resolves to `Err(panic_payload)` if the wrapped future panics.
+pub(super) type CatchPanic<Fut> = CatchUnwind<AssertUnwindSafe<Fut>>;
+
+fn catch_panic<Fut: Future>(future: Fut) -> CatchPanic<Fut> {
+    AssertUnwindSafe(future).catch_unwind()
+}
+
+impl<St> TakeThenBuffered<Map<St, fn(St::Item) -> CatchPanic<St::Item>>>
+where
+    St: Stream,
+    St::Item: Future,
+{
+    /// Construct a [TakeThenBuffered] in "catch panics" mode. A panic within one of the buffered futures is caught
+    /// and yielded as an `Err` item holding the panic payload instead of unwinding through `poll_next`, so one bad
+    /// object chunk does not take down the whole merge. Futures queued behind the panicking one still complete.
+    pub(super) fn catch_panics(stream: St, take_n_serially: usize, max_n_buffered: usize) -> Self {
+        TakeThenBuffered::new(
+            stream.map(catch_panic as fn(St::Item) -> CatchPanic<St::Item>),
+            take_n_serially,
+            max_n_buffered,
+        )
+    }
 }
 
 impl<St> Stream for TakeThenBuffered<St>
@@ -67,19 +127,33 @@ where
                 *this.max_n_buffered
             })
         {
+            let reservation = match this.memory_budget {
+                Some(budget) if !this.in_progress_queue.is_empty() => {
+                    match budget.try_reserve(*this.item_n_bytes, cx.waker()) {
+                        Some(reservation) => Some(reservation),
+                        None => break, // we'll be woken once another stream releases some memory
+                    }
+                }
+                _ => None,
+            };
             match this.stream.as_mut().poll_next(cx) {
                 Poll::Ready(Some(fut)) => {
                     this.in_progress_queue.push(fut);
+                    this.reservations.extend(reservation);
                     *this.take_n_serially = this.take_n_serially.saturating_sub(1);
                 }
-                Poll::Ready(None) | Poll::Pending => break,
+                Poll::Ready(None) | Poll::Pending => break, // dropping any reservation releases it
             }
         }
 
         // Attempt to pull the next value from the in_progress_queue
         match this.in_progress_queue.poll_next_unpin(cx) {
             Poll::Pending => return Poll::Pending,
-            x @ Poll::Ready(Some(_)) => return x,
+            x @ Poll::Ready(Some(_)) => {
+                // the next queued future (if any) is now the one being waited on rather than read-ahead
+                this.reservations.pop();
+                return x;
+            }
             Poll::Ready(None) => {}
         }
 
@@ -92,6 +166,322 @@ where
     }
 }
 
+/// A limit on the number of bytes which may be reserved at once, shared between any number of streams.
+#[derive(Clone, Debug)]
+pub struct MemoryBudget {
+    inner: Arc<MemoryBudgetInner>,
+}
+
+#[derive(Debug)]
+struct MemoryBudgetInner {
+    limit_n_bytes: usize,
+    n_bytes_reserved: AtomicUsize,
+    waiting: Mutex<Vec<Waker>>,
+}
+
+impl MemoryBudget {
+    pub fn new(limit_n_bytes: usize) -> MemoryBudget {
+        MemoryBudget {
+            inner: Arc::new(MemoryBudgetInner {
+                limit_n_bytes,
+                n_bytes_reserved: AtomicUsize::new(0),
+                waiting: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    pub fn limit_n_bytes(&self) -> usize {
+        self.inner.limit_n_bytes
+    }
+
+    /// Number of bytes currently reserved by all holders of this budget
+    pub fn n_bytes_reserved(&self) -> usize {
+        self.inner.n_bytes_reserved.load(Ordering::Acquire)
+    }
+
+    /// Reserve `n_bytes`, or register `waker` to be woken the next time memory is released if the budget can't cover them
+    fn try_reserve(&self, n_bytes: usize, waker: &Waker) -> Option<Reservation> {
+        if let Some(reservation) = self.reserve(n_bytes) {
+            return Some(reservation);
+        }
+        {
+            let mut waiting = self.inner.waiting.lock().unwrap();
+            if !waiting.iter().any(|waiting| waiting.will_wake(waker)) {
+                waiting.push(waker.clone());
+            }
+        }
+        // memory may have been released between our first attempt and registering the waker
+        self.reserve(n_bytes)
+    }
+
+    fn reserve(&self, n_bytes: usize) -> Option<Reservation> {
+        let n_bytes_reserved = &self.inner.n_bytes_reserved;
+        let mut current = n_bytes_reserved.load(Ordering::Acquire);
+        loop {
+            if current + n_bytes > self.inner.limit_n_bytes {
+                return None;
+            }
+            match n_bytes_reserved.compare_exchange_weak(
+                current,
+                current + n_bytes,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => {
+                    return Some(Reservation {
+                        budget: self.clone(),
+                        n_bytes,
+                    })
+                }
+                Err(actual) => current = actual,
+            }
+        }
+    }
+
+    fn release(&self, n_bytes: usize) {
+        self.inner
+            .n_bytes_reserved
+            .fetch_sub(n_bytes, Ordering::AcqRel);
+        let waiting = std::mem::take(&mut *self.inner.waiting.lock().unwrap());
+        for waker in waiting {
+            waker.wake();
+        }
+    }
+}
+
+/// Bytes reserved from a [MemoryBudget]. Released when dropped.
+#[derive(Debug)]
+struct Reservation {
+    budget: MemoryBudget,
+    n_bytes: usize,
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.budget.release(self.n_bytes);
+    }
+}
+
+/// A dedicated pool of threads on which each file's download, decompression and parsing task can run, rather than
+/// competing for the global `smol` executor's `SMOL_THREADS` workers. Useful for CPU-bound merges of many files. Cheap
+/// to clone: clones share the same threads, which exit once the last clone is dropped.
+#[derive(Clone)]
+pub struct DecodePool(Arc<DecodePoolInner>);
+
+struct DecodePoolInner {
+    executor: Arc<smol::Executor<'static>>,
+    n_threads: usize,
+    _shutdown: async_channel::Sender<()>, // dropping the sender closes the channel, stopping every pool thread
+}
+
+impl DecodePool {
+    pub fn new(n_threads: usize) -> DecodePool {
+        let executor = Arc::new(smol::Executor::new());
+        let (shutdown, stopped) = async_channel::bounded::<()>(1);
+        for i in 0..n_threads {
+            let executor = executor.clone();
+            let stopped = stopped.clone();
+            std::thread::Builder::new()
+                .name(format!("stream-merge-decode-{}", i))
+                .spawn(move || smol::block_on(executor.run(stopped.recv())))
+                .expect("failed to spawn decode thread");
+        }
+        DecodePool(Arc::new(DecodePoolInner {
+            executor,
+            n_threads,
+            _shutdown: shutdown,
+        }))
+    }
+
+    pub fn n_threads(&self) -> usize {
+        self.0.n_threads
+    }
+
+    pub(super) fn spawn<T: Send + 'static>(
+        &self,
+        future: impl Future<Output = T> + Send + 'static,
+    ) -> smol::Task<T> {
+        self.0.executor.spawn(future)
+    }
+}
+
+impl std::fmt::Debug for DecodePool {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("DecodePool")
+            .field("n_threads", &self.0.n_threads)
+            .finish()
+    }
+}
+
+pin_project! {
+    /// [Stream] combinator which counts the items delivered from `stream` and, via its [AbandonGuard], logs a `WARN`
+    /// if it is dropped before `stream` has ended. Dropping a file's packet stream part way through a merge would
+    /// otherwise silently truncate the output.
+    #[must_use = "streams do nothing unless polled"]
+    pub(super) struct WarnIfAbandoned<St> {
+        #[pin]
+        stream: St,
+        guard: AbandonGuard,
+    }
+}
+
+impl<St: Stream> WarnIfAbandoned<St> {
+    pub(super) fn new(stream: St, path: String) -> Self {
+        Self {
+            stream,
+            guard: AbandonGuard {
+                path,
+                n_items_delivered: 0,
+                finished: false,
+                disarmed: Arc::new(AtomicBool::new(false)),
+            },
+        }
+    }
+
+    /// A handle with which to disarm the warning, for a stream the merge means to stop reading before its end
+    pub(super) fn disarm_handle(&self) -> DisarmHandle {
+        DisarmHandle(self.guard.disarmed.clone())
+    }
+}
+
+/// Disarms the warning of a [WarnIfAbandoned] stream, from [WarnIfAbandoned::disarm_handle]
+#[derive(Clone, Debug)]
+pub(super) struct DisarmHandle(Arc<AtomicBool>);
+
+impl DisarmHandle {
+    /// Don't warn when the stream is dropped before its end, which is intended
+    pub(super) fn disarm(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+impl<St: Stream> Stream for WarnIfAbandoned<St> {
+    type Item = St::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        let item = futures::ready!(this.stream.poll_next(cx));
+        match item {
+            Some(_) => this.guard.n_items_delivered += 1,
+            None => this.guard.finished = true,
+        }
+        Poll::Ready(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.stream.size_hint()
+    }
+}
+
+/// Records the progress of a [WarnIfAbandoned] stream so that its [Drop] can report a merge abandoned before EOF
+struct AbandonGuard {
+    path: String,
+    n_items_delivered: u64,
+    finished: bool,
+    disarmed: Arc<AtomicBool>,
+}
+
+impl Drop for AbandonGuard {
+    fn drop(&mut self) {
+        if !self.finished && !self.disarmed.load(Ordering::Relaxed) {
+            tracing::warn!(
+                path = self.path.as_str(),
+                n_packets_delivered = self.n_items_delivered,
+                "Packet stream dropped before reaching the end of the file; merged output is incomplete"
+            );
+        }
+    }
+}
+
+/// Logs a `WARN` with the last merged timestamp each time `threshold` passes without a packet being [observed](IdleWatchdog::observe),
+/// so that a live merge whose inputs have all gone quiet reports it rather than stalling silently. Idleness is checked on
+/// a background thread, which stops when the watchdog is dropped.
+pub struct IdleWatchdog {
+    progress: Arc<IdleProgress>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+struct IdleProgress {
+    threshold: Duration,
+    n_packets: AtomicU64,
+    last_ts: AtomicU64,
+    stopped: Mutex<bool>,
+    stop: Condvar,
+}
+
+impl IdleWatchdog {
+    pub fn start(threshold: Duration) -> IdleWatchdog {
+        let progress = Arc::new(IdleProgress {
+            threshold,
+            n_packets: AtomicU64::new(0),
+            last_ts: AtomicU64::new(0),
+            stopped: Mutex::new(false),
+            stop: Condvar::new(),
+        });
+        // log through the subscriber of the thread starting the watchdog rather than only the global default
+        let dispatch = tracing::dispatcher::get_default(|dispatch| dispatch.clone());
+        let thread = std::thread::Builder::new()
+            .name("stream-merge-idle-watchdog".to_string())
+            .spawn({
+                let progress = progress.clone();
+                move || tracing::dispatcher::with_default(&dispatch, || progress.watch())
+            })
+            .expect("failed to spawn idle watchdog thread");
+        IdleWatchdog {
+            progress,
+            thread: Some(thread),
+        }
+    }
+
+    /// Record that the merge produced a packet with timestamp `ts`
+    pub fn observe(&self, ts: u64) {
+        self.progress.last_ts.store(ts, Ordering::Relaxed);
+        self.progress.n_packets.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl IdleProgress {
+    fn watch(&self) {
+        // checking a few times per threshold keeps observe() to a pair of atomic writes rather than reading the clock
+        let check_interval = (self.threshold / 4).max(Duration::from_millis(1));
+        let mut n_packets_seen = 0;
+        let mut idle_since = Instant::now();
+        let mut last_warning: Option<Instant> = None;
+        let mut stopped = self.stopped.lock().unwrap();
+        while !*stopped {
+            stopped = self.stop.wait_timeout(stopped, check_interval).unwrap().0;
+            let now = Instant::now();
+            let n_packets = self.n_packets.load(Ordering::Relaxed);
+            if n_packets != n_packets_seen {
+                n_packets_seen = n_packets;
+                idle_since = now;
+                last_warning = None;
+            } else if now - idle_since >= self.threshold
+                && last_warning.is_none_or(|warned| now - warned >= self.threshold)
+            {
+                let last_ts = Some(self.last_ts.load(Ordering::Relaxed)).filter(|_| n_packets > 0);
+                tracing::warn!(
+                    idle_ms = (now - idle_since).as_millis() as u64,
+                    ?last_ts,
+                    n_packets_merged = n_packets,
+                    "No packets merged while input streams are still open"
+                );
+                last_warning = Some(now);
+            }
+        }
+    }
+}
+
+impl Drop for IdleWatchdog {
+    fn drop(&mut self) {
+        *self.progress.stopped.lock().unwrap() = true;
+        self.progress.stop.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     // Note this useful idiom: importing names from outer (for mod tests) scope.
@@ -138,4 +528,197 @@ mod tests {
 
         futures_test::assert_stream_done!(stream);
     }
+
+    #[test]
+    fn test_catch_panics_reports_panicking_future_as_error_item() {
+        let stream = TakeThenBuffered::catch_panics(
+            futures::stream::iter(vec![1, 2, 3, 4]).map(|i| {
+                async move {
+                    if i == 2 {
+                        panic!("chunk {} failed to download", i);
+                    }
+                    i
+                }
+                .boxed()
+            }),
+            1,
+            4,
+        );
+
+        let results = futures::executor::block_on(stream.collect::<Vec<_>>());
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[0].as_ref().ok(), Some(&1));
+        let panic_message = results[1]
+            .as_ref()
+            .expect_err("the panicking future should be reported as an error item")
+            .downcast_ref::<String>()
+            .expect("panic payload should be the formatted panic message");
+        assert_eq!(panic_message, "chunk 2 failed to download");
+        // the futures buffered behind the panicking one still complete
+        assert_eq!(results[2].as_ref().ok(), Some(&3));
+        assert_eq!(results[3].as_ref().ok(), Some(&4));
+    }
+
+    #[test]
+    fn test_read_ahead_never_exceeds_shared_memory_budget() {
+        const ITEM_N_BYTES: usize = 10;
+        const N_ITEMS_PER_STREAM: usize = 100;
+        let budget = MemoryBudget::new(20 * ITEM_N_BYTES);
+        let mut streams: Vec<_> = (0..50)
+            .map(|_| {
+                TakeThenBuffered::new(
+                    futures::stream::iter((0..N_ITEMS_PER_STREAM).map(futures::future::ready)),
+                    0,
+                    8,
+                )
+                .with_memory_budget(Some(budget.clone()), ITEM_N_BYTES)
+            })
+            .collect();
+
+        // round-robin over the streams, as a merge over many concurrently active files would
+        let mut cx = futures_test::task::noop_context();
+        let mut next_expected = vec![0; streams.len()];
+        let mut max_n_bytes_reserved = 0;
+        while next_expected.iter().any(|&next| next < N_ITEMS_PER_STREAM) {
+            for (stream, next) in streams.iter_mut().zip(next_expected.iter_mut()) {
+                if let Poll::Ready(Some(item)) = stream.poll_next_unpin(&mut cx) {
+                    assert_eq!(item, *next);
+                    *next += 1;
+                }
+                assert!(budget.n_bytes_reserved() <= budget.limit_n_bytes());
+                max_n_bytes_reserved = max_n_bytes_reserved.max(budget.n_bytes_reserved());
+            }
+        }
+        assert!(max_n_bytes_reserved > 0, "streams should read ahead");
+
+        drop(streams);
+        assert_eq!(budget.n_bytes_reserved(), 0);
+    }
+
+    /// Collects the fields of every `WARN` event emitted while it is the default subscriber
+    #[derive(Clone, Default)]
+    struct WarningCollector {
+        warnings: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for WarningCollector {
+        fn on_event(
+            &self,
+            event: &tracing::Event<'_>,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            struct Fields(String);
+            impl tracing::field::Visit for Fields {
+                fn record_debug(
+                    &mut self,
+                    field: &tracing::field::Field,
+                    value: &dyn std::fmt::Debug,
+                ) {
+                    self.0 += &format!("{}={:?} ", field.name(), value);
+                }
+            }
+            if *event.metadata().level() == tracing::Level::WARN {
+                let mut fields = Fields(String::new());
+                event.record(&mut fields);
+                self.warnings.lock().unwrap().push(fields.0);
+            }
+        }
+    }
+
+    fn collect_warnings(f: impl FnOnce()) -> Vec<String> {
+        use tracing_subscriber::layer::SubscriberExt;
+        let collector = WarningCollector::default();
+        let subscriber = tracing_subscriber::registry().with(collector.clone());
+        tracing::subscriber::with_default(subscriber, f);
+        let warnings = collector.warnings.lock().unwrap().clone();
+        warnings
+    }
+
+    #[test]
+    fn test_idle_watchdog_warns_when_merge_pauses() {
+        let threshold = Duration::from_millis(50);
+        // a stream of packets which goes quiet for a while after its third packet
+        let pausing_stream = futures::stream::iter(1..=5u64).then(|ts| async move {
+            if ts == 4 {
+                smol::Timer::after(threshold * 4).await;
+            }
+            ts
+        });
+        let warnings = collect_warnings(|| {
+            let watchdog = IdleWatchdog::start(threshold);
+            for ts in smol::stream::block_on(Box::pin(pausing_stream)) {
+                watchdog.observe(ts);
+            }
+        });
+
+        assert!(!warnings.is_empty());
+        assert!(warnings[0].contains("last_ts=Some(3)"), "{}", warnings[0]);
+        assert!(
+            warnings[0].contains("n_packets_merged=3"),
+            "{}",
+            warnings[0]
+        );
+    }
+
+    #[test]
+    fn test_idle_watchdog_is_quiet_while_packets_flow() {
+        let warnings = collect_warnings(|| {
+            let watchdog = IdleWatchdog::start(Duration::from_secs(60));
+            for ts in 0..1000 {
+                watchdog.observe(ts);
+            }
+        });
+        assert!(warnings.is_empty(), "{:?}", warnings);
+    }
+
+    #[test]
+    fn test_decode_pool_runs_tasks_on_its_own_threads() {
+        let pool = DecodePool::new(2);
+        let thread_name =
+            smol::block_on(pool.spawn(async { std::thread::current().name().map(str::to_string) }));
+        assert!(thread_name.unwrap().starts_with("stream-merge-decode-"));
+    }
+
+    #[test]
+    fn test_dropping_stream_before_end_warns_with_n_packets_delivered() {
+        let warnings = collect_warnings(|| {
+            let mut stream =
+                WarnIfAbandoned::new(futures::stream::iter(1..=5), "a.pcap".to_string());
+            futures_test::assert_stream_next!(stream, 1);
+            futures_test::assert_stream_next!(stream, 2);
+        });
+
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains(r#"path="a.pcap""#), "{}", warnings[0]);
+        assert!(
+            warnings[0].contains("n_packets_delivered=2"),
+            "{}",
+            warnings[0]
+        );
+    }
+
+    #[test]
+    fn test_dropping_stream_after_end_does_not_warn() {
+        let warnings = collect_warnings(|| {
+            let mut stream =
+                WarnIfAbandoned::new(futures::stream::iter(1..=2), "a.pcap".to_string());
+            futures_test::assert_stream_next!(stream, 1);
+            futures_test::assert_stream_next!(stream, 2);
+            futures_test::assert_stream_done!(stream);
+        });
+
+        assert!(warnings.is_empty(), "{:?}", warnings);
+    }
+
+    #[test]
+    fn test_dropping_disarmed_stream_before_end_does_not_warn() {
+        let warnings = collect_warnings(|| {
+            let mut stream =
+                WarnIfAbandoned::new(futures::stream::iter(1..=5), "a.pcap".to_string());
+            futures_test::assert_stream_next!(stream, 1);
+            stream.disarm_handle().disarm();
+        });
+
+        assert!(warnings.is_empty(), "{:?}", warnings);
+    }
 }
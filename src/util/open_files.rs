@@ -0,0 +1,222 @@
+use futures::future::{BoxFuture, FutureExt};
+use futures::io::AsyncRead;
+use futures::task::{Context, Poll};
+use std::io::{self, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// A limit on the number of local files which may be open at once, shared between any number of streams. Cheap to
+/// clone: clones share the same limit.
+#[derive(Clone, Debug)]
+pub struct OpenFileLimit(Arc<OpenFileLimitInner>);
+
+#[derive(Debug)]
+struct OpenFileLimitInner {
+    max_n_open: usize,
+    n_open: AtomicUsize,
+    peak_n_open: AtomicUsize,
+    // one token per file which may be opened: taken before opening a file and returned once it is closed
+    tokens: (async_channel::Sender<()>, async_channel::Receiver<()>),
+}
+
+impl OpenFileLimit {
+    /// A limit of `max_n_open` files, which must be at least 1 for any file to be opened at all
+    pub fn new(max_n_open: usize) -> OpenFileLimit {
+        assert!(max_n_open > 0, "the open file limit must be at least 1");
+        let (sender, receiver) = async_channel::bounded(max_n_open);
+        for _ in 0..max_n_open {
+            sender.try_send(()).unwrap();
+        }
+        OpenFileLimit(Arc::new(OpenFileLimitInner {
+            max_n_open,
+            n_open: AtomicUsize::new(0),
+            peak_n_open: AtomicUsize::new(0),
+            tokens: (sender, receiver),
+        }))
+    }
+
+    pub fn max_n_open(&self) -> usize {
+        self.0.max_n_open
+    }
+
+    /// Number of files currently open by all holders of this limit
+    pub fn n_open(&self) -> usize {
+        self.0.n_open.load(Ordering::Acquire)
+    }
+
+    /// Most files which have been open at once by all holders of this limit
+    pub fn peak_n_open(&self) -> usize {
+        self.0.peak_n_open.load(Ordering::Acquire)
+    }
+
+    /// Wait until another file may be opened
+    async fn acquire(self) -> OpenFilePermit {
+        // the sender lives as long as the limit, so the channel is never closed
+        self.0.tokens.1.recv().await.unwrap();
+        let n_open = self.0.n_open.fetch_add(1, Ordering::AcqRel) + 1;
+        self.0.peak_n_open.fetch_max(n_open, Ordering::AcqRel);
+        OpenFilePermit(self)
+    }
+}
+
+/// Permission to hold one file open, returned to its [OpenFileLimit] on drop
+#[derive(Debug)]
+struct OpenFilePermit(OpenFileLimit);
+
+impl Drop for OpenFilePermit {
+    fn drop(&mut self) {
+        self.0 .0.n_open.fetch_sub(1, Ordering::AcqRel);
+        let _ = self.0 .0.tokens.0.try_send(());
+    }
+}
+
+/// A local file which is only opened once it is first read, within an [OpenFileLimit]. It may be closed between reads
+/// through its [LazyFileCloser], and is then reopened at the same offset by the next read.
+pub(crate) struct LazyFile {
+    path: PathBuf,
+    limit: OpenFileLimit,
+    state: Arc<Mutex<LazyFileState>>,
+}
+
+struct LazyFileState {
+    /// bytes of the file which have been read so far
+    offset: u64,
+    eof: bool,
+    handle: Handle,
+}
+
+enum Handle {
+    Closed,
+    Acquiring(BoxFuture<'static, OpenFilePermit>),
+    // the file is declared ahead of its permit so that it is closed first when both are dropped
+    Open(smol::Unblock<std::fs::File>, OpenFilePermit),
+}
+
+/// Closes a [LazyFile] while it isn't being read, e.g. while the packets read from it wait to be merged
+pub(crate) struct LazyFileCloser(Arc<Mutex<LazyFileState>>);
+
+impl LazyFile {
+    pub(crate) fn new(
+        path: impl Into<PathBuf>,
+        limit: OpenFileLimit,
+    ) -> (LazyFile, LazyFileCloser) {
+        let state = Arc::new(Mutex::new(LazyFileState {
+            offset: 0,
+            eof: false,
+            handle: Handle::Closed,
+        }));
+        let closer = LazyFileCloser(state.clone());
+        let file = LazyFile {
+            path: path.into(),
+            limit,
+            state,
+        };
+        (file, closer)
+    }
+}
+
+impl AsyncRead for LazyFile {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let mut state = this.state.lock().unwrap();
+        let state = &mut *state;
+        if state.eof {
+            return Poll::Ready(Ok(0));
+        }
+        loop {
+            match &mut state.handle {
+                Handle::Closed => {
+                    state.handle = Handle::Acquiring(this.limit.clone().acquire().boxed());
+                }
+                Handle::Acquiring(permit) => {
+                    let permit = futures::ready!(permit.poll_unpin(cx));
+                    let mut file = match std::fs::File::open(&this.path) {
+                        Ok(file) => file,
+                        Err(error) => {
+                            state.handle = Handle::Closed;
+                            return Poll::Ready(Err(error));
+                        }
+                    };
+                    if let Err(error) = file.seek(SeekFrom::Start(state.offset)) {
+                        state.handle = Handle::Closed;
+                        return Poll::Ready(Err(error));
+                    }
+                    state.handle =
+                        Handle::Open(smol::Unblock::with_capacity(1024 * 128, file), permit);
+                }
+                Handle::Open(file, _) => {
+                    let n_bytes = futures::ready!(Pin::new(file).poll_read(cx, buf))?;
+                    state.offset += n_bytes as u64;
+                    if n_bytes == 0 && !buf.is_empty() {
+                        // nothing more to read, so there's no need to hold on to the file
+                        state.eof = true;
+                        state.handle = Handle::Closed;
+                    }
+                    return Poll::Ready(Ok(n_bytes));
+                }
+            }
+        }
+    }
+}
+
+impl LazyFileCloser {
+    /// Close the file, if it's open, returning its permit to the [OpenFileLimit] once it has been closed
+    pub(crate) async fn close(&self) {
+        let handle = std::mem::replace(&mut self.0.lock().unwrap().handle, Handle::Closed);
+        if let Handle::Open(file, permit) = handle {
+            // wait out any read in progress on the blocking thread pool, so the descriptor is really closed
+            drop(file.into_inner().await);
+            drop(permit);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::io::AsyncReadExt;
+    use std::io::Write;
+
+    #[test]
+    fn reopens_at_the_same_offset_after_closing() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        let contents: Vec<u8> = (0..=255).collect();
+        file.write_all(&contents).unwrap();
+        let limit = OpenFileLimit::new(1);
+        let (mut lazy, closer) = LazyFile::new(file.path(), limit.clone());
+        assert_eq!(limit.n_open(), 0);
+
+        smol::block_on(async {
+            let mut start = [0; 100];
+            lazy.read_exact(&mut start).await.unwrap();
+            assert_eq!(limit.n_open(), 1);
+            closer.close().await;
+            assert_eq!(limit.n_open(), 0);
+
+            // another file may be opened while this one is closed
+            let (mut other, _) = LazyFile::new(file.path(), limit.clone());
+            let mut other_start = [0; 10];
+            other.read_exact(&mut other_start).await.unwrap();
+            assert_eq!(other_start[..], contents[..10]);
+            drop(other);
+
+            let mut rest = Vec::new();
+            lazy.read_to_end(&mut rest).await.unwrap();
+            assert_eq!([&start[..], &rest[..]].concat(), contents);
+        });
+        assert_eq!(limit.n_open(), 0);
+        assert_eq!(limit.peak_n_open(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "the open file limit must be at least 1")]
+    fn a_limit_of_no_open_files_is_rejected() {
+        OpenFileLimit::new(0);
+    }
+}
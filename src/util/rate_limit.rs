@@ -0,0 +1,144 @@
+use futures::future::{BoxFuture, FutureExt};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Source of time for a [RateLimiter]. [SystemClock] unless replaced, e.g. by a simulated clock in tests.
+pub trait Clock: Send + Sync {
+    /// Time elapsed since some fixed point, such as the clock's creation
+    fn now(&self) -> Duration;
+
+    /// Complete once `duration` has passed
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+}
+
+/// Wall-clock time, sleeping with [smol::Timer]
+#[derive(Debug)]
+pub struct SystemClock(Instant);
+
+impl Default for SystemClock {
+    fn default() -> SystemClock {
+        SystemClock(Instant::now())
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.0.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        smol::Timer::after(duration).map(|_instant| ()).boxed()
+    }
+}
+
+/// A token bucket capping the aggregate rate of the reads which [acquire](RateLimiter::acquire) from it, shared between
+/// any number of streams. Up to one second's worth of bytes may be read in a burst. Cheap to clone.
+///
+/// A read larger than the bucket goes ahead once the bucket has refilled from any earlier debt, leaving the bucket in
+/// debt by the excess, so reads never wait on tokens that can't accumulate.
+#[derive(Clone)]
+pub struct RateLimiter(Arc<RateLimiterInner>);
+
+struct RateLimiterInner {
+    bytes_per_second: u64,
+    clock: Arc<dyn Clock>,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    /// bytes which may be read without waiting. Negative while paying off earlier reads
+    n_bytes_available: f64,
+    refilled_at: Duration,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_second: u64) -> RateLimiter {
+        RateLimiter::with_clock(bytes_per_second, Arc::new(SystemClock::default()))
+    }
+
+    pub fn with_clock(bytes_per_second: u64, clock: Arc<dyn Clock>) -> RateLimiter {
+        assert!(bytes_per_second > 0, "the rate limit must be positive");
+        let bucket = Bucket {
+            n_bytes_available: bytes_per_second as f64,
+            refilled_at: clock.now(),
+        };
+        RateLimiter(Arc::new(RateLimiterInner {
+            bytes_per_second,
+            clock,
+            bucket: Mutex::new(bucket),
+        }))
+    }
+
+    pub fn bytes_per_second(&self) -> u64 {
+        self.0.bytes_per_second
+    }
+
+    /// Take `n_bytes` from the bucket, completing once the rate limit allows them to be read
+    pub fn acquire(&self, n_bytes: usize) -> BoxFuture<'static, ()> {
+        let rate = self.0.bytes_per_second as f64;
+        let wait = {
+            let mut bucket = self.0.bucket.lock().unwrap();
+            let now = self.0.clock.now();
+            let refill = (now - bucket.refilled_at).as_secs_f64() * rate;
+            bucket.n_bytes_available = (bucket.n_bytes_available + refill).min(rate);
+            bucket.refilled_at = now;
+
+            // wait until any debt (including this read's) is paid off
+            bucket.n_bytes_available -= n_bytes as f64;
+            if bucket.n_bytes_available < 0.0 {
+                Duration::from_secs_f64(-bucket.n_bytes_available / rate)
+            } else {
+                Duration::ZERO
+            }
+        };
+        if wait.is_zero() {
+            futures::future::ready(()).boxed()
+        } else {
+            self.0.clock.sleep(wait)
+        }
+    }
+}
+
+impl std::fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("RateLimiter")
+            .field("bytes_per_second", &self.0.bytes_per_second)
+            .finish()
+    }
+}
+
+/// [Clock] whose time only passes when slept on: each sleep advances the clock by its duration and completes immediately
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct SimulatedClock(Mutex<Duration>);
+
+#[cfg(test)]
+impl Clock for SimulatedClock {
+    fn now(&self) -> Duration {
+        *self.0.lock().unwrap()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        let mut now = self.0.lock().unwrap();
+        *now += duration;
+        futures::future::ready(()).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reads_larger_than_the_bucket_wait_rather_than_deadlock() {
+        let clock = Arc::new(SimulatedClock::default());
+        let limiter = RateLimiter::with_clock(1000, clock.clone());
+
+        smol::block_on(limiter.acquire(500)); // within the initial burst
+        assert_eq!(clock.now(), Duration::ZERO);
+        smol::block_on(limiter.acquire(2500)); // 500 bytes from the bucket, leaving 2000 in debt
+        assert_eq!(clock.now(), Duration::from_secs(2));
+        smol::block_on(limiter.acquire(1000));
+        assert_eq!(clock.now(), Duration::from_secs(3));
+    }
+}
@@ -0,0 +1,26 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use std::process::Command;
+
+#[test]
+fn a_directory_without_pcaps_fails_unless_empty_inputs_are_allowed(
+) -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    std::fs::write(dir.path().join("README.txt"), b"no captures today")?;
+
+    let output = Command::cargo_bin("merge_pcaps")?
+        .arg(dir.path())
+        .output()?;
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("No inputs matched"), "{}", stderr);
+
+    let output = Command::cargo_bin("merge_pcaps")?
+        .arg("--allow-empty")
+        .arg(dir.path())
+        .output()?;
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(output.stdout, common::PCAP_HDR_NSEC);
+    Ok(())
+}
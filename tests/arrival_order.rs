@@ -0,0 +1,93 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::NANOSECONDS_PER_SECOND;
+use std::process::Command;
+
+/// Split merged nanosecond pcap output into `(timestamp, payload)` tuples
+fn records(mut pcap: &[u8]) -> Vec<(u64, Vec<u8>)> {
+    pcap = &pcap[common::PCAP_HDR_NSEC.len()..];
+    let mut records = Vec::new();
+    while !pcap.is_empty() {
+        let field = |i: usize| {
+            let mut bytes = [0u8; 4];
+            bytes.copy_from_slice(&pcap[i * 4..(i + 1) * 4]);
+            u32::from_le_bytes(bytes) as u64
+        };
+        let (ts, len) = (
+            field(0) * NANOSECONDS_PER_SECOND + field(1),
+            field(2) as usize,
+        );
+        records.push((ts, pcap[16..16 + len].to_vec()));
+        pcap = &pcap[16 + len..];
+    }
+    records
+}
+
+#[test]
+fn arrival_ordered_input_is_interleaved_with_timestamped_input(
+) -> Result<(), Box<dyn std::error::Error>> {
+    let timestamped: Vec<_> = (1..=20)
+        .map(|i| (i * NANOSECONDS_PER_SECOND, vec![1u8, i as u8]))
+        .collect();
+    // timestamps which are unreliable (out of order and far in the future), so must not determine the merge order
+    let unreliable: Vec<_> = (1..=10)
+        .map(|i| ((100 - i) * NANOSECONDS_PER_SECOND, vec![2u8, i as u8]))
+        .collect();
+    let timestamped_input = common::nanosecond_pcap(&timestamped);
+    let arrival_input = common::nanosecond_pcap(&unreliable);
+
+    let output = Command::cargo_bin("merge_pcaps")?
+        .args(["--order", "timestamp", "--order", "arrival"])
+        .arg(timestamped_input.path())
+        .arg(arrival_input.path())
+        .output()?;
+    assert!(output.status.success());
+    let merged = records(&output.stdout);
+    assert_eq!(merged.len(), timestamped.len() + unreliable.len());
+
+    // each input keeps its own order, arrival-ordered packets are restamped so the output stays time-ordered, and no
+    // arrival-ordered packet takes a timestamp later than the last timestamped packet
+    let from = |input: u8| -> Vec<Vec<u8>> {
+        merged
+            .iter()
+            .filter(|(_ts, payload)| payload[0] == input)
+            .map(|(_ts, payload)| payload.clone())
+            .collect()
+    };
+    assert_eq!(
+        from(1),
+        timestamped.into_iter().map(|(_, p)| p).collect::<Vec<_>>()
+    );
+    assert_eq!(
+        from(2),
+        unreliable.into_iter().map(|(_, p)| p).collect::<Vec<_>>()
+    );
+    assert!(merged.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+    assert!(merged
+        .iter()
+        .all(|(ts, _)| *ts <= 20 * NANOSECONDS_PER_SECOND));
+    Ok(())
+}
+
+#[test]
+fn order_must_be_given_once_or_per_pcap() -> Result<(), Box<dyn std::error::Error>> {
+    let first = common::nanosecond_pcap(&[(0, vec![1u8; 4])]);
+    let second = common::nanosecond_pcap(&[(0, vec![2u8; 4])]);
+    let third = common::nanosecond_pcap(&[(0, vec![3u8; 4])]);
+
+    let output = Command::cargo_bin("merge_pcaps")?
+        .args(["--order", "timestamp", "--order", "arrival"])
+        .arg(first.path())
+        .arg(second.path())
+        .arg(third.path())
+        .output()?;
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(
+        stderr.contains("--order was given 2 times for 3 pcaps"),
+        "{}",
+        stderr
+    );
+    Ok(())
+}
@@ -0,0 +1,56 @@
+mod common;
+
+use common::NANOSECONDS_PER_SECOND;
+use futures::stream::StreamExt;
+use stream_merge::tournament_tree::{AsyncStream, AsyncTree};
+
+#[test]
+fn async_tree_merges_decoded_pcaps_in_one_stream() {
+    let inputs: Vec<_> = (0..5u64)
+        .map(|file| {
+            common::nanosecond_pcap(
+                &(0..300u64)
+                    .map(|i| {
+                        (
+                            (i * 5 + file) * NANOSECONDS_PER_SECOND / 10,
+                            vec![file as u8; 20 + i as usize % 50],
+                        )
+                    })
+                    .collect::<Vec<_>>(),
+            )
+        })
+        .collect();
+    let paths: Vec<_> = inputs
+        .iter()
+        .map(|input| input.path().to_str().unwrap().to_string())
+        .collect();
+
+    let streams = paths
+        .iter()
+        .map(|path| {
+            AsyncStream::new(Box::pin(stream_merge::stream_and_decode_pcap_packets(
+                path.clone(),
+            )))
+        })
+        .collect();
+    let merged: Vec<_> = smol::block_on(
+        AsyncTree::new(streams)
+            .map(|(ts, record)| (ts, record.to_vec()))
+            .collect(),
+    );
+
+    let mut blocking = Vec::new();
+    stream_merge::merge_pcap_streams_with(paths, |ts, packet| blocking.push((ts, packet.to_vec())));
+    assert_eq!(merged.len(), 1500);
+    assert!(merged.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    assert_eq!(
+        merged
+            .iter()
+            .map(|(ts, record)| (
+                *ts,
+                record[stream_merge::pcap::RECORD_HEADER_LEN..].to_vec()
+            ))
+            .collect::<Vec<_>>(),
+        blocking
+    );
+}
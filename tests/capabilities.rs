@@ -0,0 +1,25 @@
+use assert_cmd::prelude::*;
+use std::process::Command;
+
+#[test]
+fn capabilities_of_the_default_build_are_listed_as_json() -> Result<(), Box<dyn std::error::Error>>
+{
+    let output = Command::cargo_bin("merge_pcaps")?
+        .arg("--capabilities")
+        .output()?;
+    assert!(output.status.success(), "{:?}", output);
+    let capabilities: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    let listed =
+        |key: &str| -> Vec<String> { serde_json::from_value(capabilities[key].clone()).unwrap() };
+    assert_eq!(capabilities["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(listed("input_schemes"), ["local", "s3"]);
+    assert_eq!(listed("input_formats"), ["pcap", "pcapng", "raw"]);
+    assert_eq!(listed("input_compressions"), ["none", "gzip", "zstd"]);
+    assert_eq!(
+        listed("output_formats"),
+        ["pcap", "length-prefixed", "pcapng"]
+    );
+    assert_eq!(listed("output_compressions"), ["none", "gzip", "zstd"]);
+    assert_eq!(listed("features"), ["jemalloc"]);
+    Ok(())
+}
@@ -0,0 +1,74 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::NANOSECONDS_PER_SECOND;
+use std::process::Command;
+
+/// A rolling capture's file of a packet every 100ms over `[start_s, end_s)` seconds
+fn rolling_file(start_s: u64, end_s: u64) -> tempfile::NamedTempFile {
+    let packets: Vec<_> = (start_s * 10..end_s * 10)
+        .map(|i| (i * NANOSECONDS_PER_SECOND / 10, vec![i as u8]))
+        .collect();
+    common::nanosecond_pcap(&packets)
+}
+
+#[test]
+fn a_gap_between_rolling_files_is_located() {
+    let files = [
+        rolling_file(0, 10),
+        rolling_file(10, 20),
+        rolling_file(25, 30), // 5s of the capture are missing ahead of this file
+    ];
+    let output = Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .args(["--check-contiguous", "200ms"])
+        .args(files.iter().map(|file| file.path()))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("1 of 2 adjacent file pairs don't abut within 200000000 ns"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains(&format!(
+            "gap of 5100000000 ns between '{}' [10000000000, 19900000000] and '{}' [25000000000, 29900000000]",
+            files[1].path().display(),
+            files[2].path().display()
+        )),
+        "{}",
+        stderr
+    );
+
+    // the merge itself is unaffected, unless the check is required to pass
+    let n_packets = common::read_nanosecond_pcap(&output.stdout).len();
+    assert_eq!(n_packets, 250);
+    Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .args(["--check-contiguous", "200ms", "--require-contiguous"])
+        .args(files.iter().map(|file| file.path()))
+        .assert()
+        .failure();
+}
+
+#[test]
+fn abutting_and_slightly_overlapping_files_pass() {
+    let files = [rolling_file(0, 10), rolling_file(10, 20)];
+    let overlapping = common::nanosecond_pcap(&[
+        (19_950_000_000, vec![1]),
+        (21 * NANOSECONDS_PER_SECOND, vec![2]),
+    ]);
+    let output = Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .args(["--check-contiguous", "200ms", "--require-contiguous"])
+        .args(files.iter().map(|file| file.path()))
+        .arg(overlapping.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("0 of 2 adjacent file pairs don't abut")
+    );
+}
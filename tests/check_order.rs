@@ -0,0 +1,99 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::NANOSECONDS_PER_SECOND;
+use std::process::Command;
+
+#[test]
+fn descending_input_is_rejected_by_name() -> Result<(), Box<dyn std::error::Error>> {
+    let ascending = common::nanosecond_pcap(
+        &(0..10)
+            .map(|i| (i * NANOSECONDS_PER_SECOND, vec![1u8; 40]))
+            .collect::<Vec<_>>(),
+    );
+    let descending = common::nanosecond_pcap(
+        &(0..10)
+            .rev()
+            .map(|i| (i * NANOSECONDS_PER_SECOND, vec![2u8; 40]))
+            .collect::<Vec<_>>(),
+    );
+    let descending_path = descending.path().to_str().unwrap();
+
+    let output = Command::cargo_bin("merge_pcaps")?
+        .arg(ascending.path())
+        .arg(descending.path())
+        .output()?;
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(
+        stderr.contains(&format!(
+            "'{}' is not sorted in ascending time order",
+            descending_path
+        )),
+        "{}",
+        stderr
+    );
+
+    // the check can be disabled
+    Command::cargo_bin("merge_pcaps")?
+        .args(["--check-order", "false"])
+        .arg(ascending.path())
+        .arg(descending.path())
+        .assert()
+        .success();
+    Ok(())
+}
+
+#[test]
+fn unsorted_input_is_written_clamped_or_rejected() -> Result<(), Box<dyn std::error::Error>> {
+    // the first packets are in order, so only the merged output reveals that the file isn't sorted
+    let packets = [
+        (NANOSECONDS_PER_SECOND, vec![1u8; 10]),
+        (2 * NANOSECONDS_PER_SECOND, vec![2u8; 10]),
+        (3 * NANOSECONDS_PER_SECOND, vec![3u8; 10]),
+        (NANOSECONDS_PER_SECOND + 5, vec![4u8; 10]),
+        (4 * NANOSECONDS_PER_SECOND, vec![5u8; 10]),
+    ];
+    let unsorted = common::nanosecond_pcap(&packets);
+
+    // by default the packet is written as it is
+    let output = Command::cargo_bin("merge_pcaps")?
+        .arg(unsorted.path())
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(output.stdout, common::nanosecond_pcap_bytes(&packets));
+
+    let output = Command::cargo_bin("merge_pcaps")?
+        .arg("--fail-on-backwards")
+        .arg(unsorted.path())
+        .output()?;
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(
+        stderr.contains(&format!(
+            "Packet timestamp {} ns is before the previous packet's {} ns",
+            NANOSECONDS_PER_SECOND + 5,
+            3 * NANOSECONDS_PER_SECOND
+        )),
+        "{}",
+        stderr
+    );
+
+    let output = Command::cargo_bin("merge_pcaps")?
+        .arg("--clamp")
+        .arg(unsorted.path())
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(
+        output.stdout,
+        common::nanosecond_pcap_bytes(&[
+            (NANOSECONDS_PER_SECOND, vec![1u8; 10]),
+            (2 * NANOSECONDS_PER_SECOND, vec![2u8; 10]),
+            (3 * NANOSECONDS_PER_SECOND, vec![3u8; 10]),
+            (3 * NANOSECONDS_PER_SECOND, vec![4u8; 10]),
+            (4 * NANOSECONDS_PER_SECOND, vec![5u8; 10]),
+        ])
+    );
+    Ok(())
+}
@@ -0,0 +1,70 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::NANOSECONDS_PER_SECOND;
+use std::process::Command;
+
+const START: u64 = 1_637_796_620 * NANOSECONDS_PER_SECOND;
+
+/// A packet's `(nanosecond timestamp, payload)`
+type Packet = (u64, Vec<u8>);
+
+/// Split a coalesced unit's captured bytes into the `(nanosecond timestamp, payload)` of each of its packets
+fn unit_packets(unit: &[u8]) -> Vec<Packet> {
+    let n_packets = u32::from_le_bytes([unit[0], unit[1], unit[2], unit[3]]) as usize;
+    let mut records = common::PCAP_HDR_NSEC.to_vec();
+    records.extend_from_slice(&unit[4..]);
+    let packets = common::read_nanosecond_pcap(&records);
+    assert_eq!(packets.len(), n_packets);
+    packets
+}
+
+#[test]
+fn packets_within_the_window_are_coalesced() -> Result<(), Box<dyn std::error::Error>> {
+    // a burst of packets 100ns apart across both inputs, then stragglers a microsecond or more apart
+    let first = common::nanosecond_pcap(&[
+        (START, vec![1u8; 40]),
+        (START + 200, vec![1u8; 41]),
+        (START + 2_000, vec![1u8; 42]),
+    ]);
+    let second = common::nanosecond_pcap(&[
+        (START + 100, vec![2u8; 50]),
+        (START + 300, vec![2u8; 51]),
+        (START + 1_000, vec![2u8; 52]),
+    ]);
+    let output = Command::cargo_bin("merge_pcaps")?
+        .args(["--coalesce", "500ns"])
+        .arg(first.path())
+        .arg(second.path())
+        .output()?;
+    assert!(output.status.success());
+
+    let units = common::read_nanosecond_pcap(&output.stdout);
+    let units: Vec<(u64, Vec<Packet>)> = units
+        .iter()
+        .map(|(ts, unit)| (*ts, unit_packets(unit)))
+        .collect();
+    assert_eq!(
+        units,
+        vec![
+            (
+                START,
+                vec![
+                    (START, vec![1u8; 40]),
+                    (START + 100, vec![2u8; 50]),
+                    (START + 200, vec![1u8; 41]),
+                    (START + 300, vec![2u8; 51]),
+                ]
+            ),
+            (START + 1_000, vec![(START + 1_000, vec![2u8; 52])]),
+            (START + 2_000, vec![(START + 2_000, vec![1u8; 42])]),
+        ]
+    );
+
+    Command::cargo_bin("merge_pcaps")?
+        .args(["--coalesce", "0ns"])
+        .arg(first.path())
+        .assert()
+        .failure();
+    Ok(())
+}
@@ -0,0 +1,152 @@
+//! Generate corpora of pcap files for integration tests and benchmarks. Shared with `benches/` via a `#[path]` module.
+#![allow(dead_code)] // each test and benchmark binary only uses a subset of these helpers
+
+use fake::Fake;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+#[derive(Copy, Clone, Debug)]
+pub enum CompressionFormat {
+    Uncompressed,
+    Zstd, /* deaults: frame_size : 128Kb, compression_level : -12 */
+    Gzip,
+}
+
+impl std::fmt::Display for CompressionFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let text = match *self {
+            CompressionFormat::Uncompressed => "Uncompressed",
+            CompressionFormat::Gzip => "Gzip",
+            CompressionFormat::Zstd => "Zstd",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+pub enum StorageLocation<'tmpdir> {
+    Local { directory: &'tmpdir Path },
+}
+
+unsafe fn any_as_u8_slice<T: Sized>(p: &T) -> &[u8] {
+    ::std::slice::from_raw_parts((p as *const T) as *const u8, ::std::mem::size_of::<T>())
+}
+
+#[repr(C)]
+pub struct PacketHeader {
+    pub seconds: u32,
+    pub nanoseconds: u32,
+    pub caplen: u32,
+    pub len: u32,
+}
+
+fn write_fake_packet<W: std::io::Write>(
+    mut file: W,
+    seconds_since_epoch: u32,
+    nanoseconds_since_second: u32,
+) -> usize {
+    // all bytes are 7 for now. TODO: randomize these bytes for more realistic compresssion
+    let packet_bytes = [7u8; 153];
+
+    // TODO: somehow use the property testing or mocking framework for choosing the "time step" between packets that is >= 0
+    let header = PacketHeader {
+        seconds: seconds_since_epoch,
+        nanoseconds: nanoseconds_since_second,
+        caplen: packet_bytes.len() as u32,
+        len: packet_bytes.len() as u32,
+    };
+    unsafe { file.write_all(any_as_u8_slice(&header)).unwrap() };
+    file.write_all(&packet_bytes).unwrap();
+
+    std::mem::size_of::<PacketHeader>() + packet_bytes.len()
+}
+
+/// A generated set of nanosecond-precision pcaps. `paths` are compressed as configured; `uncompressed_paths` hold the
+/// same packets uncompressed (and are the same files as `paths` for [CompressionFormat::Uncompressed]).
+pub struct Corpus {
+    pub paths: Vec<PathBuf>,
+    pub uncompressed_paths: Vec<PathBuf>,
+}
+
+impl Corpus {
+    pub fn new(config: &CorpusConfiguration) -> Corpus {
+        let mut paths = Vec::with_capacity(config.n_files as usize);
+        let mut uncompressed_paths = Vec::with_capacity(config.n_files as usize);
+        let mut current_time: (u32, u32) = (1637796620, 7);
+        for i in 1..=config.n_files {
+            let (mut file, path) = match config.storage_location {
+                StorageLocation::Local { directory } => {
+                    let file_path =
+                        directory.join(std::format!("file_{}_of_{}.pcap", i, config.n_files));
+                    (
+                        std::io::BufWriter::new(std::fs::File::create(&file_path).unwrap()),
+                        file_path,
+                    )
+                }
+            };
+
+            const PCAP_HDR_NSEC: &[u8] = &hex_literal::hex!(
+                "4D 3C B2 A1 02 00 04 00 00 00 00 00 00 00 00 00
+    00 00 04 00 01 00 00 00"
+            );
+            file.write_all(PCAP_HDR_NSEC).unwrap();
+
+            let mut n_bytes_written = PCAP_HDR_NSEC.len();
+            while n_bytes_written < config.total_n_bytes / config.n_files as usize {
+                let to_advance: (u32, u32) = ((0..1).fake(), (0..1000000000).fake());
+                // carry into the seconds so that each file's timestamps never decrease
+                let nanoseconds = current_time.1 + to_advance.1;
+                current_time = (
+                    current_time.0 + to_advance.0 + nanoseconds / 1000000000,
+                    nanoseconds % 1000000000,
+                );
+                n_bytes_written += write_fake_packet(&mut file, current_time.0, current_time.1);
+                /* TODO: pick a random step for the packet timestamp? */
+            }
+
+            drop(file); // flush
+
+            // compress, keeping the uncompressed original
+            let compressed_path = match config.compression_format {
+                CompressionFormat::Uncompressed => {
+                    // nothing to do
+                    path.clone()
+                }
+                CompressionFormat::Gzip => {
+                    // gzip-compressed .pcap.gz
+                    std::process::Command::new("gzip")
+                        .arg("--keep")
+                        .arg(&path)
+                        .status()
+                        .expect("failed to gzip compress pcap");
+                    path.with_extension("pcap.gz")
+                }
+                CompressionFormat::Zstd => {
+                    let compressed_path = path.with_extension("pcap.zst");
+                    zstd::stream::copy_encode(
+                        std::fs::File::open(&path).unwrap(),
+                        std::fs::File::create(&compressed_path).unwrap(),
+                        -12,
+                    )
+                    .expect("failed to zstd compress pcap");
+                    compressed_path
+                }
+            };
+            paths.push(compressed_path);
+            uncompressed_paths.push(path);
+        }
+        Corpus {
+            paths,
+            uncompressed_paths,
+        }
+    }
+}
+
+// TODO: consider letting users control the packet size?
+// TODO better description: create a benchmark corpus locally or at s3, with the desired compression format, pcap sizes, etc..
+pub struct CorpusConfiguration<'dir> {
+    /// approximate total uncompressed size of the corpus, split evenly between files
+    pub total_n_bytes: usize,
+    pub n_files: u16,
+    pub storage_location: StorageLocation<'dir>,
+    pub compression_format: CompressionFormat,
+}
@@ -0,0 +1,68 @@
+//! Fixture helpers shared between integration tests.
+#![allow(dead_code)] // each integration test binary only uses a subset of these helpers
+
+pub mod corpus;
+
+use std::io::prelude::*;
+use tempfile::NamedTempFile;
+
+/// pcap header with nanosecond-precision timestamping
+pub const PCAP_HDR_NSEC: &[u8] = &hex_literal::hex!(
+    "4D 3C B2 A1 02 00 04 00 00 00 00 00 00 00 00 00
+    00 00 04 00 01 00 00 00"
+);
+
+pub const NANOSECONDS_PER_SECOND: u64 = 1_000_000_000;
+
+/// Append a little-endian pcap record for `payload` with the given timestamp fields to `out`
+pub fn push_record(out: &mut Vec<u8>, seconds: u32, fraction: u32, payload: &[u8]) {
+    out.extend_from_slice(&seconds.to_le_bytes());
+    out.extend_from_slice(&fraction.to_le_bytes());
+    out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    out.extend_from_slice(payload);
+}
+
+/// Build the bytes of a nanosecond-precision pcap containing `packets`, given as `(nanosecond timestamp, payload)` tuples
+pub fn nanosecond_pcap_bytes(packets: &[(u64, Vec<u8>)]) -> Vec<u8> {
+    let mut bytes = PCAP_HDR_NSEC.to_vec();
+    for (ts, payload) in packets {
+        push_record(
+            &mut bytes,
+            (ts / NANOSECONDS_PER_SECOND) as u32,
+            (ts % NANOSECONDS_PER_SECOND) as u32,
+            payload,
+        );
+    }
+    bytes
+}
+
+/// Write a nanosecond-precision pcap containing `packets` to a temporary file ending in `.pcap`
+pub fn nanosecond_pcap(packets: &[(u64, Vec<u8>)]) -> NamedTempFile {
+    let mut file = tempfile::Builder::new().suffix(".pcap").tempfile().unwrap();
+    file.write_all(&nanosecond_pcap_bytes(packets)).unwrap();
+    file.flush().unwrap();
+    file
+}
+
+/// Decode the records of a little-endian, nanosecond-precision pcap into `(nanosecond timestamp, payload)` tuples
+pub fn read_nanosecond_pcap(bytes: &[u8]) -> Vec<(u64, Vec<u8>)> {
+    assert_eq!(&bytes[..PCAP_HDR_NSEC.len()], PCAP_HDR_NSEC);
+    let mut packets = Vec::new();
+    let mut rest = &bytes[PCAP_HDR_NSEC.len()..];
+    while !rest.is_empty() {
+        let field = |offset: usize| {
+            u32::from_le_bytes([
+                rest[offset],
+                rest[offset + 1],
+                rest[offset + 2],
+                rest[offset + 3],
+            ])
+        };
+        let ts = field(0) as u64 * NANOSECONDS_PER_SECOND + field(4) as u64;
+        let caplen = field(8) as usize;
+        packets.push((ts, rest[16..16 + caplen].to_vec()));
+        rest = &rest[16 + caplen..];
+    }
+    packets
+}
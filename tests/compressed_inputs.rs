@@ -0,0 +1,58 @@
+#![cfg(any(feature = "bzip2", feature = "xz"))]
+
+mod common;
+
+use assert_cmd::prelude::*;
+use common::NANOSECONDS_PER_SECOND;
+use std::process::Command;
+
+/// Merge a pcap compressed by `compressor` (e.g. `bzip2`) with an uncompressed one, asserting the output matches
+/// merging the two uncompressed
+fn merges_like_uncompressed(
+    compressor: &str,
+    extension: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let first_packets: Vec<_> = (0..100)
+        .map(|i| (i * 2 * NANOSECONDS_PER_SECOND, vec![(i % 256) as u8; 60]))
+        .collect();
+    let second_packets: Vec<_> = (0..100)
+        .map(|i| ((i * 2 + 1) * NANOSECONDS_PER_SECOND, vec![0xEE; 90]))
+        .collect();
+    let dir = tempfile::tempdir()?;
+    let first = dir.path().join("first.pcap");
+    std::fs::write(&first, common::nanosecond_pcap_bytes(&first_packets))?;
+    let second = common::nanosecond_pcap(&second_packets);
+
+    let reference = Command::cargo_bin("merge_pcaps")?
+        .arg(&first)
+        .arg(second.path())
+        .output()?;
+    assert!(reference.status.success());
+
+    let status = Command::new(compressor)
+        .arg("--keep")
+        .arg(&first)
+        .status()?;
+    assert!(status.success());
+    let compressed = dir.path().join(format!("first.pcap.{}", extension));
+    let output = Command::cargo_bin("merge_pcaps")?
+        .arg(&compressed)
+        .arg(second.path())
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(output.stdout, reference.stdout);
+    assert_eq!(common::read_nanosecond_pcap(&output.stdout).len(), 200);
+    Ok(())
+}
+
+#[cfg(feature = "bzip2")]
+#[test]
+fn bzip2_input_merges_like_uncompressed() -> Result<(), Box<dyn std::error::Error>> {
+    merges_like_uncompressed("bzip2", "bz2")
+}
+
+#[cfg(feature = "xz")]
+#[test]
+fn xz_input_merges_like_uncompressed() -> Result<(), Box<dyn std::error::Error>> {
+    merges_like_uncompressed("xz", "xz")
+}
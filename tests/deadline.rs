@@ -0,0 +1,107 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use bytes::Bytes;
+use common::NANOSECONDS_PER_SECOND;
+use futures::future::BoxFuture;
+use futures::stream::StreamExt;
+use futures::task::{Poll, Waker};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use stream_merge::tournament_tree::{OwnedTree, PacketStream};
+use stream_merge::{Clock, Deadline};
+
+/// A [Clock] whose time only passes when [advanced](ManualClock::advance), completing the sleeps it reaches the end of
+#[derive(Clone, Default)]
+struct ManualClock(Arc<Mutex<(Duration, Vec<Waker>)>>);
+
+impl ManualClock {
+    fn advance(&self, duration: Duration) {
+        let mut state = self.0.lock().unwrap();
+        state.0 += duration;
+        state.1.drain(..).for_each(Waker::wake);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        self.0.lock().unwrap().0
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        let state = self.0.clone();
+        let until = self.now() + duration;
+        Box::pin(futures::future::poll_fn(move |cx| {
+            let mut state = state.lock().unwrap();
+            if state.0 >= until {
+                return Poll::Ready(());
+            }
+            state.1.push(cx.waker().clone());
+            Poll::Pending
+        }))
+    }
+}
+
+#[test]
+fn a_slow_input_trips_the_deadline() {
+    let budget = Duration::from_secs(300);
+    let clock = ManualClock::default();
+    let deadline = Deadline::with_clock(budget, Arc::new(clock.clone()));
+    let packet = |ts: u64| (ts * NANOSECONDS_PER_SECOND, Bytes::from(vec![ts as u8; 20]));
+    // an input whose next packet, after its first, takes longer than the whole budget to arrive
+    let stalled = clock.clone();
+    let slow = futures::stream::iter(vec![packet(1)]).chain(futures::stream::poll_fn(move |_| {
+        stalled.advance(budget * 2);
+        Poll::Pending
+    }));
+    let fast = futures::stream::iter(vec![packet(2), packet(3)]);
+
+    // merged as merge_pcaps does, checking the deadline before each packet
+    let mut merger = OwnedTree::new(vec![
+        PacketStream::new(smol::stream::block_on(deadline.bound(slow).boxed())),
+        PacketStream::new(smol::stream::block_on(deadline.bound(fast).boxed())),
+    ]);
+    let mut merged = Vec::new();
+    while !deadline.expired() {
+        match merger.pop() {
+            Some(packet) => merged.push(packet),
+            None => break,
+        }
+    }
+    // the wait for the slow input's second packet ended at the deadline, letting the packet merged then be written
+    assert_eq!(merged, vec![packet(1), packet(2)]);
+    assert!(deadline.cut_short());
+    assert_eq!(clock.now(), budget * 2);
+}
+
+#[test]
+fn a_merge_past_its_deadline_writes_the_packets_merged_so_far_and_exits_124() {
+    let input = common::nanosecond_pcap(&[(NANOSECONDS_PER_SECOND, vec![1; 20])]);
+    // with no budget, the deadline has passed before the first packet is merged
+    let output = Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .args(["--deadline", "0s"])
+        .arg(input.path())
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(124), "{:?}", output);
+    assert_eq!(common::read_nanosecond_pcap(&output.stdout), vec![]);
+}
+
+#[test]
+fn a_merge_within_its_deadline_succeeds() {
+    let packets = vec![
+        (NANOSECONDS_PER_SECOND, vec![1; 20]),
+        (2 * NANOSECONDS_PER_SECOND, vec![2; 30]),
+    ];
+    let input = common::nanosecond_pcap(&packets);
+    let output = Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .args(["--deadline", "1m"])
+        .arg(input.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(common::read_nanosecond_pcap(&output.stdout), packets);
+}
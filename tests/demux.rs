@@ -0,0 +1,59 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::NANOSECONDS_PER_SECOND;
+use std::process::Command;
+
+#[test]
+fn demuxing_a_merge_by_source_reconstructs_the_inputs() -> Result<(), Box<dyn std::error::Error>> {
+    // the first payload byte identifies which file each packet came from
+    let sources: Vec<Vec<(u64, Vec<u8>)>> = vec![
+        (0..20)
+            .map(|i| (i * 3 * NANOSECONDS_PER_SECOND, vec![0u8; 20 + i as usize]))
+            .collect(),
+        (0..15)
+            .map(|i| (i * 4 * NANOSECONDS_PER_SECOND + 1, vec![1u8; 50]))
+            .collect(),
+    ];
+    let inputs: Vec<_> = sources
+        .iter()
+        .map(|packets| common::nanosecond_pcap(packets))
+        .collect();
+
+    let directory = tempfile::tempdir()?;
+    let merged = directory.path().join("merged.pcap");
+    Command::cargo_bin("merge_pcaps")?
+        .arg("--output")
+        .arg(&merged)
+        .args(inputs.iter().map(|file| file.path()))
+        .unwrap();
+
+    let outputs = vec![
+        directory.path().join("source_0.pcap"),
+        directory.path().join("source_1.pcap"),
+    ];
+    stream_merge::demux_pcaps(merged.to_str().unwrap(), &outputs, |payload| {
+        payload[0] as usize
+    })?;
+
+    for (output, source) in outputs.iter().zip(sources.iter()) {
+        assert_eq!(
+            &common::read_nanosecond_pcap(&std::fs::read(output)?),
+            source
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn demux_reports_out_of_range_output_index() {
+    let input = common::nanosecond_pcap(&[(NANOSECONDS_PER_SECOND, vec![3u8; 10])]);
+    let directory = tempfile::tempdir().unwrap();
+    let outputs = vec![directory.path().join("only_output.pcap")];
+    let error = stream_merge::demux_pcaps(input.path().to_str().unwrap(), &outputs, |payload| {
+        payload[0] as usize
+    })
+    .unwrap_err();
+    assert!(format!("{:#}", error).contains("output index 3"));
+}
@@ -0,0 +1,56 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::NANOSECONDS_PER_SECOND;
+use std::process::Command;
+
+#[test]
+fn pcaps_in_nested_directories_are_discovered_and_merged() -> Result<(), Box<dyn std::error::Error>>
+{
+    let dir = tempfile::tempdir()?;
+    let files = [
+        "top.pcap",
+        "day_1/a.pcap",
+        "day_1/hour_3/b.pcap",
+        "day_2/c.pcap",
+    ];
+    let mut expected = Vec::new();
+    for (i, file) in files.iter().enumerate() {
+        let packets: Vec<_> = (0..5u64)
+            .map(|j| {
+                (
+                    (j * 4 + i as u64) * NANOSECONDS_PER_SECOND,
+                    vec![i as u8; 40],
+                )
+            })
+            .collect();
+        let path = dir.path().join(file);
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        std::fs::write(&path, common::nanosecond_pcap_bytes(&packets))?;
+        expected.extend(packets);
+    }
+    std::fs::write(dir.path().join("day_1/README.txt"), b"not a pcap")?;
+    expected.sort_by_key(|(ts, _)| *ts);
+
+    let output = Command::cargo_bin("merge_pcaps")?
+        .arg(dir.path())
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(output.stdout, common::nanosecond_pcap_bytes(&expected));
+
+    // only top.pcap, day_1/a.pcap and day_2/c.pcap are within two levels
+    let output = Command::cargo_bin("merge_pcaps")?
+        .args(["--max-depth", "2"])
+        .arg(dir.path())
+        .output()?;
+    assert!(output.status.success());
+    let within_two_levels: Vec<_> = expected
+        .into_iter()
+        .filter(|(_, payload)| payload[0] != 2)
+        .collect();
+    assert_eq!(
+        output.stdout,
+        common::nanosecond_pcap_bytes(&within_two_levels)
+    );
+    Ok(())
+}
@@ -0,0 +1,39 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::NANOSECONDS_PER_SECOND;
+use std::process::Command;
+
+#[test]
+fn file_passed_twice_is_merged_once_with_a_warning() -> Result<(), Box<dyn std::error::Error>> {
+    let packets: Vec<_> = (0..10)
+        .map(|i| (i * NANOSECONDS_PER_SECOND, vec![1u8; 40]))
+        .collect();
+    let file = common::nanosecond_pcap(&packets);
+    // the same file by two different spellings of its path
+    let dir = file.path().parent().unwrap();
+    let respelled = dir.join(".").join(file.path().file_name().unwrap());
+
+    let output = Command::cargo_bin("merge_pcaps")?
+        .env("RUST_LOG", "warn")
+        .arg(file.path())
+        .arg(&respelled)
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(common::read_nanosecond_pcap(&output.stdout), packets);
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(stderr.contains("WARN"), "{}", stderr);
+    assert!(stderr.contains("Skipping duplicate input"), "{}", stderr);
+
+    let output = Command::cargo_bin("merge_pcaps")?
+        .arg("--allow-duplicate-inputs")
+        .arg(file.path())
+        .arg(file.path())
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(
+        common::read_nanosecond_pcap(&output.stdout).len(),
+        2 * packets.len()
+    );
+    Ok(())
+}
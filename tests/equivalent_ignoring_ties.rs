@@ -0,0 +1,56 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::NANOSECONDS_PER_SECOND;
+use std::process::Command;
+use stream_merge::pcap;
+
+fn merge(inputs: &[&tempfile::NamedTempFile]) -> Vec<u8> {
+    let output = Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .args(inputs.iter().map(|input| input.path()))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    output.stdout
+}
+
+#[test]
+fn merges_differing_only_in_tie_order_are_equivalent() {
+    let tied_input = |payload: u8| {
+        common::nanosecond_pcap(
+            &(0..20)
+                .map(|i| (i * NANOSECONDS_PER_SECOND, vec![payload; 40 + i as usize]))
+                .collect::<Vec<_>>(),
+        )
+    };
+    let (a, b, c) = (tied_input(1), tied_input(2), tied_input(3));
+
+    // inputs are listed in a different order, so every tie is broken the other way
+    let ab = merge(&[&a, &b]);
+    let ba = merge(&[&b, &a]);
+    assert_ne!(ab, ba);
+    pcap::assert_equivalent_ignoring_ties(&ab, &ba);
+    pcap::assert_equivalent_ignoring_ties(&ab, &ab);
+
+    let ac = merge(&[&a, &c]);
+    let error = pcap::equivalent_ignoring_ties(&ab, &ac)
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("different packets at 0 ns"), "{}", error);
+
+    let error = pcap::equivalent_ignoring_ties(&ab, &merge(&[&a]))
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("different packets at 0 ns"), "{}", error);
+
+    let fewer = common::nanosecond_pcap_bytes(&common::read_nanosecond_pcap(&ab)[..38]);
+    let error = pcap::equivalent_ignoring_ties(&ab, &fewer)
+        .unwrap_err()
+        .to_string();
+    assert!(
+        error.contains("The first pcap has 2 more packets, from 19000000000 ns"),
+        "{}",
+        error
+    );
+}
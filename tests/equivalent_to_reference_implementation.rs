@@ -1,5 +1,8 @@
 // TODO: generate N identical pcaps and confirm that ordering and content are correct in the output pcap. Maybe could compare to mergecap as a reference implementation?
+mod common;
+
 use assert_cmd::prelude::*;
+use common::corpus::{CompressionFormat, Corpus, CorpusConfiguration, StorageLocation};
 
 use std::process::Command;
 use tempfile::NamedTempFile;
@@ -66,3 +69,72 @@ fn compare_to_mergecap() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+/// A small corpus of `n_files` pcaps totalling roughly 1MB, generated into `directory`
+fn small_corpus(
+    directory: &std::path::Path,
+    n_files: u16,
+    compression_format: CompressionFormat,
+) -> Corpus {
+    Corpus::new(&CorpusConfiguration {
+        total_n_bytes: 1024 * 1024,
+        n_files,
+        storage_location: StorageLocation::Local { directory },
+        compression_format,
+    })
+}
+
+fn merge_pcaps_stdout(paths: &[std::path::PathBuf]) -> Vec<u8> {
+    let mut merge_pcaps = Command::cargo_bin("merge_pcaps").unwrap();
+    merge_pcaps.stderr(std::process::Stdio::inherit());
+    merge_pcaps.args(paths);
+    merge_pcaps.unwrap().stdout
+}
+
+fn compare_corpus_to_mergecap(compression_format: CompressionFormat) {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let corpus = small_corpus(tmp_dir.path(), 4, compression_format);
+
+    // mergecap reads the uncompressed originals so that only our decompression is under test
+    let mut mergecap = Command::new("mergecap");
+    mergecap
+        .arg("-F")
+        .arg("nseclibpcap")
+        .arg("-w")
+        .arg("-")
+        .args(&corpus.uncompressed_paths);
+    let mergecap_output = mergecap.unwrap();
+
+    assert_eq!(mergecap_output.stdout, merge_pcaps_stdout(&corpus.paths));
+}
+
+#[test]
+fn compare_uncompressed_corpus_to_mergecap() {
+    compare_corpus_to_mergecap(CompressionFormat::Uncompressed);
+}
+
+#[test]
+fn compare_gzip_corpus_to_mergecap() {
+    compare_corpus_to_mergecap(CompressionFormat::Gzip);
+}
+
+#[test]
+fn compare_zstd_corpus_to_mergecap() {
+    compare_corpus_to_mergecap(CompressionFormat::Zstd);
+}
+
+#[test]
+fn compressed_corpus_merges_like_uncompressed() {
+    for compression_format in &[CompressionFormat::Gzip, CompressionFormat::Zstd] {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let corpus = small_corpus(tmp_dir.path(), 3, *compression_format);
+        let merged = merge_pcaps_stdout(&corpus.paths);
+        assert!(merged.len() > common::PCAP_HDR_NSEC.len());
+        assert_eq!(
+            merged,
+            merge_pcaps_stdout(&corpus.uncompressed_paths),
+            "{} corpus",
+            compression_format
+        );
+    }
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::NANOSECONDS_PER_SECOND;
+use std::io::prelude::*;
+use std::process::Command;
+
+#[test]
+fn failed_inputs_are_reported_by_exit_code() -> Result<(), Box<dyn std::error::Error>> {
+    let packets: Vec<_> = (0..3)
+        .map(|i| (i * NANOSECONDS_PER_SECOND, vec![i as u8; 40]))
+        .collect();
+    let input = common::nanosecond_pcap(&packets);
+    let merge = |other_input: &std::path::Path| {
+        Command::cargo_bin("merge_pcaps")
+            .unwrap()
+            .arg(input.path())
+            .arg(other_input)
+            .output()
+            .unwrap()
+    };
+
+    // the rest of the merge completes without an input which can't be read
+    let dir = tempfile::tempdir()?;
+    let output = merge(&dir.path().join("missing.pcap"));
+    assert_eq!(output.status.code(), Some(4));
+    assert_eq!(output.stdout, common::nanosecond_pcap_bytes(&packets));
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(stderr.contains("missing.pcap"), "{}", stderr);
+
+    let mut corrupt = tempfile::Builder::new().suffix(".pcap").tempfile()?;
+    corrupt.write_all(b"definitely not a pcap header")?;
+    let output = merge(corrupt.path());
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(output.stdout, common::nanosecond_pcap_bytes(&packets));
+
+    Command::cargo_bin("merge_pcaps")?
+        .arg(input.path())
+        .arg(input.path())
+        .assert()
+        .code(0);
+    Command::cargo_bin("merge_pcaps")?
+        .args(["--max-open-files", "0"])
+        .arg(input.path())
+        .assert()
+        .code(1);
+    Ok(())
+}
+
+#[test]
+fn quiet_only_logs_errors() -> Result<(), Box<dyn std::error::Error>> {
+    let input = common::nanosecond_pcap(&[(NANOSECONDS_PER_SECOND, vec![1; 40])]);
+    let mut corrupt = tempfile::Builder::new().suffix(".pcap").tempfile()?;
+    corrupt.write_all(b"definitely not a pcap header")?;
+    let stderr = |extra_args: &[&str]| {
+        let output = Command::cargo_bin("merge_pcaps")
+            .unwrap()
+            .env("RUST_LOG", "info")
+            .args(extra_args)
+            .arg(input.path())
+            .arg(corrupt.path())
+            .output()
+            .unwrap();
+        String::from_utf8(output.stderr).unwrap()
+    };
+
+    let verbose = stderr(&[]);
+    assert!(
+        verbose.contains("Detected timestamp precision"),
+        "{}",
+        verbose
+    );
+    let quiet = stderr(&["--quiet"]);
+    assert!(!quiet.contains("Detected timestamp precision"), "{}", quiet);
+    assert!(quiet.contains("Skipping file"), "{}", quiet);
+    Ok(())
+}
@@ -0,0 +1,64 @@
+#![cfg(all(feature = "ffi", unix))]
+
+mod common;
+
+use common::NANOSECONDS_PER_SECOND;
+use std::ffi::CString;
+use std::io::Read;
+use std::os::unix::io::AsRawFd;
+use stream_merge::ffi::{smerge_merge_files, SMERGE_EINVAL, SMERGE_EMERGE, SMERGE_OK};
+
+#[test]
+fn files_are_merged_to_a_pipe() {
+    let first = vec![
+        (NANOSECONDS_PER_SECOND, vec![1; 20]),
+        (3 * NANOSECONDS_PER_SECOND, vec![1; 30]),
+    ];
+    let second = vec![
+        (2 * NANOSECONDS_PER_SECOND, vec![2; 40]),
+        (4 * NANOSECONDS_PER_SECOND, vec![2; 50]),
+    ];
+    let inputs = [
+        common::nanosecond_pcap(&first),
+        common::nanosecond_pcap(&second),
+    ];
+    let paths: Vec<CString> = inputs
+        .iter()
+        .map(|input| CString::new(input.path().to_str().unwrap()).unwrap())
+        .collect();
+    let (mut reader, writer) = std::io::pipe().unwrap();
+
+    // read while merging, as a pipe only buffers so much
+    let merge = std::thread::spawn(move || {
+        let paths: Vec<_> = paths.iter().map(|path| path.as_ptr()).collect();
+        let status = unsafe { smerge_merge_files(paths.as_ptr(), paths.len(), writer.as_raw_fd()) };
+        drop(writer);
+        status
+    });
+    let mut merged = Vec::new();
+    reader.read_to_end(&mut merged).unwrap();
+    assert_eq!(merge.join().unwrap(), SMERGE_OK);
+    assert_eq!(
+        common::read_nanosecond_pcap(&merged),
+        vec![
+            first[0].clone(),
+            second[0].clone(),
+            first[1].clone(),
+            second[1].clone()
+        ]
+    );
+}
+
+#[test]
+fn errors_are_returned_as_codes() {
+    let (_reader, writer) = std::io::pipe().unwrap();
+    let missing = CString::new("/nonexistent/input.pcap").unwrap();
+    let status = unsafe { smerge_merge_files(&missing.as_ptr(), 1, writer.as_raw_fd()) };
+    assert_eq!(status, SMERGE_EMERGE);
+
+    let null = std::ptr::null();
+    let status = unsafe { smerge_merge_files(&null, 1, writer.as_raw_fd()) };
+    assert_eq!(status, SMERGE_EINVAL);
+    let status = unsafe { smerge_merge_files(std::ptr::null(), 0, -1) };
+    assert_eq!(status, SMERGE_EINVAL);
+}
@@ -0,0 +1,39 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::NANOSECONDS_PER_SECOND;
+use std::process::Command;
+
+#[test]
+fn idle_warn_merges_normally() -> Result<(), Box<dyn std::error::Error>> {
+    let packets: Vec<_> = (0..10)
+        .map(|i| (i * NANOSECONDS_PER_SECOND, vec![1u8; 40]))
+        .collect();
+    let input = common::nanosecond_pcap(&packets);
+
+    let output = Command::cargo_bin("merge_pcaps")?
+        .args(["--idle-warn", "500ms"])
+        .arg(input.path())
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(output.stdout, common::nanosecond_pcap_bytes(&packets));
+    Ok(())
+}
+
+#[test]
+fn idle_warn_rejects_unknown_units() -> Result<(), Box<dyn std::error::Error>> {
+    let input = common::nanosecond_pcap(&[(0, vec![1u8; 40])]);
+
+    let output = Command::cargo_bin("merge_pcaps")?
+        .args(["--idle-warn", "5 minutes"])
+        .arg(input.path())
+        .output()?;
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(
+        stderr.contains("Invalid duration '5 minutes'"),
+        "{}",
+        stderr
+    );
+    Ok(())
+}
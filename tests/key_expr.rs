@@ -0,0 +1,56 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::NANOSECONDS_PER_SECOND;
+use std::process::Command;
+
+/// A 40-byte payload holding `sequence` as a big-endian `u32` 4 bytes in
+fn sequenced_payload(sequence: u32) -> Vec<u8> {
+    let mut payload = vec![0xAB; 40];
+    payload[4..8].copy_from_slice(&sequence.to_be_bytes());
+    payload
+}
+
+#[test]
+fn merges_by_payload_field() -> Result<(), Box<dyn std::error::Error>> {
+    // each file is sorted by sequence number, but the sequence numbers run backwards in time across files
+    let first = common::nanosecond_pcap(&[
+        (10 * NANOSECONDS_PER_SECOND, sequenced_payload(1)),
+        (11 * NANOSECONDS_PER_SECOND, sequenced_payload(4)),
+    ]);
+    let second = common::nanosecond_pcap(&[
+        (NANOSECONDS_PER_SECOND, sequenced_payload(2)),
+        (2 * NANOSECONDS_PER_SECOND, sequenced_payload(3)),
+    ]);
+
+    let output = Command::cargo_bin("merge_pcaps")?
+        .arg("--key-expr")
+        .arg("u32be@4")
+        .arg(first.path())
+        .arg(second.path())
+        .output()?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8(output.stderr)?
+    );
+    // packets keep their own timestamps
+    assert_eq!(
+        common::read_nanosecond_pcap(&output.stdout),
+        vec![
+            (10 * NANOSECONDS_PER_SECOND, sequenced_payload(1)),
+            (NANOSECONDS_PER_SECOND, sequenced_payload(2)),
+            (2 * NANOSECONDS_PER_SECOND, sequenced_payload(3)),
+            (11 * NANOSECONDS_PER_SECOND, sequenced_payload(4)),
+        ]
+    );
+
+    let output = Command::cargo_bin("merge_pcaps")?
+        .arg("--key-expr")
+        .arg("u32@4")
+        .arg(first.path())
+        .output()?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)?.contains("Unknown key type 'u32'"));
+    Ok(())
+}
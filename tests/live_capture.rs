@@ -0,0 +1,69 @@
+#![cfg(all(feature = "live-capture", target_os = "linux"))]
+//! Requires permission to capture from the loopback interface (root or `CAP_NET_RAW`)
+
+mod common;
+
+use bytes::Bytes;
+use common::NANOSECONDS_PER_SECOND;
+use std::net::UdpSocket;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use stream_merge::tournament_tree::{OwnedTree, PacketStream};
+
+const MARKER: &[u8] = b"stream-merge live capture marker";
+
+type Packets = Box<dyn Iterator<Item = (u64, Bytes)>>;
+
+#[test]
+fn merges_loopback_capture_with_recorded_pcap() -> Result<(), Box<dyn std::error::Error>> {
+    let recorded = common::nanosecond_pcap(
+        &(1..=3)
+            .map(|i| (i * NANOSECONDS_PER_SECOND, vec![i as u8; 60]))
+            .collect::<Vec<_>>(),
+    );
+    let recorded: Packets = Box::new(smol::stream::block_on(
+        stream_merge::stream_and_decode_pcap_packets(recorded.path().to_str().unwrap().to_string()),
+    ));
+    let live: Packets = Box::new(smol::stream::block_on(
+        stream_merge::stream_and_decode_pcap_packets("iface:lo".to_string()),
+    ));
+
+    // keep sending until the marker has been merged, since capture starts some time after the stream is created
+    let sending = Arc::new(AtomicBool::new(true));
+    let sender = {
+        let sending = sending.clone();
+        let socket = UdpSocket::bind("127.0.0.1:0")?;
+        let destination = socket.local_addr()?;
+        std::thread::spawn(move || {
+            while sending.load(Ordering::Relaxed) {
+                socket.send_to(MARKER, destination).unwrap();
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        })
+    };
+
+    let mut merger = OwnedTree::new(vec![PacketStream::new(recorded), PacketStream::new(live)]);
+    let mut merged = Vec::new();
+    while let Some((ts, record)) = merger.pop() {
+        merged.push(ts);
+        let payload = &record[stream_merge::pcap::RECORD_HEADER_LEN..];
+        if payload.windows(MARKER.len()).any(|window| window == MARKER) {
+            break;
+        }
+    }
+    sending.store(false, Ordering::Relaxed);
+    sender.join().unwrap();
+
+    assert_eq!(
+        merged[..3],
+        [
+            NANOSECONDS_PER_SECOND,
+            2 * NANOSECONDS_PER_SECOND,
+            3 * NANOSECONDS_PER_SECOND
+        ]
+    );
+    assert!(merged.len() > 3, "the marker was never captured");
+    assert!(merged.windows(2).all(|pair| pair[0] <= pair[1]));
+    Ok(())
+}
@@ -0,0 +1,71 @@
+mod common;
+
+const N_FILES: u64 = 40;
+const N_PACKETS_PER_FILE: u64 = 5000; // more than a file's read-ahead, so each stays open until it's merged
+const MAX_ACTIVE_INPUTS: usize = 4;
+
+/// Number of file descriptors this process has open, where they can be listed
+fn n_open_descriptors() -> Option<usize> {
+    std::fs::read_dir("/proc/self/fd")
+        .ok()
+        .map(|entries| entries.count())
+}
+
+#[test]
+fn only_the_earliest_inputs_are_open_at_once() {
+    // a single test, so that no other test opens descriptors while they are being counted. Each file overlaps the
+    // three after it in time, and they're given latest first
+    let inputs: Vec<_> = (0..N_FILES)
+        .rev()
+        .map(|file| {
+            common::nanosecond_pcap(
+                &(0..N_PACKETS_PER_FILE)
+                    .map(|i| (file * N_PACKETS_PER_FILE / 4 + i, vec![file as u8; 20]))
+                    .collect::<Vec<_>>(),
+            )
+        })
+        .collect();
+    let paths: Vec<String> = inputs
+        .iter()
+        .map(|input| input.path().to_str().unwrap().to_string())
+        .collect();
+    // start the async runtime first, since its reactor holds descriptors of its own
+    smol::block_on(smol::spawn(async {}));
+    let baseline = n_open_descriptors();
+
+    let mut merged = Vec::new();
+    let mut peak_n_descriptors = 0;
+    let mut n_drained = 0;
+    stream_merge::PcapMerge::new(paths)
+        .max_active_inputs(MAX_ACTIVE_INPUTS)
+        .on_file_eof(|_path| n_drained += 1)
+        .run(|ts, packet| {
+            merged.push((ts, packet[0]));
+            if merged.len() % 1000 == 0 {
+                peak_n_descriptors = peak_n_descriptors.max(n_open_descriptors().unwrap_or(0));
+            }
+        });
+
+    let mut expected: Vec<(u64, u8)> = (0..N_FILES)
+        .flat_map(|file| {
+            (0..N_PACKETS_PER_FILE).map(move |i| (file * N_PACKETS_PER_FILE / 4 + i, file as u8))
+        })
+        .collect();
+    expected.sort_by_key(|(ts, _)| *ts);
+    assert_eq!(merged.len(), expected.len());
+    assert!(merged.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+    let mut sorted = merged.clone();
+    sorted.sort();
+    expected.sort();
+    assert_eq!(sorted, expected);
+    assert_eq!(n_drained, N_FILES);
+    if let Some(baseline) = baseline {
+        // a file still being decoded holds a descriptor open, as one drained may until its decode task ends
+        assert!(
+            peak_n_descriptors <= baseline + MAX_ACTIVE_INPUTS + 1,
+            "{} descriptors open during the merge, from {} before it",
+            peak_n_descriptors,
+            baseline
+        );
+    }
+}
@@ -0,0 +1,64 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::NANOSECONDS_PER_SECOND;
+use std::process::Command;
+
+const START: u64 = 1_637_796_620 * NANOSECONDS_PER_SECOND;
+const MILLISECOND: u64 = 1_000_000;
+
+#[test]
+fn markers_fill_gaps_longer_than_max_gap() -> Result<(), Box<dyn std::error::Error>> {
+    let before_gap = common::nanosecond_pcap(&[
+        (START, vec![1u8; 40]),
+        (START + 50 * MILLISECOND, vec![1u8; 40]),
+    ]);
+    // 10.5s of silence after the last packet before it, then packets close enough together to need no markers
+    let after_gap = common::nanosecond_pcap(&[
+        (
+            START + 50 * MILLISECOND + 10_500 * MILLISECOND,
+            vec![2u8; 40],
+        ),
+        (
+            START + 50 * MILLISECOND + 11_000 * MILLISECOND,
+            vec![2u8; 40],
+        ),
+    ]);
+    let output = Command::cargo_bin("merge_pcaps")?
+        .args(["--max-gap", "1s"])
+        .arg(before_gap.path())
+        .arg(after_gap.path())
+        .output()?;
+    assert!(output.status.success());
+
+    let packets = common::read_nanosecond_pcap(&output.stdout);
+    let markers: Vec<u64> = packets
+        .iter()
+        .filter(|(_, payload)| payload.is_empty())
+        .map(|(ts, _)| *ts)
+        .collect();
+    assert_eq!(
+        markers,
+        (1..=10)
+            .map(|i| START + 50 * MILLISECOND + i * NANOSECONDS_PER_SECOND)
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(packets.len(), 4 + markers.len());
+    for pair in packets.windows(2) {
+        assert!(pair[1].0 - pair[0].0 <= NANOSECONDS_PER_SECOND);
+    }
+
+    // without --max-gap, only the captured packets are written
+    let output = Command::cargo_bin("merge_pcaps")?
+        .arg(before_gap.path())
+        .arg(after_gap.path())
+        .output()?;
+    assert_eq!(common::read_nanosecond_pcap(&output.stdout).len(), 4);
+
+    Command::cargo_bin("merge_pcaps")?
+        .args(["--max-gap", "0s"])
+        .arg(before_gap.path())
+        .assert()
+        .failure();
+    Ok(())
+}
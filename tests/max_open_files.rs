@@ -0,0 +1,107 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use bytes::Bytes;
+use std::process::Command;
+use stream_merge::tournament_tree::{OwnedTree, PacketStream};
+use stream_merge::{s3, OpenFileLimit};
+
+const N_FILES: u64 = 40;
+const N_PACKETS_PER_FILE: u64 = 5000; // several batches, so each file is closed and reopened part way through
+
+type Packets = Box<dyn Iterator<Item = (u64, Bytes)>>;
+
+fn interleaved_inputs() -> Vec<tempfile::NamedTempFile> {
+    (0..N_FILES)
+        .map(|file| {
+            common::nanosecond_pcap(
+                &(0..N_PACKETS_PER_FILE)
+                    .map(|i| (i * N_FILES + file, vec![file as u8; 20]))
+                    .collect::<Vec<_>>(),
+            )
+        })
+        .collect()
+}
+
+/// Number of file descriptors this process has open, where they can be listed
+fn n_open_descriptors() -> Option<usize> {
+    std::fs::read_dir("/proc/self/fd")
+        .ok()
+        .map(|entries| entries.count())
+}
+
+#[test]
+fn open_files_stay_within_the_limit() {
+    // a single test, so that no other test opens descriptors while they are being counted
+    const MAX_OPEN_FILES: usize = 3;
+    let inputs = interleaved_inputs();
+    // start the async runtime first, since its reactor holds descriptors of its own
+    smol::block_on(smol::spawn(async {}));
+    let baseline = n_open_descriptors();
+
+    let limit = OpenFileLimit::new(MAX_OPEN_FILES);
+    let config = s3::DownloadConfig {
+        open_file_limit: Some(limit.clone()),
+        ..Default::default()
+    };
+    let streams = inputs
+        .iter()
+        .map(|input| {
+            let packets: Packets = Box::new(smol::stream::block_on(
+                stream_merge::stream_and_decode_pcap_packets_with(
+                    input.path().to_str().unwrap().to_string(),
+                    config.clone(),
+                ),
+            ));
+            PacketStream::new(packets)
+        })
+        .collect();
+    let mut merger = OwnedTree::new(streams);
+
+    let mut n_merged = 0;
+    let mut peak_n_descriptors = 0;
+    while let Some((ts, _)) = merger.pop() {
+        assert_eq!(ts, n_merged);
+        n_merged += 1;
+        if n_merged % 1000 == 0 {
+            peak_n_descriptors = peak_n_descriptors.max(n_open_descriptors().unwrap_or(0));
+        }
+    }
+    assert_eq!(n_merged, N_FILES * N_PACKETS_PER_FILE);
+    assert!(limit.peak_n_open() <= MAX_OPEN_FILES);
+    assert_eq!(limit.n_open(), 0);
+    if let Some(baseline) = baseline {
+        // the read-ahead of the files waiting to be merged is held in memory, not in open descriptors
+        assert!(
+            peak_n_descriptors <= baseline + MAX_OPEN_FILES,
+            "{} descriptors open during the merge, from {} before it",
+            peak_n_descriptors,
+            baseline
+        );
+    }
+
+    // the binary's limited merge matches its unlimited one, once descriptors are no longer being counted
+    let merge = |extra_args: &[&str]| {
+        let output = Command::cargo_bin("merge_pcaps")
+            .unwrap()
+            .args(extra_args)
+            .args(inputs.iter().map(|input| input.path()))
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        output.stdout
+    };
+    let limited = merge(&["--max-open-files", "2"]);
+    assert_eq!(
+        common::read_nanosecond_pcap(&limited).len() as u64,
+        N_FILES * N_PACKETS_PER_FILE
+    );
+    assert!(limited == merge(&[]));
+
+    Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .args(["--max-open-files", "0"])
+        .arg(inputs[0].path())
+        .assert()
+        .failure();
+}
@@ -0,0 +1,132 @@
+mod common;
+
+use common::NANOSECONDS_PER_SECOND;
+
+#[test]
+fn callback_sees_packets_in_time_order() {
+    let inputs: Vec<_> = (0..3u64)
+        .map(|file| {
+            common::nanosecond_pcap(
+                &(0..50u64)
+                    .map(|i| {
+                        (
+                            (i * 3 + file) * NANOSECONDS_PER_SECOND / 10,
+                            vec![file as u8; 20 + i as usize],
+                        )
+                    })
+                    .collect::<Vec<_>>(),
+            )
+        })
+        .collect();
+    let mut expected: Vec<_> = (0..3u64)
+        .flat_map(|file| {
+            (0..50u64).map(move |i| {
+                (
+                    (i * 3 + file) * NANOSECONDS_PER_SECOND / 10,
+                    vec![file as u8; 20 + i as usize],
+                )
+            })
+        })
+        .collect();
+    expected.sort();
+
+    let mut seen = Vec::new();
+    stream_merge::merge_pcap_streams_with(
+        inputs
+            .iter()
+            .map(|input| input.path().to_str().unwrap().to_string())
+            .collect(),
+        |ts, packet| seen.push((ts, packet.to_vec())),
+    );
+    assert_eq!(seen, expected);
+}
+
+#[test]
+fn eof_callback_fires_once_per_file_as_each_is_drained() {
+    // the second file ends first and the first file last, unlike the order they're given in
+    let last_packet_ts = [30u64, 10, 20];
+    let inputs: Vec<_> = last_packet_ts
+        .iter()
+        .enumerate()
+        .map(|(file, last_ts)| {
+            common::nanosecond_pcap(
+                &(file as u64..=*last_ts)
+                    .step_by(3)
+                    .map(|ts| (ts, vec![file as u8]))
+                    .collect::<Vec<_>>(),
+            )
+        })
+        .collect();
+    let paths: Vec<String> = inputs
+        .iter()
+        .map(|input| input.path().to_str().unwrap().to_string())
+        .collect();
+
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        Packet(u8),
+        Eof(String),
+    }
+    let events = std::cell::RefCell::new(Vec::new());
+    stream_merge::PcapMerge::new(paths.clone())
+        .on_file_eof(|path| events.borrow_mut().push(Event::Eof(path.to_string())))
+        .run(|_ts, packet| events.borrow_mut().push(Event::Packet(packet[0])));
+    let events = events.into_inner();
+
+    let eofs: Vec<_> = events
+        .iter()
+        .filter_map(|event| match event {
+            Event::Eof(path) => Some(path.clone()),
+            Event::Packet(_) => None,
+        })
+        .collect();
+    assert_eq!(
+        eofs,
+        vec![paths[1].clone(), paths[2].clone(), paths[0].clone()]
+    );
+    // each file's EOF follows its last packet, and precedes the next packet merged from any other file
+    for (file, path) in paths.iter().enumerate() {
+        let eof = events
+            .iter()
+            .position(|event| *event == Event::Eof(path.clone()))
+            .unwrap();
+        let last_packet = events
+            .iter()
+            .rposition(|event| *event == Event::Packet(file as u8))
+            .unwrap();
+        assert!(eof > last_packet);
+        assert!(events[last_packet + 1..eof]
+            .iter()
+            .all(|event| matches!(event, Event::Eof(_))));
+    }
+}
+
+#[test]
+fn inputs_whose_first_packet_cant_be_read_are_skipped_by_an_active_set_merge() {
+    let packets = [(1, vec![1]), (2, vec![2])];
+    let input = common::nanosecond_pcap(&packets);
+    let mut corrupt = tempfile::Builder::new().suffix(".pcap").tempfile().unwrap();
+    std::io::Write::write_all(&mut corrupt, b"definitely not a pcap header").unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let paths: Vec<String> = [
+        corrupt.path(),
+        input.path(),
+        &dir.path().join("missing.pcap"),
+    ]
+    .iter()
+    .map(|path| path.to_str().unwrap().to_string())
+    .collect();
+
+    let mut merged = Vec::new();
+    let drained = std::cell::RefCell::new(Vec::new());
+    stream_merge::PcapMerge::new(paths.clone())
+        .max_active_inputs(1)
+        .on_file_eof(|path| drained.borrow_mut().push(path.to_string()))
+        .run(|ts, packet| merged.push((ts, packet.to_vec())));
+    assert_eq!(merged, packets);
+    // those skipped are drained before the merge starts
+    assert_eq!(
+        drained.into_inner(),
+        vec![paths[0].clone(), paths[2].clone(), paths[1].clone()]
+    );
+}
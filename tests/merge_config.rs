@@ -0,0 +1,128 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::NANOSECONDS_PER_SECOND;
+use std::io::prelude::*;
+use std::process::Command;
+use stream_merge::config::{InputConfig, MergeConfig, TimeWindow};
+use stream_merge::pcap::OutputFormat;
+
+#[test]
+fn config_file_merge_matches_equivalent_command_line_flags(
+) -> Result<(), Box<dyn std::error::Error>> {
+    let first = common::nanosecond_pcap(
+        &(0..10)
+            .map(|i| (i * 2 * NANOSECONDS_PER_SECOND, vec![1u8; 40]))
+            .collect::<Vec<_>>(),
+    );
+    let second = common::nanosecond_pcap(
+        &(0..10)
+            .map(|i| ((i * 2 + 1) * NANOSECONDS_PER_SECOND, vec![2u8; 60]))
+            .collect::<Vec<_>>(),
+    );
+    let (first_path, second_path) = (
+        first.path().to_str().unwrap(),
+        second.path().to_str().unwrap(),
+    );
+
+    let mut config_file = tempfile::Builder::new().suffix(".json").tempfile()?;
+    write!(
+        config_file,
+        r#"{{
+            "inputs": [{{ "path": "{}" }}, {{ "path": "{}" }}],
+            "window": {{ "start_ns": {}, "end_ns": {} }},
+            "format": "length-prefixed"
+        }}"#,
+        first_path,
+        second_path,
+        3 * NANOSECONDS_PER_SECOND,
+        12 * NANOSECONDS_PER_SECOND
+    )?;
+
+    let config = MergeConfig::from_path(config_file.path())?;
+    assert_eq!(
+        config,
+        MergeConfig {
+            inputs: vec![
+                InputConfig::new(first_path.to_string()),
+                InputConfig::new(second_path.to_string())
+            ],
+            window: TimeWindow {
+                start_ns: Some(3 * NANOSECONDS_PER_SECOND),
+                end_ns: Some(12 * NANOSECONDS_PER_SECOND),
+            },
+            output: None,
+            format: Some(OutputFormat::LengthPrefixed),
+            compression: None,
+        }
+    );
+
+    let from_config = Command::cargo_bin("merge_pcaps")?
+        .arg("--config")
+        .arg(config_file.path())
+        .unwrap()
+        .stdout;
+    let from_flags = Command::cargo_bin("merge_pcaps")?
+        .args(["--format", "length-prefixed"])
+        .args(["--start-ns", &(3 * NANOSECONDS_PER_SECOND).to_string()])
+        .args(["--end-ns", &(12 * NANOSECONDS_PER_SECOND).to_string()])
+        .arg(first_path)
+        .arg(second_path)
+        .unwrap()
+        .stdout;
+    assert_eq!(from_config, from_flags);
+    // packets at seconds 3 through 11 inclusive, each framed with a 12 byte prefix
+    assert_eq!(from_config.len(), 5 * (12 + 60) + 4 * (12 + 40));
+
+    Ok(())
+}
+
+#[test]
+fn command_line_flags_override_config_values() -> Result<(), Box<dyn std::error::Error>> {
+    let input = common::nanosecond_pcap(&[(NANOSECONDS_PER_SECOND, vec![1u8; 10])]);
+    let mut config_file = tempfile::Builder::new().suffix(".json").tempfile()?;
+    write!(
+        config_file,
+        r#"{{ "inputs": [{{ "path": "{}", "offset_ns": 5 }}], "format": "length-prefixed" }}"#,
+        input.path().to_str().unwrap()
+    )?;
+
+    let output = Command::cargo_bin("merge_pcaps")?
+        .arg("--config")
+        .arg(config_file.path())
+        .args(["--format", "pcap"])
+        .unwrap()
+        .stdout;
+    assert_eq!(
+        common::read_nanosecond_pcap(&output),
+        vec![(NANOSECONDS_PER_SECOND + 5, vec![1u8; 10])]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn a_merge_ending_at_its_window_does_not_warn_of_abandoned_inputs(
+) -> Result<(), Box<dyn std::error::Error>> {
+    let input = common::nanosecond_pcap(
+        &(0..1000)
+            .map(|i| (i * NANOSECONDS_PER_SECOND, vec![1u8; 40]))
+            .collect::<Vec<_>>(),
+    );
+
+    let output = Command::cargo_bin("merge_pcaps")?
+        .env("RUST_LOG", "warn")
+        .args(["--end-ns", &(10 * NANOSECONDS_PER_SECOND).to_string()])
+        .arg(input.path())
+        .output()?;
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(common::read_nanosecond_pcap(&output.stdout).len(), 10);
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(
+        !stderr.contains("dropped before reaching the end"),
+        "{}",
+        stderr
+    );
+
+    Ok(())
+}
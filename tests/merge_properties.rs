@@ -0,0 +1,93 @@
+mod common;
+
+use proptest::prelude::*;
+use std::io::Write;
+use stream_merge::compression::Compression;
+
+const START: u64 = 1_637_796_620 * common::NANOSECONDS_PER_SECOND;
+
+/// A packet's `(nanosecond timestamp, payload)`
+type Packet = (u64, Vec<u8>);
+
+/// An input's packets, in time order, and how its file is compressed
+#[derive(Clone, Debug)]
+struct Input {
+    compression: Compression,
+    packets: Vec<Packet>,
+}
+
+/// Sorted packets generated as the gaps between them, so that shrinking a gap (towards a tie with the packet before)
+/// keeps the input sorted. Each payload is a pattern of its length, shrinking towards empty, and a seed byte
+fn input() -> impl Strategy<Value = Input> {
+    let compression = prop_oneof![
+        Just(Compression::None),
+        Just(Compression::Gzip),
+        Just(Compression::Zstd),
+    ];
+    let gaps_and_payloads = prop::collection::vec(
+        (
+            0..3 * common::NANOSECONDS_PER_SECOND,
+            0..2000usize,
+            any::<u8>(),
+        ),
+        0..150,
+    );
+    (compression, gaps_and_payloads).prop_map(|(compression, gaps_and_payloads)| {
+        let mut ts = START;
+        let packets = gaps_and_payloads
+            .into_iter()
+            .map(|(gap, len, seed)| {
+                ts += gap;
+                (
+                    ts,
+                    (0..len)
+                        .map(|i| (i as u8).wrapping_mul(seed) ^ seed)
+                        .collect(),
+                )
+            })
+            .collect();
+        Input {
+            compression,
+            packets,
+        }
+    })
+}
+
+/// Write `input` as a nanosecond pcap in `dir`, compressed as it says, returning its path
+fn write_input(dir: &std::path::Path, n: usize, input: &Input) -> String {
+    let extension = match input.compression {
+        Compression::Gzip => ".gz",
+        Compression::Zstd => ".zst",
+        _ => "",
+    };
+    let path = dir.join(format!("input_{}.pcap{}", n, extension));
+    let file = std::fs::File::create(&path).unwrap();
+    let mut encoder = input.compression.encoder(file).unwrap();
+    encoder
+        .write_all(&common::nanosecond_pcap_bytes(&input.packets))
+        .unwrap();
+    encoder.finish().unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+proptest! {
+    #[test]
+    fn merged_output_is_the_sorted_union_of_the_inputs(inputs in prop::collection::vec(input(), 1..5)) {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = inputs
+            .iter()
+            .enumerate()
+            .map(|(n, input)| write_input(dir.path(), n, input))
+            .collect();
+
+        let mut merged = Vec::new();
+        stream_merge::merge_pcap_streams_with(paths, |ts, packet| merged.push((ts, packet.to_vec())));
+
+        // packets sharing a timestamp may be merged in any order
+        prop_assert!(merged.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+        let mut expected: Vec<Packet> = inputs.into_iter().flat_map(|input| input.packets).collect();
+        expected.sort();
+        merged.sort();
+        prop_assert_eq!(merged, expected);
+    }
+}
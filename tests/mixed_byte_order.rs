@@ -0,0 +1,106 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::{NANOSECONDS_PER_SECOND, PCAP_HDR_NSEC};
+use std::io::Write;
+use std::process::Command;
+
+/// Bytes the original packet was longer than the bytes captured of it, distinguishing the two record lengths
+const N_BYTES_NOT_CAPTURED: u32 = 10;
+
+/// Write a nanosecond-precision pcap of `packets` to a temporary file, with its header and every record in big-endian
+/// byte order if `is_bigendian`, otherwise little-endian
+fn pcap(is_bigendian: bool, packets: &[(u64, Vec<u8>)]) -> tempfile::NamedTempFile {
+    let u32_bytes = |value: u32| {
+        if is_bigendian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        }
+    };
+    let mut bytes = u32_bytes(0xA1B2_3C4D).to_vec();
+    for version in &[2u16, 4] {
+        if is_bigendian {
+            bytes.extend_from_slice(&version.to_be_bytes());
+        } else {
+            bytes.extend_from_slice(&version.to_le_bytes());
+        }
+    }
+    for field in &[0, 0, 262_144, 1] {
+        bytes.extend_from_slice(&u32_bytes(*field)); // thiszone, sigfigs, snaplen, linktype
+    }
+    for (ts, payload) in packets {
+        bytes.extend_from_slice(&u32_bytes((ts / NANOSECONDS_PER_SECOND) as u32));
+        bytes.extend_from_slice(&u32_bytes((ts % NANOSECONDS_PER_SECOND) as u32));
+        bytes.extend_from_slice(&u32_bytes(payload.len() as u32));
+        bytes.extend_from_slice(&u32_bytes(payload.len() as u32 + N_BYTES_NOT_CAPTURED));
+        bytes.extend_from_slice(payload);
+    }
+    let mut file = tempfile::Builder::new().suffix(".pcap").tempfile().unwrap();
+    file.write_all(&bytes).unwrap();
+    file.flush().unwrap();
+    file
+}
+
+#[test]
+fn little_and_big_endian_pcaps_merge_into_little_endian_output() {
+    let little: Vec<_> = (0..20u64)
+        .map(|i| (i * 2 * NANOSECONDS_PER_SECOND + i, vec![1, i as u8]))
+        .collect();
+    let big: Vec<_> = (0..20u64)
+        .map(|i| {
+            (
+                (i * 2 + 1) * NANOSECONDS_PER_SECOND + i,
+                vec![2, i as u8, 0xFF],
+            )
+        })
+        .collect();
+    let mut expected: Vec<_> = little.iter().chain(&big).cloned().collect();
+    expected.sort();
+    let inputs = [pcap(false, &little), pcap(true, &big)];
+
+    for order in &[[0, 1], [1, 0]] {
+        let output = Command::cargo_bin("merge_pcaps")
+            .unwrap()
+            .args(order.iter().map(|i| inputs[*i].path()))
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        assert_eq!(output.stdout[..PCAP_HDR_NSEC.len()], PCAP_HDR_NSEC[..]);
+        let merged = common::read_nanosecond_pcap(&output.stdout);
+        assert_eq!(merged, expected);
+        assert!(merged.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+
+        // both lengths of every record are written little-endian, whichever byte order it was read in
+        let mut rest = &output.stdout[PCAP_HDR_NSEC.len()..];
+        while !rest.is_empty() {
+            let field = |offset: usize| {
+                u32::from_le_bytes([
+                    rest[offset],
+                    rest[offset + 1],
+                    rest[offset + 2],
+                    rest[offset + 3],
+                ])
+            };
+            let (caplen, original_len) = (field(8), field(12));
+            assert_eq!(original_len, caplen + N_BYTES_NOT_CAPTURED);
+            rest = &rest[16 + caplen as usize..];
+        }
+    }
+}
+
+#[test]
+fn a_lone_big_endian_pcap_is_rewritten_little_endian() {
+    // unlike a little-endian one, its records can't be copied verbatim
+    let packets: Vec<_> = (0..5u64)
+        .map(|i| (i * NANOSECONDS_PER_SECOND, vec![i as u8; 4]))
+        .collect();
+    let input = pcap(true, &packets);
+    let output = Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .arg(input.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(common::read_nanosecond_pcap(&output.stdout), packets);
+}
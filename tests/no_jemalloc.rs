@@ -0,0 +1,31 @@
+//! Builds `merge_pcaps` again without its default features, in a target directory of its own, so takes as long as a
+//! clean build the first time it's run. Ignored by a plain `cargo test` for that reason: run it as a step of its own
+//! with `cargo test --test no_jemalloc -- --ignored`, or check the build alone with
+//! `cargo check --no-default-features`.
+
+use std::path::Path;
+use std::process::Command;
+
+#[test]
+#[ignore = "builds the crate again from scratch"]
+fn merge_pcaps_builds_with_the_system_allocator() -> Result<(), Box<dyn std::error::Error>> {
+    let target_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("no-default-features");
+    let build = Command::new(env!("CARGO"))
+        .args(["build", "--bin", "merge_pcaps", "--no-default-features"])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .env("CARGO_TARGET_DIR", &target_dir)
+        .output()?;
+    assert!(
+        build.status.success(),
+        "{}",
+        String::from_utf8_lossy(&build.stderr)
+    );
+
+    let output = Command::new(target_dir.join("debug").join("merge_pcaps"))
+        .arg("--capabilities")
+        .output()?;
+    assert!(output.status.success(), "{:?}", output);
+    let capabilities: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(capabilities["features"], serde_json::json!([]));
+    Ok(())
+}
@@ -0,0 +1,120 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::NANOSECONDS_PER_SECOND;
+use std::io::Write;
+use std::process::Command;
+
+#[test]
+fn each_input_is_written_to_its_own_file() -> Result<(), Box<dyn std::error::Error>> {
+    let first = vec![
+        (NANOSECONDS_PER_SECOND, vec![1; 20]),
+        (3 * NANOSECONDS_PER_SECOND, vec![1; 30]),
+    ];
+    let second = vec![
+        (2 * NANOSECONDS_PER_SECOND, vec![2; 40]),
+        (4 * NANOSECONDS_PER_SECOND, vec![2; 50]),
+    ];
+    let input_dir = tempfile::tempdir()?;
+    std::fs::write(
+        input_dir.path().join("first.pcap"),
+        common::nanosecond_pcap_bytes(&first),
+    )?;
+    let mut encoder = flate2::write::GzEncoder::new(
+        std::fs::File::create(input_dir.path().join("second.pcap.gz"))?,
+        flate2::Compression::default(),
+    );
+    encoder.write_all(&common::nanosecond_pcap_bytes(&second))?;
+    encoder.finish()?;
+    let output_dir = tempfile::tempdir()?;
+
+    Command::cargo_bin("merge_pcaps")?
+        .arg("--no-merge")
+        .arg("--output-dir")
+        .arg(output_dir.path())
+        .arg(input_dir.path().join("first.pcap"))
+        .arg(input_dir.path().join("second.pcap.gz"))
+        .assert()
+        .success()
+        .stdout("");
+
+    let mut names: Vec<_> = std::fs::read_dir(output_dir.path())?
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["first.pcap", "second.pcap"]);
+    let read = |name: &str| {
+        common::read_nanosecond_pcap(&std::fs::read(output_dir.path().join(name)).unwrap())
+    };
+    assert_eq!(read("first.pcap"), first);
+    assert_eq!(read("second.pcap"), second);
+    Ok(())
+}
+
+#[test]
+fn each_input_is_rebased_and_windowed_on_its_own() -> Result<(), Box<dyn std::error::Error>> {
+    let input_dir = tempfile::tempdir()?;
+    let write = |name: &str, seconds: &[u64]| {
+        let packets: Vec<_> = seconds
+            .iter()
+            .map(|s| (s * NANOSECONDS_PER_SECOND, vec![*s as u8; 20]))
+            .collect();
+        std::fs::write(
+            input_dir.path().join(name),
+            common::nanosecond_pcap_bytes(&packets),
+        )
+        .unwrap();
+    };
+    // the first input is cut short by the window's end, and the second still streamed after it
+    write("early.pcap", &[1, 2, 3, 8]);
+    write("late.pcap", &[5, 6, 9]);
+    let output_dir = tempfile::tempdir()?;
+
+    Command::cargo_bin("merge_pcaps")?
+        .args(["--no-merge", "--rebase-to-zero"])
+        .args(["--end-ns", &(7 * NANOSECONDS_PER_SECOND).to_string()])
+        .arg("--output-dir")
+        .arg(output_dir.path())
+        .arg(input_dir.path().join("early.pcap"))
+        .arg(input_dir.path().join("late.pcap"))
+        .assert()
+        .success();
+
+    let read = |name: &str| -> Vec<(u64, u8)> {
+        common::read_nanosecond_pcap(&std::fs::read(output_dir.path().join(name)).unwrap())
+            .into_iter()
+            .map(|(ts, payload)| (ts, payload[0]))
+            .collect()
+    };
+    assert_eq!(
+        read("early.pcap"),
+        vec![
+            (0, 1),
+            (NANOSECONDS_PER_SECOND, 2),
+            (2 * NANOSECONDS_PER_SECOND, 3)
+        ]
+    );
+    assert_eq!(read("late.pcap"), vec![(0, 5), (NANOSECONDS_PER_SECOND, 6)]);
+    Ok(())
+}
+
+#[test]
+fn inputs_of_the_same_name_are_rejected() {
+    let dirs = [tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap()];
+    for dir in &dirs {
+        std::fs::write(
+            dir.path().join("capture.pcap"),
+            common::nanosecond_pcap_bytes(&[(1, vec![0; 20])]),
+        )
+        .unwrap();
+    }
+    let output_dir = tempfile::tempdir().unwrap();
+    Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .arg("--no-merge")
+        .arg("--output-dir")
+        .arg(output_dir.path())
+        .args(dirs.iter().map(|dir| dir.path().join("capture.pcap")))
+        .assert()
+        .failure();
+}
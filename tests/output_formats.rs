@@ -0,0 +1,77 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::NANOSECONDS_PER_SECOND;
+use std::process::Command;
+
+#[test]
+fn length_prefixed_output_frames_each_packet() -> Result<(), Box<dyn std::error::Error>> {
+    let first = common::nanosecond_pcap(&[
+        (NANOSECONDS_PER_SECOND, vec![1u8; 10]),
+        (3 * NANOSECONDS_PER_SECOND + 5, vec![3u8; 30]),
+    ]);
+    let second = common::nanosecond_pcap(&[(2 * NANOSECONDS_PER_SECOND + 7, vec![2u8; 20])]);
+
+    let mut merge_pcaps = Command::cargo_bin("merge_pcaps")?;
+    merge_pcaps.stderr(std::process::Stdio::inherit());
+    merge_pcaps
+        .arg("--format")
+        .arg("length-prefixed")
+        .arg(first.path())
+        .arg(second.path());
+    let output = merge_pcaps.unwrap().stdout;
+
+    // decode the framing: u64 timestamp, u32 length, then the payload
+    let mut decoded = Vec::new();
+    let mut rest = &output[..];
+    while !rest.is_empty() {
+        let mut ts = [0u8; 8];
+        ts.copy_from_slice(&rest[..8]);
+        let mut len = [0u8; 4];
+        len.copy_from_slice(&rest[8..12]);
+        let len = u32::from_le_bytes(len) as usize;
+        decoded.push((u64::from_le_bytes(ts), rest[12..12 + len].to_vec()));
+        rest = &rest[12 + len..];
+    }
+
+    assert_eq!(
+        decoded,
+        vec![
+            (NANOSECONDS_PER_SECOND, vec![1u8; 10]),
+            (2 * NANOSECONDS_PER_SECOND + 7, vec![2u8; 20]),
+            (3 * NANOSECONDS_PER_SECOND + 5, vec![3u8; 30]),
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn fsync_output_file_has_merged_contents() -> Result<(), Box<dyn std::error::Error>> {
+    let first = common::nanosecond_pcap(&[
+        (NANOSECONDS_PER_SECOND, vec![1u8; 10]),
+        (3 * NANOSECONDS_PER_SECOND, vec![3u8; 30]),
+    ]);
+    let second = common::nanosecond_pcap(&[(2 * NANOSECONDS_PER_SECOND, vec![2u8; 20])]);
+    let output_dir = tempfile::tempdir()?;
+    let output_path = output_dir.path().join("merged.pcap");
+
+    Command::cargo_bin("merge_pcaps")?
+        .arg("--fsync")
+        .arg("-o")
+        .arg(&output_path)
+        .arg(first.path())
+        .arg(second.path())
+        .assert()
+        .success();
+
+    assert_eq!(
+        std::fs::read(&output_path)?,
+        common::nanosecond_pcap_bytes(&[
+            (NANOSECONDS_PER_SECOND, vec![1u8; 10]),
+            (2 * NANOSECONDS_PER_SECOND, vec![2u8; 20]),
+            (3 * NANOSECONDS_PER_SECOND, vec![3u8; 30]),
+        ])
+    );
+    Ok(())
+}
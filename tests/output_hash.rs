@@ -0,0 +1,70 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::NANOSECONDS_PER_SECOND;
+use sha2::Digest;
+use std::process::Command;
+
+fn sha256(bytes: &[u8]) -> String {
+    format!("{:x}", sha2::Sha256::digest(bytes))
+}
+
+#[test]
+fn reported_hash_matches_the_output() -> Result<(), Box<dyn std::error::Error>> {
+    let inputs: Vec<_> = (0..2u64)
+        .map(|file| {
+            common::nanosecond_pcap(
+                &(0..100)
+                    .map(|i| (i * NANOSECONDS_PER_SECOND + file, vec![file as u8; 60]))
+                    .collect::<Vec<_>>(),
+            )
+        })
+        .collect();
+    let dir = tempfile::tempdir()?;
+
+    for compress in &["none", "gzip"] {
+        let path = dir.path().join(format!("merged-{}.pcap", compress));
+        let output = Command::cargo_bin("merge_pcaps")?
+            .args(["--output-hash", "sha256", "--compress", compress, "-o"])
+            .arg(&path)
+            .args(inputs.iter().map(|input| input.path()))
+            .output()?;
+        assert!(output.status.success());
+        // the hash is of the bytes written, i.e. of the compressed output
+        let expected = format!("{}  {}\n", sha256(&std::fs::read(&path)?), path.display());
+        assert_eq!(String::from_utf8(output.stderr)?, expected);
+    }
+
+    let output = Command::cargo_bin("merge_pcaps")?
+        .args(["--output-hash", "sha256"])
+        .args(inputs.iter().map(|input| input.path()))
+        .output()?;
+    assert!(output.status.success());
+    let expected = format!("{}  -\n", sha256(&output.stdout));
+    assert_eq!(String::from_utf8(output.stderr)?, expected);
+
+    // a lone input which is copied verbatim is hashed all the same
+    let output = Command::cargo_bin("merge_pcaps")?
+        .args(["--output-hash", "sha256"])
+        .arg(inputs[0].path())
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(output.stdout, std::fs::read(inputs[0].path())?);
+    let expected = format!("{}  -\n", sha256(&output.stdout));
+    assert_eq!(String::from_utf8(output.stderr)?, expected);
+
+    let output = Command::cargo_bin("merge_pcaps")?
+        .args(["--output-hash", "xxh3"])
+        .args(inputs.iter().map(|input| input.path()))
+        .output()?;
+    assert!(output.status.success());
+    let expected = format!("{:016x}  -\n", xxhash_rust::xxh3::xxh3_64(&output.stdout));
+    assert_eq!(String::from_utf8(output.stderr)?, expected);
+
+    Command::cargo_bin("merge_pcaps")?
+        .args(["--output-hash", "md5"])
+        .arg(inputs[0].path())
+        .assert()
+        .failure();
+    Ok(())
+}
@@ -0,0 +1,109 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::NANOSECONDS_PER_SECOND;
+use std::io::prelude::*;
+use std::process::Command;
+
+const START: u64 = 1_637_796_620 * NANOSECONDS_PER_SECOND;
+
+fn timestamps(output: &[u8]) -> Vec<u64> {
+    common::read_nanosecond_pcap(output)
+        .into_iter()
+        .map(|(ts, _)| ts)
+        .collect()
+}
+
+#[test]
+fn rebase_to_zero_preserves_spacing_after_per_file_offsets(
+) -> Result<(), Box<dyn std::error::Error>> {
+    let first = common::nanosecond_pcap(
+        &(0..5)
+            .map(|i| (START + i * 2 * NANOSECONDS_PER_SECOND, vec![1u8; 40]))
+            .collect::<Vec<_>>(),
+    );
+    let second = common::nanosecond_pcap(
+        &(0..5)
+            .map(|i| (START + i * 2 * NANOSECONDS_PER_SECOND, vec![2u8; 40]))
+            .collect::<Vec<_>>(),
+    );
+    // the second file is shifted a second earlier first, so that it leads the merge
+    let mut config_file = tempfile::Builder::new().suffix(".json").tempfile()?;
+    write!(
+        config_file,
+        r#"{{ "inputs": [{{ "path": "{}" }}, {{ "path": "{}", "offset_ns": -{} }}] }}"#,
+        first.path().to_str().unwrap(),
+        second.path().to_str().unwrap(),
+        NANOSECONDS_PER_SECOND
+    )?;
+
+    let unshifted = Command::cargo_bin("merge_pcaps")?
+        .arg("--config")
+        .arg(config_file.path())
+        .unwrap()
+        .stdout;
+    let unshifted = timestamps(&unshifted);
+    assert_eq!(unshifted[0], START - NANOSECONDS_PER_SECOND);
+
+    let rebased = Command::cargo_bin("merge_pcaps")?
+        .arg("--rebase-to-zero")
+        .arg("--config")
+        .arg(config_file.path())
+        .unwrap()
+        .stdout;
+    let rebased = timestamps(&rebased);
+    assert_eq!(rebased[0], 0);
+    assert_eq!(
+        rebased,
+        unshifted
+            .iter()
+            .map(|ts| ts - unshifted[0])
+            .collect::<Vec<_>>()
+    );
+
+    // a window selects packets by their timestamps before the output is shifted
+    let windowed = Command::cargo_bin("merge_pcaps")?
+        .arg("--rebase-to-zero")
+        .args([
+            "--start-ns",
+            &(START + 3 * NANOSECONDS_PER_SECOND).to_string(),
+        ])
+        .arg("--config")
+        .arg(config_file.path())
+        .unwrap()
+        .stdout;
+    assert_eq!(
+        timestamps(&windowed)[..3],
+        [0, NANOSECONDS_PER_SECOND, 2 * NANOSECONDS_PER_SECOND]
+    );
+    Ok(())
+}
+
+#[test]
+fn output_offset_shifts_every_packet() -> Result<(), Box<dyn std::error::Error>> {
+    let input = common::nanosecond_pcap(
+        &(1..=3)
+            .map(|i| (START + i * NANOSECONDS_PER_SECOND, vec![1u8; 40]))
+            .collect::<Vec<_>>(),
+    );
+    let shifted = Command::cargo_bin("merge_pcaps")?
+        .args(["--output-offset", &format!("-{}", START)])
+        .arg(input.path())
+        .unwrap()
+        .stdout;
+    assert_eq!(
+        timestamps(&shifted),
+        [
+            NANOSECONDS_PER_SECOND,
+            2 * NANOSECONDS_PER_SECOND,
+            3 * NANOSECONDS_PER_SECOND
+        ]
+    );
+
+    Command::cargo_bin("merge_pcaps")?
+        .args(["--output-offset", "5", "--rebase-to-zero"])
+        .arg(input.path())
+        .assert()
+        .failure();
+    Ok(())
+}
@@ -0,0 +1,142 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::NANOSECONDS_PER_SECOND;
+use std::io::Write;
+use std::process::Command;
+
+/// Append a little-endian pcapng block of type `block_type` with the given `body` to `out`
+fn push_block(out: &mut Vec<u8>, block_type: u32, body: &[u8]) {
+    let block_len = (8 + body.len() + 4) as u32;
+    out.extend_from_slice(&block_type.to_le_bytes());
+    out.extend_from_slice(&block_len.to_le_bytes());
+    out.extend_from_slice(body);
+    out.extend_from_slice(&block_len.to_le_bytes());
+}
+
+/// `(nanosecond timestamp, payload, epb_flags, epb_dropcount)` of a packet written by [pcapng]
+type Packet = (u64, Vec<u8>, Option<u32>, Option<u64>);
+
+/// Write a little-endian pcapng to a temporary file ending in `.pcapng`, with a nanosecond interface and then one
+/// the `packets` are captured on
+fn pcapng(packets: &[Packet]) -> tempfile::NamedTempFile {
+    let mut bytes = Vec::new();
+    let mut shb = 0x1A2B_3C4Du32.to_le_bytes().to_vec();
+    shb.extend_from_slice(&[1, 0, 0, 0]);
+    shb.extend_from_slice(&(-1i64).to_le_bytes());
+    push_block(&mut bytes, 0x0A0D_0D0A, &shb);
+    for _ in 0..2 {
+        // Ethernet, no snaplen, if_tsresol of nanoseconds
+        push_block(
+            &mut bytes,
+            1,
+            &[1, 0, 0, 0, 0, 0, 0, 0, 9, 0, 1, 0, 9, 0, 0, 0, 0, 0, 0, 0],
+        );
+    }
+    for (ts, payload, flags, drop_count) in packets {
+        let mut epb = Vec::new();
+        for field in [
+            1,
+            (ts >> 32) as u32,
+            *ts as u32,
+            payload.len() as u32,
+            payload.len() as u32,
+        ] {
+            epb.extend_from_slice(&field.to_le_bytes());
+        }
+        epb.extend_from_slice(payload);
+        epb.resize(epb.len().div_ceil(4) * 4, 0);
+        if let Some(flags) = flags {
+            epb.extend_from_slice(&[2, 0, 4, 0]);
+            epb.extend_from_slice(&flags.to_le_bytes());
+        }
+        if let Some(drop_count) = drop_count {
+            epb.extend_from_slice(&[4, 0, 8, 0]);
+            epb.extend_from_slice(&drop_count.to_le_bytes());
+        }
+        epb.extend_from_slice(&[0; 4]);
+        push_block(&mut bytes, 6, &epb);
+    }
+    let mut file = tempfile::Builder::new()
+        .suffix(".pcapng")
+        .tempfile()
+        .unwrap();
+    file.write_all(&bytes).unwrap();
+    file.flush().unwrap();
+    file
+}
+
+/// `(source, interface_id, direction, drop_count, ts, payload)` of a frame of source-tagged, length-prefixed output with
+/// packet metadata
+type Frame = (u32, u32, u8, u64, u64, Vec<u8>);
+
+fn read_frames(mut bytes: &[u8]) -> Vec<Frame> {
+    let u32_at = |bytes: &[u8], at: usize| {
+        let mut field = [0; 4];
+        field.copy_from_slice(&bytes[at..at + 4]);
+        u32::from_le_bytes(field)
+    };
+    let u64_at = |bytes: &[u8], at: usize| {
+        let mut field = [0; 8];
+        field.copy_from_slice(&bytes[at..at + 8]);
+        u64::from_le_bytes(field)
+    };
+    let mut frames = Vec::new();
+    while !bytes.is_empty() {
+        let len = u32_at(bytes, 25) as usize;
+        frames.push((
+            u32_at(bytes, 0),
+            u32_at(bytes, 4),
+            bytes[8],
+            u64_at(bytes, 9),
+            u64_at(bytes, 17),
+            bytes[29..29 + len].to_vec(),
+        ));
+        bytes = &bytes[29 + len..];
+    }
+    frames
+}
+
+#[test]
+fn pcapng_metadata_is_carried_through_the_merge_into_each_frame() {
+    let captured = pcapng(&[
+        (NANOSECONDS_PER_SECOND, vec![1, 1], Some(1), Some(7)),
+        (3 * NANOSECONDS_PER_SECOND, vec![1, 2, 3], Some(2), None),
+        (4 * NANOSECONDS_PER_SECOND, vec![1, 3], None, None),
+    ]);
+    let plain = common::nanosecond_pcap(&[(2 * NANOSECONDS_PER_SECOND, vec![2, 1])]);
+
+    let output = Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .args([
+            "--format",
+            "length-prefixed",
+            "--tag-source",
+            "--packet-meta",
+        ])
+        .arg(captured.path())
+        .arg(plain.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        read_frames(&output.stdout),
+        vec![
+            (0, 1, 1, 7, NANOSECONDS_PER_SECOND, vec![1, 1]),
+            (1, 0, 0, u64::MAX, 2 * NANOSECONDS_PER_SECOND, vec![2, 1]),
+            (0, 1, 2, u64::MAX, 3 * NANOSECONDS_PER_SECOND, vec![1, 2, 3]),
+            (0, 1, 0, u64::MAX, 4 * NANOSECONDS_PER_SECOND, vec![1, 3]),
+        ]
+    );
+}
+
+#[test]
+fn packet_metadata_requires_length_prefixed_output() {
+    let input = common::nanosecond_pcap(&[(1, vec![1])]);
+    Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .arg("--packet-meta")
+        .arg(input.path())
+        .assert()
+        .failure();
+}
@@ -0,0 +1,41 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::NANOSECONDS_PER_SECOND;
+use std::io::prelude::*;
+use std::process::Command;
+
+#[test]
+fn parse_errors_are_reported_at_the_offset_of_the_corrupt_record() {
+    let packets: Vec<_> = (0..3)
+        .map(|i| (i * NANOSECONDS_PER_SECOND, vec![i as u8; 40]))
+        .collect();
+    let mut bytes = common::nanosecond_pcap_bytes(&packets);
+    // the third record, 24 + 2 * (16 + 40) bytes in, loses all but 10 bytes of its payload
+    bytes.truncate(bytes.len() - 30);
+    let mut corrupt = tempfile::Builder::new().suffix(".pcap").tempfile().unwrap();
+    corrupt.write_all(&bytes).unwrap();
+
+    let output = Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .arg("--strict")
+        .arg(corrupt.path())
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(common::read_nanosecond_pcap(&output.stdout), packets[..2]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("error at byte 136"), "{}", stderr);
+    assert!(stderr.contains("offset: 136"), "{}", stderr);
+
+    // a file header which can't be parsed is at the start of the file
+    corrupt.as_file().set_len(0).unwrap();
+    corrupt.write_all(b"definitely not a pcap header").unwrap();
+    let output = Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .arg(corrupt.path())
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("error at byte 0"), "{}", stderr);
+}
@@ -0,0 +1,208 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::NANOSECONDS_PER_SECOND;
+use std::io::prelude::*;
+use std::process::Command;
+
+const START: u64 = 1_637_796_620 * NANOSECONDS_PER_SECOND;
+
+const SECTION_HEADER_BLOCK: u32 = 0x0A0D_0D0A;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 1;
+const ENHANCED_PACKET_BLOCK: u32 = 6;
+
+fn push_block(out: &mut Vec<u8>, block_type: u32, body: &[u8]) {
+    let block_len = (12 + body.len()) as u32;
+    out.extend_from_slice(&block_type.to_le_bytes());
+    out.extend_from_slice(&block_len.to_le_bytes());
+    out.extend_from_slice(body);
+    out.extend_from_slice(&block_len.to_le_bytes());
+}
+
+fn padded(bytes: &[u8]) -> Vec<u8> {
+    let mut padded = bytes.to_vec();
+    padded.resize(bytes.len().div_ceil(4) * 4, 0);
+    padded
+}
+
+/// A little-endian pcapng with an interface for each `(link type, name, if_tsresol)`, then the `(interface,
+/// timestamp in units of its resolution, payload)` packets
+fn pcapng(
+    interfaces: &[(u16, &str, u8)],
+    packets: &[(u32, u64, Vec<u8>)],
+) -> tempfile::NamedTempFile {
+    let mut bytes = Vec::new();
+    let mut shb = 0x1A2B_3C4Du32.to_le_bytes().to_vec();
+    shb.extend_from_slice(&[1, 0, 0, 0]);
+    shb.extend_from_slice(&(-1i64).to_le_bytes());
+    push_block(&mut bytes, SECTION_HEADER_BLOCK, &shb);
+    for (link_type, name, ts_resolution) in interfaces {
+        let mut idb = link_type.to_le_bytes().to_vec();
+        idb.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+        idb.extend_from_slice(&2u16.to_le_bytes());
+        idb.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        idb.extend_from_slice(&padded(name.as_bytes()));
+        idb.extend_from_slice(&[9, 0, 1, 0, *ts_resolution, 0, 0, 0]);
+        idb.extend_from_slice(&[0; 4]);
+        push_block(&mut bytes, INTERFACE_DESCRIPTION_BLOCK, &idb);
+    }
+    for (interface, ts, payload) in packets {
+        let mut epb = Vec::new();
+        for field in &[
+            *interface,
+            (ts >> 32) as u32,
+            *ts as u32,
+            payload.len() as u32,
+            payload.len() as u32,
+        ] {
+            epb.extend_from_slice(&field.to_le_bytes());
+        }
+        epb.extend_from_slice(&padded(payload));
+        push_block(&mut bytes, ENHANCED_PACKET_BLOCK, &epb);
+    }
+    let mut file = tempfile::Builder::new()
+        .suffix(".pcapng")
+        .tempfile()
+        .unwrap();
+    file.write_all(&bytes).unwrap();
+    file
+}
+
+/// An interface described by an output IDB: its link type and name
+type OutputInterface = (u16, String);
+
+/// A packet of an output EPB: its interface ID, nanosecond timestamp and data
+type OutputPacket = (u32, u64, Vec<u8>);
+
+/// The interfaces and packets of a little-endian, nanosecond-resolution pcapng written by the merge
+fn read_output(bytes: &[u8]) -> (Vec<OutputInterface>, Vec<OutputPacket>) {
+    let u16_at =
+        |bytes: &[u8], offset: usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+    let u32_at = |bytes: &[u8], offset: usize| {
+        u32::from_le_bytes([
+            bytes[offset],
+            bytes[offset + 1],
+            bytes[offset + 2],
+            bytes[offset + 3],
+        ])
+    };
+    assert_eq!(u32_at(bytes, 0), SECTION_HEADER_BLOCK);
+    let (mut interfaces, mut packets) = (Vec::new(), Vec::new());
+    let mut rest = &bytes[u32_at(bytes, 4) as usize..];
+    while !rest.is_empty() {
+        let block_len = u32_at(rest, 4) as usize;
+        assert_eq!(u32_at(rest, block_len - 4) as usize, block_len);
+        let body = &rest[8..block_len - 4];
+        match u32_at(rest, 0) {
+            INTERFACE_DESCRIPTION_BLOCK => {
+                let mut name = String::new();
+                let mut options = &body[8..];
+                while u16_at(options, 0) != 0 {
+                    let (code, len) = (u16_at(options, 0), u16_at(options, 2) as usize);
+                    match code {
+                        2 => name = String::from_utf8(options[4..4 + len].to_vec()).unwrap(),
+                        9 => assert_eq!(options[4], 9, "output timestamps are in nanoseconds"),
+                        _ => {}
+                    }
+                    options = &options[4 + len.div_ceil(4) * 4..];
+                }
+                interfaces.push((u16_at(body, 0), name));
+            }
+            ENHANCED_PACKET_BLOCK => {
+                let ts = (u32_at(body, 4) as u64) << 32 | u32_at(body, 8) as u64;
+                let captured_len = u32_at(body, 12) as usize;
+                packets.push((u32_at(body, 0), ts, body[20..20 + captured_len].to_vec()));
+            }
+            block_type => panic!("Unexpected block type {}", block_type),
+        }
+        rest = &rest[block_len..];
+    }
+    (interfaces, packets)
+}
+
+fn merge_to_pcapng(inputs: &[&tempfile::NamedTempFile]) -> Vec<u8> {
+    let output = Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .args(["--format", "pcapng"])
+        .args(inputs.iter().map(|input| input.path()))
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output.stdout
+}
+
+#[test]
+fn merged_packets_refer_to_the_interface_of_their_input() {
+    // microsecond timestamps on one interface, nanosecond on the other
+    let eth0 = pcapng(
+        &[(1, "eth0", 6)],
+        &[
+            (0, START / 1000, vec![1; 60]),
+            (0, START / 1000 + 2, vec![1; 61]),
+        ],
+    );
+    let wlan0 = pcapng(
+        &[(105, "wlan0", 9)],
+        &[
+            (0, START + 1_000, vec![2; 70]),
+            (0, START + 3_000, vec![2; 71]),
+        ],
+    );
+    let (interfaces, packets) = read_output(&merge_to_pcapng(&[&eth0, &wlan0]));
+    assert_eq!(
+        interfaces,
+        vec![(1, "eth0".to_string()), (105, "wlan0".to_string())]
+    );
+    assert_eq!(
+        packets,
+        vec![
+            (0, START, vec![1; 60]),
+            (1, START + 1_000, vec![2; 70]),
+            (0, START + 2_000, vec![1; 61]),
+            (1, START + 3_000, vec![2; 71]),
+        ]
+    );
+}
+
+#[test]
+fn each_interface_of_a_multi_interface_input_is_described() {
+    let both = pcapng(
+        &[(1, "eth0", 9), (1, "eth1", 9)],
+        &[
+            (1, START, vec![1; 40]),
+            (0, START + 1, vec![0; 40]),
+            (1, START + 2, vec![1; 41]),
+        ],
+    );
+    let legacy = common::nanosecond_pcap(&[(START + 3, vec![3; 50])]);
+    let (interfaces, packets) = read_output(&merge_to_pcapng(&[&both, &legacy]));
+    assert_eq!(
+        interfaces,
+        vec![
+            (1, "eth0".to_string()),
+            (1, "eth1".to_string()),
+            (1, String::new()),
+        ]
+    );
+    assert_eq!(
+        packets,
+        vec![
+            (1, START, vec![1; 40]),
+            (0, START + 1, vec![0; 40]),
+            (1, START + 2, vec![1; 41]),
+            (2, START + 3, vec![3; 50]),
+        ]
+    );
+
+    // merged to a legacy pcap, the interfaces' packets are flattened together
+    let output = Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .arg(both.path())
+        .output()
+        .unwrap();
+    assert_eq!(common::read_nanosecond_pcap(&output.stdout).len(), 3);
+}
@@ -0,0 +1,59 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::NANOSECONDS_PER_SECOND;
+use std::process::Command;
+
+fn inputs() -> [tempfile::NamedTempFile; 2] {
+    let packets = |first: u64| -> Vec<(u64, Vec<u8>)> {
+        (0..2000u64)
+            .map(|i| ((first + 2 * i) * NANOSECONDS_PER_SECOND, vec![i as u8; 64]))
+            .collect()
+    };
+    [
+        common::nanosecond_pcap(&packets(0)),
+        common::nanosecond_pcap(&packets(1)),
+    ]
+}
+
+#[test]
+fn output_piped_through_cat_matches_stdout() {
+    let inputs = inputs();
+    let direct = Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .args(inputs.iter().map(|input| input.path()))
+        .output()
+        .unwrap();
+    assert!(direct.status.success(), "{:?}", direct);
+
+    // cat inherits stdout, so writes the output it's piped where merge_pcaps would have
+    let piped = Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .args(["--pipe-to", "cat"])
+        .args(inputs.iter().map(|input| input.path()))
+        .output()
+        .unwrap();
+    assert!(piped.status.success(), "{:?}", piped);
+    assert_eq!(piped.stdout, direct.stdout);
+    assert_eq!(common::read_nanosecond_pcap(&piped.stdout).len(), 4000);
+}
+
+#[cfg(unix)]
+#[test]
+fn a_failing_command_exit_status_is_forwarded() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let script = dir.path().join("fail.sh");
+    std::fs::write(&script, "#!/bin/sh\ncat > /dev/null\nexit 7\n").unwrap();
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let inputs = inputs();
+    Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .arg("--pipe-to")
+        .arg(&script)
+        .args(inputs.iter().map(|input| input.path()))
+        .assert()
+        .code(7);
+}
@@ -0,0 +1,38 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::NANOSECONDS_PER_SECOND;
+use std::process::Command;
+
+#[test]
+fn a_failed_preflight_aborts_before_any_input_is_opened_or_output_written(
+) -> Result<(), Box<dyn std::error::Error>> {
+    let input = common::nanosecond_pcap(&[(NANOSECONDS_PER_SECOND, vec![1; 40])]);
+    let dir = tempfile::tempdir()?;
+    // a profile with no credentials, so every lookup fails without a request being sent
+    let aws_file = dir.path().join("aws");
+    std::fs::write(&aws_file, "")?;
+    let output_path = dir.path().join("merged.pcap");
+
+    let output = Command::cargo_bin("merge_pcaps")?
+        .env("AWS_SHARED_CREDENTIALS_FILE", &aws_file)
+        .env("AWS_CONFIG_FILE", &aws_file)
+        .args(["--preflight", "--profile", "absent", "--output"])
+        .arg(&output_path)
+        .arg(input.path())
+        .arg(dir.path().join("missing.pcap"))
+        .args(["s3://bucket/a.pcap", "s3://bucket/b.pcap"])
+        .output()?;
+    assert_eq!(output.status.code(), Some(3), "{:?}", output);
+    assert!(output.stdout.is_empty());
+    assert!(!output_path.exists());
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(
+        stderr.contains("2 of 2 s3:// inputs failed --preflight"),
+        "{}",
+        stderr
+    );
+    // the local inputs were never opened, or the missing one would have been reported
+    assert!(!stderr.contains("missing.pcap"), "{}", stderr);
+    Ok(())
+}
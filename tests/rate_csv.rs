@@ -0,0 +1,53 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::NANOSECONDS_PER_SECOND;
+use std::process::Command;
+
+#[test]
+fn rate_csv_counts_packets_per_bucket() -> Result<(), Box<dyn std::error::Error>> {
+    // 3 packets in [0s, 1s), none in [1s, 2s), then 2 in [2s, 3s) split across the two files
+    let first = common::nanosecond_pcap(&[
+        (0, vec![1u8; 10]),
+        (NANOSECONDS_PER_SECOND / 2, vec![1u8; 20]),
+        (2 * NANOSECONDS_PER_SECOND + 1, vec![1u8; 40]),
+    ]);
+    let second = common::nanosecond_pcap(&[
+        (NANOSECONDS_PER_SECOND - 1, vec![2u8; 30]),
+        (2 * NANOSECONDS_PER_SECOND + 5, vec![2u8; 50]),
+    ]);
+    let dir = tempfile::tempdir()?;
+    let csv = dir.path().join("rate.csv");
+
+    let output = Command::cargo_bin("merge_pcaps")?
+        .arg("--rate-csv")
+        .arg(&csv)
+        .arg(first.path())
+        .arg(second.path())
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(
+        std::fs::read_to_string(&csv)?,
+        "bucket_start_ns,packet_count,byte_count\n\
+         0,3,60\n\
+         1000000000,0,0\n\
+         2000000000,2,90\n"
+    );
+
+    let output = Command::cargo_bin("merge_pcaps")?
+        .arg("--rate-csv")
+        .arg(&csv)
+        .arg("--rate-bucket")
+        .arg("2s")
+        .arg(first.path())
+        .arg(second.path())
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(
+        std::fs::read_to_string(&csv)?,
+        "bucket_start_ns,packet_count,byte_count\n\
+         0,3,60\n\
+         2000000000,2,90\n"
+    );
+    Ok(())
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::NANOSECONDS_PER_SECOND;
+use std::io::prelude::*;
+use std::process::Command;
+
+/// Write `packets` as headerless raw framed records to a temporary file ending in `suffix`
+fn raw_file(packets: &[(u64, Vec<u8>)], suffix: &str) -> tempfile::NamedTempFile {
+    let mut file = tempfile::Builder::new().suffix(suffix).tempfile().unwrap();
+    for (ts, payload) in packets {
+        file.write_all(&ts.to_le_bytes()).unwrap();
+        file.write_all(&(payload.len() as u32).to_le_bytes())
+            .unwrap();
+        file.write_all(payload).unwrap();
+    }
+    file.flush().unwrap();
+    file
+}
+
+#[test]
+fn raw_file_merges_with_pcap_file() -> Result<(), Box<dyn std::error::Error>> {
+    let pcap_packets: Vec<_> = (0..20)
+        .map(|i| (i * 2 * NANOSECONDS_PER_SECOND + 5, vec![1u8; 40]))
+        .collect();
+    let raw_packets: Vec<_> = (0..20)
+        .map(|i| {
+            (
+                (i * 2 + 1) * NANOSECONDS_PER_SECOND + 7,
+                vec![2u8; 10 + i as usize],
+            )
+        })
+        .collect();
+    let pcap = common::nanosecond_pcap(&pcap_packets);
+    let mut expected: Vec<_> = pcap_packets
+        .iter()
+        .chain(raw_packets.iter())
+        .cloned()
+        .collect();
+    expected.sort();
+
+    // detected from the .raw extension
+    let raw = raw_file(&raw_packets, ".raw");
+    let output = Command::cargo_bin("merge_pcaps")?
+        .arg(pcap.path())
+        .arg(raw.path())
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(common::read_nanosecond_pcap(&output.stdout), expected);
+
+    // chosen explicitly for a file without the extension
+    let raw = raw_file(&raw_packets, ".bin");
+    let mut config_file = tempfile::Builder::new().suffix(".json").tempfile()?;
+    write!(
+        config_file,
+        r#"{{ "inputs": [{{ "path": "{}" }}, {{ "path": "{}", "format": "raw" }}] }}"#,
+        pcap.path().to_str().unwrap(),
+        raw.path().to_str().unwrap()
+    )?;
+    let output = Command::cargo_bin("merge_pcaps")?
+        .arg("--config")
+        .arg(config_file.path())
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(common::read_nanosecond_pcap(&output.stdout), expected);
+    Ok(())
+}
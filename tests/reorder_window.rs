@@ -0,0 +1,42 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use std::process::Command;
+
+const MICROSECOND: u64 = 1000;
+
+#[test]
+fn locally_disordered_packets_are_merged_in_order() {
+    // every few packets, a pair is swapped by a few microseconds
+    let disordered: Vec<(u64, Vec<u8>)> = [0, 10, 5, 20, 30, 27, 40, 55, 50, 60]
+        .iter()
+        .map(|us| (1_000_000 + us * MICROSECOND, vec![*us as u8; 20]))
+        .collect();
+    let ordered: Vec<(u64, Vec<u8>)> = [15, 35, 45, 65]
+        .iter()
+        .map(|us| (1_000_000 + us * MICROSECOND, vec![*us as u8; 30]))
+        .collect();
+    let inputs = [
+        common::nanosecond_pcap(&disordered),
+        common::nanosecond_pcap(&ordered),
+    ];
+
+    let output = Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .args(["--reorder-window", "10us"])
+        .args(inputs.iter().map(|input| input.path()))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let mut expected = [disordered, ordered].concat();
+    expected.sort();
+    assert_eq!(common::read_nanosecond_pcap(&output.stdout), expected);
+
+    // without the window, the disordered input is rejected
+    Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .arg("--fail-on-backwards")
+        .args(inputs.iter().map(|input| input.path()))
+        .assert()
+        .failure();
+}
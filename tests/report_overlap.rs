@@ -0,0 +1,49 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::NANOSECONDS_PER_SECOND;
+use std::process::Command;
+
+#[test]
+fn overlapping_time_ranges_are_reported() -> Result<(), Box<dyn std::error::Error>> {
+    // seconds [0, 10] and [7, 20] overlap by 3 seconds; [30, 40] overlaps neither
+    let first = common::nanosecond_pcap(
+        &(0..=10)
+            .map(|i| (i * NANOSECONDS_PER_SECOND, vec![1u8; 40]))
+            .collect::<Vec<_>>(),
+    );
+    let second = common::nanosecond_pcap(
+        &(7..=20)
+            .map(|i| (i * NANOSECONDS_PER_SECOND, vec![2u8; 40]))
+            .collect::<Vec<_>>(),
+    );
+    let third = common::nanosecond_pcap(
+        &(30..=40)
+            .map(|i| (i * NANOSECONDS_PER_SECOND, vec![3u8; 40]))
+            .collect::<Vec<_>>(),
+    );
+
+    let output = Command::cargo_bin("merge_pcaps")?
+        .arg("--report-overlap")
+        .arg(third.path())
+        .arg(second.path())
+        .arg(first.path())
+        .output()?;
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(
+        stderr.contains("Overlap report: 1 of 2 adjacent file pairs overlap in time"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains(&format!(
+            "'{}' [0, 10000000000] overlaps '{}' [7000000000, 20000000000] by 3000000000 ns",
+            first.path().display(),
+            second.path().display()
+        )),
+        "{}",
+        stderr
+    );
+    Ok(())
+}
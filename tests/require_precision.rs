@@ -0,0 +1,56 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::NANOSECONDS_PER_SECOND;
+use std::io::Write;
+use std::process::Command;
+
+#[test]
+fn mismatched_precision_is_rejected_by_name() -> Result<(), Box<dyn std::error::Error>> {
+    let nanosecond = common::nanosecond_pcap(
+        &(0..10)
+            .map(|i| (i * NANOSECONDS_PER_SECOND, vec![1u8; 40]))
+            .collect::<Vec<_>>(),
+    );
+    let mut microsecond_bytes = common::PCAP_HDR_NSEC.to_vec();
+    microsecond_bytes[..4].copy_from_slice(&[0xD4, 0xC3, 0xB2, 0xA1]);
+    for i in 0..10 {
+        common::push_record(&mut microsecond_bytes, i, 500, &[2u8; 40]);
+    }
+    let mut microsecond = tempfile::Builder::new().suffix(".pcap").tempfile()?;
+    microsecond.write_all(&microsecond_bytes)?;
+    let microsecond_path = microsecond.path().to_str().unwrap();
+
+    let output = Command::cargo_bin("merge_pcaps")?
+        .args(["--require-precision", "ns"])
+        .arg(nanosecond.path())
+        .arg(microsecond.path())
+        .output()?;
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(
+        stderr.contains(&format!(
+            "'{}' has us-precision timestamps, but --require-precision ns was given",
+            microsecond_path
+        )),
+        "{}",
+        stderr
+    );
+
+    // without the requirement, the microsecond file is normalized and merged
+    let output = Command::cargo_bin("merge_pcaps")?
+        .arg(nanosecond.path())
+        .arg(microsecond.path())
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(common::read_nanosecond_pcap(&output.stdout).len(), 20);
+
+    // a matching requirement merges as usual
+    Command::cargo_bin("merge_pcaps")?
+        .args(["--require-precision", "ns"])
+        .arg(nanosecond.path())
+        .assert()
+        .success();
+    Ok(())
+}
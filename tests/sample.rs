@@ -0,0 +1,73 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::NANOSECONDS_PER_SECOND;
+use std::process::Command;
+use tempfile::NamedTempFile;
+
+/// Two pcaps of 50 packets each, interleaving in time, and their 100 packets in merged order
+fn inputs() -> (NamedTempFile, NamedTempFile, Vec<(u64, Vec<u8>)>) {
+    let packets: Vec<(u64, Vec<u8>)> = (0..100u64)
+        .map(|i| {
+            (
+                NANOSECONDS_PER_SECOND + i * 1000,
+                vec![i as u8; 20 + i as usize],
+            )
+        })
+        .collect();
+    let (even, odd): (Vec<_>, Vec<_>) = packets.iter().cloned().partition(|(ts, _)| ts % 2000 == 0);
+    (
+        common::nanosecond_pcap(&even),
+        common::nanosecond_pcap(&odd),
+        packets,
+    )
+}
+
+fn sample(args: &[&str], pcaps: &[&NamedTempFile]) -> Vec<(u64, Vec<u8>)> {
+    let output = Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .args(args)
+        .args(pcaps.iter().map(|pcap| pcap.path()))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    common::read_nanosecond_pcap(&output.stdout)
+}
+
+#[test]
+fn every_tenth_merged_packet_is_kept() {
+    let (even, odd, packets) = inputs();
+    let sampled = sample(&["--sample", "1/10"], &[&even, &odd]);
+    assert_eq!(sampled.len(), 10);
+    assert_eq!(sampled, packets.into_iter().step_by(10).collect::<Vec<_>>());
+}
+
+#[test]
+fn hashed_samples_are_stable() {
+    let (even, odd, packets) = inputs();
+    let args = ["--sample", "1/4", "--sample-by", "hash"];
+    let sampled = sample(&args, &[&even, &odd]);
+    assert!(!sampled.is_empty() && sampled.len() < packets.len());
+    assert!(sampled.iter().all(|packet| packets.contains(packet)));
+    assert_eq!(sample(&args, &[&even, &odd]), sampled);
+
+    // the packets of one input which are kept don't depend on what they're merged with
+    let sampled_even: Vec<_> = sampled
+        .into_iter()
+        .filter(|(ts, _)| ts % 2000 == 0)
+        .collect();
+    assert_eq!(sample(&args, &[&even]), sampled_even);
+}
+
+#[test]
+fn malformed_rates_are_rejected() {
+    let (even, _odd, _packets) = inputs();
+    for rate in &["2/10", "1/0", "10"] {
+        Command::cargo_bin("merge_pcaps")
+            .unwrap()
+            .args(["--sample", rate])
+            .arg(even.path())
+            .assert()
+            .failure();
+    }
+}
@@ -0,0 +1,97 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::NANOSECONDS_PER_SECOND;
+use std::collections::HashMap;
+use std::io::Write;
+use std::process::Command;
+
+/// An Ethernet/IPv4/UDP frame from port `src_port` carrying `payload`
+fn udp_frame(src_port: u16, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0u8; 12];
+    frame.extend_from_slice(&[0x08, 0x00]); // IPv4
+    frame.extend_from_slice(&[0x45, 0, 0, 0, 0, 0, 0, 0, 64, 17, 0, 0]);
+    frame.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2]);
+    frame.extend_from_slice(&src_port.to_be_bytes());
+    frame.extend_from_slice(&53u16.to_be_bytes());
+    frame.extend_from_slice(&[0, 0, 0, 0]);
+    frame.extend_from_slice(payload);
+    frame
+}
+
+fn src_port(frame: &[u8]) -> u16 {
+    u16::from_be_bytes([frame[34], frame[35]])
+}
+
+#[test]
+fn shards_are_time_ordered_and_keep_flows_together() -> Result<(), Box<dyn std::error::Error>> {
+    let inputs: Vec<_> = (0..2u64)
+        .map(|file| {
+            common::nanosecond_pcap(
+                &(0..200u64)
+                    .map(|i| {
+                        (
+                            (i * 2 + file) * NANOSECONDS_PER_SECOND,
+                            udp_frame(1000 + (i % 16) as u16, &[file as u8; 20]),
+                        )
+                    })
+                    .collect::<Vec<_>>(),
+            )
+        })
+        .collect();
+    let output_dir = tempfile::tempdir()?;
+
+    Command::cargo_bin("merge_pcaps")?
+        .args(["--shard-by-hash", "4", "--output-dir"])
+        .arg(output_dir.path())
+        .args(inputs.iter().map(|input| input.path()))
+        .assert()
+        .success();
+
+    let mut shard_of_flow = HashMap::new();
+    let mut n_packets = 0;
+    let mut n_non_empty_shards = 0;
+    for shard in 0..4 {
+        let bytes = std::fs::read(output_dir.path().join(format!("shard_{}_of_4.pcap", shard)))?;
+        let packets = common::read_nanosecond_pcap(&bytes);
+        assert!(packets.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+        for (_ts, frame) in &packets {
+            assert_eq!(
+                *shard_of_flow.entry(src_port(frame)).or_insert(shard),
+                shard
+            );
+        }
+        n_packets += packets.len();
+        n_non_empty_shards += !packets.is_empty() as usize;
+    }
+    assert_eq!(n_packets, 400);
+    assert!(n_non_empty_shards > 1);
+    Ok(())
+}
+
+#[test]
+fn the_configs_output_file_is_rejected_rather_than_ignored(
+) -> Result<(), Box<dyn std::error::Error>> {
+    let input = common::nanosecond_pcap(&[(NANOSECONDS_PER_SECOND, udp_frame(1000, &[1; 20]))]);
+    let output_dir = tempfile::tempdir()?;
+    let output_path = output_dir.path().join("merged.pcap");
+    let mut config_file = tempfile::Builder::new().suffix(".json").tempfile()?;
+    write!(
+        config_file,
+        r#"{{ "inputs": [{{ "path": "{}" }}], "output": "{}" }}"#,
+        input.path().to_str().unwrap(),
+        output_path.to_str().unwrap()
+    )?;
+
+    let output = Command::cargo_bin("merge_pcaps")?
+        .args(["--shard-by-hash", "4", "--output-dir"])
+        .arg(output_dir.path())
+        .arg("--config")
+        .arg(config_file.path())
+        .output()?;
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(stderr.contains("config's output file"), "{}", stderr);
+    assert_eq!(std::fs::read_dir(output_dir.path())?.count(), 0);
+    Ok(())
+}
@@ -0,0 +1,41 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::NANOSECONDS_PER_SECOND;
+use std::process::Command;
+
+#[test]
+fn single_sorted_input_is_copied_like_a_full_merge() -> Result<(), Box<dyn std::error::Error>> {
+    let packets: Vec<_> = (0..500)
+        .map(|i| {
+            (
+                i * NANOSECONDS_PER_SECOND / 3,
+                vec![(i % 256) as u8; 20 + i as usize % 50],
+            )
+        })
+        .collect();
+    let mut input_bytes = common::nanosecond_pcap_bytes(&packets);
+    input_bytes[16..20].copy_from_slice(&65535u32.to_le_bytes()); // a snaplen unlike the merged output's
+    let input = tempfile::Builder::new().suffix(".pcap").tempfile()?;
+    std::fs::write(input.path(), &input_bytes)?;
+
+    let copied = Command::cargo_bin("merge_pcaps")?
+        .env("RUST_LOG", "debug")
+        .arg(input.path())
+        .output()?;
+    assert!(copied.status.success());
+    assert!(String::from_utf8(copied.stderr)?.contains("Copied the only input's records verbatim"));
+    assert_eq!(copied.stdout, common::nanosecond_pcap_bytes(&packets));
+
+    // a window which includes every packet forces a full merge
+    let merged = Command::cargo_bin("merge_pcaps")?
+        .env("RUST_LOG", "debug")
+        .arg("--start-ns")
+        .arg("0")
+        .arg(input.path())
+        .output()?;
+    assert!(merged.status.success());
+    assert!(!String::from_utf8(merged.stderr)?.contains("Copied the only input's records verbatim"));
+    assert_eq!(merged.stdout, copied.stdout);
+    Ok(())
+}
@@ -0,0 +1,74 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::NANOSECONDS_PER_SECOND;
+use std::process::Command;
+
+#[test]
+fn output_rolls_over_every_n_packets() -> Result<(), Box<dyn std::error::Error>> {
+    let inputs: Vec<_> = (0..2u64)
+        .map(|file| {
+            common::nanosecond_pcap(
+                &(0..125u64)
+                    .map(|i| {
+                        (
+                            (i * 2 + file) * NANOSECONDS_PER_SECOND,
+                            vec![file as u8; 40],
+                        )
+                    })
+                    .collect::<Vec<_>>(),
+            )
+        })
+        .collect();
+    let output_dir = tempfile::tempdir()?;
+
+    Command::cargo_bin("merge_pcaps")?
+        .args(["--split-packets", "100", "--output-dir"])
+        .arg(output_dir.path())
+        .args(inputs.iter().map(|input| input.path()))
+        .assert()
+        .success();
+
+    let mut names: Vec<_> = std::fs::read_dir(output_dir.path())?
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    assert_eq!(
+        names,
+        ["part_000000.pcap", "part_000001.pcap", "part_000002.pcap"]
+    );
+    let parts: Vec<_> = names
+        .iter()
+        .map(|name| {
+            common::read_nanosecond_pcap(&std::fs::read(output_dir.path().join(name)).unwrap())
+        })
+        .collect();
+    assert_eq!(
+        parts.iter().map(|part| part.len()).collect::<Vec<_>>(),
+        [100, 100, 50]
+    );
+    // the parts are the merged output, split between packets
+    let timestamps: Vec<_> = parts.iter().flatten().map(|(ts, _)| *ts).collect();
+    assert_eq!(
+        timestamps,
+        (0..250)
+            .map(|i| i * NANOSECONDS_PER_SECOND)
+            .collect::<Vec<_>>()
+    );
+    Ok(())
+}
+
+#[test]
+fn output_dir_requires_shards_or_splits() -> Result<(), Box<dyn std::error::Error>> {
+    let input = common::nanosecond_pcap(&[(0, vec![1u8; 40])]);
+    let output_dir = tempfile::tempdir()?;
+    let output = Command::cargo_bin("merge_pcaps")?
+        .arg("--output-dir")
+        .arg(output_dir.path())
+        .arg(input.path())
+        .output()?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)?
+        .contains("--output-dir requires --shard-by-hash, --split-packets or --no-merge"));
+    Ok(())
+}
@@ -0,0 +1,100 @@
+#![cfg(unix)]
+
+mod common;
+
+use assert_cmd::prelude::*;
+use common::NANOSECONDS_PER_SECOND;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Connect to the stats socket at `path`, retrying until the merge has started listening on it
+fn read_stats(path: &std::path::Path) -> serde_json::Value {
+    let started = Instant::now();
+    let mut connection = loop {
+        match UnixStream::connect(path) {
+            Ok(connection) => break connection,
+            Err(error) if started.elapsed() > Duration::from_secs(10) => panic!("{}", error),
+            Err(_) => std::thread::sleep(Duration::from_millis(20)),
+        }
+    };
+    let mut stats = String::new();
+    connection.read_to_string(&mut stats).unwrap();
+    assert!(
+        stats.ends_with('\n') && stats.lines().count() == 1,
+        "{}",
+        stats
+    );
+    serde_json::from_str(&stats).unwrap()
+}
+
+/// Read the stats at `path` until the merge has merged at least `n_packets` packets
+fn wait_for_stats(path: &std::path::Path, n_packets: u64) -> serde_json::Value {
+    let started = Instant::now();
+    loop {
+        let stats = read_stats(path);
+        if stats["packets_merged"].as_u64().unwrap() >= n_packets {
+            return stats;
+        }
+        assert!(
+            started.elapsed() < Duration::from_secs(10),
+            "only {} merged",
+            stats
+        );
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn progress_is_served_while_merging() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("stats.sock");
+    // an input written in two batches, paced so that the merge waits on each
+    let paced = dir.path().join("paced.pcap");
+    assert!(Command::new("mkfifo")
+        .arg(&paced)
+        .status()
+        .unwrap()
+        .success());
+    let packets: Vec<_> = (0..200u64)
+        .map(|i| (NANOSECONDS_PER_SECOND + i, vec![i as u8; 30]))
+        .collect();
+    let bytes = common::nanosecond_pcap_bytes(&packets);
+    let second_batch = common::PCAP_HDR_NSEC.len() + 100 * (16 + 30);
+
+    let merge = Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .arg("--stats-socket")
+        .arg(&socket)
+        .arg(&paced)
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut fifo = std::fs::OpenOptions::new()
+        .write(true)
+        .open(&paced)
+        .unwrap();
+    fifo.write_all(&bytes[..second_batch]).unwrap();
+    let first = wait_for_stats(&socket, 100);
+    fifo.write_all(&bytes[second_batch..]).unwrap();
+    let second = wait_for_stats(&socket, 200);
+    drop(fifo);
+
+    let output = merge.wait_with_output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(common::read_nanosecond_pcap(&output.stdout), packets);
+    assert!(
+        !socket.exists(),
+        "the socket is removed once the merge is done"
+    );
+
+    let first_n_packets = first["packets_merged"].as_u64().unwrap();
+    let second_n_packets = second["packets_merged"].as_u64().unwrap();
+    assert_eq!((first_n_packets, second_n_packets), (100, 200));
+    assert_eq!(second["inputs"][0]["path"], paced.to_str().unwrap());
+    assert_eq!(second["inputs"][0]["packets_merged"], second_n_packets);
+    assert!(second["merge_ts_ns"].as_u64().unwrap() >= NANOSECONDS_PER_SECOND + 100);
+    // nothing says how much more a fifo will yield
+    assert!(second["eta_secs"].is_null(), "{}", second);
+}
@@ -0,0 +1,161 @@
+mod common;
+
+use common::NANOSECONDS_PER_SECOND;
+use futures::stream::{StreamExt, TryStreamExt};
+use stream_merge::compression::Compression;
+use stream_merge::pcap;
+
+#[test]
+fn stream_file_matches_full_pipeline() -> Result<(), Box<dyn std::error::Error>> {
+    let file = common::nanosecond_pcap(
+        &(0..1000)
+            .map(|i| {
+                (
+                    i * NANOSECONDS_PER_SECOND / 7,
+                    vec![(i % 256) as u8; 40 + i as usize % 100],
+                )
+            })
+            .collect::<Vec<_>>(),
+    );
+    let path = file.path().to_str().unwrap();
+
+    let streamed = smol::block_on(pcap::stream_file(path).try_collect::<Vec<_>>())?;
+    let pipelined = smol::block_on(
+        stream_merge::stream_and_decode_pcap_packets(path.to_string()).collect::<Vec<_>>(),
+    );
+    assert_eq!(streamed.len(), 1000);
+    assert_eq!(streamed, pipelined);
+    Ok(())
+}
+
+#[test]
+fn stream_and_decode_reader_decodes_in_memory_pcaps() -> Result<(), Box<dyn std::error::Error>> {
+    let packets: Vec<_> = (0..100)
+        .map(|i| {
+            (
+                i * NANOSECONDS_PER_SECOND / 3,
+                vec![i as u8; 30 + i as usize],
+            )
+        })
+        .collect();
+    let bytes = common::nanosecond_pcap_bytes(&packets);
+    let mut gzipped = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    std::io::Write::write_all(&mut gzipped, &bytes)?;
+    let gzipped = gzipped.finish()?;
+
+    for (bytes, compression) in [(bytes, Compression::None), (gzipped, Compression::Gzip)] {
+        let decoded = smol::block_on(
+            stream_merge::stream_and_decode_reader(futures::io::Cursor::new(bytes), compression)
+                .map_ok(|(ts, record)| (ts, record[pcap::RECORD_HEADER_LEN..].to_vec()))
+                .try_collect::<Vec<_>>(),
+        )?;
+        assert_eq!(decoded, packets);
+    }
+
+    let truncated_header = &common::PCAP_HDR_NSEC[..10];
+    let result = smol::block_on(
+        stream_merge::stream_and_decode_reader(truncated_header, Compression::None)
+            .try_collect::<Vec<_>>(),
+    );
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("Invalid pcap header"));
+    Ok(())
+}
+
+#[test]
+fn stream_and_decode_reader_with_honours_strict_and_fix_wraparound() {
+    let mut bytes = common::PCAP_HDR_NSEC.to_vec();
+    common::push_record(&mut bytes, u32::MAX, 0, &[1]);
+    common::push_record(&mut bytes, 1, 0, &[2]);
+    common::push_record(&mut bytes, 2, 0, &[3]);
+    bytes.truncate(bytes.len() - 1);
+    let decode = |config: stream_merge::s3::DownloadConfig| {
+        smol::block_on(
+            stream_merge::stream_and_decode_reader_with(
+                futures::io::Cursor::new(bytes.clone()),
+                Compression::None,
+                config,
+            )
+            .collect::<Vec<_>>(),
+        )
+    };
+
+    let lenient: Vec<u64> = decode(Default::default())
+        .into_iter()
+        .map(|packet| packet.unwrap().0)
+        .collect();
+    assert_eq!(
+        lenient,
+        vec![
+            u32::MAX as u64 * NANOSECONDS_PER_SECOND,
+            NANOSECONDS_PER_SECOND
+        ]
+    );
+
+    let mut decoded = decode(stream_merge::s3::DownloadConfig {
+        strict: true,
+        fix_wraparound: true,
+        ..Default::default()
+    });
+    let error = decoded.pop().unwrap().unwrap_err();
+    assert!(
+        format!("{:#}", error).contains("Failed to parse packet"),
+        "{:#}",
+        error
+    );
+    let fixed: Vec<u64> = decoded
+        .into_iter()
+        .map(|packet| packet.unwrap().0)
+        .collect();
+    assert_eq!(
+        fixed,
+        vec![
+            u32::MAX as u64 * NANOSECONDS_PER_SECOND,
+            (1 << 32) * NANOSECONDS_PER_SECOND + NANOSECONDS_PER_SECOND
+        ]
+    );
+}
+
+#[test]
+fn stream_file_yields_error_for_missing_file() {
+    let result =
+        smol::block_on(pcap::stream_file("/nonexistent/missing.pcap").try_collect::<Vec<_>>());
+    assert!(result.unwrap_err().to_string().contains("missing.pcap"));
+}
+
+#[test]
+fn decode_failure_is_observable_through_decode_task() -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = tempfile::Builder::new().suffix(".pcap").tempfile()?;
+    std::io::Write::write_all(&mut file, b"definitely not a pcap header")?;
+    let path = file.path().to_str().unwrap().to_string();
+
+    let (packets, decode_task) = stream_merge::stream_and_decode_packets_as(
+        path.clone(),
+        pcap::InputFormat::Pcap,
+        Default::default(),
+    );
+    let (packets, result) = smol::block_on(futures::future::join(
+        packets.collect::<Vec<_>>(),
+        decode_task,
+    ));
+    assert!(packets.is_empty());
+    let error = result.unwrap_err().to_string();
+    assert!(
+        error.contains("Failed to read the pcap header"),
+        "{}",
+        error
+    );
+    assert!(error.contains(&path), "{}", error);
+
+    let (packets, decode_task) = stream_merge::stream_and_decode_packets_as(
+        "/nonexistent/missing.pcap".to_string(),
+        pcap::InputFormat::Pcap,
+        Default::default(),
+    );
+    assert!(smol::block_on(packets.collect::<Vec<_>>()).is_empty());
+    let error = smol::block_on(decode_task).unwrap_err().to_string();
+    assert!(error.contains("missing.pcap"), "{}", error);
+    Ok(())
+}
@@ -0,0 +1,48 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::NANOSECONDS_PER_SECOND;
+use std::io::prelude::*;
+use std::process::Command;
+
+/// A pcap of `packets` whose final record is missing its last 10 bytes
+fn truncated_pcap(packets: &[(u64, Vec<u8>)]) -> tempfile::NamedTempFile {
+    let bytes = common::nanosecond_pcap_bytes(packets);
+    let mut file = tempfile::Builder::new().suffix(".pcap").tempfile().unwrap();
+    file.write_all(&bytes[..bytes.len() - 10]).unwrap();
+    file
+}
+
+#[test]
+fn truncated_record_is_reported_only_in_strict_mode() -> Result<(), Box<dyn std::error::Error>> {
+    let packets: Vec<_> = (0..4)
+        .map(|i| (i * NANOSECONDS_PER_SECOND, vec![1u8; 40]))
+        .collect();
+    let input = truncated_pcap(&packets);
+    let complete_packets = common::nanosecond_pcap_bytes(&packets[..3]);
+
+    let output = Command::cargo_bin("merge_pcaps")?
+        .arg(input.path())
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(output.stdout, complete_packets);
+    assert!(
+        output.stderr.is_empty(),
+        "{}",
+        String::from_utf8(output.stderr)?
+    );
+
+    let output = Command::cargo_bin("merge_pcaps")?
+        .arg("--strict")
+        .arg(input.path())
+        .output()?;
+    assert_eq!(output.status.code(), Some(2)); // merged up to the failure, which is reported
+    assert_eq!(output.stdout, complete_packets);
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(
+        stderr.contains("TruncatedRecord { remaining_bytes: 46 }"),
+        "{}",
+        stderr
+    );
+    Ok(())
+}
@@ -0,0 +1,62 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::NANOSECONDS_PER_SECOND;
+use std::process::Command;
+
+/// Read the `(source, ts, payload)` frames of source-tagged, length-prefixed output
+fn read_tagged_frames(mut bytes: &[u8]) -> Vec<(u32, u64, Vec<u8>)> {
+    let mut frames = Vec::new();
+    while !bytes.is_empty() {
+        let source = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let mut ts = [0; 8];
+        ts.copy_from_slice(&bytes[4..12]);
+        let len = u32::from_le_bytes([bytes[12], bytes[13], bytes[14], bytes[15]]) as usize;
+        frames.push((source, u64::from_le_bytes(ts), bytes[16..16 + len].to_vec()));
+        bytes = &bytes[16 + len..];
+    }
+    frames
+}
+
+#[test]
+fn frames_are_interleaved_by_time_and_tagged_with_their_input() {
+    let first = common::nanosecond_pcap(&[
+        (NANOSECONDS_PER_SECOND, vec![1, 1]),
+        (3 * NANOSECONDS_PER_SECOND, vec![1, 2]),
+        (4 * NANOSECONDS_PER_SECOND, vec![1, 3]),
+    ]);
+    let second = common::nanosecond_pcap(&[
+        (2 * NANOSECONDS_PER_SECOND, vec![2, 1, 0xFF]),
+        (5 * NANOSECONDS_PER_SECOND, vec![2, 2]),
+    ]);
+
+    let output = Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .args(["--format", "length-prefixed", "--tag-source"])
+        .arg(first.path())
+        .arg(second.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        read_tagged_frames(&output.stdout),
+        vec![
+            (0, NANOSECONDS_PER_SECOND, vec![1, 1]),
+            (1, 2 * NANOSECONDS_PER_SECOND, vec![2, 1, 0xFF]),
+            (0, 3 * NANOSECONDS_PER_SECOND, vec![1, 2]),
+            (0, 4 * NANOSECONDS_PER_SECOND, vec![1, 3]),
+            (1, 5 * NANOSECONDS_PER_SECOND, vec![2, 2]),
+        ]
+    );
+}
+
+#[test]
+fn tagging_sources_requires_length_prefixed_output() {
+    let input = common::nanosecond_pcap(&[(1, vec![1])]);
+    Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .arg("--tag-source")
+        .arg(input.path())
+        .assert()
+        .failure();
+}
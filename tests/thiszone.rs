@@ -0,0 +1,67 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::{NANOSECONDS_PER_SECOND, PCAP_HDR_NSEC};
+use std::io::Write;
+use std::process::Command;
+
+fn i32_at(bytes: &[u8], offset: usize) -> i32 {
+    i32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+#[test]
+fn the_output_header_carries_the_configured_thiszone_and_sigfigs() {
+    let first = common::nanosecond_pcap(&[(NANOSECONDS_PER_SECOND, vec![1; 20])]);
+    let second = common::nanosecond_pcap(&[(2 * NANOSECONDS_PER_SECOND, vec![2; 20])]);
+    let output = Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .args(["--thiszone", "-18000", "--sigfigs", "6"])
+        .arg(first.path())
+        .arg(second.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let merged = output.stdout;
+    assert_eq!(i32_at(&merged, 8), -18000);
+    assert_eq!(i32_at(&merged, 12), 6);
+    assert_eq!(merged[..8], PCAP_HDR_NSEC[..8]);
+    assert_eq!(merged[16..24], PCAP_HDR_NSEC[16..24]);
+}
+
+#[test]
+fn the_first_inputs_fields_are_carried_over_by_default() {
+    let mut bytes = common::nanosecond_pcap_bytes(&[(1, vec![1; 20]), (3, vec![3; 20])]);
+    bytes[8..12].copy_from_slice(&3600i32.to_le_bytes());
+    bytes[12..16].copy_from_slice(&9u32.to_le_bytes());
+    let mut first = tempfile::Builder::new().suffix(".pcap").tempfile().unwrap();
+    first.write_all(&bytes).unwrap();
+    first.flush().unwrap();
+    let second = common::nanosecond_pcap(&[(2, vec![2; 20])]);
+
+    // whether merged, or copied as a lone input
+    for inputs in &[vec![first.path(), second.path()], vec![first.path()]] {
+        let output = Command::cargo_bin("merge_pcaps")
+            .unwrap()
+            .args(inputs)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        assert_eq!(output.stdout[8..16], bytes[8..16]);
+    }
+
+    // and a flag given overrides only its own field
+    let output = Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .args(["--thiszone", "0"])
+        .arg(first.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(i32_at(&output.stdout, 8), 0);
+    assert_eq!(i32_at(&output.stdout, 12), 9);
+}
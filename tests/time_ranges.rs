@@ -0,0 +1,80 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::NANOSECONDS_PER_SECOND;
+use std::io::prelude::*;
+use std::process::Command;
+
+fn packets(first_ts: u64, n_packets: u64) -> Vec<(u64, Vec<u8>)> {
+    (0..n_packets)
+        .map(|i| (first_ts + i * 1000, vec![i as u8; 100]))
+        .collect()
+}
+
+fn time_ranges(paths: &[&std::path::Path]) -> String {
+    let output = Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .arg("--time-ranges-only")
+        .args(paths)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn time_ranges_of_uncompressed_pcaps_are_read_from_their_ends() {
+    let first_ts = NANOSECONDS_PER_SECOND;
+    let mut bytes = common::nanosecond_pcap_bytes(&packets(first_ts, 5000));
+    // records far from either end which can't be parsed, so the last timestamp can't have come from decoding them
+    for byte in &mut bytes[200_000..300_000] {
+        *byte = 0xFF;
+    }
+    let mut pcap = tempfile::Builder::new().suffix(".pcap").tempfile().unwrap();
+    pcap.write_all(&bytes).unwrap();
+
+    // a compressed pcap is decoded in full
+    let mut gzipped = tempfile::Builder::new()
+        .suffix(".pcap.gz")
+        .tempfile()
+        .unwrap();
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+    encoder
+        .write_all(&common::nanosecond_pcap_bytes(&packets(500, 3)))
+        .unwrap();
+    gzipped.write_all(&encoder.finish().unwrap()).unwrap();
+
+    let empty = common::nanosecond_pcap(&[]);
+    let ranges = time_ranges(&[pcap.path(), gzipped.path(), empty.path()]);
+    assert_eq!(
+        ranges,
+        format!(
+            "'{}' [{}, {}]\n'{}' [500, 2500]\n'{}' has no packets\nmerged [500, {}]\n",
+            pcap.path().display(),
+            first_ts,
+            first_ts + 4999 * 1000,
+            gzipped.path().display(),
+            empty.path().display(),
+            first_ts + 4999 * 1000
+        )
+    );
+}
+
+#[test]
+fn a_pcap_ending_part_way_through_a_record_is_decoded_in_full() {
+    let mut bytes = common::nanosecond_pcap_bytes(&packets(NANOSECONDS_PER_SECOND, 100));
+    bytes.truncate(bytes.len() - 10);
+    let mut pcap = tempfile::Builder::new().suffix(".pcap").tempfile().unwrap();
+    pcap.write_all(&bytes).unwrap();
+    assert_eq!(
+        time_ranges(&[pcap.path()]),
+        format!(
+            "'{}' [{}, {}]\nmerged [{}, {}]\n",
+            pcap.path().display(),
+            NANOSECONDS_PER_SECOND,
+            NANOSECONDS_PER_SECOND + 98 * 1000,
+            NANOSECONDS_PER_SECOND,
+            NANOSECONDS_PER_SECOND + 98 * 1000
+        )
+    );
+}
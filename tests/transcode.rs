@@ -0,0 +1,99 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::{NANOSECONDS_PER_SECOND, PCAP_HDR_NSEC};
+use std::io::Write;
+use std::process::Command;
+
+/// A Linux cooked (SLL) header for a unicast packet sent by this host from `source`, of the given EtherType
+fn sll_header(source: [u8; 6], ethertype: u16) -> Vec<u8> {
+    let mut header = vec![0, 4, 0, 1, 0, 6];
+    header.extend_from_slice(&source);
+    header.extend_from_slice(&[0, 0]); // address padding
+    header.extend_from_slice(&ethertype.to_be_bytes());
+    header
+}
+
+/// An Ethernet header from `source` to the all-zero MAC, of the given EtherType
+fn ethernet_header(source: [u8; 6], ethertype: u16) -> Vec<u8> {
+    let mut header = vec![0; 6];
+    header.extend_from_slice(&source);
+    header.extend_from_slice(&ethertype.to_be_bytes());
+    header
+}
+
+#[test]
+fn sll_packets_are_rewritten_as_ethernet() {
+    let source = [0x02, 0x42, 0xAC, 0x11, 0x00, 0x02];
+    let ipv4 = vec![0x45; 40];
+    let ipv6 = vec![0x60; 60];
+
+    let mut sll_bytes = common::nanosecond_pcap_bytes(&[
+        (
+            NANOSECONDS_PER_SECOND,
+            [sll_header(source, 0x0800), ipv4.clone()].concat(),
+        ),
+        (
+            3 * NANOSECONDS_PER_SECOND,
+            [sll_header(source, 0x86DD), ipv6.clone()].concat(),
+        ),
+    ]);
+    sll_bytes[20..24].copy_from_slice(&113u32.to_le_bytes());
+    let mut sll = tempfile::Builder::new().suffix(".pcap").tempfile().unwrap();
+    sll.write_all(&sll_bytes).unwrap();
+    sll.flush().unwrap();
+
+    let ethernet_packet = [ethernet_header([1; 6], 0x0800), vec![0x45; 20]].concat();
+    let ethernet =
+        common::nanosecond_pcap(&[(2 * NANOSECONDS_PER_SECOND, ethernet_packet.clone())]);
+
+    let output = Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .args(["--transcode", "sll-to-ethernet"])
+        .arg(sll.path())
+        .arg(ethernet.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    // the output is of Ethernet framing, its SLL records 2 bytes shorter with the same payloads
+    assert_eq!(
+        common::read_nanosecond_pcap(&output.stdout),
+        vec![
+            (
+                NANOSECONDS_PER_SECOND,
+                [ethernet_header(source, 0x0800), ipv4].concat()
+            ),
+            (2 * NANOSECONDS_PER_SECOND, ethernet_packet),
+            (
+                3 * NANOSECONDS_PER_SECOND,
+                [ethernet_header(source, 0x86DD), ipv6].concat()
+            ),
+        ]
+    );
+    // each record's original length shrinks with its captured length
+    let rest = &output.stdout[PCAP_HDR_NSEC.len()..];
+    assert_eq!(rest[8..12], rest[12..16]);
+    assert_eq!(
+        u32::from_le_bytes([rest[8], rest[9], rest[10], rest[11]]),
+        54
+    );
+}
+
+#[test]
+fn sll_packets_are_left_alone_without_transcode() {
+    let packets = vec![(1, sll_header([3; 6], 0x0800))];
+    let mut sll_bytes = common::nanosecond_pcap_bytes(&packets);
+    sll_bytes[20..24].copy_from_slice(&113u32.to_le_bytes());
+    let mut sll = tempfile::Builder::new().suffix(".pcap").tempfile().unwrap();
+    sll.write_all(&sll_bytes).unwrap();
+    sll.flush().unwrap();
+
+    let output = Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .arg(sll.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(common::read_nanosecond_pcap(&output.stdout), packets);
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::{NANOSECONDS_PER_SECOND, PCAP_HDR_NSEC};
+use std::process::Command;
+
+const SNAPLEN: usize = 64;
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+#[test]
+fn oversized_packets_are_truncated_with_consistent_record_headers() {
+    let payload = |len: usize, fill: u8| -> Vec<u8> { (0..len).map(|i| fill ^ i as u8).collect() };
+    let first = common::nanosecond_pcap(&[
+        (NANOSECONDS_PER_SECOND, payload(1500, 1)),
+        (3 * NANOSECONDS_PER_SECOND, payload(SNAPLEN, 1)),
+    ]);
+    let second = common::nanosecond_pcap(&[
+        (2 * NANOSECONDS_PER_SECOND, payload(20, 2)),
+        (4 * NANOSECONDS_PER_SECOND, payload(SNAPLEN + 1, 2)),
+    ]);
+
+    let output = Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .args(["--truncate-snaplen", &SNAPLEN.to_string()])
+        .arg(first.path())
+        .arg(second.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let merged = output.stdout;
+
+    // the output header gives the truncated snaplen
+    assert_eq!(u32_at(&merged, 16), SNAPLEN as u32);
+    assert_eq!(merged[..16], PCAP_HDR_NSEC[..16]);
+    assert_eq!(merged[20..24], PCAP_HDR_NSEC[20..24]);
+
+    // every record captures at most the snaplen, with its header's captured length matching its bytes and its
+    // original length still that of the whole packet
+    let expected = [(1500, 1), (20, 2), (SNAPLEN, 1), (SNAPLEN + 1, 2)];
+    let mut rest = &merged[PCAP_HDR_NSEC.len()..];
+    for (original_len, fill) in expected.iter() {
+        let (caplen, len) = (u32_at(rest, 8) as usize, u32_at(rest, 12) as usize);
+        assert_eq!(caplen, (*original_len).min(SNAPLEN));
+        assert_eq!(len, *original_len);
+        assert_eq!(
+            rest[16..16 + caplen],
+            payload(*original_len, *fill)[..caplen]
+        );
+        rest = &rest[16 + caplen..];
+    }
+    assert!(rest.is_empty());
+}
+
+#[test]
+fn a_lone_input_is_truncated_rather_than_copied_verbatim() {
+    let input = common::nanosecond_pcap(&[(1, vec![7; 100]), (2, vec![8; 10])]);
+    let output = Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .args(["--truncate-snaplen", "16"])
+        .arg(input.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let mut merged = output.stdout;
+    merged[..PCAP_HDR_NSEC.len()].copy_from_slice(PCAP_HDR_NSEC);
+    assert_eq!(
+        common::read_nanosecond_pcap(&merged),
+        vec![(1, vec![7; 16]), (2, vec![8; 10])]
+    );
+}
@@ -0,0 +1,116 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::NANOSECONDS_PER_SECOND;
+use std::io::Write;
+use std::process::Command;
+
+/// Write a timestamp sidecar holding `timestamps` to a temporary file
+fn sidecar(timestamps: &[u64]) -> tempfile::NamedTempFile {
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    for ts in timestamps {
+        file.write_all(&ts.to_le_bytes()).unwrap();
+    }
+    file.flush().unwrap();
+    file
+}
+
+fn ts_sidecar_arg(pcap: &tempfile::NamedTempFile, sidecar: &tempfile::NamedTempFile) -> String {
+    format!("{}={}", pcap.path().display(), sidecar.path().display())
+}
+
+#[test]
+fn sidecar_timestamps_reorder_packets_relative_to_their_own() {
+    // by their own timestamps, every packet of `late` would be merged after every packet of `early`
+    let early = common::nanosecond_pcap(&[
+        (NANOSECONDS_PER_SECOND, vec![1, 1]),
+        (2 * NANOSECONDS_PER_SECOND, vec![1, 2]),
+        (3 * NANOSECONDS_PER_SECOND, vec![1, 3]),
+    ]);
+    let late = common::nanosecond_pcap(&[
+        (100 * NANOSECONDS_PER_SECOND, vec![2, 1]),
+        (101 * NANOSECONDS_PER_SECOND, vec![2, 2]),
+    ]);
+    // the hardware's timestamps interleave them instead
+    let early_sidecar = sidecar(&[10, 30, 50]);
+    let late_sidecar = sidecar(&[20, 40]);
+
+    let output = Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .arg("--ts-sidecar")
+        .arg(ts_sidecar_arg(&early, &early_sidecar))
+        .arg("--ts-sidecar")
+        .arg(ts_sidecar_arg(&late, &late_sidecar))
+        .arg(early.path())
+        .arg(late.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        common::read_nanosecond_pcap(&output.stdout),
+        vec![
+            (10, vec![1, 1]),
+            (20, vec![2, 1]),
+            (30, vec![1, 2]),
+            (40, vec![2, 2]),
+            (50, vec![1, 3]),
+        ]
+    );
+
+    // only the input given a sidecar is merged by it
+    let output = Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .arg("--ts-sidecar")
+        .arg(ts_sidecar_arg(
+            &late,
+            &sidecar(&[0, 2 * NANOSECONDS_PER_SECOND + 1]),
+        ))
+        .arg(early.path())
+        .arg(late.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let order: Vec<Vec<u8>> = common::read_nanosecond_pcap(&output.stdout)
+        .into_iter()
+        .map(|(_ts, payload)| payload)
+        .collect();
+    assert_eq!(
+        order,
+        vec![vec![2, 1], vec![1, 1], vec![1, 2], vec![2, 2], vec![1, 3]]
+    );
+}
+
+#[test]
+fn a_sidecar_short_of_timestamps_ends_its_input_with_a_warning() {
+    let pcap = common::nanosecond_pcap(&[(1, vec![1]), (2, vec![2]), (3, vec![3])]);
+    let sidecar = sidecar(&[10, 20]);
+    let output = Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .env("RUST_LOG", "warn")
+        .arg("--ts-sidecar")
+        .arg(ts_sidecar_arg(&pcap, &sidecar))
+        .arg(pcap.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        common::read_nanosecond_pcap(&output.stdout),
+        vec![(10, vec![1]), (20, vec![2])]
+    );
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("leaving packets (which were not merged) unmatched"));
+}
+
+#[test]
+fn a_sidecar_must_name_an_input() {
+    let pcap = common::nanosecond_pcap(&[(1, vec![1])]);
+    let other = common::nanosecond_pcap(&[(1, vec![1])]);
+    let sidecar = sidecar(&[10]);
+    Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .arg("--ts-sidecar")
+        .arg(ts_sidecar_arg(&other, &sidecar))
+        .arg(pcap.path())
+        .assert()
+        .failure();
+}
@@ -0,0 +1,155 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::NANOSECONDS_PER_SECOND;
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Command, Stdio};
+
+#[test]
+fn files_appearing_in_the_watched_directory_are_merged() {
+    let dir = tempfile::tempdir().unwrap();
+    let first = vec![
+        (NANOSECONDS_PER_SECOND, vec![1; 20]),
+        (3 * NANOSECONDS_PER_SECOND, vec![1; 30]),
+    ];
+    let second = vec![
+        (4 * NANOSECONDS_PER_SECOND, vec![2; 40]),
+        (5 * NANOSECONDS_PER_SECOND, vec![2; 50]),
+    ];
+    std::fs::write(
+        dir.path().join("ring-1.pcap"),
+        common::nanosecond_pcap_bytes(&first),
+    )
+    .unwrap();
+
+    let mut merge = Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .env("RUST_LOG", "merge_pcaps=info")
+        .arg("--watch")
+        .arg(dir.path().join("ring-*.pcap"))
+        .args(["--watch-interval", "20ms", "--watch-idle", "2s"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // the first file has been scanned once the watch is logged, so the second appears after it
+    let mut log = BufReader::new(merge.stderr.take().unwrap()).lines();
+    assert!(log.any(|line| line.unwrap().contains("Watching for new files")));
+    let log = std::thread::spawn(move || log.count());
+    // written under a name which doesn't match, then renamed into place once complete
+    let partial = dir.path().join("ring-2.pcap.tmp");
+    std::fs::write(&partial, common::nanosecond_pcap_bytes(&second)).unwrap();
+    std::fs::rename(&partial, dir.path().join("ring-2.pcap")).unwrap();
+
+    let mut merged = Vec::new();
+    merge
+        .stdout
+        .take()
+        .unwrap()
+        .read_to_end(&mut merged)
+        .unwrap();
+    assert!(merge.wait().unwrap().success());
+    log.join().unwrap();
+    assert_eq!(
+        common::read_nanosecond_pcap(&merged),
+        [first, second].concat()
+    );
+}
+
+#[test]
+fn a_watch_may_begin_with_no_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .arg("--watch")
+        .arg(dir.path().join("*.pcap"))
+        .args(["--watch-interval", "10ms", "--watch-idle", "50ms"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert!(common::read_nanosecond_pcap(&output.stdout).is_empty());
+}
+
+/// Merge `first`, then `late` once the merge has written the packet at `written_ts`, with `args`
+fn merge_with_late_file(
+    first: &[(u64, Vec<u8>)],
+    written_ts: u64,
+    late: &[(u64, Vec<u8>)],
+    args: &[&str],
+) -> std::process::Output {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join("ring-1.pcap"),
+        common::nanosecond_pcap_bytes(first),
+    )
+    .unwrap();
+    let mut merge = Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .env("RUST_LOG", "merge_pcaps=trace")
+        .arg("--watch")
+        .arg(dir.path().join("ring-*.pcap"))
+        .args(["--watch-interval", "20ms", "--watch-idle", "500ms"])
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut log = BufReader::new(merge.stderr.take().unwrap()).lines();
+    let written = format!("ts={}", written_ts);
+    assert!(log.any(|line| {
+        let line = line.unwrap();
+        line.contains("Wrote packet") && line.contains(&written)
+    }));
+    let log = std::thread::spawn(move || log.count());
+    let partial = dir.path().join("ring-2.pcap.tmp");
+    std::fs::write(&partial, common::nanosecond_pcap_bytes(late)).unwrap();
+    std::fs::rename(&partial, dir.path().join("ring-2.pcap")).unwrap();
+
+    let output = merge.wait_with_output().unwrap();
+    log.join().unwrap();
+    output
+}
+
+#[test]
+fn a_new_files_packets_older_than_those_merged_are_written_next() {
+    let first = vec![
+        (NANOSECONDS_PER_SECOND, vec![1; 20]),
+        (3 * NANOSECONDS_PER_SECOND, vec![1; 30]),
+    ];
+    let late = vec![
+        (2 * NANOSECONDS_PER_SECOND, vec![2; 40]),
+        (4 * NANOSECONDS_PER_SECOND, vec![2; 50]),
+    ];
+
+    let output = merge_with_late_file(&first, 3 * NANOSECONDS_PER_SECOND, &late, &[]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        common::read_nanosecond_pcap(&output.stdout),
+        vec![
+            first[0].clone(),
+            first[1].clone(),
+            late[0].clone(),
+            late[1].clone()
+        ]
+    );
+
+    let output = merge_with_late_file(&first, 3 * NANOSECONDS_PER_SECOND, &late, &["--clamp"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        common::read_nanosecond_pcap(&output.stdout),
+        vec![
+            first[0].clone(),
+            first[1].clone(),
+            (3 * NANOSECONDS_PER_SECOND, vec![2; 40]),
+            late[1].clone()
+        ]
+    );
+
+    let output = merge_with_late_file(
+        &first,
+        3 * NANOSECONDS_PER_SECOND,
+        &late,
+        &["--fail-on-backwards"],
+    );
+    assert!(!output.status.success());
+}
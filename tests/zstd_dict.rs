@@ -0,0 +1,53 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::NANOSECONDS_PER_SECOND;
+use std::io::Write;
+use std::process::Command;
+
+#[test]
+fn a_dictionary_compressed_input_is_only_decoded_given_its_dictionary() {
+    // a raw content dictionary of bytes common to the packets, which the compressed frame refers to rather than holds
+    let dictionary: Vec<u8> = (0..4096u32).map(|i| (i * 7 % 251) as u8).collect();
+    let packets: Vec<(u64, Vec<u8>)> = (0..50u64)
+        .map(|i| {
+            let start = i as usize * 13;
+            (
+                i * NANOSECONDS_PER_SECOND,
+                dictionary[start..start + 400].to_vec(),
+            )
+        })
+        .collect();
+    let mut input = tempfile::Builder::new()
+        .suffix(".pcap.zst")
+        .tempfile()
+        .unwrap();
+    let mut encoder =
+        zstd::stream::write::Encoder::with_dictionary(Vec::new(), 3, &dictionary).unwrap();
+    encoder
+        .write_all(&common::nanosecond_pcap_bytes(&packets))
+        .unwrap();
+    input.write_all(&encoder.finish().unwrap()).unwrap();
+    input.flush().unwrap();
+    let mut dictionary_file = tempfile::NamedTempFile::new().unwrap();
+    dictionary_file.write_all(&dictionary).unwrap();
+    dictionary_file.flush().unwrap();
+
+    let output = Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .arg("--zstd-dict")
+        .arg(dictionary_file.path())
+        .arg(input.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(common::read_nanosecond_pcap(&output.stdout), packets);
+
+    let output = Command::cargo_bin("merge_pcaps")
+        .unwrap()
+        .arg(input.path())
+        .output()
+        .unwrap();
+    // its frame refers to a dictionary which wasn't loaded
+    assert!(!output.status.success(), "{:?}", output);
+}